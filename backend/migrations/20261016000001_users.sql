@@ -0,0 +1,22 @@
+-- Accounts. Nested settings are JSON, as the API sends them.
+CREATE TABLE users (
+    username TEXT PRIMARY KEY NOT NULL,
+    password_hash TEXT NOT NULL,
+    email TEXT,
+    -- Base32 TOTP secret and bcrypt hashes of the recovery codes (JSON
+    -- list); NULL without 2FA
+    totp_secret TEXT,
+    totp_enabled INTEGER NOT NULL DEFAULT 0,
+    totp_recovery_codes TEXT NOT NULL DEFAULT '[]',
+    profile TEXT NOT NULL DEFAULT '{}',
+    rooms TEXT NOT NULL DEFAULT '{}',
+    display TEXT NOT NULL DEFAULT '{}',
+    -- Sealed by the client; opaque here
+    settings TEXT NOT NULL DEFAULT '{}',
+    onboarding_completed INTEGER NOT NULL DEFAULT 0,
+    disabled INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+);
+
+-- Not unique: telling someone an address is taken would reveal accounts
+CREATE INDEX users_email ON users (lower(email)) WHERE email IS NOT NULL;
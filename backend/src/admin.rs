@@ -0,0 +1,397 @@
+use axum::{
+    extract::{DefaultBodyLimit, Path, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put, MethodRouter},
+    Extension, Json, Router,
+};
+use serde::Serialize;
+use serde_json::Value;
+use tower_http::limit::RequestBodyLimitLayer;
+use tracing::{info, warn};
+
+#[cfg(feature = "chaos")]
+use crate::chaos::ChaosSettings;
+
+use crate::backup;
+use crate::maintenance::MaintenanceWindow;
+use crate::rooms::SweepStats;
+use crate::{
+    bearer_token, broadcast, current_maintenance, disconnect_clients, end_sessions, persist_rooms, persist_user,
+    send_reset_link, user_sessions, validate_token, AppState, RoomInfo, CLOSE_REMOVED,
+};
+
+/// Username of the admin making the request, set by [`require_admin`].
+#[derive(Debug, Clone)]
+pub struct AdminUser(pub String);
+
+#[derive(Debug, Serialize)]
+struct UserSummary {
+    username: String,
+    email: Option<String>,
+    admin: bool,
+    disabled: bool,
+    totp_enabled: bool,
+    sessions: usize,
+    rooms: Vec<String>,
+}
+
+/// Routes under `/admin`; every one of them requires the admin claim.
+pub fn routes(state: AppState) -> Router<AppState> {
+    let router = Router::new()
+        .route("/users", get(list_users))
+        .route("/users/:username/rooms", get(user_rooms))
+        .route("/users/:username/disable", post(disable_user))
+        .route("/users/:username/enable", post(enable_user))
+        .route("/users/:username/reset-password", post(force_password_reset))
+        .route("/feedback", get(list_feedback))
+        .route("/rooms", get(list_rooms))
+        .route("/rooms/sweeps", get(room_sweeps))
+        .route("/rooms/:name", delete(close_room))
+        .route("/maintenance", put(schedule_maintenance).get(get_maintenance).delete(cancel_maintenance));
+    #[cfg(feature = "chaos")]
+    let router = router.route("/chaos", get(chaos_settings).put(update_chaos_settings));
+    let body_limit = state.config.body_limit;
+    router
+        .layer(RequestBodyLimitLayer::new(body_limit))
+        .route("/backup", backup_body_limit(get(take_backup).put(restore_backup)))
+        .route_layer(axum::middleware::from_fn_with_state(state, require_admin))
+}
+
+/// A backup is as big as the server's data. Besides the body limit, this
+/// lifts axum's own 2 MB limit on `Json`, which applies regardless.
+fn backup_body_limit<S: Clone + Send + Sync + 'static>(route: MethodRouter<S>) -> MethodRouter<S> {
+    route.layer((DefaultBodyLimit::max(backup::MAX_BYTES), RequestBodyLimitLayer::new(backup::MAX_BYTES)))
+}
+
+/// Lets the request through only if its bearer token carries the admin claim.
+async fn require_admin(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let Some(token) = bearer_token(request.headers()) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    match validate_token(&state, token).await {
+        Ok(claims) if claims.admin => {
+            request.extensions_mut().insert(AdminUser(claims.sub));
+            next.run(request).await
+        }
+        Ok(_) => StatusCode::FORBIDDEN.into_response(),
+        Err(status) => status.into_response(),
+    }
+}
+
+/// Rooms the user is in or knocking on.
+async fn rooms_of(state: &AppState, username: &str) -> Vec<String> {
+    let rooms = state.rooms.lock().await;
+    let mut names: Vec<String> = rooms
+        .iter()
+        .filter(|(_, room)| {
+            room.peers.values().any(|(u, _)| u == username) || room.waiting.iter().any(|k| k.username == username)
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    names.sort();
+    names
+}
+
+async fn list_users(State(state): State<AppState>) -> impl IntoResponse {
+    let accounts: Vec<_> = state
+        .users
+        .lock()
+        .await
+        .iter()
+        .map(|(name, user)| (name.clone(), user.email.clone(), user.disabled, user.totp.as_ref().is_some_and(|t| t.enabled)))
+        .collect();
+
+    let mut list = Vec::with_capacity(accounts.len());
+    for (username, email, disabled, totp_enabled) in accounts {
+        list.push(UserSummary {
+            admin: state.admins.contains(&username),
+            sessions: user_sessions(&state, &username).await.len(),
+            rooms: rooms_of(&state, &username).await,
+            username,
+            email,
+            disabled,
+            totp_enabled,
+        });
+    }
+    list.sort_by(|a, b| a.username.cmp(&b.username));
+    Json(list)
+}
+
+async fn user_rooms(State(state): State<AppState>, Path(username): Path<String>) -> impl IntoResponse {
+    if !state.users.lock().await.contains_key(&username) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    Json(rooms_of(&state, &username).await).into_response()
+}
+
+/// Blocks logins and ends every session of the account.
+async fn disable_user(
+    State(state): State<AppState>,
+    Extension(AdminUser(admin)): Extension<AdminUser>,
+    Path(username): Path<String>,
+) -> impl IntoResponse {
+    if username == admin {
+        return (StatusCode::BAD_REQUEST, "You cannot disable your own account").into_response();
+    }
+    match state.users.lock().await.get_mut(&username) {
+        Some(user) => user.disabled = true,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    }
+    persist_user(&state, &username).await;
+    end_sessions(&state, &username, None, "Account disabled").await;
+    info!("User {} disabled by admin {}", username, admin);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn enable_user(
+    State(state): State<AppState>,
+    Extension(AdminUser(admin)): Extension<AdminUser>,
+    Path(username): Path<String>,
+) -> impl IntoResponse {
+    match state.users.lock().await.get_mut(&username) {
+        Some(user) => user.disabled = false,
+        None => return StatusCode::NOT_FOUND,
+    }
+    persist_user(&state, &username).await;
+    info!("User {} enabled by admin {}", username, admin);
+    StatusCode::NO_CONTENT
+}
+
+/// Invalidates the current password, signs the user out everywhere and mails
+/// them a reset link. Accounts without an email cannot be reset this way.
+async fn force_password_reset(
+    State(state): State<AppState>,
+    Extension(AdminUser(admin)): Extension<AdminUser>,
+    Path(username): Path<String>,
+) -> impl IntoResponse {
+    let email = match state.users.lock().await.get_mut(&username) {
+        Some(user) => match user.email.clone() {
+            Some(email) => {
+                // An empty hash never verifies, so only the reset link gets them back in
+                user.password_hash.clear();
+                email
+            }
+            None => return (StatusCode::CONFLICT, "User has no email address to send a reset link to").into_response(),
+        },
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+    persist_user(&state, &username).await;
+    end_sessions(&state, &username, None, "Password reset required").await;
+    send_reset_link(&state, &username, &email).await;
+    info!("Password reset forced for user {} by admin {}", username, admin);
+    StatusCode::ACCEPTED.into_response()
+}
+
+/// Reports from the in-app feedback form, newest first.
+async fn list_feedback(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.feedback.lock().await.list())
+}
+
+/// Every room, unlisted and direct-message ones too, by name.
+async fn list_rooms(State(state): State<AppState>) -> impl IntoResponse {
+    let mut list: Vec<RoomInfo> = state.rooms.lock().await.iter().map(|(name, room)| RoomInfo::new(name, room)).collect();
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+    Json(list)
+}
+
+/// Removes a room at once, disconnecting everyone inside or knocking.
+async fn close_room(
+    State(state): State<AppState>,
+    Extension(AdminUser(admin)): Extension<AdminUser>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    let Some(room) = state.rooms.lock().await.remove(&name) else {
+        return StatusCode::NOT_FOUND;
+    };
+    let clients: Vec<_> = room.peers.keys().copied().chain(room.waiting.iter().map(|k| k.client_id)).collect();
+    disconnect_clients(&state, &clients, CLOSE_REMOVED, &format!("Room {} was closed", name)).await;
+    state.activity.lock().await.forget(&name);
+    state.digests.lock().await.forget_room(&name);
+    if room.persistent {
+        persist_rooms(&state).await;
+    }
+    info!("Room {} closed by admin {}", name, admin);
+    StatusCode::NO_CONTENT
+}
+
+async fn take_backup(
+    State(state): State<AppState>,
+    Extension(AdminUser(admin)): Extension<AdminUser>,
+) -> impl IntoResponse {
+    let backup = backup::create(&state).await;
+    info!("Backup taken by admin {}", admin);
+    Json(backup)
+}
+
+/// Replaces accounts, persistent rooms and mail with a backup's. Everyone
+/// is signed out, and persistent rooms and rooms the backup has are closed
+/// first; other rooms carry on.
+async fn restore_backup(
+    State(state): State<AppState>,
+    Extension(AdminUser(admin)): Extension<AdminUser>,
+    Json(backup): Json<Value>,
+) -> impl IntoResponse {
+    let snapshot = match backup::open(backup) {
+        Ok(snapshot) => snapshot,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    if !snapshot.has_user(&admin) {
+        return (StatusCode::CONFLICT, "The backup has no account for you; restoring it would lock you out").into_response();
+    }
+    let closing: Vec<_> = {
+        let rooms = state.rooms.lock().await;
+        rooms
+            .iter()
+            .filter(|(name, room)| room.persistent || snapshot.rooms().any(|r| r == name.as_str()))
+            .map(|(name, room)| {
+                let clients: Vec<_> = room.peers.keys().copied().chain(room.waiting.iter().map(|k| k.client_id)).collect();
+                (name.clone(), clients)
+            })
+            .collect()
+    };
+    for (name, clients) in &closing {
+        disconnect_clients(&state, clients, CLOSE_REMOVED, &format!("Room {} was restored from a backup", name)).await;
+        state.activity.lock().await.forget(name);
+        state.digests.lock().await.forget_room(name);
+    }
+    let usernames: Vec<String> = state.users.lock().await.keys().cloned().collect();
+    for username in &usernames {
+        end_sessions(&state, username, None, "Server data restored").await;
+        if !snapshot.has_user(username) {
+            state.digests.lock().await.forget_user(username);
+        }
+    }
+    snapshot.restore(&state).await;
+    persist_rooms(&state).await;
+    if let Some(store) = &state.user_store {
+        if let Err(err) = store.save_all(&state.users).await {
+            warn!("Could not save the restored accounts: {}", err);
+        }
+    }
+    info!("Backup restored by admin {}", admin);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[derive(Debug, Serialize)]
+struct RoomSweepReport {
+    empty_ttl_secs: u64,
+    persistent_idle_secs: u64,
+    rooms: usize,
+    #[serde(flatten)]
+    stats: SweepStats,
+}
+
+/// The room retention policy and how many rooms it has reclaimed.
+async fn room_sweeps(State(state): State<AppState>) -> impl IntoResponse {
+    Json(RoomSweepReport {
+        empty_ttl_secs: state.room_retention.empty_ttl.as_secs(),
+        persistent_idle_secs: state.room_retention.persistent_idle.as_secs(),
+        rooms: state.rooms.lock().await.len(),
+        stats: state.room_sweeps.lock().await.clone(),
+    })
+}
+
+async fn get_maintenance(State(state): State<AppState>) -> impl IntoResponse {
+    match current_maintenance(&state).await {
+        Some(window) => Json(window).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Schedules downtime, replacing any window already scheduled, and tells
+/// every connected client. Clients connecting later hear of it after
+/// `Hello`.
+async fn schedule_maintenance(
+    State(state): State<AppState>,
+    Extension(AdminUser(admin)): Extension<AdminUser>,
+    Json(window): Json<MaintenanceWindow>,
+) -> impl IntoResponse {
+    if let Err(message) = window.validate() {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    *state.maintenance.lock().await = Some(window.clone());
+    broadcast(&state, window.notice()).await;
+    info!("Maintenance scheduled for {} ({}s) by admin {}", window.starts_at, window.duration, admin);
+    Json(window).into_response()
+}
+
+async fn cancel_maintenance(
+    State(state): State<AppState>,
+    Extension(AdminUser(admin)): Extension<AdminUser>,
+) -> impl IntoResponse {
+    if current_maintenance(&state).await.is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+    state.maintenance.lock().await.take();
+    broadcast(&state, serde_json::json!({"type": "maintenance_cancelled"})).await;
+    info!("Maintenance cancelled by admin {}", admin);
+    StatusCode::NO_CONTENT
+}
+
+#[cfg(feature = "chaos")]
+async fn chaos_settings(State(state): State<AppState>) -> impl IntoResponse {
+    Json(*state.chaos.lock().await)
+}
+
+/// Makes relayed signaling and connections misbehave as asked; all rates at
+/// 0 turn chaos off again.
+#[cfg(feature = "chaos")]
+async fn update_chaos_settings(
+    State(state): State<AppState>,
+    Extension(AdminUser(admin)): Extension<AdminUser>,
+    Json(settings): Json<ChaosSettings>,
+) -> impl IntoResponse {
+    if let Err(message) = settings.validate() {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    *state.chaos.lock().await = settings;
+    if settings.is_calm() {
+        info!("Chaos turned off by admin {}", admin);
+    } else {
+        warn!("Chaos set to {:?} by admin {}", settings, admin);
+    }
+    Json(settings).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::header::CONTENT_TYPE};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// Sends `backup` to a `/backup` route with the real limits, whose
+    /// handler only opens it.
+    async fn restore(backup: Value) -> StatusCode {
+        let app = Router::new().route(
+            "/backup",
+            backup_body_limit(put(|Json(backup): Json<Value>| async move {
+                match backup::open(backup) {
+                    Ok(_) => StatusCode::NO_CONTENT,
+                    Err(_) => StatusCode::BAD_REQUEST,
+                }
+            })),
+        );
+        let request = axum::http::Request::put("/backup")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(backup.to_string()))
+            .unwrap();
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn restores_backups_over_two_megabytes() {
+        let backup = backup::tests::with_users(8000);
+        assert!(backup.to_string().len() > 2 * 1024 * 1024);
+        assert_eq!(restore(backup).await, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_tampered_backup() {
+        let mut backup = backup::tests::with_users(8000);
+        backup["users"][0]["username"] = "mallory".into();
+        assert_eq!(restore(backup).await, StatusCode::BAD_REQUEST);
+    }
+}
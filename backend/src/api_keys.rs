@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Every API key starts with this, which tells them apart from JWTs.
+pub const KEY_PREFIX: &str = "p2pk_";
+/// Keys a single user may hold at once.
+pub const MAX_KEYS_PER_USER: usize = 10;
+
+/// What a key may be used for. Account security (password, sessions, 2FA,
+/// the keys themselves) always needs an interactive login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Connect to `/ws`, join rooms and relay signaling messages
+    Signaling,
+    /// Browse the room directory and edit rooms the key's user moderates
+    Rooms,
+    /// Read and update the profile and saved rooms
+    Profile,
+}
+
+impl Scope {
+    /// The scope a REST path needs, or `None` if keys are not accepted there.
+    pub fn for_path(path: &str) -> Option<Scope> {
+        match path {
+            "/rooms" => Some(Scope::Rooms),
+            "/profile" | "/account/rooms" => Some(Scope::Profile),
+            _ if path.starts_with("/rooms/") => Some(Scope::Rooms),
+            _ => None,
+        }
+    }
+}
+
+/// A key as stored: only the SHA-256 of the secret is kept, so the key itself
+/// is shown once at creation. Keys are long and random, so a fast hash is
+/// enough here, unlike passwords.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    #[serde(skip)]
+    pub username: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+fn digest(key: &str) -> String {
+    Sha256::digest(key.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// API keys by the hash of their secret.
+#[derive(Debug, Default)]
+pub struct ApiKeyStore {
+    keys: HashMap<String, ApiKey>,
+}
+
+impl ApiKeyStore {
+    /// Creates a key for `username` and returns it along with the secret to
+    /// hand to the user.
+    pub fn create(&mut self, username: &str, name: String, scopes: Vec<Scope>) -> (ApiKey, String) {
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let key = format!("{}{}", KEY_PREFIX, secret.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+
+        let api_key = ApiKey {
+            id: Uuid::new_v4(),
+            username: username.to_string(),
+            name,
+            scopes,
+            created_at: Utc::now(),
+            last_used: None,
+        };
+        self.keys.insert(digest(&key), api_key.clone());
+        (api_key, key)
+    }
+
+    /// The key matching `key`; marks it as just used.
+    pub fn verify(&mut self, key: &str) -> Option<ApiKey> {
+        let api_key = self.keys.get_mut(&digest(key))?;
+        api_key.last_used = Some(Utc::now());
+        Some(api_key.clone())
+    }
+
+    /// Deletes `username`'s key `id`; false if they have no such key.
+    pub fn revoke(&mut self, username: &str, id: &Uuid) -> bool {
+        let before = self.keys.len();
+        self.keys.retain(|_, k| !(k.id == *id && k.username == username));
+        self.keys.len() < before
+    }
+
+    /// Deletes all of `username`'s keys and returns their ids.
+    pub fn remove_user(&mut self, username: &str) -> Vec<Uuid> {
+        let ids: Vec<Uuid> = self.keys.values().filter(|k| k.username == username).map(|k| k.id).collect();
+        self.keys.retain(|_, k| k.username != username);
+        ids
+    }
+
+    /// `username`'s keys, newest first.
+    pub fn for_user(&self, username: &str) -> Vec<ApiKey> {
+        let mut list: Vec<ApiKey> = self.keys.values().filter(|k| k.username == username).cloned().collect();
+        list.sort_by_key(|k| std::cmp::Reverse(k.created_at));
+        list
+    }
+}
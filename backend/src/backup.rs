@@ -0,0 +1,243 @@
+use chrono::Utc;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::mailbox;
+use crate::room_store::StoredRoom;
+use crate::totp::TotpState;
+use crate::{AccountRooms, AccountSettings, AppState, DisplaySettings, Profile, User};
+
+/// Marks a file as one of these backups.
+const FORMAT: &str = "p2p-chat-backup";
+/// Goes up whenever the layout of a section changes.
+const VERSION: u64 = 1;
+/// The parts of a backup, each with its own checksum.
+const SECTIONS: [&str; 4] = ["users", "rooms", "mailbox", "settings"];
+/// Largest backup `PUT /admin/backup` takes, in bytes.
+pub const MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// An account without its synced settings, which have their own section.
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredUser {
+    username: String,
+    password_hash: String,
+    email: Option<String>,
+    totp: Option<TotpState>,
+    profile: Profile,
+    rooms: AccountRooms,
+    onboarding_completed: bool,
+    disabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredSettings {
+    username: String,
+    display: DisplaySettings,
+    settings: AccountSettings,
+}
+
+/// What a backup holds, checked and ready to restore.
+pub struct Snapshot {
+    users: Vec<StoredUser>,
+    rooms: Vec<StoredRoom>,
+    mailbox: mailbox::Contents,
+    settings: Vec<StoredSettings>,
+}
+
+/// Hex SHA-256 of a section's JSON. Object keys come out sorted, so the
+/// checksum does not depend on how the file was formatted.
+fn checksum(section: &Value) -> String {
+    let json = serde_json::to_vec(section).expect("JSON values serialize");
+    Sha256::digest(json).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn to_value(section: impl Serialize) -> Value {
+    serde_json::to_value(section).expect("backup sections serialize to JSON")
+}
+
+/// Everything that outlives a restart of a server with storage: accounts,
+/// persistent rooms, waiting mail and account settings. Sessions, live
+/// rooms and digests are left out. The stores are locked together, so
+/// the sections agree with each other.
+pub async fn create(state: &AppState) -> Value {
+    let rooms = state.rooms.lock().await;
+    let users = state.users.lock().await;
+    let mut mailbox = state.mailbox.lock().await;
+    seal([
+        to_value(
+            users
+                .iter()
+                .map(|(username, user)| StoredUser {
+                    username: username.clone(),
+                    password_hash: user.password_hash.clone(),
+                    email: user.email.clone(),
+                    totp: user.totp.clone(),
+                    profile: user.profile.clone(),
+                    rooms: user.rooms.clone(),
+                    onboarding_completed: user.onboarding_completed,
+                    disabled: user.disabled,
+                })
+                .collect::<Vec<_>>(),
+        ),
+        to_value(crate::room_store::persistent(&rooms)),
+        to_value(mailbox.contents()),
+        to_value(
+            users
+                .iter()
+                .map(|(username, user)| StoredSettings {
+                    username: username.clone(),
+                    display: user.display.clone(),
+                    settings: user.settings.clone(),
+                })
+                .collect::<Vec<_>>(),
+        ),
+    ])
+}
+
+/// Puts the sections together with the format, version and checksums.
+fn seal(mut sections: [Value; 4]) -> Value {
+    let mut backup = Map::new();
+    backup.insert("format".into(), FORMAT.into());
+    backup.insert("version".into(), VERSION.into());
+    backup.insert("created_at".into(), Utc::now().to_rfc3339().into());
+    let checksums: Map<String, Value> =
+        SECTIONS.iter().zip(&sections).map(|(name, section)| (name.to_string(), checksum(section).into())).collect();
+    backup.insert("checksums".into(), checksums.into());
+    for (name, section) in SECTIONS.iter().zip(&mut sections) {
+        backup.insert(name.to_string(), section.take());
+    }
+    Value::Object(backup)
+}
+
+/// Checks a backup's format, version and checksums, and reads its sections.
+pub fn open(mut backup: Value) -> Result<Snapshot, String> {
+    if backup.get("format").and_then(Value::as_str) != Some(FORMAT) {
+        return Err("Not a backup of this server".to_string());
+    }
+    match backup.get("version").and_then(Value::as_u64) {
+        Some(VERSION) => {}
+        Some(version) => return Err(format!("Backup version {} cannot be restored here, only {}", version, VERSION)),
+        None => return Err("The backup has no version".to_string()),
+    }
+    let mut section = |name: &str| -> Result<Value, String> {
+        let value = backup.get_mut(name).map(Value::take).ok_or_else(|| format!("The backup has no {} section", name))?;
+        let expected = backup["checksums"].get(name).and_then(Value::as_str);
+        if expected != Some(checksum(&value).as_str()) {
+            return Err(format!("The {} section of the backup does not match its checksum", name));
+        }
+        Ok(value)
+    };
+    fn read<T: DeserializeOwned>(name: &str, value: Value) -> Result<T, String> {
+        serde_json::from_value(value).map_err(|err| format!("Cannot read the {} section of the backup: {}", name, err))
+    }
+    let snapshot = Snapshot {
+        users: read("users", section("users")?)?,
+        rooms: read("rooms", section("rooms")?)?,
+        mailbox: read("mailbox", section("mailbox")?)?,
+        settings: read("settings", section("settings")?)?,
+    };
+    if let Some(stray) = snapshot.settings.iter().find(|s| !snapshot.users.iter().any(|u| u.username == s.username)) {
+        return Err(format!("The backup has settings for {}, who has no account", stray.username));
+    }
+    Ok(snapshot)
+}
+
+impl Snapshot {
+    pub fn has_user(&self, username: &str) -> bool {
+        self.users.iter().any(|user| user.username == username)
+    }
+
+    /// The names of the rooms it holds.
+    pub fn rooms(&self) -> impl Iterator<Item = &str> {
+        self.rooms.iter().map(|room| room.name.as_str())
+    }
+
+    /// Replaces accounts, persistent rooms and mail with the backup's, all
+    /// under the same locks. Accounts without settings in it get the
+    /// defaults. Callers end sessions and close the rooms being replaced.
+    pub async fn restore(self, state: &AppState) {
+        let mut rooms = state.rooms.lock().await;
+        let mut users = state.users.lock().await;
+        let mut mailbox = state.mailbox.lock().await;
+        let mut settings = self.settings;
+        *users = self
+            .users
+            .into_iter()
+            .map(|stored| {
+                let (display, account_settings) = match settings.iter().position(|s| s.username == stored.username) {
+                    Some(i) => {
+                        let s = settings.swap_remove(i);
+                        (s.display, s.settings)
+                    }
+                    None => Default::default(),
+                };
+                let user = User {
+                    password_hash: stored.password_hash,
+                    email: stored.email,
+                    totp: stored.totp,
+                    profile: stored.profile,
+                    rooms: stored.rooms,
+                    display,
+                    settings: account_settings,
+                    onboarding_completed: stored.onboarding_completed,
+                    disabled: stored.disabled,
+                };
+                (stored.username, user)
+            })
+            .collect();
+        rooms.retain(|_, room| !room.persistent);
+        rooms.extend(self.rooms.into_iter().map(StoredRoom::into_room));
+        mailbox.restore(self.mailbox);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A backup holding `count` accounts and nothing else.
+    pub(crate) fn with_users(count: usize) -> Value {
+        let users: Vec<_> = (0..count)
+            .map(|i| StoredUser {
+                username: format!("user{}", i),
+                password_hash: format!("$argon2id$v=19$m=19456,t=2,p=1${}", "x".repeat(400)),
+                email: Some(format!("user{}@example.com", i)),
+                totp: None,
+                profile: Profile::default(),
+                rooms: AccountRooms::default(),
+                onboarding_completed: true,
+                disabled: false,
+            })
+            .collect();
+        seal([to_value(users), to_value(Vec::<StoredRoom>::new()), to_value(mailbox::Contents::default()), Value::Array(Vec::new())])
+    }
+
+    #[test]
+    fn opens_what_it_seals() {
+        let snapshot = open(with_users(3)).unwrap();
+        assert!(snapshot.has_user("user2"));
+        assert_eq!(snapshot.rooms().count(), 0);
+    }
+
+    #[test]
+    fn rejects_a_tampered_section() {
+        let mut backup = with_users(3);
+        backup["users"][1]["disabled"] = true.into();
+        assert_eq!(open(backup).err().unwrap(), "The users section of the backup does not match its checksum");
+    }
+
+    #[test]
+    fn rejects_a_tampered_checksum() {
+        let mut backup = with_users(3);
+        backup["checksums"]["mailbox"] = checksum(&Value::Null).into();
+        assert_eq!(open(backup).err().unwrap(), "The mailbox section of the backup does not match its checksum");
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut backup = with_users(1);
+        backup["version"] = (VERSION + 1).into();
+        assert!(open(backup).err().unwrap().starts_with("Backup version 2 cannot be restored here"));
+    }
+}
@@ -0,0 +1,288 @@
+//! Command-line client for the server's admin API, for scripting what the
+//! admin dashboard does. Run `adminctl help` for the commands.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::ExitCode;
+
+use reqwest::{Method, StatusCode};
+use serde_json::Value;
+
+/// Token of an admin account, from `POST /login`. Read from the environment
+/// only, so it does not show up in `ps`.
+const TOKEN_VAR: &str = "ADMINCTL_TOKEN";
+/// Where the server is, with its base path if it has one.
+const SERVER_VAR: &str = "ADMINCTL_SERVER";
+const DEFAULT_SERVER: &str = "http://localhost:3000";
+
+const USAGE: &str = "\
+Usage: adminctl [--server <url>] [--json] <command> [arguments]
+
+Commands:
+  users                         List accounts
+  user-rooms <username>         Rooms a user is in or knocking on
+  ban <username>                Disable an account and end its sessions
+  unban <username>              Enable a disabled account
+  reset-password <username>     Sign a user out and mail them a reset link
+  rooms                         List every room, unlisted ones too
+  close-room <name>             Remove a room and disconnect everyone in it
+  feedback                      Feedback reports, newest first
+  sweeps                        Room retention and what it reclaimed
+  maintenance                   The scheduled maintenance window, if any
+  schedule-maintenance <starts_at> <duration_secs> [message]
+                                Announce downtime; starts_at is RFC 3339
+  cancel-maintenance            Call off the scheduled maintenance
+  backup <file>                 Save accounts, persistent rooms, mail and settings
+  restore <file>                Replace the server's data with a backup's
+
+Options:
+  --server <url>  Server to manage; ADMINCTL_SERVER, else http://localhost:3000
+  --json          Print the server's JSON instead of tables
+
+The admin token is read from ADMINCTL_TOKEN.";
+
+/// An admin API call: method, path under `/admin` and body.
+struct Call {
+    method: Method,
+    path: String,
+    body: Option<Value>,
+    /// Printed when the server answers without a body
+    done: String,
+    /// What a bare 404 means
+    not_found: &'static str,
+    /// File the body is read from
+    input: Option<String>,
+    /// File the answer is written to instead of printed
+    output: Option<String>,
+}
+
+impl Call {
+    fn new(method: Method, path: String, done: impl Into<String>) -> Self {
+        Call { method, path, body: None, done: done.into(), not_found: "No such user or room", input: None, output: None }
+    }
+}
+
+/// Encodes a username or room name as one path segment.
+fn segment(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn parse(command: &str, args: &[String]) -> Result<Call, String> {
+    let one = |what: &str| match args {
+        [value] => Ok(segment(value)),
+        _ => Err(format!("{} takes a {}", command, what)),
+    };
+    let file = || match args {
+        [path] => Ok(path.clone()),
+        _ => Err(format!("{} takes a file", command)),
+    };
+    let none = || if args.is_empty() { Ok(()) } else { Err(format!("{} takes no arguments", command)) };
+    let call = match command {
+        "users" => {
+            none()?;
+            Call::new(Method::GET, "/users".into(), "")
+        }
+        "user-rooms" => Call::new(Method::GET, format!("/users/{}/rooms", one("username")?), ""),
+        "ban" => Call::new(Method::POST, format!("/users/{}/disable", one("username")?), "Account disabled."),
+        "unban" => Call::new(Method::POST, format!("/users/{}/enable", one("username")?), "Account enabled."),
+        "reset-password" => {
+            Call::new(Method::POST, format!("/users/{}/reset-password", one("username")?), "Reset link sent.")
+        }
+        "rooms" => {
+            none()?;
+            Call::new(Method::GET, "/rooms".into(), "")
+        }
+        "close-room" => Call::new(Method::DELETE, format!("/rooms/{}", one("room name")?), "Room closed."),
+        "feedback" => {
+            none()?;
+            Call::new(Method::GET, "/feedback".into(), "")
+        }
+        "sweeps" => {
+            none()?;
+            Call::new(Method::GET, "/rooms/sweeps".into(), "")
+        }
+        "maintenance" => {
+            none()?;
+            Call { not_found: "No maintenance scheduled", ..Call::new(Method::GET, "/maintenance".into(), "") }
+        }
+        "cancel-maintenance" => {
+            none()?;
+            let call = Call::new(Method::DELETE, "/maintenance".into(), "Maintenance cancelled.");
+            Call { not_found: "No maintenance scheduled", ..call }
+        }
+        "schedule-maintenance" => {
+            let (starts_at, duration, message) = match args {
+                [starts_at, duration] => (starts_at, duration, None),
+                [starts_at, duration, message] => (starts_at, duration, Some(message)),
+                _ => return Err("schedule-maintenance takes <starts_at> <duration_secs> [message]".to_string()),
+            };
+            let duration: u64 = duration.parse().map_err(|_| "duration_secs must be a number of seconds".to_string())?;
+            let mut call = Call::new(Method::PUT, "/maintenance".into(), "");
+            call.body = Some(serde_json::json!({"starts_at": starts_at, "duration": duration, "message": message}));
+            call
+        }
+        "backup" => {
+            let path = file()?;
+            let call = Call::new(Method::GET, "/backup".into(), format!("Backup written to {}.", path));
+            Call { output: Some(path), ..call }
+        }
+        "restore" => {
+            let call = Call::new(Method::PUT, "/backup".into(), "Backup restored. Everyone has to log in again.");
+            Call { input: Some(file()?), ..call }
+        }
+        other => return Err(format!("Unknown command {:?}", other)),
+    };
+    Ok(call)
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_string(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(cell).collect::<Vec<_>>().join(", "),
+        other => other.to_string(),
+    }
+}
+
+/// Lists of objects become a table, one column per field, led by what
+/// names the row; objects become `field: value` lines.
+fn print_table(value: &Value) {
+    match value {
+        Value::Array(rows) if rows.iter().all(Value::is_object) => {
+            let Some(Value::Object(first)) = rows.first() else {
+                println!("(none)");
+                return;
+            };
+            let mut columns: Vec<&str> = first.keys().map(String::as_str).collect();
+            if let Some(lead) = columns.iter().position(|c| matches!(*c, "username" | "name")) {
+                let lead = columns.remove(lead);
+                columns.insert(0, lead);
+            }
+            let table: Vec<Vec<String>> = std::iter::once(columns.iter().map(|c| c.to_uppercase()).collect())
+                .chain(rows.iter().map(|row| columns.iter().map(|c| cell(&row[*c])).collect()))
+                .collect();
+            let widths: Vec<usize> =
+                (0..columns.len()).map(|i| table.iter().map(|row| row[i].chars().count()).max().unwrap_or(0)).collect();
+            for row in table {
+                let line: Vec<String> = row.iter().zip(&widths).map(|(cell, &width)| format!("{:<width$}", cell)).collect();
+                println!("{}", line.join("  ").trim_end());
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|item| println!("{}", cell(item))),
+        Value::Object(fields) => {
+            let width = fields.keys().map(String::len).max().unwrap_or(0);
+            for (field, value) in fields {
+                println!("{:<width$}  {}", format!("{}:", field), cell(value), width = width + 1);
+            }
+        }
+        other => println!("{}", cell(other)),
+    }
+}
+
+/// Writes a backup readable by its owner only: it holds password hashes
+/// and 2FA secrets.
+fn save(path: &str, data: &str) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(data.as_bytes())
+}
+
+async fn run(server: &str, token: &str, mut call: Call, json: bool) -> Result<(), String> {
+    if let Some(path) = &call.input {
+        let data = fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path, err))?;
+        let body = serde_json::from_str(&data).map_err(|err| format!("{} is not JSON: {}", path, err))?;
+        call.body = Some(body);
+    }
+    let url = format!("{}/admin{}", server.trim_end_matches('/'), call.path);
+    let mut request = reqwest::Client::new().request(call.method, &url).bearer_auth(token);
+    if let Some(body) = &call.body {
+        request = request.header("content-type", "application/json").body(body.to_string());
+    }
+    let response = request.send().await.map_err(|err| format!("Cannot reach {}: {}", server, err))?;
+    let status = response.status();
+    let text = response.text().await.map_err(|err| format!("Cannot read the answer: {}", err))?;
+    match status {
+        StatusCode::UNAUTHORIZED => return Err(format!("The server did not accept {}", TOKEN_VAR)),
+        StatusCode::FORBIDDEN => return Err(format!("{} is not an admin's token", TOKEN_VAR)),
+        StatusCode::NOT_FOUND if text.is_empty() => return Err(call.not_found.to_string()),
+        status if !status.is_success() => return Err(format!("{}: {}", status, text)),
+        _ => {}
+    }
+    if text.is_empty() {
+        if !json {
+            println!("{}", call.done);
+        }
+        return Ok(());
+    }
+    let value: Value = serde_json::from_str(&text).map_err(|_| format!("Unexpected answer: {}", text))?;
+    if let Some(path) = &call.output {
+        let data = serde_json::to_string_pretty(&value).unwrap_or(text);
+        save(path, &data).map_err(|err| format!("Cannot write {}: {}", path, err))?;
+        println!("{}", call.done);
+    } else if json {
+        println!("{}", serde_json::to_string_pretty(&value).unwrap_or(text));
+    } else {
+        print_table(&value);
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut server = env::var(SERVER_VAR).ok().filter(|s| !s.is_empty()).unwrap_or_else(|| DEFAULT_SERVER.to_string());
+    let mut json = false;
+    let mut args = env::args().skip(1).peekable();
+    while let Some(option) = args.next_if(|arg| arg.starts_with("--")) {
+        match option.split_once('=') {
+            Some(("--server", url)) => server = url.to_string(),
+            None if option == "--server" => match args.next() {
+                Some(url) => server = url,
+                None => {
+                    eprintln!("--server needs a value");
+                    return ExitCode::from(2);
+                }
+            },
+            None if option == "--json" => json = true,
+            _ => {
+                eprintln!("Unknown option {:?}\n\n{}", option, USAGE);
+                return ExitCode::from(2);
+            }
+        }
+    }
+    let Some(command) = args.next() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(2);
+    };
+    if matches!(command.as_str(), "help" | "-h") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let rest: Vec<String> = args.collect();
+    let call = match parse(&command, &rest) {
+        Ok(call) => call,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let Some(token) = env::var(TOKEN_VAR).ok().filter(|t| !t.is_empty()) else {
+        eprintln!("Set {} to an admin's token", TOKEN_VAR);
+        return ExitCode::from(2);
+    };
+    match run(&server, &token, call, json).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
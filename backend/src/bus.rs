@@ -0,0 +1,593 @@
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_nats::jetstream::kv::{self, Operation};
+use futures::future::{self, BoxFuture};
+use futures::stream::BoxStream;
+use futures::{FutureExt, StreamExt};
+use redis::{aio::ConnectionManager, AsyncCommands, Script};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::config::{Layers, Setting};
+
+/// What instances share rooms through: `memory` (the default) for a single
+/// instance, a Redis URL such as `redis://redis.internal:6379`, or a NATS
+/// URL such as `nats://nats.internal:4222`. No flag: the URL may hold a
+/// password.
+pub const URL: Setting = Setting { key: "signaling_bus", var: "SIGNALING_BUS", flag: None };
+
+/// How long a room stays registered to its instance unless renewed; the
+/// rooms of an instance that died are free again after this.
+pub const CLAIM_TTL: Duration = Duration::from_secs(60);
+/// How long a shared session lasts after a token was last issued for it,
+/// as long as that token, see [`crate::sessions::TOKEN_TTL`].
+pub const SESSION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Records the instances share, each kind kept for its own time. Writing
+/// a record again restarts its time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Table {
+    /// Logins, so a token works at every instance; kept for [`SESSION_TTL`]
+    Sessions,
+    /// Public rooms as the directory lists them; kept for [`CLAIM_TTL`]
+    /// and renewed along with the claim
+    Directory,
+}
+
+impl Table {
+    fn ttl(self) -> Duration {
+        match self {
+            Table::Sessions => SESSION_TTL,
+            Table::Directory => CLAIM_TTL,
+        }
+    }
+}
+
+const REDIS_ROOM_PREFIX: &str = "p2p-chat:room:";
+const REDIS_CHANNEL_PREFIX: &str = "p2p-chat:instance:";
+const REDIS_BROADCAST_CHANNEL: &str = "p2p-chat:all";
+/// Registers the room to ARGV[1] for ARGV[2] seconds unless another
+/// instance has it; returns whose it is.
+const REDIS_CLAIM_SCRIPT: &str = r"
+local home = redis.call('GET', KEYS[1])
+if home and home ~= ARGV[1] then return home end
+redis.call('SET', KEYS[1], ARGV[1], 'EX', ARGV[2])
+return ARGV[1]
+";
+/// Frees the room if it is still registered to ARGV[1].
+const REDIS_RELEASE_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) end
+return 0
+";
+
+/// JetStream key-value bucket rooms are registered in; entries expire
+/// after [`CLAIM_TTL`].
+const NATS_BUCKET: &str = "p2p-chat-rooms";
+const NATS_SESSIONS_BUCKET: &str = "p2p-chat-sessions";
+const NATS_DIRECTORY_BUCKET: &str = "p2p-chat-directory";
+const NATS_SUBJECT_PREFIX: &str = "p2p-chat.instance.";
+const NATS_BROADCAST_SUBJECT: &str = "p2p-chat.all";
+
+/// How the instances of a deployment find each other's rooms and reach
+/// each other, see [`crate::cluster`]. Rooms are registered to the
+/// instance that has them, and each instance reads what is published to it.
+/// Keys of [`Table`] records are letters, digits, `-` and `.`; see [`hex`].
+pub trait SignalingBus: Debug + Send + Sync {
+    /// Registers `room` to `instance` for [`CLAIM_TTL`], or renews it,
+    /// unless another instance has it; returns whose it is.
+    fn claim<'a>(&'a self, room: &'a str, instance: Uuid) -> BoxFuture<'a, Result<Uuid, String>>;
+    /// Frees `room` if it is registered to `instance`.
+    fn release<'a>(&'a self, room: &'a str, instance: Uuid) -> BoxFuture<'a, Result<(), String>>;
+    /// The instance `room` is registered to, if any.
+    fn home<'a>(&'a self, room: &'a str) -> BoxFuture<'a, Result<Option<Uuid>, String>>;
+    fn publish(&self, instance: Uuid, payload: Vec<u8>) -> BoxFuture<'_, Result<(), String>>;
+    /// Publishes to every instance, this one included.
+    fn broadcast(&self, payload: Vec<u8>) -> BoxFuture<'_, Result<(), String>>;
+    /// What is published or broadcast to `instance` from now on. The stream
+    /// ends when the bus lost the connection; subscribe again then.
+    fn subscribe(&self, instance: Uuid) -> BoxFuture<'_, Result<BoxStream<'static, Vec<u8>>, String>>;
+    /// Stores `value` under `key` in `table` for the table's time.
+    fn put<'a>(&'a self, table: Table, key: &'a str, value: Vec<u8>) -> BoxFuture<'a, Result<(), String>>;
+    fn get<'a>(&'a self, table: Table, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, String>>;
+    fn delete<'a>(&'a self, table: Table, key: &'a str) -> BoxFuture<'a, Result<(), String>>;
+    /// The records of `table` whose keys start with `prefix`.
+    fn scan<'a>(&'a self, table: Table, prefix: &'a str) -> BoxFuture<'a, Result<Vec<Record>, String>>;
+}
+
+/// A record's key and value.
+pub type Record = (String, Vec<u8>);
+
+/// `text` as key characters: room and user names may hold anything,
+/// keys only letters, digits and a little punctuation.
+pub fn hex(text: &str) -> String {
+    text.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The bus `SIGNALING_BUS` names.
+pub async fn load(layers: &Layers) -> Result<Arc<dyn SignalingBus>, String> {
+    let url = layers.get(&URL).filter(|url| !url.is_empty()).unwrap_or_else(|| "memory".to_string());
+    match url.split_once("://").map_or(url.as_str(), |(scheme, _)| scheme) {
+        "memory" => Ok(Arc::new(MemoryBus::default())),
+        "redis" | "rediss" => {
+            let bus = RedisBus::connect(&url).await?;
+            info!("Sharing rooms with other instances through Redis");
+            Ok(Arc::new(bus))
+        }
+        "nats" | "tls" => {
+            let bus = NatsBus::connect(&url).await?;
+            info!("Sharing rooms with other instances through NATS");
+            Ok(Arc::new(bus))
+        }
+        _ => Err(format!("{} must be memory, a redis:// or a nats:// URL", URL.var)),
+    }
+}
+
+/// Keeps everything in this process, for a server running on its own.
+#[derive(Debug, Default)]
+pub struct MemoryBus {
+    rooms: Mutex<HashMap<String, (Uuid, Instant)>>,
+    subscribers: Mutex<HashMap<Uuid, futures::channel::mpsc::UnboundedSender<Vec<u8>>>>,
+    records: Mutex<HashMap<(Table, String), Expiring>>,
+}
+
+/// A record's value and when it expires.
+type Expiring = (Vec<u8>, Instant);
+
+impl SignalingBus for MemoryBus {
+    fn claim<'a>(&'a self, room: &'a str, instance: Uuid) -> BoxFuture<'a, Result<Uuid, String>> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let home = match rooms.get(room) {
+            Some((home, expires)) if *home != instance && *expires > Instant::now() => *home,
+            _ => {
+                rooms.insert(room.to_string(), (instance, Instant::now() + CLAIM_TTL));
+                instance
+            }
+        };
+        future::ready(Ok(home)).boxed()
+    }
+
+    fn release<'a>(&'a self, room: &'a str, instance: Uuid) -> BoxFuture<'a, Result<(), String>> {
+        let mut rooms = self.rooms.lock().unwrap();
+        if rooms.get(room).is_some_and(|(home, _)| *home == instance) {
+            rooms.remove(room);
+        }
+        future::ready(Ok(())).boxed()
+    }
+
+    fn home<'a>(&'a self, room: &'a str) -> BoxFuture<'a, Result<Option<Uuid>, String>> {
+        let rooms = self.rooms.lock().unwrap();
+        let home = rooms.get(room).filter(|(_, expires)| *expires > Instant::now()).map(|(home, _)| *home);
+        future::ready(Ok(home)).boxed()
+    }
+
+    fn publish(&self, instance: Uuid, payload: Vec<u8>) -> BoxFuture<'_, Result<(), String>> {
+        if let Some(subscriber) = self.subscribers.lock().unwrap().get(&instance) {
+            let _ = subscriber.unbounded_send(payload);
+        }
+        future::ready(Ok(())).boxed()
+    }
+
+    fn broadcast(&self, payload: Vec<u8>) -> BoxFuture<'_, Result<(), String>> {
+        for subscriber in self.subscribers.lock().unwrap().values() {
+            let _ = subscriber.unbounded_send(payload.clone());
+        }
+        future::ready(Ok(())).boxed()
+    }
+
+    fn subscribe(&self, instance: Uuid) -> BoxFuture<'_, Result<BoxStream<'static, Vec<u8>>, String>> {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        self.subscribers.lock().unwrap().insert(instance, tx);
+        future::ready(Ok(rx.boxed())).boxed()
+    }
+
+    fn put<'a>(&'a self, table: Table, key: &'a str, value: Vec<u8>) -> BoxFuture<'a, Result<(), String>> {
+        self.records.lock().unwrap().insert((table, key.to_string()), (value, Instant::now() + table.ttl()));
+        future::ready(Ok(())).boxed()
+    }
+
+    fn get<'a>(&'a self, table: Table, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, String>> {
+        let records = self.records.lock().unwrap();
+        let value = records
+            .get(&(table, key.to_string()))
+            .filter(|(_, expires)| *expires > Instant::now())
+            .map(|(value, _)| value.clone());
+        future::ready(Ok(value)).boxed()
+    }
+
+    fn delete<'a>(&'a self, table: Table, key: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        self.records.lock().unwrap().remove(&(table, key.to_string()));
+        future::ready(Ok(())).boxed()
+    }
+
+    fn scan<'a>(&'a self, table: Table, prefix: &'a str) -> BoxFuture<'a, Result<Vec<Record>, String>> {
+        let mut records = self.records.lock().unwrap();
+        let now = Instant::now();
+        records.retain(|_, (_, expires)| *expires > now);
+        let found = records
+            .iter()
+            .filter(|((t, key), _)| *t == table && key.starts_with(prefix))
+            .map(|((_, key), (value, _))| (key.clone(), value.clone()))
+            .collect();
+        future::ready(Ok(found)).boxed()
+    }
+}
+
+/// Rooms and records as keys that expire, and pub/sub with a channel per
+/// instance plus one for all.
+pub struct RedisBus {
+    client: redis::Client,
+    connection: ConnectionManager,
+    claim: Script,
+    release: Script,
+}
+
+impl Debug for RedisBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisBus").finish_non_exhaustive()
+    }
+}
+
+impl RedisBus {
+    async fn connect(url: &str) -> Result<Self, String> {
+        let client = redis::Client::open(url).map_err(|err| format!("Invalid {}: {}", URL.var, err))?;
+        let connection = ConnectionManager::new(client.clone())
+            .await
+            .map_err(|err| format!("Cannot connect to the Redis server in {}: {}", URL.var, err))?;
+        Ok(RedisBus {
+            client,
+            connection,
+            claim: Script::new(REDIS_CLAIM_SCRIPT),
+            release: Script::new(REDIS_RELEASE_SCRIPT),
+        })
+    }
+}
+
+fn redis_key(room: &str) -> String {
+    format!("{}{}", REDIS_ROOM_PREFIX, room)
+}
+
+fn redis_record_key(table: Table, key: &str) -> String {
+    let table = match table {
+        Table::Sessions => "session",
+        Table::Directory => "directory",
+    };
+    format!("p2p-chat:{}:{}", table, key)
+}
+
+fn redis_channel(instance: &Uuid) -> String {
+    format!("{}{}", REDIS_CHANNEL_PREFIX, instance)
+}
+
+impl SignalingBus for RedisBus {
+    fn claim<'a>(&'a self, room: &'a str, instance: Uuid) -> BoxFuture<'a, Result<Uuid, String>> {
+        async move {
+            let home: String = self
+                .claim
+                .key(redis_key(room))
+                .arg(instance.to_string())
+                .arg(CLAIM_TTL.as_secs())
+                .invoke_async(&mut self.connection.clone())
+                .await
+                .map_err(|err| err.to_string())?;
+            home.parse().map_err(|_| format!("Room {} is registered to {}, which is no instance", room, home))
+        }
+        .boxed()
+    }
+
+    fn release<'a>(&'a self, room: &'a str, instance: Uuid) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            self.release
+                .key(redis_key(room))
+                .arg(instance.to_string())
+                .invoke_async(&mut self.connection.clone())
+                .await
+                .map_err(|err| err.to_string())
+        }
+        .boxed()
+    }
+
+    fn home<'a>(&'a self, room: &'a str) -> BoxFuture<'a, Result<Option<Uuid>, String>> {
+        async move {
+            let home: Option<String> = self.connection.clone().get(redis_key(room)).await.map_err(|err| err.to_string())?;
+            Ok(home.and_then(|home| home.parse().ok()))
+        }
+        .boxed()
+    }
+
+    fn publish(&self, instance: Uuid, payload: Vec<u8>) -> BoxFuture<'_, Result<(), String>> {
+        async move { self.connection.clone().publish(redis_channel(&instance), payload).await.map_err(|err| err.to_string()) }
+            .boxed()
+    }
+
+    fn broadcast(&self, payload: Vec<u8>) -> BoxFuture<'_, Result<(), String>> {
+        async move { self.connection.clone().publish(REDIS_BROADCAST_CHANNEL, payload).await.map_err(|err| err.to_string()) }
+            .boxed()
+    }
+
+    fn subscribe(&self, instance: Uuid) -> BoxFuture<'_, Result<BoxStream<'static, Vec<u8>>, String>> {
+        async move {
+            let mut pubsub = self.client.get_async_pubsub().await.map_err(|err| err.to_string())?;
+            pubsub.subscribe(&[redis_channel(&instance), REDIS_BROADCAST_CHANNEL.to_string()]).await.map_err(|err| err.to_string())?;
+            Ok(pubsub.into_on_message().filter_map(|message| future::ready(message.get_payload().ok())).boxed())
+        }
+        .boxed()
+    }
+
+    fn put<'a>(&'a self, table: Table, key: &'a str, value: Vec<u8>) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            self.connection
+                .clone()
+                .set_ex(redis_record_key(table, key), value, table.ttl().as_secs())
+                .await
+                .map_err(|err| err.to_string())
+        }
+        .boxed()
+    }
+
+    fn get<'a>(&'a self, table: Table, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, String>> {
+        async move { self.connection.clone().get(redis_record_key(table, key)).await.map_err(|err| err.to_string()) }.boxed()
+    }
+
+    fn delete<'a>(&'a self, table: Table, key: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        async move { self.connection.clone().del(redis_record_key(table, key)).await.map_err(|err| err.to_string()) }.boxed()
+    }
+
+    fn scan<'a>(&'a self, table: Table, prefix: &'a str) -> BoxFuture<'a, Result<Vec<Record>, String>> {
+        async move {
+            let mut connection = self.connection.clone();
+            // Keys are hex and punctuation, nothing a pattern would read as a wildcard
+            let pattern = format!("{}*", redis_record_key(table, prefix));
+            let keys: Vec<String> = connection.scan_match(pattern).await.map_err(|err| err.to_string())?.collect().await;
+            let start = redis_record_key(table, "").len();
+            let mut found = Vec::new();
+            for key in keys {
+                // Gone since the scan if expired meanwhile
+                let value: Option<Vec<u8>> = connection.get(&key).await.map_err(|err| err.to_string())?;
+                found.extend(value.map(|value| (key[start..].to_string(), value)));
+            }
+            Ok(found)
+        }
+        .boxed()
+    }
+}
+
+/// Rooms and records in JetStream key-value buckets whose entries expire,
+/// and a subject per instance plus one for all.
+pub struct NatsBus {
+    client: async_nats::Client,
+    rooms: kv::Store,
+    sessions: kv::Store,
+    directory: kv::Store,
+}
+
+impl Debug for NatsBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NatsBus").finish_non_exhaustive()
+    }
+}
+
+impl NatsBus {
+    async fn connect(url: &str) -> Result<Self, String> {
+        let client = async_nats::connect(url)
+            .await
+            .map_err(|err| format!("Cannot connect to the NATS server in {}: {}", URL.var, err))?;
+        let jetstream = async_nats::jetstream::new(client.clone());
+        let bucket = |name: &str, max_age| {
+            let config = kv::Config { bucket: name.to_string(), max_age, ..Default::default() };
+            let jetstream = jetstream.clone();
+            let name = name.to_string();
+            async move {
+                jetstream
+                    .create_key_value(config)
+                    .await
+                    .map_err(|err| format!("Cannot open the {} bucket, is JetStream enabled? {}", name, err))
+            }
+        };
+        Ok(NatsBus {
+            rooms: bucket(NATS_BUCKET, CLAIM_TTL).await?,
+            sessions: bucket(NATS_SESSIONS_BUCKET, SESSION_TTL).await?,
+            directory: bucket(NATS_DIRECTORY_BUCKET, CLAIM_TTL).await?,
+            client,
+        })
+    }
+
+    fn bucket(&self, table: Table) -> &kv::Store {
+        match table {
+            Table::Sessions => &self.sessions,
+            Table::Directory => &self.directory,
+        }
+    }
+}
+
+fn nats_subject(instance: &Uuid) -> String {
+    format!("{}{}", NATS_SUBJECT_PREFIX, instance)
+}
+
+impl SignalingBus for NatsBus {
+    fn claim<'a>(&'a self, room: &'a str, instance: Uuid) -> BoxFuture<'a, Result<Uuid, String>> {
+        async move {
+            let key = hex(room);
+            // Another instance may register it between reading and writing;
+            // the write then fails and the next round sees whose it is
+            for _ in 0..3 {
+                let entry = self.rooms.entry(&key).await.map_err(|err| err.to_string())?;
+                let revision = match entry {
+                    Some(entry) if entry.operation == Operation::Put => {
+                        let home = std::str::from_utf8(&entry.value).ok().and_then(|home| home.parse().ok());
+                        if home.is_some_and(|home| home != instance) {
+                            return home.ok_or_else(|| format!("Room {} is registered to no instance", room));
+                        }
+                        entry.revision
+                    }
+                    Some(deleted) => deleted.revision,
+                    None => 0,
+                };
+                // A new revision restarts the entry's time to live
+                if self.rooms.update(&key, instance.to_string().into(), revision).await.is_ok() {
+                    return Ok(instance);
+                }
+            }
+            Err(format!("Room {} keeps changing hands", room))
+        }
+        .boxed()
+    }
+
+    fn release<'a>(&'a self, room: &'a str, instance: Uuid) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            if self.home(room).await? == Some(instance) {
+                self.rooms.delete(hex(room)).await.map_err(|err| err.to_string())?;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn home<'a>(&'a self, room: &'a str) -> BoxFuture<'a, Result<Option<Uuid>, String>> {
+        async move {
+            let home = self.rooms.get(hex(room)).await.map_err(|err| err.to_string())?;
+            Ok(home.and_then(|home| std::str::from_utf8(&home).ok()?.parse().ok()))
+        }
+        .boxed()
+    }
+
+    fn publish(&self, instance: Uuid, payload: Vec<u8>) -> BoxFuture<'_, Result<(), String>> {
+        async move { self.client.publish(nats_subject(&instance), payload.into()).await.map_err(|err| err.to_string()) }
+            .boxed()
+    }
+
+    fn broadcast(&self, payload: Vec<u8>) -> BoxFuture<'_, Result<(), String>> {
+        async move { self.client.publish(NATS_BROADCAST_SUBJECT, payload.into()).await.map_err(|err| err.to_string()) }.boxed()
+    }
+
+    fn subscribe(&self, instance: Uuid) -> BoxFuture<'_, Result<BoxStream<'static, Vec<u8>>, String>> {
+        async move {
+            let own = self.client.subscribe(nats_subject(&instance)).await.map_err(|err| err.to_string())?;
+            let all = self.client.subscribe(NATS_BROADCAST_SUBJECT).await.map_err(|err| err.to_string())?;
+            Ok(futures::stream::select(own, all).map(|message| message.payload.to_vec()).boxed())
+        }
+        .boxed()
+    }
+
+    fn put<'a>(&'a self, table: Table, key: &'a str, value: Vec<u8>) -> BoxFuture<'a, Result<(), String>> {
+        async move {
+            self.bucket(table).put(key, value.into()).await.map_err(|err| err.to_string())?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn get<'a>(&'a self, table: Table, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, String>> {
+        async move {
+            let value = self.bucket(table).get(key).await.map_err(|err| err.to_string())?;
+            Ok(value.map(|value| value.to_vec()))
+        }
+        .boxed()
+    }
+
+    fn delete<'a>(&'a self, table: Table, key: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        async move { self.bucket(table).delete(key).await.map_err(|err| err.to_string()) }.boxed()
+    }
+
+    fn scan<'a>(&'a self, table: Table, prefix: &'a str) -> BoxFuture<'a, Result<Vec<Record>, String>> {
+        async move {
+            let bucket = self.bucket(table);
+            let mut keys = bucket.keys().await.map_err(|err| err.to_string())?;
+            let mut found = Vec::new();
+            while let Some(key) = keys.next().await {
+                let key = key.map_err(|err| err.to_string())?;
+                if !key.starts_with(prefix) {
+                    continue;
+                }
+                // Deleted or expired since listed: left out
+                if let Some(value) = bucket.get(&key).await.map_err(|err| err.to_string())? {
+                    found.push((key, value.to_vec()));
+                }
+            }
+            Ok(found)
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rooms_belong_to_the_first_claim() {
+        let bus = MemoryBus::default();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(bus.claim("lobby", first).await, Ok(first));
+        assert_eq!(bus.claim("lobby", second).await, Ok(first));
+        assert_eq!(bus.claim("lobby", first).await, Ok(first), "renewing");
+        assert_eq!(bus.home("lobby").await, Ok(Some(first)));
+        assert_eq!(bus.home("elsewhere").await, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn only_the_home_releases_a_room() {
+        let bus = MemoryBus::default();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        bus.claim("lobby", first).await.unwrap();
+        bus.release("lobby", second).await.unwrap();
+        assert_eq!(bus.home("lobby").await, Ok(Some(first)));
+        bus.release("lobby", first).await.unwrap();
+        assert_eq!(bus.claim("lobby", second).await, Ok(second));
+    }
+
+    #[tokio::test]
+    async fn expired_claims_are_free() {
+        let bus = MemoryBus::default();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        bus.rooms.lock().unwrap().insert("lobby".to_string(), (first, Instant::now()));
+        assert_eq!(bus.home("lobby").await, Ok(None));
+        assert_eq!(bus.claim("lobby", second).await, Ok(second));
+    }
+
+    #[tokio::test]
+    async fn instances_read_what_is_published_to_them() {
+        let bus = MemoryBus::default();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut inbox = bus.subscribe(first).await.unwrap();
+        bus.publish(second, b"lost".to_vec()).await.unwrap();
+        bus.publish(first, b"hello".to_vec()).await.unwrap();
+        assert_eq!(inbox.next().await, Some(b"hello".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn broadcasts_reach_every_instance() {
+        let bus = MemoryBus::default();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut inboxes = (bus.subscribe(first).await.unwrap(), bus.subscribe(second).await.unwrap());
+        bus.broadcast(b"hello".to_vec()).await.unwrap();
+        assert_eq!(inboxes.0.next().await, Some(b"hello".to_vec()));
+        assert_eq!(inboxes.1.next().await, Some(b"hello".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn records_are_scanned_by_prefix_until_they_expire() {
+        let bus = MemoryBus::default();
+        bus.put(Table::Sessions, "616c696365.1", b"one".to_vec()).await.unwrap();
+        bus.put(Table::Sessions, "616c696365.2", b"two".to_vec()).await.unwrap();
+        bus.put(Table::Sessions, "626f62.1", b"bob".to_vec()).await.unwrap();
+        bus.put(Table::Directory, "616c696365.3", b"room".to_vec()).await.unwrap();
+        let mut found = bus.scan(Table::Sessions, "616c696365.").await.unwrap();
+        found.sort();
+        assert_eq!(found, [("616c696365.1".to_string(), b"one".to_vec()), ("616c696365.2".to_string(), b"two".to_vec())]);
+
+        bus.delete(Table::Sessions, "616c696365.1").await.unwrap();
+        assert_eq!(bus.get(Table::Sessions, "616c696365.1").await, Ok(None));
+        bus.records.lock().unwrap().get_mut(&(Table::Sessions, "616c696365.2".to_string())).unwrap().1 = Instant::now();
+        assert_eq!(bus.get(Table::Sessions, "616c696365.2").await, Ok(None));
+        assert!(bus.scan(Table::Sessions, "616c696365.").await.unwrap().is_empty());
+        assert_eq!(bus.get(Table::Directory, "616c696365.3").await, Ok(Some(b"room".to_vec())));
+    }
+
+    #[test]
+    fn bucket_keys_stay_within_the_allowed_characters() {
+        assert_eq!(hex("Café 1/2"), "436166c3a920312f32");
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use axum::extract::ws::Message;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+/// Longest delay an admin may ask for; anything longer looks like a drop.
+const MAX_DELAY_MS: u64 = 30_000;
+
+/// How badly the server misbehaves, set through `PUT /admin/chaos`. Rates
+/// are chances between 0 and 1; all of them start at 0.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChaosSettings {
+    /// Chance that a relayed message never arrives
+    pub drop_rate: f64,
+    /// Chance that it arrives twice
+    pub duplicate_rate: f64,
+    /// Chance that it is held back, for up to `max_delay_ms`, and so may
+    /// overtake or be overtaken by others
+    pub delay_rate: f64,
+    pub max_delay_ms: u64,
+    /// Chance, per message a client sends, that its connection is cut
+    /// without a close frame
+    pub disconnect_rate: f64,
+}
+
+impl ChaosSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        let rates = [
+            ("drop_rate", self.drop_rate),
+            ("duplicate_rate", self.duplicate_rate),
+            ("delay_rate", self.delay_rate),
+            ("disconnect_rate", self.disconnect_rate),
+        ];
+        if let Some((name, _)) = rates.iter().find(|(_, rate)| !(0.0..=1.0).contains(rate)) {
+            return Err(format!("{} must be between 0 and 1", name));
+        }
+        if self.max_delay_ms > MAX_DELAY_MS {
+            return Err(format!("max_delay_ms can be at most {}", MAX_DELAY_MS));
+        }
+        Ok(())
+    }
+
+    pub fn is_calm(&self) -> bool {
+        self.drop_rate == 0.0 && self.duplicate_rate == 0.0 && self.delay_rate == 0.0 && self.disconnect_rate == 0.0
+    }
+
+    /// Hands a relayed message to its recipient, or not, or twice, or late.
+    pub fn deliver(&self, tx: &mpsc::Sender<Message>, msg: Message) {
+        let mut rng = rand::thread_rng();
+        if rng.gen_bool(self.drop_rate) {
+            return;
+        }
+        let copies = if rng.gen_bool(self.duplicate_rate) { 2 } else { 1 };
+        if self.max_delay_ms > 0 && rng.gen_bool(self.delay_rate) {
+            let delay = Duration::from_millis(rng.gen_range(0..=self.max_delay_ms));
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                for _ in 0..copies {
+                    let _ = tx.try_send(msg.clone());
+                }
+            });
+            return;
+        }
+        for _ in 0..copies {
+            let _ = tx.try_send(msg.clone());
+        }
+    }
+
+    /// Whether to cut the connection that just sent a message.
+    pub fn disconnect(&self) -> bool {
+        rand::thread_rng().gen_bool(self.disconnect_rate)
+    }
+}
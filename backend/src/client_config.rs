@@ -0,0 +1,89 @@
+use std::env;
+
+use serde::Serialize;
+
+/// Public URL of this server as browsers reach it, e.g.
+/// `https://chat.example.com`. Left out of the config when unset, and the
+/// frontend then assumes its own origin.
+const PUBLIC_URL_VAR: &str = "PUBLIC_URL";
+const BRAND_NAME_VAR: &str = "BRAND_NAME";
+const BRAND_LOGO_VAR: &str = "BRAND_LOGO_URL";
+
+const DEFAULT_BRAND_NAME: &str = "P2P Chat";
+const ICE_CONFIG_PATH: &str = "/ice-config";
+
+#[derive(Debug, Serialize)]
+struct Branding {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logo_url: Option<String>,
+}
+
+/// Server features the frontend adapts to before it has a socket open.
+#[derive(Debug, Serialize)]
+pub struct Features {
+    pub chat_relay: bool,
+    pub moderator_delegation: bool,
+    pub turn: bool,
+}
+
+/// Response of `GET /config.json`.
+#[derive(Debug, Serialize)]
+pub struct ClientConfigDocument<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    api_base: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ws_url: Option<String>,
+    ice_config_path: &'static str,
+    features: Features,
+    branding: &'a Branding,
+}
+
+/// What the frontend reads at startup, so one build of it can be pointed
+/// at any deployment.
+#[derive(Debug)]
+pub struct ClientConfig {
+    public_url: Option<String>,
+    /// `BASE_PATH`, or empty
+    base_path: String,
+    branding: Branding,
+}
+
+impl ClientConfig {
+    /// `base_path` is where the routes live, see [`crate::proxy`].
+    pub fn from_env(base_path: &str) -> Self {
+        let var = |name: &str| env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        ClientConfig {
+            public_url: var(PUBLIC_URL_VAR).map(|url| url.trim_end_matches('/').to_string()),
+            base_path: base_path.to_string(),
+            branding: Branding {
+                name: var(BRAND_NAME_VAR).unwrap_or_else(|| DEFAULT_BRAND_NAME.to_string()),
+                logo_url: var(BRAND_LOGO_VAR),
+            },
+        }
+    }
+
+    /// Without `PUBLIC_URL`, a base path still goes out as a path-only
+    /// `api_base` that the frontend resolves against its own origin.
+    pub fn document(&self, features: Features) -> ClientConfigDocument<'_> {
+        let ws_url = self.public_url.as_ref().map(|url| {
+            let url = match url.strip_prefix("https://") {
+                Some(rest) => format!("wss://{}", rest),
+                None => url.replacen("http://", "ws://", 1),
+            };
+            format!("{}{}/ws", url, self.base_path)
+        });
+        let api_base = match &self.public_url {
+            Some(url) => Some(format!("{}{}", url, self.base_path)),
+            None if !self.base_path.is_empty() => Some(self.base_path.clone()),
+            None => None,
+        };
+        ClientConfigDocument {
+            api_base,
+            ws_url,
+            ice_config_path: ICE_CONFIG_PATH,
+            features,
+            branding: &self.branding,
+        }
+    }
+}
@@ -0,0 +1,499 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::extract::ws::Message;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Mutex};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::bus::{hex, SignalingBus, Table, CLAIM_TTL};
+use crate::sessions::Session;
+use crate::{disconnect_clients, handle_signal, hold_places, kick_sessions, AppState, RoomInfo, SignalingClient};
+
+/// How often an instance renews its rooms, well within [`CLAIM_TTL`]
+const RENEW_INTERVAL: Duration = Duration::from_secs(CLAIM_TTL.as_secs() / 3);
+/// How long a room's home, once looked up, is trusted without asking again
+const HOME_CACHE_TTL: Duration = Duration::from_secs(10);
+/// Wait before subscribing again after the bus connection dropped
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+/// What instances send each other, on the channel of the one it is for or
+/// broadcast to all.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Envelope {
+    /// A signaling message for a room homed at the receiver, from a client
+    /// of instance `from`
+    Signal { from: Uuid, client_id: Uuid, username: String, room_scope: Option<String>, text: String },
+    /// Something for a client of the receiver
+    Deliver { client_id: Uuid, frame: Frame },
+    /// A client that signaled through the receiver disconnected
+    Gone { client_id: Uuid },
+    /// Broadcast: sessions were signed out; their sockets are closed with
+    /// `reason`
+    SessionsEnded { session_ids: Vec<Uuid>, reason: String },
+}
+
+/// Where a session is shared: under its user, so theirs are found together.
+fn session_key(username: &str, session_id: Uuid) -> String {
+    format!("{}.{}", hex(username), session_id)
+}
+
+/// A WebSocket frame the room's home sends a client, whatever its transport.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Frame {
+    Text(String),
+    Close { code: u16, reason: String },
+}
+
+impl Frame {
+    fn new(message: Message) -> Option<Self> {
+        match message {
+            Message::Text(text) => Some(Frame::Text(text)),
+            Message::Close(frame) => Some(match frame {
+                Some(frame) => Frame::Close { code: frame.code, reason: frame.reason.into_owned() },
+                None => Frame::Close { code: axum::extract::ws::close_code::NORMAL, reason: String::new() },
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A client of another instance, as the home of its rooms sees it.
+#[derive(Debug)]
+struct Proxy {
+    /// Goes back to the client's instance
+    tx: mpsc::Sender<Message>,
+    /// Its signaling messages, handled in order
+    inbound: mpsc::Sender<String>,
+}
+
+/// Instances of a deployment sharing rooms. Each room has a home: the
+/// instance it was created on, as registered on the [`SignalingBus`].
+/// Everything about a room happens there. Clients connected elsewhere have
+/// their instance forward their messages for it to the home, where they
+/// take part as a proxy whose events are sent back. Peers on different
+/// instances thus signal each other with the rooms' rules unchanged.
+/// Sessions and the public rooms of the directory are shared on the bus
+/// too. With the in-memory bus every room is homed here.
+#[derive(Debug)]
+pub struct Cluster {
+    /// How other instances address this one
+    pub instance: Uuid,
+    bus: Arc<dyn SignalingBus>,
+    /// Rooms registered to this instance
+    claimed: Mutex<HashSet<String>>,
+    homes: Mutex<HashMap<String, (Uuid, Instant)>>,
+    /// Clients of other instances taking part in rooms homed here
+    proxies: Mutex<HashMap<Uuid, Proxy>>,
+    /// The instances each client of ours signals through
+    forwarded: Mutex<HashMap<Uuid, HashSet<Uuid>>>,
+}
+
+impl Cluster {
+    pub fn new(bus: Arc<dyn SignalingBus>) -> Self {
+        Cluster {
+            instance: Uuid::new_v4(),
+            bus,
+            claimed: Mutex::default(),
+            homes: Mutex::default(),
+            proxies: Mutex::default(),
+            forwarded: Mutex::default(),
+        }
+    }
+
+    /// Registers a new room to this instance; `false` if another one has a
+    /// room by that name.
+    pub async fn claim(&self, room: &str) -> Result<bool, String> {
+        let home = self.bus.claim(room, self.instance).await.map_err(|err| format!("Room registry unavailable: {}", err))?;
+        if home == self.instance {
+            self.claimed.lock().await.insert(room.to_string());
+        }
+        Ok(home == self.instance)
+    }
+
+    /// The instance `room` is homed at, if it is registered at all.
+    async fn home(&self, room: &str) -> Option<Uuid> {
+        if let Some((home, at)) = self.homes.lock().await.get(room) {
+            if at.elapsed() < HOME_CACHE_TTL {
+                return Some(*home);
+            }
+        }
+        let home = match self.bus.home(room).await {
+            Ok(home) => home?,
+            Err(err) => {
+                warn!("Cannot look up the home of room {}: {}", room, err);
+                return None;
+            }
+        };
+        self.homes.lock().await.insert(room.to_string(), (home, Instant::now()));
+        Some(home)
+    }
+
+    /// Shares a session, so its tokens work at the other instances for as
+    /// long as the last one issued.
+    pub async fn share_session(&self, session_id: Uuid, session: &Session) {
+        let value = serde_json::to_vec(session).expect("sessions serialize to JSON");
+        if let Err(err) = self.bus.put(Table::Sessions, &session_key(&session.username, session_id), value).await {
+            warn!("Cannot share session {}: {}", session_id, err);
+        }
+    }
+
+    /// A session of `username` another instance shared.
+    pub async fn shared_session(&self, username: &str, session_id: Uuid) -> Option<Session> {
+        let value = match self.bus.get(Table::Sessions, &session_key(username, session_id)).await {
+            Ok(value) => value?,
+            Err(err) => {
+                warn!("Cannot look up session {}: {}", session_id, err);
+                return None;
+            }
+        };
+        let session: Session = serde_json::from_slice(&value).ok()?;
+        Some(Session { username: username.to_string(), ..session })
+    }
+
+    /// `username`'s sessions at every instance, as last shared.
+    pub async fn shared_sessions(&self, username: &str) -> Vec<(Uuid, Session)> {
+        let prefix = format!("{}.", hex(username));
+        let records = match self.bus.scan(Table::Sessions, &prefix).await {
+            Ok(records) => records,
+            Err(err) => {
+                warn!("Cannot list the sessions of {}: {}", username, err);
+                return Vec::new();
+            }
+        };
+        records
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let session: Session = serde_json::from_slice(&value).ok()?;
+                Some((key[prefix.len()..].parse().ok()?, Session { username: username.to_string(), ..session }))
+            })
+            .collect()
+    }
+
+    /// Stops sharing `username`'s `session_ids` and has every instance drop
+    /// them and close their sockets: this one too, in case it took one up
+    /// again meanwhile.
+    pub async fn end_sessions(&self, username: &str, session_ids: &[Uuid], reason: &str) {
+        if session_ids.is_empty() {
+            return;
+        }
+        for session_id in session_ids {
+            if let Err(err) = self.bus.delete(Table::Sessions, &session_key(username, *session_id)).await {
+                warn!("Cannot stop sharing session {}: {}", session_id, err);
+            }
+        }
+        let envelope = Envelope::SessionsEnded { session_ids: session_ids.to_vec(), reason: reason.to_string() };
+        let payload = serde_json::to_vec(&envelope).expect("envelopes serialize to JSON");
+        if let Err(err) = self.bus.broadcast(payload).await {
+            warn!("Cannot tell the other instances about ended sessions: {}", err);
+        }
+    }
+
+    /// The public rooms other instances list in the directory.
+    pub async fn directory(&self) -> Vec<RoomInfo> {
+        match self.bus.scan(Table::Directory, "").await {
+            Ok(records) => records.into_iter().filter_map(|(_, value)| serde_json::from_slice(&value).ok()).collect(),
+            Err(err) => {
+                warn!("Cannot read the shared room directory: {}", err);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Lists a public room of ours in the shared directory until it is
+    /// no longer renewed.
+    pub async fn list_room(&self, info: &RoomInfo) {
+        let value = serde_json::to_vec(info).expect("room infos serialize to JSON");
+        if let Err(err) = self.bus.put(Table::Directory, &hex(&info.name), value).await {
+            warn!("Cannot list room {} in the shared directory: {}", info.name, err);
+        }
+    }
+
+    async fn unlist_room(&self, room: &str) {
+        if let Err(err) = self.bus.delete(Table::Directory, &hex(room)).await {
+            warn!("Cannot take room {} out of the shared directory: {}", room, err);
+        }
+    }
+
+    /// Sends `text` from our `client` to the home of `room`, which is not
+    /// here; `false` if no other instance has it either.
+    async fn forward(&self, client: &SignalingClient, room: &str, text: &str) -> bool {
+        let Some(home) = self.home(room).await.filter(|home| *home != self.instance) else {
+            return false;
+        };
+        self.forwarded.lock().await.entry(client.client_id).or_default().insert(home);
+        let envelope = Envelope::Signal {
+            from: self.instance,
+            client_id: client.client_id,
+            username: client.username.clone(),
+            room_scope: client.room_scope.clone(),
+            text: text.to_string(),
+        };
+        self.publish(home, &envelope).await;
+        true
+    }
+
+    /// Renews the claims on `rooms`, this instance's rooms with the
+    /// directory entry of each public one, and frees the names of those
+    /// claimed before that are gone.
+    async fn renew(&self, rooms: HashMap<String, Option<RoomInfo>>) {
+        // Taken first: rooms claimed meanwhile are in the new set and the snapshot
+        let previous = std::mem::take(&mut *self.claimed.lock().await);
+        for room in previous.iter().filter(|room| !rooms.contains_key(*room)) {
+            self.unlist_room(room).await;
+            if let Err(err) = self.bus.release(room, self.instance).await {
+                warn!("Cannot free room {} in the registry: {}", room, err);
+            }
+        }
+        let mut claimed = HashSet::new();
+        for (room, info) in rooms {
+            match self.bus.claim(&room, self.instance).await {
+                Ok(home) if home == self.instance => {
+                    match info {
+                        Some(info) => self.list_room(&info).await,
+                        // Made unlisted since
+                        None if previous.contains(&room) => self.unlist_room(&room).await,
+                        None => {}
+                    }
+                    claimed.insert(room);
+                }
+                Ok(home) => warn!("Room {} is homed at instance {}; its members here cannot reach the others", room, home),
+                Err(err) => warn!("Cannot renew room {} in the registry: {}", room, err),
+            }
+        }
+        self.claimed.lock().await.extend(claimed);
+    }
+
+    async fn publish(&self, instance: Uuid, envelope: &Envelope) {
+        let payload = serde_json::to_vec(envelope).expect("envelopes serialize to JSON");
+        if let Err(err) = self.bus.publish(instance, payload).await {
+            warn!("Cannot reach instance {}: {}", instance, err);
+        }
+    }
+
+    /// Tells the homes our client signaled through that it disconnected, so
+    /// they hold its places as for their own clients.
+    pub async fn client_gone(&self, client_id: Uuid) {
+        let Some(instances) = self.forwarded.lock().await.remove(&client_id) else { return };
+        for instance in instances {
+            self.publish(instance, &Envelope::Gone { client_id }).await;
+        }
+    }
+
+    /// Stops serving the proxies among `client_ids`, handing back where to
+    /// send them their close frames.
+    pub async fn take_proxies(&self, client_ids: &[Uuid]) -> Vec<(Uuid, mpsc::Sender<Message>)> {
+        let mut proxies = self.proxies.lock().await;
+        client_ids.iter().filter_map(|id| proxies.remove(id).map(|proxy| (*id, proxy.tx))).collect()
+    }
+}
+
+/// Sends `text` from our `client` to the home of `room` if that is another
+/// instance; `false` if the room is handled here.
+pub async fn forward(state: &AppState, client: &SignalingClient, room: &str, text: &str) -> bool {
+    if state.rooms.lock().await.contains_key(room) {
+        return false;
+    }
+    state.cluster.forward(client, room, text).await
+}
+
+/// Serves this instance's part of the cluster: what other instances send
+/// it, and keeping its rooms registered.
+pub async fn run(state: AppState) {
+    tokio::spawn(renew_claims(state.clone()));
+    let cluster = &state.cluster;
+    loop {
+        let mut inbox = match cluster.bus.subscribe(cluster.instance).await {
+            Ok(inbox) => inbox,
+            Err(err) => {
+                warn!("Cannot subscribe to the signaling bus: {}", err);
+                tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+                continue;
+            }
+        };
+        while let Some(payload) = inbox.next().await {
+            match serde_json::from_slice::<Envelope>(&payload) {
+                Ok(envelope) => receive(&state, envelope).await,
+                Err(_) => warn!("Ignoring a malformed message from another instance"),
+            }
+        }
+        warn!("Lost the signaling bus subscription, subscribing again");
+        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+    }
+}
+
+async fn receive(state: &AppState, envelope: Envelope) {
+    match envelope {
+        Envelope::Signal { from, client_id, username, room_scope, text } => {
+            let mut proxies = state.cluster.proxies.lock().await;
+            let proxy = proxies.entry(client_id).or_insert_with(|| {
+                let client = SignalingClient { client_id, username, room_scope, tx: send_back(state, from, client_id) };
+                let (inbound, queue) = mpsc::channel(state.config.channel_size);
+                let proxy = Proxy { tx: client.tx.clone(), inbound };
+                tokio::spawn(serve_proxy(state.clone(), client, queue));
+                proxy
+            });
+            if proxy.inbound.try_send(text).is_err() {
+                warn!("Dropping a message from client {} of instance {}: too many queued", client_id, from);
+            }
+        }
+        Envelope::Deliver { client_id, frame } => {
+            let tx = state.clients.lock().await.get(&client_id).map(|(_, tx)| tx.clone());
+            match (frame, tx) {
+                (Frame::Text(text), Some(tx)) => {
+                    let _ = tx.try_send(Message::Text(text));
+                }
+                (Frame::Close { code, reason }, Some(_)) => disconnect_clients(state, &[client_id], code, &reason).await,
+                // The client is gone; its Gone is on the way
+                (_, None) => {}
+            }
+        }
+        Envelope::Gone { client_id } => {
+            // Dropping the proxy ends its queue, and serve_proxy holds its places
+            state.cluster.proxies.lock().await.remove(&client_id);
+        }
+        Envelope::SessionsEnded { session_ids, reason } => {
+            let mut sessions = state.sessions.lock().await;
+            for session_id in &session_ids {
+                sessions.remove(session_id);
+            }
+            drop(sessions);
+            kick_sessions(state, &session_ids, &reason).await;
+        }
+    }
+}
+
+/// A channel whose frames go to `client_id` at `instance`, in order.
+fn send_back(state: &AppState, instance: Uuid, client_id: Uuid) -> mpsc::Sender<Message> {
+    let (tx, mut rx) = mpsc::channel(state.config.channel_size);
+    let state = state.clone();
+    tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            if let Some(frame) = Frame::new(message) {
+                state.cluster.publish(instance, &Envelope::Deliver { client_id, frame }).await;
+            }
+        }
+    });
+    tx
+}
+
+/// Handles a proxy's messages as a connection of ours would, and holds its
+/// places once its client disconnected.
+async fn serve_proxy(state: AppState, client: SignalingClient, mut queue: mpsc::Receiver<String>) {
+    while let Some(text) = queue.recv().await {
+        let _ = handle_signal(&state, &client, &text, None).await;
+    }
+    hold_places(&state, client.client_id).await;
+}
+
+/// Keeps this instance's rooms registered to it and its public rooms in the
+/// shared directory, and frees the names of rooms it no longer has.
+async fn renew_claims(state: AppState) {
+    let mut interval = tokio::time::interval(RENEW_INTERVAL);
+    loop {
+        interval.tick().await;
+        let rooms = state
+            .rooms
+            .lock()
+            .await
+            .iter()
+            .map(|(name, room)| (name.clone(), room.public.then(|| RoomInfo::new(name, room))))
+            .collect();
+        state.cluster.renew(rooms).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::ws::{close_code, CloseFrame};
+
+    use super::*;
+    use crate::bus::MemoryBus;
+    use crate::rooms::Room;
+
+    /// Two instances sharing an in-memory bus.
+    fn pair() -> (Cluster, Cluster, Arc<MemoryBus>) {
+        let bus = Arc::new(MemoryBus::default());
+        (Cluster::new(bus.clone()), Cluster::new(bus.clone()), bus)
+    }
+
+    fn client() -> SignalingClient {
+        let (tx, _) = mpsc::channel(1);
+        SignalingClient { client_id: Uuid::new_v4(), username: "alice".to_string(), room_scope: None, tx }
+    }
+
+    fn listing(name: &str) -> Option<RoomInfo> {
+        Some(RoomInfo::new(name, &Room::new("alice".to_string(), 4)))
+    }
+
+    #[tokio::test]
+    async fn a_room_name_is_claimed_once_across_instances() {
+        let (first, second, _) = pair();
+        assert_eq!(first.claim("lobby").await, Ok(true));
+        assert_eq!(second.claim("lobby").await, Ok(false));
+        assert_eq!(first.claim("lobby").await, Ok(true), "renewing");
+        assert!(first.claimed.lock().await.contains("lobby"));
+        assert!(second.claimed.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn signaling_for_a_room_homed_elsewhere_goes_to_its_home() {
+        let (first, second, bus) = pair();
+        first.claim("lobby").await.unwrap();
+        let mut inbox = bus.subscribe(first.instance).await.unwrap();
+        let client = client();
+
+        assert!(second.forward(&client, "lobby", "{}").await);
+        let Ok(Envelope::Signal { from, client_id, username, text, .. }) = serde_json::from_slice(&inbox.next().await.unwrap()) else {
+            panic!("not a signal");
+        };
+        assert_eq!((from, client_id, username.as_str(), text.as_str()), (second.instance, client.client_id, "alice", "{}"));
+        assert!(second.forwarded.lock().await[&client.client_id].contains(&first.instance));
+
+        assert!(!second.forward(&client, "nowhere", "{}").await, "registered nowhere");
+        assert!(!first.forward(&client, "lobby", "{}").await, "homed here");
+    }
+
+    #[tokio::test]
+    async fn renewing_lists_public_rooms_and_frees_the_gone() {
+        let (first, second, bus) = pair();
+        first.claim("lobby").await.unwrap();
+        first.claim("closed").await.unwrap();
+        first.renew(HashMap::from([("lobby".to_string(), listing("lobby")), ("secret".to_string(), None)])).await;
+        assert_eq!(bus.home("closed").await, Ok(None));
+        assert_eq!(bus.home("secret").await, Ok(Some(first.instance)));
+        let names: Vec<String> = second.directory().await.into_iter().map(|info| info.name).collect();
+        assert_eq!(names, ["lobby"]);
+
+        // Made unlisted, then deleted
+        first.renew(HashMap::from([("lobby".to_string(), None)])).await;
+        assert!(second.directory().await.is_empty());
+        first.renew(HashMap::new()).await;
+        assert_eq!(second.claim("lobby").await, Ok(true));
+    }
+
+    #[test]
+    fn frames_carry_text_and_close_reasons() {
+        let close = Message::Close(Some(CloseFrame { code: 4003, reason: "Banned from room lobby".into() }));
+        assert!(matches!(Frame::new(close), Some(Frame::Close { code: 4003, reason }) if reason == "Banned from room lobby"));
+        assert!(matches!(Frame::new(Message::Close(None)), Some(Frame::Close { code: close_code::NORMAL, .. })));
+        assert!(matches!(Frame::new(Message::Text("{}".into())), Some(Frame::Text(text)) if text == "{}"));
+        assert!(Frame::new(Message::Ping(Vec::new())).is_none());
+    }
+
+    #[test]
+    fn envelopes_survive_the_wire() {
+        let client_id = Uuid::new_v4();
+        let envelope = Envelope::Deliver { client_id, frame: Frame::Close { code: 4003, reason: "Banned".into() } };
+        let json = serde_json::to_string(&envelope).unwrap();
+        let Envelope::Deliver { client_id: read, frame: Frame::Close { code, reason } } = serde_json::from_str(&json).unwrap() else {
+            panic!("not a close delivery: {}", json);
+        };
+        assert_eq!((read, code, reason.as_str()), (client_id, 4003, "Banned"));
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+
+use crate::profile::{self, Bind, Profile};
+use crate::tls::{self, TlsFiles};
+use crate::{bus, jwt_keys, mail, proxy};
+
+/// A setting by its name in each layer: the config file key, the
+/// environment variable and, unless it is a secret that `ps` would show,
+/// the command-line flag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Setting {
+    pub key: &'static str,
+    pub var: &'static str,
+    pub flag: Option<&'static str>,
+}
+
+/// Path of the TOML config file; there is none unless one is named.
+const CONFIG_FLAG: &str = "--config";
+const CONFIG_VAR: &str = "P2P_CHAT_CONFIG";
+/// Subcommand bringing the database schema up to date and exiting.
+const MIGRATE_COMMAND: &str = "migrate";
+/// With `migrate`: only list the migrations that would be applied.
+const DRY_RUN_FLAG: &str = "--dry-run";
+
+/// Largest request body, in bytes, outside the routes with their own limit.
+pub const BODY_LIMIT: Setting = Setting { key: "body_limit", var: "BODY_LIMIT", flag: Some("--body-limit") };
+/// Signaling messages queued for one connection before it counts as stuck.
+pub const CHANNEL_SIZE: Setting = Setting { key: "channel_size", var: "CHANNEL_SIZE", flag: Some("--channel-size") };
+/// SQLite database whose schema `migrate` manages, e.g.
+/// `sqlite:///var/lib/p2p-chat/chat.db`.
+pub const DATABASE_URL: Setting = Setting { key: "database_url", var: "DATABASE_URL", flag: Some("--database-url") };
+/// Tracing filter; the profile picks one otherwise.
+pub const LOG: Setting = Setting { key: "log", var: "RUST_LOG", flag: Some("--log") };
+/// How log lines are written: `pretty` for people, `json` for collectors.
+pub const LOG_FORMAT: Setting = Setting { key: "log_format", var: "LOG_FORMAT", flag: Some("--log-format") };
+
+const DEFAULT_BODY_LIMIT: usize = 10 * 1024;
+const BODY_LIMITS: std::ops::RangeInclusive<usize> = 1024..=1024 * 1024;
+const DEFAULT_CHANNEL_SIZE: usize = 32;
+const CHANNEL_SIZES: std::ops::RangeInclusive<usize> = 1..=4096;
+
+/// Everything the file and the command line may set. The rest of the
+/// server's settings are read from the environment only.
+const SETTINGS: [Setting; 18] = [
+    profile::PROFILE,
+    profile::BIND_ADDR,
+    profile::FRONTEND_URL,
+    LOG,
+    LOG_FORMAT,
+    BODY_LIMIT,
+    CHANNEL_SIZE,
+    DATABASE_URL,
+    proxy::TRUSTED_PROXIES,
+    proxy::BASE_PATH,
+    tls::CERT,
+    tls::KEY,
+    jwt_keys::KEYS,
+    jwt_keys::SECRET,
+    jwt_keys::ACTIVE_KID,
+    mail::SMTP_URL,
+    mail::FROM,
+    bus::URL,
+];
+
+/// What the process was started to do.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Command {
+    #[default]
+    Serve,
+    /// `migrate [--dry-run]`
+    Migrate { dry_run: bool },
+}
+
+/// Where settings come from. A flag beats the environment, which beats the
+/// config file, which beats the profile's defaults.
+#[derive(Debug, Default)]
+pub struct Layers {
+    pub command: Command,
+    flags: HashMap<&'static str, String>,
+    file: HashMap<&'static str, String>,
+}
+
+impl Layers {
+    /// Reads the command line and, if `--config` or `P2P_CHAT_CONFIG` names
+    /// one, the config file. Flags take `--flag value` or `--flag=value`,
+    /// after the subcommand if there is one.
+    pub fn load() -> Result<Self, String> {
+        let mut flags = HashMap::new();
+        let mut path = None;
+        let mut args = env::args().skip(1).peekable();
+        let mut command = match args.next_if(|arg| arg == MIGRATE_COMMAND) {
+            Some(_) => Command::Migrate { dry_run: false },
+            None => Command::Serve,
+        };
+        while let Some(arg) = args.next() {
+            if arg == DRY_RUN_FLAG {
+                let Command::Migrate { dry_run } = &mut command else {
+                    return Err(format!("{} only goes with {}", DRY_RUN_FLAG, MIGRATE_COMMAND));
+                };
+                *dry_run = true;
+                continue;
+            }
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let setting = SETTINGS.iter().find(|setting| setting.flag == Some(flag.as_str()));
+            if setting.is_none() && flag != CONFIG_FLAG {
+                return Err(format!("Unknown option {:?}", flag));
+            }
+            let value = match inline {
+                Some(value) => value,
+                None => args.next().ok_or_else(|| format!("{} needs a value", flag))?,
+            };
+            match setting {
+                Some(setting) => flags.insert(setting.key, value),
+                None => path.replace(value),
+            };
+        }
+
+        let path = path.or_else(|| env::var(CONFIG_VAR).ok().filter(|p| !p.is_empty()));
+        let file = match path {
+            Some(path) => {
+                let text = fs::read_to_string(&path).map_err(|err| format!("Cannot read config file {}: {}", path, err))?;
+                parse_file(&text).map_err(|err| format!("Config file {}: {}", path, err))?
+            }
+            None => HashMap::new(),
+        };
+        Ok(Layers { command, flags, file })
+    }
+
+    /// The value of `setting` from the strongest layer that has one.
+    pub fn get(&self, setting: &Setting) -> Option<String> {
+        self.flags
+            .get(setting.key)
+            .cloned()
+            .or_else(|| env::var(setting.var).ok())
+            .or_else(|| self.file.get(setting.key).cloned())
+    }
+}
+
+/// Reads a flat TOML table keyed like [`SETTINGS`]. Values are kept in the
+/// form their environment variable takes, so lists become comma-separated.
+fn parse_file(text: &str) -> Result<HashMap<&'static str, String>, String> {
+    let table: toml::Table = text.parse().map_err(|err: toml::de::Error| err.message().to_string())?;
+    let mut values = HashMap::new();
+    for (key, value) in table {
+        let setting = SETTINGS
+            .iter()
+            .find(|setting| setting.key == key)
+            .ok_or_else(|| format!("unknown setting {:?}", key))?;
+        let value = match value {
+            toml::Value::String(s) => s,
+            toml::Value::Integer(n) => n.to_string(),
+            toml::Value::Boolean(b) => b.to_string(),
+            toml::Value::Array(items) => items
+                .into_iter()
+                .map(|item| match item {
+                    toml::Value::String(s) => Ok(s),
+                    _ => Err(format!("{} must list strings", key)),
+                })
+                .collect::<Result<Vec<_>, _>>()?
+                .join(","),
+            _ => return Err(format!("{} must be a string, a number or a list of strings", key)),
+        };
+        values.insert(setting.key, value);
+    }
+    Ok(values)
+}
+
+/// Reads `setting` as a number within `range`, or `default` when unset.
+fn number(layers: &Layers, setting: &Setting, default: usize, range: std::ops::RangeInclusive<usize>) -> Result<usize, String> {
+    let Some(value) = layers.get(setting) else { return Ok(default) };
+    value
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .filter(|n| range.contains(n))
+        .ok_or_else(|| format!("{} must be a number from {} to {}", setting.var, range.start(), range.end()))
+}
+
+/// How log lines are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One readable line per event, with its spans' fields in front
+    Pretty,
+    /// One JSON object per line, with the fields of the event and of every
+    /// span it happened in
+    Json,
+}
+
+impl LogFormat {
+    fn load(layers: &Layers) -> Result<Self, String> {
+        match layers.get(&LOG_FORMAT).as_deref().map(str::trim) {
+            None | Some("" | "pretty") => Ok(LogFormat::Pretty),
+            Some("json") => Ok(LogFormat::Json),
+            Some(other) => Err(format!("{} must be pretty or json, not {:?}", LOG_FORMAT.var, other)),
+        }
+    }
+}
+
+/// The server's startup settings, checked once so nothing later has to.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub profile: Profile,
+    pub log_filter: String,
+    pub log_format: LogFormat,
+    pub binds: Vec<Bind>,
+    /// Whether `binds` was configured rather than the profile's default
+    pub binds_configured: bool,
+    pub frontend_url: String,
+    pub base_path: String,
+    /// Certificate and key for serving `https` and `wss` ourselves
+    pub tls: Option<TlsFiles>,
+    pub body_limit: usize,
+    pub channel_size: usize,
+    pub database_url: Option<String>,
+}
+
+impl Config {
+    pub fn load(layers: &Layers) -> Result<Self, String> {
+        let profile = Profile::load(layers)?;
+        let log_filter = layers.get(&LOG).unwrap_or_else(|| profile.log_filter().to_string());
+        tracing_subscriber::EnvFilter::try_new(&log_filter).map_err(|err| format!("{} is not a valid filter: {}", LOG.var, err))?;
+        let (binds, binds_configured) = profile.binds(layers)?;
+        Ok(Config {
+            profile,
+            log_filter,
+            log_format: LogFormat::load(layers)?,
+            binds,
+            binds_configured,
+            frontend_url: profile.frontend_url(layers)?,
+            base_path: proxy::base_path(layers)?,
+            tls: TlsFiles::load(layers)?,
+            body_limit: number(layers, &BODY_LIMIT, DEFAULT_BODY_LIMIT, BODY_LIMITS)?,
+            channel_size: number(layers, &CHANNEL_SIZE, DEFAULT_CHANNEL_SIZE, CHANNEL_SIZES)?,
+            database_url: layers.get(&DATABASE_URL).filter(|url| !url.trim().is_empty()),
+        })
+    }
+}
@@ -0,0 +1,88 @@
+//! Golden-file checks of the signaling protocol. The fixtures in
+//! `protocol/fixtures` are shared with the frontend's tests, so both sides
+//! read and write the same frames. Run with `UPDATE_GOLDEN=1` to rewrite the
+//! binary fixtures after an intended protocol change.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::{env, fs};
+
+use axum::extract::ws::Message;
+use serde_json::Value;
+
+use crate::{encoding, SignalingMessage};
+
+/// Protocol version the fixtures describe, see `PROTOCOL_VERSION`.
+const FIXTURE_VERSION: &str = "v1";
+/// Every request a client can send; each needs at least one fixture.
+const REQUEST_TYPES: &[&str] = &[
+    "Hello", "TimeSync", "JoinRoom", "LeaveRoom", "Offer", "Answer", "IceCandidate", "ChatRelay", "AnswerKnock", "LockRoom", "SetRoomTopic", "Kick", "Ban", "MailAck",
+];
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../protocol/fixtures").join(FIXTURE_VERSION).join(name)
+}
+
+fn frames(name: &str) -> Vec<Value> {
+    let path = fixture_path(&format!("{}.json", name));
+    let text = fs::read_to_string(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    serde_json::from_str(&text).unwrap_or_else(|err| panic!("{}: {}", path.display(), err))
+}
+
+/// Leaves out fields that are `null`, which clients omit instead.
+fn without_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(fields.into_iter().filter(|(_, v)| !v.is_null()).collect()),
+        other => other,
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+}
+
+#[test]
+fn every_request_type_has_a_fixture() {
+    let covered: BTreeSet<String> = frames("requests").iter().map(|f| f["type"].as_str().unwrap().to_string()).collect();
+    let expected: BTreeSet<String> = REQUEST_TYPES.iter().map(|t| t.to_string()).collect();
+    assert_eq!(covered, expected);
+}
+
+#[test]
+fn requests_round_trip() {
+    for frame in frames("requests") {
+        let msg: SignalingMessage =
+            serde_json::from_value(frame.clone()).unwrap_or_else(|err| panic!("{} does not parse: {}", frame, err));
+        assert_eq!(without_nulls(serde_json::to_value(&msg).unwrap()), frame);
+    }
+}
+
+#[test]
+fn binary_frames_match_golden_files() {
+    for name in ["requests", "events"] {
+        let frames = frames(name);
+        let encoded: Vec<String> = frames
+            .iter()
+            .map(|frame| match encoding::to_binary(Message::Text(frame.to_string())) {
+                Message::Binary(bytes) => to_hex(&bytes),
+                other => panic!("{} was not encoded: {:?}", frame, other),
+            })
+            .collect();
+        let path = fixture_path(&format!("{}.msgpack.hex", name));
+        if env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(&path, encoded.join("\n") + "\n").unwrap();
+            continue;
+        }
+        let golden = fs::read_to_string(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+        let golden: Vec<&str> = golden.lines().collect();
+        assert_eq!(encoded, golden, "{} is out of date, rerun with UPDATE_GOLDEN=1", path.display());
+        for (frame, hex) in frames.iter().zip(golden) {
+            let decoded = encoding::from_binary(&from_hex(hex)).expect("golden frame decodes");
+            assert_eq!(serde_json::from_str::<Value>(&decoded).unwrap(), *frame);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::str::FromStr;
+
+use sqlx::migrate::{Migration, Migrator};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+
+/// The schema, from `backend/migrations`, built into the binary so a
+/// deployment cannot run against migrations of another version.
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Where sqlx records what it applied.
+const MIGRATIONS_TABLE: &str = "_sqlx_migrations";
+
+fn options(url: &str) -> Result<SqliteConnectOptions, String> {
+    SqliteConnectOptions::from_str(url).map_err(|err| format!("Invalid database URL: {}", err))
+}
+
+/// Opens the SQLite database at `url`, e.g. `sqlite:///var/lib/p2p-chat/chat.db`,
+/// creating the file if there is none.
+pub async fn connect(url: &str) -> Result<SqlitePool, String> {
+    let options = options(url)?.create_if_missing(true).foreign_keys(true);
+    SqlitePool::connect_with(options).await.map_err(|err| format!("Cannot open the database: {}", err))
+}
+
+/// Migrations not applied to the database yet, oldest first. Reads only.
+pub async fn pending(pool: &SqlitePool) -> Result<Vec<&'static Migration>, String> {
+    let tracked: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?)")
+        .bind(MIGRATIONS_TABLE)
+        .fetch_one(pool)
+        .await
+        .map_err(|err| format!("Cannot read the schema: {}", err))?;
+    let applied: Vec<i64> = if tracked {
+        sqlx::query_scalar(&format!("SELECT version FROM {} WHERE success", MIGRATIONS_TABLE))
+            .fetch_all(pool)
+            .await
+            .map_err(|err| format!("Cannot read applied migrations: {}", err))?
+    } else {
+        Vec::new()
+    };
+    Ok(MIGRATOR.iter().filter(|m| !applied.contains(&m.version)).collect())
+}
+
+/// Applies every pending migration, each in its own transaction. Fails if
+/// one that was applied has since been edited.
+pub async fn migrate(pool: &SqlitePool) -> Result<(), String> {
+    MIGRATOR.run(pool).await.map_err(|err| format!("Migration failed: {}", err))
+}
+
+/// The `migrate` command: brings the schema up to date and says what it
+/// applied, or with `dry_run` only what it would apply. A dry run leaves
+/// the database untouched, and does not create it.
+pub async fn migrate_command(url: &str, dry_run: bool) -> Result<(), String> {
+    let pending = if dry_run && !options(url)?.get_filename().exists() {
+        MIGRATOR.iter().collect()
+    } else {
+        pending(&connect(url).await?).await?
+    };
+    if pending.is_empty() {
+        println!("The schema is up to date");
+        return Ok(());
+    }
+    for migration in &pending {
+        let verb = if dry_run { "Would apply" } else { "Applying" };
+        println!("{} {} {}", verb, migration.version, migration.description);
+    }
+    if !dry_run {
+        migrate(&connect(url).await?).await?;
+        println!("Applied {} migrations", pending.len());
+    }
+    Ok(())
+}
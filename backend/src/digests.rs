@@ -0,0 +1,205 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration as StdDuration;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::AppState;
+
+/// Longest message kept for digests, in characters.
+pub const MAX_MESSAGE_CHARS: usize = 2000;
+/// Most messages kept per room; a busy room's weekly digest starts with
+/// the oldest of these.
+const MAX_HISTORY: usize = 500;
+/// Messages older than the longest digest period are of no more use.
+const HISTORY_TTL: Duration = Duration::weeks(1);
+/// How often due digests are looked for and sent.
+const SEND_INTERVAL: StdDuration = StdDuration::from_secs(15 * 60);
+
+/// How often a subscriber hears from a room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    Daily,
+    Weekly,
+}
+
+impl Frequency {
+    fn period(self) -> Duration {
+        match self {
+            Frequency::Daily => Duration::days(1),
+            Frequency::Weekly => Duration::weeks(1),
+        }
+    }
+
+    fn adjective(self) -> &'static str {
+        match self {
+            Frequency::Daily => "daily",
+            Frequency::Weekly => "weekly",
+        }
+    }
+}
+
+/// A message sent in a room with digests, as its sender posted it.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub at: DateTime<Utc>,
+    pub author: String,
+    pub text: String,
+}
+
+#[derive(Debug, Clone)]
+struct Subscription {
+    username: String,
+    room: String,
+    frequency: Frequency,
+    /// Messages after this go in the next digest
+    since: DateTime<Utc>,
+}
+
+/// A digest ready to be mailed.
+pub struct Digest {
+    pub username: String,
+    pub room: String,
+    pub frequency: Frequency,
+    /// Token for the unsubscribe link
+    pub token: Uuid,
+    pub entries: Vec<Entry>,
+}
+
+/// Recent messages of rooms with digests, and who gets them by email.
+/// Subscriptions are keyed by the random token of their unsubscribe link,
+/// so following it needs no login. In memory only, like sessions.
+#[derive(Debug, Default)]
+pub struct Digests {
+    history: HashMap<String, VecDeque<Entry>>,
+    subscriptions: HashMap<Uuid, Subscription>,
+}
+
+impl Digests {
+    pub fn record(&mut self, room: &str, entry: Entry) {
+        let history = self.history.entry(room.to_string()).or_default();
+        history.push_back(entry);
+        if history.len() > MAX_HISTORY {
+            history.pop_front();
+        }
+    }
+
+    /// Subscribes `username` to `room`'s digests, or changes how often they
+    /// get them. Messages already sent are not repeated.
+    pub fn subscribe(&mut self, username: &str, room: &str, frequency: Frequency) {
+        if let Some(subscription) = self.find_mut(username, room) {
+            subscription.frequency = frequency;
+            return;
+        }
+        let subscription =
+            Subscription { username: username.to_string(), room: room.to_string(), frequency, since: Utc::now() };
+        self.subscriptions.insert(Uuid::new_v4(), subscription);
+    }
+
+    fn find_mut(&mut self, username: &str, room: &str) -> Option<&mut Subscription> {
+        self.subscriptions.values_mut().find(|s| s.username == username && s.room == room)
+    }
+
+    pub fn frequency(&self, username: &str, room: &str) -> Option<Frequency> {
+        self.subscriptions.values().find(|s| s.username == username && s.room == room).map(|s| s.frequency)
+    }
+
+    /// Returns whether there was a subscription.
+    pub fn unsubscribe(&mut self, username: &str, room: &str) -> bool {
+        let before = self.subscriptions.len();
+        self.subscriptions.retain(|_, s| s.username != username || s.room != room);
+        self.subscriptions.len() < before
+    }
+
+    /// Ends the subscription an unsubscribe link is for, naming its room.
+    pub fn unsubscribe_token(&mut self, token: &Uuid) -> Option<String> {
+        self.subscriptions.remove(token).map(|s| s.room)
+    }
+
+    /// Drops a room's messages and subscriptions, when it is removed.
+    pub fn forget_room(&mut self, room: &str) {
+        self.history.remove(room);
+        self.subscriptions.retain(|_, s| s.room != room);
+    }
+
+    /// Drops a user's subscriptions, when their account goes.
+    pub fn forget_user(&mut self, username: &str) {
+        self.subscriptions.retain(|_, s| s.username != username);
+    }
+
+    /// Takes the digests due at `now`. Subscriptions with nothing new wait
+    /// for the next period rather than sending an empty mail.
+    fn due(&mut self, now: DateTime<Utc>) -> Vec<Digest> {
+        for history in self.history.values_mut() {
+            while history.front().is_some_and(|e| now - e.at > HISTORY_TTL) {
+                history.pop_front();
+            }
+        }
+        self.history.retain(|_, history| !history.is_empty());
+        let mut digests = Vec::new();
+        for (token, subscription) in &mut self.subscriptions {
+            if now - subscription.since < subscription.frequency.period() {
+                continue;
+            }
+            let since = std::mem::replace(&mut subscription.since, now);
+            let entries: Vec<Entry> = self
+                .history
+                .get(&subscription.room)
+                .map(|history| history.iter().filter(|e| e.at > since).cloned().collect())
+                .unwrap_or_default();
+            if !entries.is_empty() {
+                digests.push(Digest {
+                    username: subscription.username.clone(),
+                    room: subscription.room.clone(),
+                    frequency: subscription.frequency,
+                    token: *token,
+                    entries,
+                });
+            }
+        }
+        digests
+    }
+}
+
+impl Digest {
+    /// Subject and plain-text body of the mail.
+    pub fn render(&self, frontend_url: &str) -> (String, String) {
+        let subject = format!("Your {} digest of {}", self.frequency.adjective(), self.room);
+        let mut body = format!("Hi {},\n\nHere is what was said in {}:\n\n", self.username, self.room);
+        for entry in &self.entries {
+            body.push_str(&format!("[{}] {}: {}\n", entry.at.format("%Y-%m-%d %H:%M UTC"), entry.author, entry.text));
+        }
+        body.push_str(&format!(
+            "\nTo stop getting these emails, open:\n{}/digests/unsubscribe?token={}\n",
+            frontend_url, self.token
+        ));
+        (subject, body)
+    }
+}
+
+/// Mails the digests that are due, every [`SEND_INTERVAL`]. Subscribers who
+/// removed the email address from their account are skipped.
+pub async fn send_due(state: AppState) {
+    let mut interval = tokio::time::interval(SEND_INTERVAL);
+    loop {
+        interval.tick().await;
+        let due = state.digests.lock().await.due(Utc::now());
+        if due.is_empty() {
+            continue;
+        }
+        let users = state.users.lock().await;
+        let addressed: Vec<(String, Digest)> = due
+            .into_iter()
+            .filter_map(|digest| Some((users.get(&digest.username)?.email.clone()?, digest)))
+            .collect();
+        drop(users);
+        for (email, digest) in addressed {
+            let (subject, body) = digest.render(&state.config.frontend_url);
+            state.mailer.send(&email, &subject, &body);
+            info!("Sent the {} digest of room {} to user: {}", digest.frequency.adjective(), digest.room, digest.username);
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use sha2::{Digest, Sha256};
+
+/// Names of direct-message rooms start with this; other rooms cannot.
+pub const ROOM_PREFIX: &str = "dm-";
+/// Most users `GET /users` returns at once.
+pub const SEARCH_LIMIT: usize = 20;
+/// Shorter searches would list most of the user base.
+pub const MIN_QUERY_LENGTH: usize = 2;
+
+/// The direct-message room of two users, the same whoever asks. Usernames
+/// may hold any character, so the name is a hash of the pair rather than
+/// the pair itself.
+pub fn room_name(a: &str, b: &str) -> String {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    let digest = Sha256::new().chain_update(first).chain_update([0]).chain_update(second).finalize();
+    let hex: String = digest.iter().take(16).map(|b| format!("{:02x}", b)).collect();
+    format!("{}{}", ROOM_PREFIX, hex)
+}
+
+/// Whether a user matches a directory search: `query`, already lowercase,
+/// appears in their username or display name.
+pub fn matches(query: &str, username: &str, display_name: Option<&str>) -> bool {
+    username.to_lowercase().contains(query) || display_name.is_some_and(|name| name.to_lowercase().contains(query))
+}
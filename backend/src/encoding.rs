@@ -0,0 +1,28 @@
+use axum::extract::ws::Message;
+
+/// Binary encoding a client may ask for in its `Hello`. Clients that ask
+/// for none keep talking JSON.
+pub const MSGPACK: &str = "msgpack";
+
+/// The encoding to switch to after the `welcome`, if the client offers one
+/// we speak.
+pub fn negotiate(offered: &[String]) -> Option<&'static str> {
+    offered.iter().any(|e| e == MSGPACK).then_some(MSGPACK)
+}
+
+/// Re-encodes an outgoing JSON text frame as MessagePack, keeping field
+/// names so both encodings carry the same messages. Other frames pass
+/// through.
+pub fn to_binary(msg: Message) -> Message {
+    let Message::Text(text) = &msg else { return msg };
+    match serde_json::from_str::<serde_json::Value>(text).ok().and_then(|v| rmp_serde::to_vec_named(&v).ok()) {
+        Some(bytes) => Message::Binary(bytes),
+        None => msg,
+    }
+}
+
+/// Decodes an incoming MessagePack frame into the JSON the signaling
+/// handler works with.
+pub fn from_binary(data: &[u8]) -> Option<String> {
+    rmp_serde::from_slice::<serde_json::Value>(data).ok().map(|v| v.to_string())
+}
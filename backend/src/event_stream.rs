@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+
+use axum::extract::ws::{CloseFrame, Message, close_code};
+use axum::response::sse::Event;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::ws_limits::MessageBudget;
+
+/// A signaling client on `GET /events`, for networks whose proxies break
+/// WebSockets: it hears from us over server-sent events and talks back with
+/// `POST /signal`.
+#[derive(Debug)]
+pub struct EventStream {
+    pub username: String,
+    /// Session, or API key, the stream was opened with; posts must come
+    /// from the same one
+    pub session_id: Uuid,
+    /// Guests may only signal in the room their token is for
+    pub room_scope: Option<String>,
+    pub tx: mpsc::Sender<Message>,
+    pub budget: MessageBudget,
+}
+
+/// Open event streams by client id, which the stream's first event tells
+/// the client.
+#[derive(Debug, Default)]
+pub struct EventStreams {
+    streams: HashMap<Uuid, EventStream>,
+}
+
+impl EventStreams {
+    pub fn open(&mut self, client_id: Uuid, stream: EventStream) {
+        self.streams.insert(client_id, stream);
+    }
+
+    pub fn close(&mut self, client_id: &Uuid) {
+        self.streams.remove(client_id);
+    }
+
+    /// The stream `client_id`, if `username` opened it with `session_id`.
+    pub fn get_mut(&mut self, client_id: &Uuid, username: &str, session_id: &Uuid) -> Option<&mut EventStream> {
+        self.streams
+            .get_mut(client_id)
+            .filter(|stream| stream.username == username && stream.session_id == *session_id)
+    }
+}
+
+/// First event on every stream: the client id to post with.
+pub fn session_event(client_id: &Uuid) -> Result<Event, Infallible> {
+    Ok(Event::default().event("session").data(client_id.to_string()))
+}
+
+/// What a message queued for a WebSocket becomes on the stream. A close
+/// carries its code and reason like a WebSocket close would, and is the
+/// last event; pings are left to the stream's keep-alive.
+pub fn to_event(msg: Message) -> Option<Result<Event, Infallible>> {
+    let event = match msg {
+        Message::Text(text) => Event::default().data(text),
+        Message::Close(frame) => {
+            let CloseFrame { code, reason } = frame.unwrap_or(CloseFrame { code: close_code::NORMAL, reason: "".into() });
+            Event::default()
+                .event("close")
+                .data(serde_json::json!({"code": code, "reason": reason}).to_string())
+        }
+        _ => return None,
+    };
+    Some(Ok(event))
+}
@@ -0,0 +1,84 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Submissions allowed per user within `RATE_WINDOW`.
+const MAX_PER_WINDOW: usize = 5;
+const RATE_WINDOW: Duration = Duration::from_secs(60 * 60);
+/// Oldest reports are dropped beyond this many.
+const MAX_STORED: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Bug,
+    Connection,
+    Idea,
+    Other,
+}
+
+/// A report sent from the in-app feedback form.
+#[derive(Debug, Clone, Serialize)]
+pub struct Feedback {
+    pub id: Uuid,
+    pub username: String,
+    pub category: Category,
+    pub description: String,
+    /// Connection details the user chose to attach
+    pub diagnostics: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Feedback waiting for admins to review, with a per-user submission limit.
+#[derive(Debug, Default)]
+pub struct FeedbackStore {
+    entries: VecDeque<Feedback>,
+    recent: HashMap<String, VecDeque<Instant>>,
+}
+
+impl FeedbackStore {
+    /// Stores the report, or returns how long `username` must wait if they
+    /// sent too many lately.
+    pub fn submit(
+        &mut self,
+        username: &str,
+        category: Category,
+        description: String,
+        diagnostics: Option<String>,
+    ) -> Result<Uuid, Duration> {
+        let now = Instant::now();
+        self.recent.retain(|_, times| {
+            while times.front().is_some_and(|t| now.duration_since(*t) >= RATE_WINDOW) {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+        let times = self.recent.entry(username.to_string()).or_default();
+        if times.len() >= MAX_PER_WINDOW {
+            return Err(RATE_WINDOW.saturating_sub(now.duration_since(times[0])));
+        }
+        times.push_back(now);
+
+        let id = Uuid::new_v4();
+        self.entries.push_back(Feedback {
+            id,
+            username: username.to_string(),
+            category,
+            description,
+            diagnostics,
+            created_at: Utc::now(),
+        });
+        if self.entries.len() > MAX_STORED {
+            self.entries.pop_front();
+        }
+        Ok(id)
+    }
+
+    /// All stored reports, newest first.
+    pub fn list(&self) -> Vec<Feedback> {
+        self.entries.iter().rev().cloned().collect()
+    }
+}
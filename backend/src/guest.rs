@@ -0,0 +1,29 @@
+use chrono::Duration;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// Usernames starting with this are reserved for guests.
+pub const USERNAME_PREFIX: &str = "guest-";
+/// Guests get a short-lived token instead of a day-long one.
+pub const TOKEN_TTL: Duration = Duration::hours(2);
+
+const ADJECTIVES: &[&str] = &[
+    "amber", "brave", "calm", "clever", "cosmic", "eager", "gentle", "happy", "lucky", "mellow",
+    "misty", "nimble", "quiet", "rapid", "silver", "sunny", "swift", "witty",
+];
+const ANIMALS: &[&str] = &[
+    "badger", "crane", "falcon", "fox", "gecko", "heron", "koala", "lynx", "marten", "otter",
+    "owl", "panda", "puffin", "raven", "seal", "tiger", "walrus", "wren",
+];
+
+/// A random guest username such as `guest-quiet-otter-42`.
+pub fn nickname() -> String {
+    let mut rng = rand::thread_rng();
+    format!(
+        "{}{}-{}-{}",
+        USERNAME_PREFIX,
+        ADJECTIVES.choose(&mut rng).unwrap(),
+        ANIMALS.choose(&mut rng).unwrap(),
+        rng.gen_range(10..100)
+    )
+}
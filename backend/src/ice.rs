@@ -0,0 +1,135 @@
+use std::env;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+
+/// Comma-separated STUN URLs; a public Google server when unset.
+const STUN_URLS_VAR: &str = "STUN_URLS";
+/// Comma-separated TURN URLs, e.g. `turn:turn.example.com:3478?transport=udp`.
+const TURN_URLS_VAR: &str = "TURN_URLS";
+/// Shared with the TURN server (coturn's `static-auth-secret`).
+const TURN_SECRET_VAR: &str = "TURN_SECRET";
+/// How long issued TURN credentials stay valid, in seconds.
+const TURN_TTL_VAR: &str = "TURN_CREDENTIAL_TTL_SECS";
+
+const DEFAULT_STUN_URL: &str = "stun:stun.l.google.com:19302";
+const DEFAULT_TURN_TTL_SECS: i64 = 12 * 60 * 60;
+
+/// Most servers a room can bring, and URLs per server.
+pub const MAX_ROOM_ICE_SERVERS: usize = 5;
+const MAX_ROOM_ICE_URLS: usize = 5;
+/// Longest URL, username or credential a room server may have.
+const MAX_ROOM_ICE_FIELD: usize = 256;
+
+/// One entry of `RTCConfiguration.iceServers`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IceServer {
+    urls: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    credential: Option<String>,
+}
+
+impl IceServer {
+    /// Checks a server a room creator supplied. Browsers reject the whole
+    /// configuration over one bad entry, so this is stricter than they are:
+    /// only `stun:`, `stuns:`, `turn:` and `turns:` URLs, and TURN needs a
+    /// username and credential since we cannot mint them for a foreign server.
+    pub fn check(&self) -> Result<(), String> {
+        if self.urls.is_empty() || self.urls.len() > MAX_ROOM_ICE_URLS {
+            return Err(format!("Each ICE server needs between 1 and {} URLs", MAX_ROOM_ICE_URLS));
+        }
+        let mut fields = self.urls.iter().chain(&self.username).chain(&self.credential);
+        if fields.any(|f| f.len() > MAX_ROOM_ICE_FIELD || f.chars().any(char::is_control)) {
+            return Err(format!("ICE server fields are at most {} characters on one line", MAX_ROOM_ICE_FIELD));
+        }
+        let mut turn = false;
+        for url in &self.urls {
+            match url.split_once(':') {
+                Some(("stun" | "stuns", rest)) if !rest.is_empty() => {}
+                Some(("turn" | "turns", rest)) if !rest.is_empty() => turn = true,
+                _ => return Err(format!("{} is not a stun:, stuns:, turn: or turns: URL", url)),
+            }
+        }
+        if turn && (self.username.is_none() || self.credential.is_none()) {
+            return Err("TURN servers need a username and credential".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Response of `GET /ice-config`.
+#[derive(Debug, Serialize)]
+pub struct IceServers {
+    ice_servers: Vec<IceServer>,
+    /// Seconds until the TURN credentials expire
+    ttl: i64,
+}
+
+/// STUN and TURN servers handed to clients. TURN credentials are minted per
+/// request following the TURN REST API convention coturn implements: the
+/// username is `<expiry timestamp>:<user>` and the password the base64
+/// HMAC-SHA1 of that username under the shared secret, so the TURN server
+/// can check them without asking us.
+#[derive(Debug)]
+pub struct IceConfig {
+    stun_urls: Vec<String>,
+    turn_urls: Vec<String>,
+    turn_secret: Option<String>,
+    ttl_secs: i64,
+}
+
+fn url_list(var: &str) -> Option<Vec<String>> {
+    let value = env::var(var).ok()?;
+    Some(value.split(',').map(str::trim).filter(|u| !u.is_empty()).map(str::to_string).collect())
+}
+
+impl IceConfig {
+    /// Reads the servers from the environment. TURN URLs without a secret are
+    /// a configuration error rather than servers nobody could log into.
+    pub fn from_env() -> Result<Self, String> {
+        let stun_urls = url_list(STUN_URLS_VAR).unwrap_or_else(|| vec![DEFAULT_STUN_URL.to_string()]);
+        let turn_urls = url_list(TURN_URLS_VAR).unwrap_or_default();
+        let turn_secret = env::var(TURN_SECRET_VAR).ok().filter(|s| !s.is_empty());
+        if !turn_urls.is_empty() && turn_secret.is_none() {
+            return Err(format!("{} is set but {} is not", TURN_URLS_VAR, TURN_SECRET_VAR));
+        }
+        let ttl_secs = match env::var(TURN_TTL_VAR) {
+            Ok(value) => value
+                .parse()
+                .ok()
+                .filter(|ttl| *ttl > 0)
+                .ok_or_else(|| format!("{} must be a positive number of seconds", TURN_TTL_VAR))?,
+            Err(_) => DEFAULT_TURN_TTL_SECS,
+        };
+        Ok(IceConfig { stun_urls, turn_urls, turn_secret, ttl_secs })
+    }
+
+    pub fn has_turn(&self) -> bool {
+        !self.turn_urls.is_empty()
+    }
+
+    /// The servers for `username`, with fresh TURN credentials.
+    pub fn servers_for(&self, username: &str) -> IceServers {
+        let mut ice_servers = Vec::new();
+        if !self.stun_urls.is_empty() {
+            ice_servers.push(IceServer { urls: self.stun_urls.clone(), username: None, credential: None });
+        }
+        if let (false, Some(secret)) = (self.turn_urls.is_empty(), &self.turn_secret) {
+            let expires = Utc::now().timestamp() + self.ttl_secs;
+            let turn_username = format!("{}:{}", expires, username);
+            let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+            mac.update(turn_username.as_bytes());
+            ice_servers.push(IceServer {
+                urls: self.turn_urls.clone(),
+                username: Some(turn_username),
+                credential: Some(STANDARD.encode(mac.finalize().into_bytes())),
+            });
+        }
+        IceServers { ice_servers, ttl: self.ttl_secs }
+    }
+}
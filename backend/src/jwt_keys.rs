@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use jsonwebtoken::{decode, decode_header, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::config::{Layers, Setting};
+
+/// Comma-separated `kid:secret` pairs, e.g. `2024-06:abc...,2024-01:def...`.
+pub const KEYS: Setting = Setting { key: "jwt_keys", var: "JWT_KEYS", flag: None };
+/// Id of the key new tokens are signed with; defaults to the first in `JWT_KEYS`.
+pub const ACTIVE_KID: Setting = Setting { key: "jwt_active_kid", var: "JWT_ACTIVE_KID", flag: None };
+/// Single-key shorthand, used under the id `default` when `JWT_KEYS` is unset.
+pub const SECRET: Setting = Setting { key: "jwt_secret", var: "JWT_SECRET", flag: None };
+
+/// Signing keys by id. New tokens are signed with the active key and carry
+/// its id in the `kid` header; any configured key is accepted when verifying,
+/// so a retired key can stay listed until the tokens it signed expire.
+pub struct JwtKeys {
+    active_kid: String,
+    keys: HashMap<String, (EncodingKey, DecodingKey)>,
+}
+
+impl fmt::Debug for JwtKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut kids: Vec<&String> = self.keys.keys().collect();
+        kids.sort();
+        f.debug_struct("JwtKeys")
+            .field("active_kid", &self.active_kid)
+            .field("kids", &kids)
+            .finish()
+    }
+}
+
+impl JwtKeys {
+    /// Reads the keys from the environment or the config file. Fails rather
+    /// than falling back to a built-in secret, since anyone with the source
+    /// could forge tokens.
+    pub fn load(layers: &Layers) -> Result<Self, String> {
+        Self::new(layers.get(&KEYS), layers.get(&SECRET), layers.get(&ACTIVE_KID))
+    }
+
+    /// The keys as `JWT_KEYS`, `JWT_SECRET` and `JWT_ACTIVE_KID` give them.
+    pub fn new(list: Option<String>, secret: Option<String>, active_kid: Option<String>) -> Result<Self, String> {
+        let mut pairs = Vec::new();
+        if let Some(list) = list {
+            for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let (kid, secret) = entry
+                    .split_once(':')
+                    .ok_or_else(|| format!("{} entry {:?} is not in kid:secret form", KEYS.var, entry))?;
+                pairs.push((kid.trim().to_string(), secret.trim().to_string()));
+            }
+        } else if let Some(secret) = secret {
+            pairs.push(("default".to_string(), secret));
+        }
+
+        let active_kid = match active_kid {
+            Some(kid) => kid,
+            None => pairs
+                .first()
+                .map(|(kid, _)| kid.clone())
+                .ok_or_else(|| format!("No JWT signing key configured; set {} or {}", KEYS.var, SECRET.var))?,
+        };
+
+        let mut keys = HashMap::new();
+        for (kid, secret) in pairs {
+            if kid.is_empty() || secret.is_empty() {
+                return Err(format!("{} contains an empty key id or secret", KEYS.var));
+            }
+            let secret = secret.as_bytes();
+            keys.insert(kid, (EncodingKey::from_secret(secret), DecodingKey::from_secret(secret)));
+        }
+        if !keys.contains_key(&active_kid) {
+            return Err(format!("{} {:?} is not one of the configured keys", ACTIVE_KID.var, active_kid));
+        }
+        Ok(JwtKeys { active_kid, keys })
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> String {
+        let header = Header {
+            kid: Some(self.active_kid.clone()),
+            ..Header::default()
+        };
+        encode(&header, claims, &self.keys[&self.active_kid].0).unwrap()
+    }
+
+    /// Verifies `token` with the key named by its `kid` header. Tokens
+    /// without a `kid` are rejected.
+    pub fn decode<T: DeserializeOwned>(&self, token: &str) -> Option<T> {
+        let kid = decode_header(token).ok()?.kid?;
+        let (_, key) = self.keys.get(&kid)?;
+        decode::<T>(token, key, &Validation::default()).ok().map(|data| data.claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Claims {
+        sub: String,
+        exp: usize,
+    }
+
+    fn claims() -> Claims {
+        Claims { sub: "alice".to_string(), exp: (chrono::Utc::now().timestamp() + 60) as usize }
+    }
+
+    fn keys(list: &str, active_kid: Option<&str>) -> JwtKeys {
+        JwtKeys::new(Some(list.to_string()), None, active_kid.map(str::to_string)).unwrap()
+    }
+
+    #[test]
+    fn retired_keys_verify_until_they_are_removed() {
+        let old = keys("2024-01:old-secret", None);
+        let token = old.encode(&claims());
+        let rotated = keys("2024-06:new-secret, 2024-01:old-secret", None);
+        assert_eq!(rotated.decode::<Claims>(&token), Some(claims()));
+        assert_eq!(decode_header(&rotated.encode(&claims())).unwrap().kid.as_deref(), Some("2024-06"));
+
+        let rotated_out = keys("2024-06:new-secret", None);
+        assert_eq!(rotated_out.decode::<Claims>(&token), None);
+    }
+
+    #[test]
+    fn tokens_naming_an_unknown_key_are_rejected() {
+        let keys = keys("a:secret", None);
+        let unknown = Header { kid: Some("b".to_string()), ..Header::default() };
+        let token = encode(&unknown, &claims(), &EncodingKey::from_secret(b"secret")).unwrap();
+        assert_eq!(keys.decode::<Claims>(&token), None);
+        let without_kid = encode(&Header::default(), &claims(), &EncodingKey::from_secret(b"secret")).unwrap();
+        assert_eq!(keys.decode::<Claims>(&without_kid), None);
+    }
+
+    #[test]
+    fn the_secret_is_the_default_key() {
+        let keys = JwtKeys::new(None, Some("secret".to_string()), None).unwrap();
+        assert_eq!(keys.active_kid, "default");
+        assert_eq!(keys.decode::<Claims>(&keys.encode(&claims())), Some(claims()));
+    }
+
+    #[test]
+    fn malformed_key_lists_fail_to_load() {
+        let error = JwtKeys::new(Some("a:secret,nocolon".to_string()), None, None).unwrap_err();
+        assert!(error.contains("\"nocolon\" is not in kid:secret form"), "{}", error);
+        assert!(JwtKeys::new(Some("a:".to_string()), None, None).is_err(), "empty secret");
+        assert!(JwtKeys::new(Some(":secret".to_string()), None, None).is_err(), "empty key id");
+        let error = JwtKeys::new(Some("a:secret".to_string()), None, Some("b".to_string())).unwrap_err();
+        assert!(error.contains("\"b\" is not one of the configured keys"), "{}", error);
+    }
+
+    #[test]
+    fn there_is_no_built_in_key() {
+        let error = JwtKeys::new(None, None, None).unwrap_err();
+        assert!(error.starts_with("No JWT signing key configured"), "{}", error);
+        assert!(JwtKeys::new(Some(String::new()), None, None).is_err(), "an empty list");
+    }
+}
@@ -0,0 +1,214 @@
+use std::io;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::Path;
+
+use axum::extract::ConnectInfo;
+use axum::Router;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::sync::watch;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+use tracing::{debug, warn};
+
+use crate::profile::Bind;
+use crate::tls::HANDSHAKE_TIMEOUT;
+
+/// Sockets systemd passes in start at this descriptor, see sd_listen_fds(3).
+#[cfg(unix)]
+const SYSTEMD_FIRST_FD: i32 = 3;
+
+/// A socket taking connections.
+pub enum Listener {
+    Tcp(TcpListener),
+    /// TCP with TLS in front, for `https` and `wss`
+    Tls(TcpListener, TlsAcceptor),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// Opens the sockets `bind` stands for, each with a name for the logs.
+/// With `tls`, TCP sockets speak TLS; unix sockets never do, as only a
+/// proxy on this host can reach them.
+pub fn open(bind: &Bind, tls: Option<&TlsAcceptor>) -> io::Result<Vec<(String, Listener)>> {
+    let listeners = match bind {
+        Bind::Tcp(addr) => vec![(format!("http://{}", addr), Listener::Tcp(listen(*addr)?))],
+        #[cfg(unix)]
+        Bind::Unix(path) => vec![(bind.to_string(), Listener::Unix(listen_unix(path)?))],
+        #[cfg(unix)]
+        Bind::Systemd => systemd_listeners()?,
+        #[cfg(not(unix))]
+        Bind::Unix(_) | Bind::Systemd => return Err(io::Error::new(io::ErrorKind::Unsupported, "only supported on unix")),
+    };
+    let Some(tls) = tls else { return Ok(listeners) };
+    Ok(listeners
+        .into_iter()
+        .map(|(name, listener)| match listener {
+            Listener::Tcp(listener) => (name.replacen("http://", "https://", 1), Listener::Tls(listener, tls.clone())),
+            listener => (name, listener),
+        })
+        .collect())
+}
+
+/// Listens on `addr`. IPv6 sockets take only IPv6, so `[::]` can be listed
+/// next to `0.0.0.0` on every OS.
+fn listen(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = socket2::Socket::new(socket2::Domain::for_address(addr), socket2::Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Listens on a unix domain socket at `path`, replacing the socket a
+/// previous run left behind. Anything else at `path` is kept and fails the
+/// bind.
+#[cfg(unix)]
+fn listen_unix(path: &Path) -> io::Result<UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+/// Takes over the sockets systemd opened for us, in the order the socket
+/// unit lists them. Fails unless systemd started this very process with
+/// sockets.
+#[cfg(unix)]
+fn systemd_listeners() -> io::Result<Vec<(String, Listener)>> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    let for_us = std::env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) == Some(std::process::id());
+    let count = std::env::var("LISTEN_FDS").ok().and_then(|n| n.parse::<i32>().ok()).filter(|_| for_us);
+    // Children must not take them for theirs
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+    let count = count
+        .filter(|n| *n > 0)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "systemd passed no sockets (LISTEN_FDS)"))?;
+
+    let mut listeners = Vec::new();
+    for raw in SYSTEMD_FIRST_FD..SYSTEMD_FIRST_FD + count {
+        // SAFETY: systemd hands us these descriptors open and owned by this
+        // process, and nothing else here uses them
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+        let addr = socket2::SockRef::from(&fd).local_addr()?;
+        let listener = if let Some(addr) = addr.as_socket() {
+            let listener = std::net::TcpListener::from(fd);
+            listener.set_nonblocking(true)?;
+            (format!("http://{} (systemd)", addr), Listener::Tcp(TcpListener::from_std(listener)?))
+        } else if addr.is_unix() {
+            let listener = std::os::unix::net::UnixListener::from(fd);
+            listener.set_nonblocking(true)?;
+            let path = addr.as_pathname().map(|p| p.display().to_string()).unwrap_or_default();
+            (format!("unix:{} (systemd)", path), Listener::Unix(UnixListener::from_std(listener)?))
+        } else {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, format!("systemd socket {} is neither TCP nor unix", raw)));
+        };
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+/// Serves `app` on `listener` until `shutdown` fires.
+pub async fn serve(listener: Listener, app: Router, mut shutdown: watch::Receiver<()>) -> io::Result<()> {
+    match listener {
+        Listener::Tcp(listener) => {
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(async move {
+                    let _ = shutdown.changed().await;
+                })
+                .await
+        }
+        Listener::Tls(listener, acceptor) => {
+            serve_tls(listener, acceptor, app, shutdown).await;
+            Ok(())
+        }
+        #[cfg(unix)]
+        Listener::Unix(listener) => {
+            serve_unix(listener, app, shutdown).await;
+            Ok(())
+        }
+    }
+}
+
+/// Serves HTTP/1.1, WebSocket upgrades included, on one connection. Handlers
+/// see `peer` as the connection's address, if it has one.
+async fn serve_connection<I>(io: I, app: Router, peer: Option<SocketAddr>)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let app = app.map_request(move |mut request: axum::extract::Request<hyper::body::Incoming>| {
+        if let Some(peer) = peer {
+            request.extensions_mut().insert(ConnectInfo(peer));
+        }
+        request
+    });
+    let connection = hyper::server::conn::http1::Builder::new()
+        .serve_connection(hyper_util::rt::TokioIo::new(io), hyper_util::service::TowerToHyperService::new(app))
+        .with_upgrades();
+    if let Err(err) = connection.await {
+        debug!("Connection from {:?} ended: {}", peer, err);
+    }
+}
+
+/// Serves HTTPS and WSS on `listener`. Each connection shakes hands on its
+/// own task, so a slow client holds up nobody else. Stops taking
+/// connections on shutdown, like unix sockets.
+async fn serve_tls(listener: TcpListener, acceptor: TlsAcceptor, app: Router, mut shutdown: watch::Receiver<()>) {
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!("Cannot accept TLS connection: {}", err);
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            _ = shutdown.changed() => break,
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => serve_connection(stream, app, Some(peer)).await,
+                Ok(Err(err)) => debug!("TLS handshake with {} failed: {}", peer, err),
+                Err(_) => debug!("TLS handshake with {} timed out", peer),
+            }
+        });
+    }
+}
+
+/// Serves HTTP/1.1, WebSocket upgrades included, on a unix domain socket.
+/// Requests carry no peer address; [`crate::proxy::real_client`] fills it
+/// in. Stops taking connections on shutdown; signaling has wound down by
+/// then.
+#[cfg(unix)]
+async fn serve_unix(listener: UnixListener, app: Router, mut shutdown: watch::Receiver<()>) {
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    // e.g. out of file descriptors; give it a moment
+                    warn!("Cannot accept on unix socket: {}", err);
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            _ = shutdown.changed() => break,
+        };
+        tokio::spawn(serve_connection(stream, app.clone(), None));
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Failures allowed per key before backoff kicks in.
+const FREE_ATTEMPTS: u32 = 3;
+/// Upper bound for the exponential backoff between attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(15 * 60);
+/// Consecutive failures on one username that trigger a temporary lockout.
+const LOCKOUT_THRESHOLD: u32 = 10;
+const LOCKOUT_DURATION: Duration = Duration::from_secs(30 * 60);
+/// Records without a failure for this long are forgotten.
+const RECORD_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy)]
+struct FailureRecord {
+    failures: u32,
+    /// Attempts let through by `check` whose outcome is not recorded yet
+    pending: u32,
+    last_failure: Instant,
+    blocked_until: Instant,
+}
+
+impl FailureRecord {
+    fn new(now: Instant) -> Self {
+        FailureRecord { failures: 0, pending: 0, last_failure: now, blocked_until: now }
+    }
+
+    /// How long until another attempt is allowed. Attempts still pending
+    /// count as failures, so a burst of parallel logins gets no more tries
+    /// than the same logins one after another.
+    fn wait(&self, now: Instant) -> Duration {
+        let blocked = self.blocked_until.saturating_duration_since(now);
+        if self.pending == 0 {
+            return blocked;
+        }
+        blocked.max(backoff(self.failures + self.pending))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum LimitKey {
+    Ip(IpAddr),
+    Username(String),
+}
+
+/// Tracks failed logins per client IP and per username. Each failure past
+/// `FREE_ATTEMPTS` doubles the wait before the next attempt is allowed, and
+/// a username that keeps failing is locked out regardless of source IP.
+#[derive(Debug, Default)]
+pub struct LoginLimiter {
+    records: HashMap<LimitKey, FailureRecord>,
+}
+
+impl LoginLimiter {
+    /// Returns how long the caller must wait if either the IP or the
+    /// username is currently blocked. Otherwise the attempt counts as
+    /// pending until `record_failure`, `record_success` or `release`, since
+    /// the password check in between runs without the lock.
+    pub fn check(&mut self, ip: IpAddr, username: &str) -> Result<(), Duration> {
+        self.check_at(ip, username, Instant::now())
+    }
+
+    fn check_at(&mut self, ip: IpAddr, username: &str, now: Instant) -> Result<(), Duration> {
+        self.records
+            .retain(|_, record| record.pending > 0 || now.duration_since(record.last_failure) < RECORD_TTL);
+
+        let keys = [LimitKey::Ip(ip), LimitKey::Username(username.to_string())];
+        let wait = keys
+            .iter()
+            .filter_map(|key| self.records.get(key))
+            .map(|record| record.wait(now))
+            .max()
+            .unwrap_or_default();
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        for key in keys {
+            self.records.entry(key).or_insert_with(|| FailureRecord::new(now)).pending += 1;
+        }
+        Ok(())
+    }
+
+    pub fn record_failure(&mut self, ip: IpAddr, username: &str) {
+        self.record_failure_at(ip, username, Instant::now());
+    }
+
+    fn record_failure_at(&mut self, ip: IpAddr, username: &str, now: Instant) {
+        for key in [LimitKey::Ip(ip), LimitKey::Username(username.to_string())] {
+            let is_username = matches!(key, LimitKey::Username(_));
+            let record = self.records.entry(key).or_insert_with(|| FailureRecord::new(now));
+            record.pending = record.pending.saturating_sub(1);
+            record.failures += 1;
+            record.last_failure = now;
+            record.blocked_until = now + backoff(record.failures);
+            if is_username && record.failures >= LOCKOUT_THRESHOLD {
+                record.blocked_until = record.blocked_until.max(now + LOCKOUT_DURATION);
+            }
+        }
+    }
+
+    /// Clears the username's failures. The IP's are left to decay: one
+    /// account that works says nothing about the other names tried from
+    /// the same address.
+    pub fn record_success(&mut self, ip: IpAddr, username: &str) {
+        self.records.remove(&LimitKey::Username(username.to_string()));
+        self.settle(LimitKey::Ip(ip));
+    }
+
+    /// Ends an attempt that neither failed nor succeeded, such as one that
+    /// still needs its second factor.
+    pub fn release(&mut self, ip: IpAddr, username: &str) {
+        self.settle(LimitKey::Ip(ip));
+        self.settle(LimitKey::Username(username.to_string()));
+    }
+
+    fn settle(&mut self, key: LimitKey) {
+        if let Some(record) = self.records.get_mut(&key) {
+            record.pending = record.pending.saturating_sub(1);
+            if record.pending == 0 && record.failures == 0 {
+                self.records.remove(&key);
+            }
+        }
+    }
+}
+
+/// Seconds for the `Retry-After` header: past the end of the wait, so a
+/// client that waits that long is let through.
+pub fn retry_after(wait: Duration) -> u64 {
+    wait.as_secs() + 1
+}
+
+fn backoff(failures: u32) -> Duration {
+    if failures < FREE_ATTEMPTS {
+        return Duration::ZERO;
+    }
+    let exponent = (failures - FREE_ATTEMPTS).min(16);
+    Duration::from_secs(1 << exponent).min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER_IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 2));
+
+    fn fail(limiter: &mut LoginLimiter, ip: IpAddr, username: &str, times: u32, now: Instant) {
+        for _ in 0..times {
+            limiter.record_failure_at(ip, username, now);
+        }
+    }
+
+    #[test]
+    fn free_attempts_are_not_delayed() {
+        let mut limiter = LoginLimiter::default();
+        let now = Instant::now();
+        fail(&mut limiter, IP, "alice", FREE_ATTEMPTS - 1, now);
+        assert_eq!(limiter.check_at(IP, "alice", now), Ok(()));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        assert_eq!(backoff(FREE_ATTEMPTS - 1), Duration::ZERO);
+        assert_eq!(backoff(FREE_ATTEMPTS), Duration::from_secs(1));
+        assert_eq!(backoff(FREE_ATTEMPTS + 1), Duration::from_secs(2));
+        assert_eq!(backoff(FREE_ATTEMPTS + 4), Duration::from_secs(16));
+        assert_eq!(backoff(FREE_ATTEMPTS + 30), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn blocks_until_the_backoff_passes() {
+        let mut limiter = LoginLimiter::default();
+        let now = Instant::now();
+        fail(&mut limiter, IP, "alice", FREE_ATTEMPTS + 2, now);
+        assert_eq!(limiter.check_at(IP, "alice", now), Err(Duration::from_secs(4)));
+        assert_eq!(limiter.check_at(IP, "alice", now + Duration::from_secs(3)), Err(Duration::from_secs(1)));
+        assert_eq!(limiter.check_at(IP, "alice", now + Duration::from_secs(4)), Ok(()));
+    }
+
+    #[test]
+    fn blocks_the_ip_for_other_usernames() {
+        let mut limiter = LoginLimiter::default();
+        let now = Instant::now();
+        fail(&mut limiter, IP, "alice", FREE_ATTEMPTS, now);
+        assert!(limiter.check_at(IP, "bob", now).is_err());
+        assert_eq!(limiter.check_at(OTHER_IP, "bob", now), Ok(()));
+    }
+
+    #[test]
+    fn locks_out_a_username_from_every_ip() {
+        let mut limiter = LoginLimiter::default();
+        let now = Instant::now();
+        for i in 0..LOCKOUT_THRESHOLD {
+            let ip = IpAddr::V4(std::net::Ipv4Addr::new(198, 51, 100, i as u8));
+            limiter.record_failure_at(ip, "alice", now);
+        }
+        let wait = limiter.check_at(OTHER_IP, "alice", now).unwrap_err();
+        assert!(wait >= LOCKOUT_DURATION, "waits only {:?}", wait);
+        assert_eq!(limiter.check_at(OTHER_IP, "bob", now), Ok(()));
+        assert_eq!(limiter.check_at(OTHER_IP, "alice", now + LOCKOUT_DURATION), Ok(()));
+    }
+
+    #[test]
+    fn success_clears_the_username_but_not_the_ip() {
+        let mut limiter = LoginLimiter::default();
+        let now = Instant::now();
+        fail(&mut limiter, IP, "alice", FREE_ATTEMPTS + 1, now);
+        limiter.record_success(IP, "alice");
+        assert!(limiter.check_at(IP, "bob", now).is_err());
+        assert_eq!(limiter.check_at(OTHER_IP, "alice", now), Ok(()));
+        limiter.release(OTHER_IP, "alice");
+        assert!(!limiter.records.contains_key(&LimitKey::Username("alice".to_string())));
+    }
+
+    #[test]
+    fn pending_attempts_count_as_failures() {
+        let mut limiter = LoginLimiter::default();
+        let now = Instant::now();
+        for _ in 0..FREE_ATTEMPTS {
+            assert_eq!(limiter.check_at(IP, "alice", now), Ok(()));
+        }
+        assert_eq!(limiter.check_at(IP, "alice", now), Err(Duration::from_secs(1)));
+        assert!(limiter.check_at(IP, "bob", now).is_err());
+        limiter.release(IP, "alice");
+        assert_eq!(limiter.check_at(IP, "alice", now), Ok(()));
+    }
+
+    #[test]
+    fn settled_attempts_leave_no_record() {
+        let mut limiter = LoginLimiter::default();
+        let now = Instant::now();
+        assert_eq!(limiter.check_at(IP, "alice", now), Ok(()));
+        limiter.record_success(IP, "alice");
+        assert_eq!(limiter.check_at(IP, "bob", now), Ok(()));
+        limiter.release(IP, "bob");
+        assert!(limiter.records.is_empty());
+    }
+
+    #[test]
+    fn old_records_are_forgotten() {
+        let mut limiter = LoginLimiter::default();
+        let now = Instant::now();
+        fail(&mut limiter, IP, "alice", LOCKOUT_THRESHOLD, now);
+        assert!(limiter.check_at(IP, "alice", now + RECORD_TTL).is_ok());
+        limiter.release(IP, "alice");
+        assert!(limiter.records.is_empty());
+    }
+
+    #[test]
+    fn retry_after_never_undershoots() {
+        assert_eq!(retry_after(Duration::ZERO), 1);
+        assert_eq!(retry_after(Duration::from_millis(1500)), 2);
+        assert_eq!(retry_after(Duration::from_secs(4)), 5);
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// How long a letter waits when the sender does not say.
+pub const DEFAULT_TTL: Duration = Duration::days(7);
+pub const MAX_TTL: Duration = Duration::days(30);
+/// Largest payload, in bytes of its text. Request bodies are capped at
+/// 10 KiB, so this leaves room for the JSON around it.
+pub const MAX_PAYLOAD_BYTES: usize = 8 * 1024;
+/// Most letters waiting for one user, and from one sender among them, so a
+/// single sender cannot fill someone's mailbox.
+const MAX_PER_RECIPIENT: usize = 200;
+const MAX_PER_SENDER: usize = 50;
+
+/// A message left for a user who was not around to take it. The server
+/// cannot read the payload: clients encrypt it for the recipient.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Letter {
+    pub id: Uuid,
+    pub from: String,
+    pub payload: String,
+    pub sent_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Tells a sender their letter reached `to`. Kept for the sender until
+/// they connect, or the letter would have expired.
+#[derive(Debug, Clone, Serialize)]
+pub struct Receipt {
+    pub id: Uuid,
+    pub to: String,
+    #[serde(skip)]
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostError {
+    /// The recipient has too many letters waiting, or too many from this
+    /// sender
+    Full,
+}
+
+/// What is waiting in the mailbox, as a backup keeps it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Contents {
+    letters: HashMap<String, Vec<Letter>>,
+    /// Receipts by sender: letter id, recipient and when it stops being kept
+    receipts: HashMap<String, Vec<(Uuid, String, DateTime<Utc>)>>,
+}
+
+/// Letters by recipient, kept until acknowledged or expired, and receipts
+/// by sender. In memory only, like sessions.
+#[derive(Debug, Default)]
+pub struct Mailbox {
+    letters: HashMap<String, Vec<Letter>>,
+    receipts: HashMap<String, Vec<Receipt>>,
+    /// Signaling clients that asked for their mail, with their username;
+    /// new letters and receipts go straight to them
+    readers: HashMap<Uuid, String>,
+}
+
+impl Mailbox {
+    fn expire(&mut self) {
+        let now = Utc::now();
+        self.letters.retain(|_, letters| {
+            letters.retain(|letter| letter.expires_at > now);
+            !letters.is_empty()
+        });
+        self.receipts.retain(|_, receipts| {
+            receipts.retain(|receipt| receipt.expires_at > now);
+            !receipts.is_empty()
+        });
+    }
+
+    /// Leaves `payload` for `to` until `ttl` from now.
+    pub fn post(&mut self, from: &str, to: &str, payload: String, ttl: Duration) -> Result<Letter, PostError> {
+        self.expire();
+        let letters = self.letters.entry(to.to_string()).or_default();
+        let from_sender = letters.iter().filter(|letter| letter.from == from).count();
+        if letters.len() >= MAX_PER_RECIPIENT || from_sender >= MAX_PER_SENDER {
+            return Err(PostError::Full);
+        }
+        let now = Utc::now();
+        let letter = Letter { id: Uuid::new_v4(), from: from.to_string(), payload, sent_at: now, expires_at: now + ttl };
+        letters.push(letter.clone());
+        Ok(letter)
+    }
+
+    /// Everything waiting for `username`, oldest first. Letters stay until
+    /// acknowledged, so a client that drops before acking gets them again.
+    pub fn letters(&mut self, username: &str) -> Vec<Letter> {
+        self.expire();
+        self.letters.get(username).cloned().unwrap_or_default()
+    }
+
+    /// Removes the letters `username` acknowledged and returns the receipts
+    /// for their senders.
+    pub fn acknowledge(&mut self, username: &str, ids: &[Uuid]) -> Vec<(String, Receipt)> {
+        self.expire();
+        let Some(letters) = self.letters.get_mut(username) else { return Vec::new() };
+        let ids: HashSet<&Uuid> = ids.iter().collect();
+        let mut receipts = Vec::new();
+        letters.retain(|letter| {
+            let delivered = ids.contains(&letter.id);
+            if delivered {
+                let receipt = Receipt { id: letter.id, to: username.to_string(), expires_at: letter.expires_at };
+                receipts.push((letter.from.clone(), receipt));
+            }
+            !delivered
+        });
+        if letters.is_empty() {
+            self.letters.remove(username);
+        }
+        receipts
+    }
+
+    /// Keeps a receipt for a sender who is not connected.
+    pub fn keep_receipt(&mut self, sender: &str, receipt: Receipt) {
+        self.receipts.entry(sender.to_string()).or_default().push(receipt);
+    }
+
+    /// Receipts kept for `username`, handed over once.
+    pub fn take_receipts(&mut self, username: &str) -> Vec<Receipt> {
+        self.expire();
+        self.receipts.remove(username).unwrap_or_default()
+    }
+
+    pub fn open(&mut self, client_id: Uuid, username: &str) {
+        self.readers.insert(client_id, username.to_string());
+    }
+
+    pub fn close(&mut self, client_id: &Uuid) {
+        self.readers.remove(client_id);
+    }
+
+    /// Connected clients of `username` that read mail.
+    pub fn readers(&self, username: &str) -> Vec<Uuid> {
+        self.readers.iter().filter(|(_, reader)| *reader == username).map(|(id, _)| *id).collect()
+    }
+
+    /// Letters and receipts that have not expired, for a backup.
+    pub fn contents(&mut self) -> Contents {
+        self.expire();
+        let receipts = self
+            .receipts
+            .iter()
+            .map(|(sender, receipts)| {
+                (sender.clone(), receipts.iter().map(|r| (r.id, r.to.clone(), r.expires_at)).collect())
+            })
+            .collect();
+        Contents { letters: self.letters.clone(), receipts }
+    }
+
+    /// Replaces the letters and receipts with those of a backup. Readers
+    /// stay; they get what is theirs the next time they ask.
+    pub fn restore(&mut self, contents: Contents) {
+        self.letters = contents.letters;
+        self.receipts = contents
+            .receipts
+            .into_iter()
+            .map(|(sender, receipts)| {
+                (sender, receipts.into_iter().map(|(id, to, expires_at)| Receipt { id, to, expires_at }).collect())
+            })
+            .collect();
+        self.expire();
+    }
+
+    /// Drops everything to and from a deleted account.
+    pub fn remove_user(&mut self, username: &str) {
+        self.letters.remove(username);
+        self.receipts.remove(username);
+        for letters in self.letters.values_mut() {
+            letters.retain(|letter| letter.from != username);
+        }
+        self.letters.retain(|_, letters| !letters.is_empty());
+    }
+}
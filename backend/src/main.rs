@@ -1,35 +1,84 @@
+mod admin;
+mod api_keys;
+mod backup;
+mod bus;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod client_config;
+mod cluster;
+mod config;
+#[cfg(test)]
+mod conformance;
+mod db;
+mod digests;
+mod direct;
+mod encoding;
+mod event_stream;
+mod feedback;
+mod guest;
+mod ice;
+mod jwt_keys;
+mod listeners;
+mod login_limiter;
+mod mail;
+mod mailbox;
+mod maintenance;
+mod otel;
+mod profile;
+mod proxy;
+mod qr_login;
+mod room_store;
+mod rooms;
+mod sessions;
+mod telemetry;
+mod tls;
+mod totp;
+mod trending;
+mod user_store;
+mod webhooks;
+mod ws_limits;
+
 use axum::{
-    extract::{Query, State, WebSocketUpgrade},
-    extract::ws::{Message, WebSocket},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, Path, Query, State, WebSocketUpgrade},
+    extract::ws::{close_code, CloseFrame, Message, WebSocket},
+    http::{header::{AUTHORIZATION, RETRY_AFTER, SEC_WEBSOCKET_PROTOCOL, USER_AGENT}, request::Parts, HeaderMap, StatusCode},
+    response::{sse::{KeepAlive, Sse}, IntoResponse},
+    routing::{delete, get, post, put},
     Router,
     Json,
 };
-use tokio::net::TcpListener;
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use futures::{sink::SinkExt, stream::StreamExt};
-use jsonwebtoken::{decode, encode, DecodingKey, Header, EncodingKey, Validation};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
+use std::ops::ControlFlow;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use tokio::sync::{Mutex, mpsc};
 use uuid::Uuid;
 use validator::Validate;
 use tower_http::{
-    cors::CorsLayer,
     trace::TraceLayer,
     limit::RequestBodyLimitLayer,
 };
-use tracing::info;
+use tracing::{info, warn, Instrument};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Claims {
     sub: String,
+    iat: usize,
     exp: usize,
+    /// Session the token belongs to; the token stops working once it is revoked
+    sid: Uuid,
+    /// Set on guest tokens: the only room the holder may use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    room: Option<String>,
+    /// Grants the `/admin` endpoints
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    admin: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Validate)]
@@ -38,6 +87,20 @@ struct LoginRequest {
     username: String,
     #[validate(length(min = 6))]
     password: String,
+    /// TOTP or recovery code, required once the account has 2FA enabled
+    #[serde(default)]
+    totp_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QrConfirmRequest {
+    approve: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct QrRedeemRequest {
+    /// The secret handed out when the code was claimed
+    claim: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Validate)]
@@ -46,82 +109,1037 @@ struct RegisterRequest {
     username: String,
     #[validate(length(min = 6, max = 100))]
     password: String,
+    /// Only used for password reset emails
+    #[validate(email)]
+    #[serde(default)]
+    email: Option<String>,
+}
+
+/// User-editable public profile. Display names are what other peers see.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Validate)]
+struct Profile {
+    #[validate(length(min = 1, max = 40))]
+    display_name: Option<String>,
+    #[validate(url, length(max = 500))]
+    avatar_url: Option<String>,
+    #[validate(length(max = 280))]
+    bio: Option<String>,
+}
+
+/// How the web client renders for this user: text size, contrast and
+/// spacing. Stored with the account so it applies on every device.
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+struct DisplaySettings {
+    /// Text size in percent of the default
+    #[validate(range(min = 75, max = 200))]
+    font_scale: u16,
+    high_contrast: bool,
+    /// Solid backgrounds instead of translucent overlays
+    reduced_transparency: bool,
+    density: Density,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Density {
+    Compact,
+    #[default]
+    Cozy,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        DisplaySettings {
+            font_scale: 100,
+            high_contrast: false,
+            reduced_transparency: false,
+            density: Density::default(),
+        }
+    }
+}
+
+/// Free-form client settings such as theme, notification and privacy
+/// preferences, kept with the account. `version` goes up with every save,
+/// so a device saving over changes it has not seen gets a conflict instead.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct AccountSettings {
+    version: u64,
+    settings: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateSettingsRequest {
+    /// Version the client's settings are based on
+    base_version: u64,
+    settings: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Serialized size limit of a user's settings.
+const MAX_SETTINGS_BYTES: usize = 8 * 1024;
+
+/// Quick-access rooms, kept on the server so they follow the user across
+/// devices. Both lists are most recent first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Validate)]
+struct AccountRooms {
+    #[validate(length(max = 10))]
+    #[serde(default)]
+    recent: Vec<String>,
+    #[validate(length(max = 50))]
+    #[serde(default)]
+    favorites: Vec<String>,
+}
+
+const MAX_RECENT_ROOMS: usize = 10;
+const MAX_ROOM_NAME_LENGTH: usize = 64;
+
+impl AccountRooms {
+    /// Moves `room` to the front of the recent list.
+    fn visit(&mut self, room: &str) {
+        self.recent.retain(|r| r != room);
+        self.recent.insert(0, room.to_string());
+        self.recent.truncate(MAX_RECENT_ROOMS);
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ForgotPasswordRequest {
+    email: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+struct ResetPasswordRequest {
+    token: String,
+    #[validate(length(min = 6, max = 100))]
+    new_password: String,
 }
 
+/// Claims of a signed invite letting the holder into a password-protected
+/// room without the password until it expires.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RoomInviteClaims {
+    room: String,
+    exp: usize,
+    purpose: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateInviteRequest {
+    /// Hours the invite stays valid; a day if left out
+    expires_in_hours: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct InviteResponse {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// Claims of the signed, single-use token mailed for a password reset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PasswordResetClaims {
+    sub: String,
+    exp: usize,
+    jti: Uuid,
+    purpose: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+struct ChangePasswordRequest {
+    current_password: String,
+    #[validate(length(min = 6, max = 100))]
+    new_password: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+struct GuestRequest {
+    #[validate(length(min = 1, max = 64))]
+    room: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TotpConfirmRequest {
+    code: String,
+}
 
 #[derive(Clone, Debug, Deserialize)]
 struct WsQuery {
+    /// Deprecated: query strings end up in access logs and proxies
     token: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+struct SignalQuery {
+    /// Client id from the event stream's `session` event
+    session: Uuid,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 enum SignalingMessage {
-    JoinRoom { room: String },
-    Offer { room: String, sdp: String },
-    Answer { room: String, sdp: String },
-    IceCandidate { room: String, candidate: String },
+    /// Sent first by current clients; answered with a `welcome`. Clients that
+    /// skip it are served as protocol version 1.
+    Hello {
+        version: u32,
+        #[serde(default)]
+        capabilities: Vec<String>,
+        /// Binary encodings the client can read and write, see `encoding`
+        #[serde(default)]
+        encodings: Vec<String>,
+    },
+    /// Clock sync probe; answered with a `time` event carrying our clock.
+    /// `sent` is the client's own clock in milliseconds and comes back as is.
+    TimeSync { sent: f64 },
+    /// Password-protected rooms also need the `password` or an `invite` token.
+    /// A `resume` token from an earlier `peers` event gets back the place a
+    /// dropped connection held, see [`RESUME_GRACE`].
+    JoinRoom {
+        room: String,
+        #[serde(default)]
+        password: Option<String>,
+        #[serde(default)]
+        invite: Option<String>,
+        #[serde(default)]
+        resume: Option<String>,
+    },
+    /// Leaves the room, or its knock queue, keeping the connection open for
+    /// another room; answered with a `left` event
+    LeaveRoom { room: String },
+    /// Peer-to-peer negotiation, relayed to the member `to`. It may be left
+    /// out while there is only one other member.
+    Offer { room: String, sdp: String, #[serde(default)] to: Option<Uuid> },
+    Answer { room: String, sdp: String, #[serde(default)] to: Option<Uuid> },
+    IceCandidate { room: String, candidate: String, #[serde(default)] to: Option<Uuid> },
+    /// A chat payload for peers that could not connect directly, relayed
+    /// like negotiation messages. Only served when `CHAT_RELAY` is on.
+    ChatRelay { room: String, payload: String, #[serde(default)] to: Option<Uuid> },
+    /// A member's answer to someone knocking on the room
+    AnswerKnock { room: String, id: Uuid, admit: bool },
+    /// Locked rooms make newcomers knock even when there is space
+    LockRoom { room: String, locked: bool },
+    /// A moderator setting the room's topic; empty clears it. Everyone
+    /// inside gets a `room_updated` event.
+    SetRoomTopic { room: String, topic: String },
+    /// A moderator removing a member; they may come back
+    Kick { room: String, peer_id: Uuid },
+    /// A moderator banning a user for `duration` seconds, or for good
+    Ban {
+        room: String,
+        username: String,
+        #[serde(default)]
+        duration: Option<u64>,
+    },
+    /// Letters from a `mail` event that reached us; their senders get a
+    /// `mail_delivered` receipt
+    MailAck { ids: Vec<Uuid> },
+}
+
+impl SignalingMessage {
+    /// The message's `type`.
+    fn kind(&self) -> &'static str {
+        match self {
+            SignalingMessage::Hello { .. } => "Hello",
+            SignalingMessage::TimeSync { .. } => "TimeSync",
+            SignalingMessage::JoinRoom { .. } => "JoinRoom",
+            SignalingMessage::LeaveRoom { .. } => "LeaveRoom",
+            SignalingMessage::Offer { .. } => "Offer",
+            SignalingMessage::Answer { .. } => "Answer",
+            SignalingMessage::IceCandidate { .. } => "IceCandidate",
+            SignalingMessage::ChatRelay { .. } => "ChatRelay",
+            SignalingMessage::AnswerKnock { .. } => "AnswerKnock",
+            SignalingMessage::LockRoom { .. } => "LockRoom",
+            SignalingMessage::SetRoomTopic { .. } => "SetRoomTopic",
+            SignalingMessage::Kick { .. } => "Kick",
+            SignalingMessage::Ban { .. } => "Ban",
+            SignalingMessage::MailAck { .. } => "MailAck",
+        }
+    }
+
+    fn room(&self) -> Option<&str> {
+        let room = match self {
+            SignalingMessage::Hello { .. } | SignalingMessage::TimeSync { .. } | SignalingMessage::MailAck { .. } => {
+                return None
+            }
+            SignalingMessage::JoinRoom { room, .. }
+            | SignalingMessage::LeaveRoom { room }
+            | SignalingMessage::Offer { room, .. }
+            | SignalingMessage::Answer { room, .. }
+            | SignalingMessage::IceCandidate { room, .. }
+            | SignalingMessage::ChatRelay { room, .. }
+            | SignalingMessage::AnswerKnock { room, .. }
+            | SignalingMessage::LockRoom { room, .. }
+            | SignalingMessage::SetRoomTopic { room, .. }
+            | SignalingMessage::Kick { room, .. }
+            | SignalingMessage::Ban { room, .. } => room,
+        };
+        Some(room)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RoomInfo {
+    name: String,
+    owner: String,
+    /// Appointed by the owner
+    moderators: Vec<String>,
+    peers: usize,
+    capacity: usize,
+    full: bool,
+    locked: bool,
+    /// Users knocking to get in
+    waiting: usize,
+    description: String,
+    category: Option<String>,
+    tags: Vec<String>,
+    public: bool,
+    persistent: bool,
+    /// Joining takes a password or an invite
+    protected: bool,
+    /// Everyone but the owner waits to be admitted by them
+    lobby: bool,
+    /// Only the owner sends; everyone else watches
+    broadcast: bool,
+    /// Messages are kept for members' email digests
+    digests: bool,
+    /// Members watching a broadcast room now, and the most there were
+    #[serde(skip_serializing_if = "Option::is_none")]
+    viewers: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peak_viewers: Option<usize>,
+    topic: String,
+}
+
+impl RoomInfo {
+    fn new(name: &str, room: &rooms::Room) -> Self {
+        RoomInfo {
+            name: name.to_string(),
+            owner: room.owner.clone(),
+            moderators: room.moderators.clone(),
+            peers: room.peers.len(),
+            capacity: room.capacity,
+            full: room.is_full(),
+            locked: room.locked,
+            waiting: room.waiting.len(),
+            description: room.description.clone(),
+            category: room.category.clone(),
+            tags: room.tags.clone(),
+            public: room.public,
+            persistent: room.persistent,
+            protected: room.password_hash.is_some(),
+            lobby: room.lobby,
+            broadcast: room.broadcast,
+            digests: room.digests,
+            viewers: room.broadcast.then(|| room.viewers()),
+            peak_viewers: room.broadcast.then_some(room.peak_viewers),
+            topic: room.topic.clone(),
+        }
+    }
+
+    /// Whether every search term appears in the room's name, description or
+    /// tags. Terms must already be lowercase.
+    fn matches(&self, terms: &[String]) -> bool {
+        let text = format!("{} {} {}", self.name, self.description, self.tags.join(" ")).to_lowercase();
+        terms.iter().all(|term| text.contains(term.as_str()))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RoomQuery {
+    /// Space-separated terms matched against name, description and tags
+    #[serde(default)]
+    q: String,
+    tag: Option<String>,
+    category: Option<String>,
+    /// 1-based
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
+
+/// One page of the room directory, plus the tags and categories in use so
+/// the lobby can offer them as filters.
+#[derive(Debug, Serialize)]
+struct RoomPage {
+    rooms: Vec<RoomInfo>,
+    total: usize,
+    page: usize,
+    per_page: usize,
+    categories: Vec<String>,
+    tags: Vec<String>,
+    /// Largest `max_size` a new room may have
+    max_capacity: usize,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+struct CreateRoomRequest {
+    #[validate(length(min = 1, max = 64))]
+    name: String,
+    #[validate(length(max = 200))]
+    #[serde(default)]
+    description: String,
+    /// Peers allowed at once; the maximum if left out
+    max_size: Option<usize>,
+    /// Unlisted rooms stay out of `GET /rooms` and trending
+    #[serde(default = "default_public")]
+    public: bool,
+    /// Kept while empty for months rather than a day
+    #[serde(default)]
+    persistent: bool,
+    /// Everyone but the owner must give it to join
+    #[validate(length(min = 4, max = 100))]
+    password: Option<String>,
+    /// Everyone but the owner waits until the owner admits them
+    #[serde(default)]
+    lobby: bool,
+    /// The owner broadcasts, everyone else watches
+    #[serde(default)]
+    broadcast: bool,
+    /// Messages are kept on the server so members can get them by email;
+    /// public rooms only
+    #[serde(default)]
+    digests: bool,
+    /// STUN/TURN servers members use instead of ours
+    #[serde(default)]
+    ice_servers: Vec<ice::IceServer>,
+}
+
+fn default_public() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Validate)]
+struct RoomDetailsRequest {
+    #[validate(length(max = 200))]
+    #[serde(default)]
+    description: String,
+    #[validate(length(min = 1, max = 30))]
+    category: Option<String>,
+    #[validate(length(max = 5))]
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct User {
+    password_hash: String,
+    email: Option<String>,
+    totp: Option<totp::TotpState>,
+    profile: Profile,
+    rooms: AccountRooms,
+    display: DisplaySettings,
+    settings: AccountSettings,
+    /// Finished or skipped the first-login tour, on any device
+    onboarding_completed: bool,
+    /// Set by an admin; disabled accounts cannot log in
+    disabled: bool,
 }
 
+impl User {
+    fn display_name<'a>(&'a self, username: &'a str) -> &'a str {
+        self.profile.display_name.as_deref().unwrap_or(username)
+    }
+}
+
+/// A connected client's username and outgoing message queue.
+type Peer = (String, mpsc::Sender<Message>);
+/// A connected socket's session id and outgoing message queue.
+type Client = (Uuid, mpsc::Sender<Message>);
+
 #[derive(Debug, Clone)]
 struct AppState {
-    users: Arc<Mutex<HashMap<String, String>>>,
-    rooms: Arc<Mutex<HashMap<String, HashMap<Uuid, (String, tokio::sync::mpsc::Sender<Message>)>>>>,
+    users: Arc<Mutex<HashMap<String, User>>>,
+    rooms: Arc<Mutex<HashMap<String, rooms::Room>>>,
+    login_limiter: Arc<Mutex<login_limiter::LoginLimiter>>,
+    /// Connected WebSocket clients by id, so a session's sockets can be found and kicked
+    clients: Arc<Mutex<HashMap<Uuid, Client>>>,
+    /// Clients signaling over `GET /events` instead, also listed in `clients`
+    event_streams: Arc<Mutex<event_stream::EventStreams>>,
+    /// Set once the server is shutting down; new signaling connections are
+    /// turned away
+    shutting_down: Arc<AtomicBool>,
+    sessions: Arc<Mutex<sessions::SessionStore>>,
+    /// Outstanding password reset token ids, with whose they are and when
+    /// they expire; a token is consumed when used
+    password_resets: Arc<Mutex<HashMap<Uuid, (String, usize)>>>,
+    /// Logins being handed to new devices by QR code
+    qr_logins: Arc<Mutex<qr_login::QrLoginStore>>,
+    mailer: Arc<dyn mail::Mailer>,
+    jwt_keys: Arc<jwt_keys::JwtKeys>,
+    /// Whether a member stands in as moderator while a room's owner is away
+    moderator_delegation: bool,
+    /// Most peers a room can be created for
+    max_room_capacity: usize,
+    /// Whether chat may go through the server when peers cannot connect
+    /// directly. Off by default: relayed messages are readable here.
+    chat_relay: bool,
+    /// Usernames whose tokens carry the admin claim
+    admins: Arc<HashSet<String>>,
+    /// Recent joins and relays per room, for `GET /rooms/trending`
+    activity: Arc<Mutex<trending::ActivityTracker>>,
+    api_keys: Arc<Mutex<api_keys::ApiKeyStore>>,
+    feedback: Arc<Mutex<feedback::FeedbackStore>>,
+    /// STUN/TURN servers for `GET /ice-config`
+    ice: Arc<ice::IceConfig>,
+    room_retention: rooms::RetentionPolicy,
+    room_sweeps: Arc<Mutex<rooms::SweepStats>>,
+    /// Where persistent rooms are saved, if anywhere
+    room_store: Option<Arc<room_store::RoomStore>>,
+    /// Where accounts are saved, if anywhere
+    user_store: Option<Arc<user_store::UserStore>>,
+    client_config: Arc<client_config::ClientConfig>,
+    /// Operator endpoints told when rooms are created, filled and emptied
+    webhooks: Arc<webhooks::Webhooks>,
+    /// Letters waiting for users who were offline, see `POST /mailbox/:username`
+    mailbox: Arc<Mutex<mailbox::Mailbox>>,
+    /// Messages of rooms with digests and who gets them by email, see
+    /// `PUT /rooms/:name/digest`
+    digests: Arc<Mutex<digests::Digests>>,
+    /// Startup settings from the config file, environment and flags
+    config: Arc<config::Config>,
+    /// Downtime announced to clients, see `PUT /admin/maintenance`
+    maintenance: Arc<Mutex<Option<maintenance::MaintenanceWindow>>>,
+    /// Prometheus exporter behind `GET /metrics`
+    metrics: Arc<telemetry::Metrics>,
+    /// Other instances sharing rooms with this one, if any
+    cluster: Arc<cluster::Cluster>,
+    /// Misbehaviour injected into signaling, see `PUT /admin/chaos`
+    #[cfg(feature = "chaos")]
+    chaos: Arc<Mutex<chaos::ChaosSettings>>,
 }
 
-const JWT_SECRET: &str = "secret";
+const PASSWORD_RESET_PURPOSE: &str = "password_reset";
+const PASSWORD_RESET_TTL_MINUTES: i64 = 30;
+const ROOM_INVITE_PURPOSE: &str = "room_invite";
+const DEFAULT_INVITE_TTL_HOURS: i64 = 24;
+const MAX_INVITE_TTL_HOURS: i64 = 7 * 24;
 
-async fn validate_token(token: &str) -> Result<String, StatusCode> {
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(JWT_SECRET.as_ref()),
-        &Validation::default(),
-    )
-    .map_err(|_| StatusCode::UNAUTHORIZED)?;
+/// Close code sent to sockets that are disconnected by the server on purpose.
+const CLOSE_KICKED: u16 = 4001;
+/// Close code for members kicked or banned from their room.
+const CLOSE_REMOVED: u16 = 4003;
+/// Longest timed ban; longer ones should leave out the duration.
+const MAX_BAN_SECS: u64 = 365 * 24 * 60 * 60;
+/// Close code for clients speaking a protocol version we no longer serve.
+const CLOSE_UNSUPPORTED_VERSION: u16 = 4002;
+/// Signaling protocol spoken by this server, and the oldest one it still
+/// accepts in a `Hello`.
+const PROTOCOL_VERSION: u32 = 1;
+const MIN_PROTOCOL_VERSION: u32 = 1;
+/// Optional protocol features, listed in the `welcome` when the client
+/// supports them too.
+const CAPABILITIES: &[&str] = &["broadcast", "knock", "lobby", MAILBOX_CAPABILITY, "mesh", "presence", "protected_rooms", "resume", "time_sync", RELAY_CAPABILITY];
+/// Clients that negotiate it get their letters and receipts on connect.
+const MAILBOX_CAPABILITY: &str = "mailbox";
+/// Only offered when the server relays chat, see `AppState::chat_relay`.
+const RELAY_CAPABILITY: &str = "relay";
+/// How often the server pings every WebSocket.
+const HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(20);
+/// Connections that sent nothing for this long, pongs included, are treated
+/// as dead and dropped from their rooms.
+const IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+/// How long signaling clients get to finish what they are in the middle of,
+/// e.g. a handshake, once the server is shutting down.
+const SHUTDOWN_DRAIN: std::time::Duration = std::time::Duration::from_secs(5);
+/// Seconds clients are told to wait before reconnecting after a shutdown.
+const SHUTDOWN_RECONNECT_AFTER_SECS: u64 = 5;
+/// How long a member whose connection dropped keeps their place in the room
+/// for a new connection to resume; the others are told to wait that long.
+const RESUME_GRACE: std::time::Duration = std::time::Duration::from_secs(30);
+
+async fn validate_token(state: &AppState, token: &str) -> Result<Claims, StatusCode> {
+    let claims = state.jwt_keys.decode::<Claims>(token).ok_or(StatusCode::UNAUTHORIZED)?;
+    if state.sessions.lock().await.touch(&claims.sid, &claims.sub) {
+        return Ok(claims);
+    }
+    // Signed in at another instance
+    let session = state.cluster.shared_session(&claims.sub, claims.sid).await.ok_or(StatusCode::UNAUTHORIZED)?;
+    state.sessions.lock().await.adopt(claims.sid, session);
+    Ok(claims)
+}
+
+async fn issue_token(state: &AppState, username: &str, session_id: Uuid) -> String {
+    sign_token(state, username, session_id, sessions::TOKEN_TTL, None).await
+}
+
+async fn sign_token(
+    state: &AppState,
+    username: &str,
+    session_id: Uuid,
+    ttl: Duration,
+    room: Option<String>,
+) -> String {
+    let now = Utc::now();
+    let claims = Claims {
+        sub: username.to_string(),
+        iat: now.timestamp() as usize,
+        exp: (now + ttl).timestamp() as usize,
+        sid: session_id,
+        // Guests never get admin rights, whatever name they were given
+        admin: room.is_none() && state.admins.contains(username),
+        room,
+    };
+    // Keeps the session alive for as long as this token is, here and at
+    // the other instances
+    let session = {
+        let mut sessions = state.sessions.lock().await;
+        sessions.touch(&session_id, username);
+        sessions.get(&session_id).cloned()
+    };
+    if let Some(session) = session {
+        state.cluster.share_session(session_id, &session).await;
+    }
+    state.jwt_keys.encode(&claims)
+}
+
+/// Revokes all of `username`'s sessions except `keep`, at every instance, and
+/// disconnects their sockets. Without a session to keep, their API keys are
+/// revoked as well.
+async fn end_sessions(state: &AppState, username: &str, keep: Option<Uuid>, reason: &'static str) {
+    let mut ids: Vec<Uuid> =
+        state.cluster.shared_sessions(username).await.into_iter().map(|(id, _)| id).filter(|id| Some(*id) != keep).collect();
+    ids.extend(state.sessions.lock().await.remove_user(username, keep));
+    ids.sort();
+    ids.dedup();
+    state.cluster.end_sessions(username, &ids, reason).await;
+    if keep.is_none() {
+        ids.extend(state.api_keys.lock().await.remove_user(username));
+    }
+    kick_sessions(state, &ids, reason).await;
+}
+
+async fn hash_password(password: String) -> String {
+    // bcrypt is deliberately slow; keep it off the async workers
+    tokio::task::spawn_blocking(move || bcrypt::hash(password, bcrypt::DEFAULT_COST))
+        .await
+        .unwrap()
+        .expect("bcrypt hashing failed")
+}
+
+async fn verify_password(password: String, hash: String) -> bool {
+    tokio::task::spawn_blocking(move || bcrypt::verify(password, &hash).unwrap_or(false))
+        .await
+        .unwrap_or(false)
+}
+
+/// Fresh 2FA recovery codes and their hashes, see [`totp::recovery_codes`].
+async fn new_recovery_codes() -> (Vec<String>, Vec<String>) {
+    tokio::task::spawn_blocking(totp::recovery_codes).await.unwrap()
+}
+
+/// Checks a current TOTP code or else an unused recovery code, which is
+/// used up. `false` without 2FA enabled. The recovery codes are compared
+/// without holding `users`.
+async fn verify_second_factor(state: &AppState, username: &str, code: &str) -> bool {
+    let totp = state.users.lock().await.get(username).and_then(|u| u.totp.clone()).filter(|t| t.enabled);
+    let Some(totp) = totp else {
+        return false;
+    };
+    if totp.check_code(username, code) {
+        return true;
+    }
+    let hashes = totp.recovery_hashes();
+    let code = code.to_string();
+    let Ok(Some(hash)) = tokio::task::spawn_blocking(move || totp::find_recovery_code(&hashes, &code)).await else {
+        return false;
+    };
+    // Spent under the lock, so two logins cannot both use it
+    let spent =
+        state.users.lock().await.get_mut(username).and_then(|u| u.totp.as_mut()).is_some_and(|t| t.use_recovery_code(&hash));
+    if spent {
+        persist_user(state, username).await;
+    }
+    spent
+}
+
+/// `username`'s sessions here and at the other instances, most recently
+/// used first.
+async fn user_sessions(state: &AppState, username: &str) -> Vec<(Uuid, sessions::Session)> {
+    let mut list = state.sessions.lock().await.for_user(username);
+    for (id, session) in state.cluster.shared_sessions(username).await {
+        if !list.iter().any(|(known, _)| *known == id) {
+            list.push((id, session));
+        }
+    }
+    list.sort_by_key(|(_, s)| std::cmp::Reverse(s.last_seen));
+    list
+}
+
+/// Closes all of a user's WebSocket connections and drops them from their rooms.
+async fn kick_sessions(state: &AppState, session_ids: &[Uuid], reason: &str) {
+    let ids: Vec<Uuid> = state
+        .clients
+        .lock()
+        .await
+        .iter()
+        .filter(|(_, (session_id, _))| session_ids.contains(session_id))
+        .map(|(id, _)| *id)
+        .collect();
+    disconnect_clients(state, &ids, CLOSE_KICKED, reason).await;
+}
+
+/// Closes the given WebSocket connections with `code` and `reason` and drops
+/// them from their rooms.
+async fn disconnect_clients(state: &AppState, client_ids: &[Uuid], code: u16, reason: &str) {
+    let closed: Vec<_> = {
+        let mut clients = state.clients.lock().await;
+        client_ids.iter().filter_map(|id| clients.remove(id).map(|(_, tx)| (*id, tx))).collect()
+    };
+    // Clients of other instances in rooms homed here are closed through theirs
+    let closed = closed.into_iter().chain(state.cluster.take_proxies(client_ids).await);
+
+    for (client_id, tx) in closed {
+        let _ = tx.try_send(Message::Close(Some(CloseFrame {
+            code,
+            reason: reason.to_string().into(),
+        })));
+        remove_from_rooms(state, &client_id).await;
+    }
+}
+
+/// Checks an API key against `scope`. Keys of disabled accounts stop working
+/// without being revoked.
+async fn verify_api_key(state: &AppState, key: &str, scope: api_keys::Scope) -> Result<api_keys::ApiKey, StatusCode> {
+    let api_key = state.api_keys.lock().await.verify(key).ok_or(StatusCode::UNAUTHORIZED)?;
+    if !api_key.scopes.contains(&scope) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match state.users.lock().await.get(&api_key.username) {
+        Some(user) if !user.disabled => Ok(api_key),
+        Some(_) => Err(StatusCode::FORBIDDEN),
+        None => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Username of the caller, taken from an `Authorization: Bearer <JWT>` header.
+/// API keys are accepted too on routes their scope covers.
+struct AuthUser(String);
+
+/// Like [`AuthUser`], for handlers that also need the caller's session.
+/// Both reject room-scoped guest tokens, which only work for signaling, and
+/// this one rejects API keys.
+struct AuthSession {
+    username: String,
+    session_id: Uuid,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthSession {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let token = bearer_token(&parts.headers).ok_or(StatusCode::UNAUTHORIZED)?;
+        if token.starts_with(api_keys::KEY_PREFIX) {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        let claims = validate_token(state, token).await?;
+        if claims.room.is_some() {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        Ok(AuthSession {
+            username: claims.sub,
+            session_id: claims.sid,
+        })
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        if let Some(key) = bearer_token(&parts.headers).filter(|t| t.starts_with(api_keys::KEY_PREFIX)) {
+            let scope = api_keys::Scope::for_path(parts.uri.path()).ok_or(StatusCode::FORBIDDEN)?;
+            return verify_api_key(state, key, scope).await.map(|api_key| AuthUser(api_key.username));
+        }
+        AuthSession::from_request_parts(parts, state).await.map(|auth| AuthUser(auth.username))
+    }
+}
+
+/// Runtime configuration for the frontend. Public: the page needs it before
+/// anyone has logged in.
+async fn client_config(State(state): State<AppState>) -> impl IntoResponse {
+    let features = client_config::Features {
+        chat_relay: state.chat_relay,
+        moderator_delegation: state.moderator_delegation,
+        turn: state.ice.has_turn(),
+    };
+    Json(state.client_config.document(features)).into_response()
+}
+
+/// STUN and TURN servers for the caller's peer connections. Anyone who may
+/// signal gets them, guests and API keys with the signaling scope included.
+async fn ice_config(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let Some(token) = bearer_token(&headers) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    let username = if token.starts_with(api_keys::KEY_PREFIX) {
+        verify_api_key(&state, token, api_keys::Scope::Signaling).await.map(|key| key.username)
+    } else {
+        validate_token(&state, token).await.map(|claims| claims.sub)
+    };
+    match username {
+        Ok(username) => Json(state.ice.servers_for(&username)).into_response(),
+        Err(status) => status.into_response(),
+    }
+}
 
-    Ok(token_data.claims.sub)
+/// Subprotocol the server selects when the client authenticates through
+/// `Sec-WebSocket-Protocol`; browsers cannot set headers on a WebSocket.
+const WS_PROTOCOL: &str = "p2p-chat";
+/// Prefix of the subprotocol entry that carries the JWT, i.e. `bearer.<JWT>`.
+const WS_TOKEN_PROTOCOL_PREFIX: &str = "bearer.";
+
+/// Finds the JWT for a WebSocket upgrade: an `Authorization: Bearer` header,
+/// a `bearer.<JWT>` subprotocol entry, or the deprecated `?token=` parameter.
+fn ws_token(headers: &HeaderMap, query: WsQuery) -> Option<String> {
+    if let Some(token) = bearer_token(headers) {
+        return Some(token.to_string());
+    }
+    if let Some(token) = headers
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|protocol| protocol.trim().strip_prefix(WS_TOKEN_PROTOCOL_PREFIX))
+    {
+        return Some(token.to_string());
+    }
+    let token = query.token?;
+    warn!("WebSocket authenticated via ?token= query parameter, which is deprecated");
+    Some(token)
 }
 
 async fn ws_handler(
     State(state): State<AppState>,
     Query(query): Query<WsQuery>,
+    headers: HeaderMap,
     ws: WebSocketUpgrade,
 ) -> impl IntoResponse {
-    let token = if let Some(token) = query.token {
-        token
-    } else {
+    if state.shutting_down.load(Ordering::Relaxed) {
+        return shutting_down();
+    }
+    let Some(token) = ws_token(&headers, query) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    // The transport drops anything far beyond our own limit before it is
+    // buffered; handle_socket turns away the rest with a proper close code
+    let ws = ws.max_message_size(4 * ws_limits::MAX_MESSAGE_BYTES);
+    let (username, session_id, room_scope) = match signaling_identity(&state, &token).await {
+        Ok(identity) => identity,
+        Err(status) => return status.into_response(),
+    };
+
+    let client_id = Uuid::new_v4();
+    let span = connection_span(telemetry::Transport::WebSocket, client_id, &username);
+    // Select our protocol name so the token entry is never echoed back
+    ws.protocols([WS_PROTOCOL]).on_upgrade(move |socket| {
+        handle_socket(socket, state, client_id, username, session_id, room_scope).instrument(span)
+    })
+}
+
+/// The span everything a signaling connection logs happens in, so each
+/// line names the client and user it concerns.
+fn connection_span(transport: telemetry::Transport, client_id: Uuid, username: &str) -> tracing::Span {
+    tracing::info_span!("connection", transport = transport.label(), %client_id, username)
+}
+
+/// Turns away new signaling connections while the server shuts down.
+fn shutting_down() -> axum::response::Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(RETRY_AFTER, SHUTDOWN_RECONNECT_AFTER_SECS.to_string())],
+        "Server is shutting down",
+    )
+        .into_response()
+}
+
+/// Who signals with `token`: username, session and guest room. Bots connect
+/// with an API key; its id stands in for the session so revoking the key
+/// closes the connection.
+async fn signaling_identity(state: &AppState, token: &str) -> Result<(String, Uuid, Option<String>), StatusCode> {
+    if token.starts_with(api_keys::KEY_PREFIX) {
+        let key = verify_api_key(state, token, api_keys::Scope::Signaling).await?;
+        return Ok((key.username, key.id, None));
+    }
+    let claims = validate_token(state, token).await?;
+    Ok((claims.sub, claims.sid, claims.room))
+}
+
+/// Signaling over server-sent events, for networks whose proxies break
+/// WebSockets. The first event is the client id to `POST /signal` with;
+/// the rest are what the WebSocket would carry, always as JSON.
+async fn signal_events(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    if state.shutting_down.load(Ordering::Relaxed) {
+        return shutting_down();
+    }
+    let Some(token) = bearer_token(&headers) else {
         return StatusCode::UNAUTHORIZED.into_response();
     };
-    let username = match validate_token(&token).await {
-        Ok(u) => u,
+    let (username, session_id, room_scope) = match signaling_identity(&state, token).await {
+        Ok(identity) => identity,
         Err(status) => return status.into_response(),
     };
+    let client_id = Uuid::new_v4();
+    let (tx, rx) = mpsc::channel(state.config.channel_size);
+    state.clients.lock().await.insert(client_id, (session_id, tx.clone()));
+    telemetry::connection_opened(telemetry::Transport::EventStream);
+    let span = connection_span(telemetry::Transport::EventStream, client_id, &username);
+    let stream = event_stream::EventStream {
+        username,
+        session_id,
+        room_scope,
+        tx,
+        budget: ws_limits::MessageBudget::default(),
+    };
+    state.event_streams.lock().await.open(client_id, stream);
+
+    // The guard goes with the close event, or with the response when the
+    // client hangs up
+    let guard = EventStreamGuard { state, client_id, span };
+    let events = futures::stream::unfold((rx, Some(guard)), |(mut rx, guard)| async move {
+        guard.as_ref()?;
+        loop {
+            let msg = rx.recv().await?;
+            let last = matches!(msg, Message::Close(_));
+            if let Some(event) = event_stream::to_event(msg) {
+                return Some((event, (rx, guard.filter(|_| !last))));
+            }
+        }
+    });
+    let events = futures::stream::once(async move { event_stream::session_event(&client_id) }).chain(events);
+    // Buffering proxies such as nginx would hold the events back
+    (
+        [("x-accel-buffering", "no")],
+        Sse::new(events).keep_alive(KeepAlive::new().interval(HEARTBEAT_INTERVAL)),
+    )
+        .into_response()
+}
+
+/// Takes an event stream's client out of the registry once the stream ends,
+/// holding its places in rooms for a while.
+struct EventStreamGuard {
+    state: AppState,
+    client_id: Uuid,
+    /// Spans the stream's lifetime, as a WebSocket connection's does
+    span: tracing::Span,
+}
+
+impl Drop for EventStreamGuard {
+    fn drop(&mut self) {
+        let (state, client_id) = (self.state.clone(), self.client_id);
+        let span = tracing::info_span!(parent: &self.span, "disconnect");
+        tokio::spawn(
+            async move {
+                state.clients.lock().await.remove(&client_id);
+                telemetry::connection_closed(telemetry::Transport::EventStream);
+                state.event_streams.lock().await.close(&client_id);
+                state.mailbox.lock().await.close(&client_id);
+                hold_places(&state, client_id).await;
+            }
+            .instrument(span),
+        );
+    }
+}
 
-    ws.on_upgrade(move |socket| handle_socket(socket, state, username, token))
+/// A signaling message from an event stream's client, with the same limits
+/// as on a WebSocket. Whatever it leads to arrives on the stream.
+async fn post_signal(
+    State(state): State<AppState>,
+    Query(query): Query<SignalQuery>,
+    headers: HeaderMap,
+    text: String,
+) -> impl IntoResponse {
+    let Some(token) = bearer_token(&headers) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    let (username, session_id, _) = match signaling_identity(&state, token).await {
+        Ok(identity) => identity,
+        Err(status) => return status.into_response(),
+    };
+    if text.len() > ws_limits::MAX_MESSAGE_BYTES {
+        return (StatusCode::PAYLOAD_TOO_LARGE, "Message too large").into_response();
+    }
+    // Kicked clients are removed from the registry; their stream is closing
+    if !state.clients.lock().await.contains_key(&query.session) {
+        return (StatusCode::NOT_FOUND, "No such event stream").into_response();
+    }
+    let client = {
+        let mut streams = state.event_streams.lock().await;
+        let Some(stream) = streams.get_mut(&query.session, &username, &session_id) else {
+            return (StatusCode::NOT_FOUND, "No such event stream").into_response();
+        };
+        if !stream.budget.take() {
+            return (StatusCode::TOO_MANY_REQUESTS, "Too many messages").into_response();
+        }
+        SignalingClient {
+            client_id: query.session,
+            username,
+            room_scope: stream.room_scope.clone(),
+            tx: stream.tx.clone(),
+        }
+    };
+    let span = connection_span(telemetry::Transport::EventStream, client.client_id, &client.username);
+    // Messages that end the connection closed the stream already
+    let _ = handle_signal(&state, &client, &text, None).instrument(span).await;
+    StatusCode::NO_CONTENT.into_response()
 }
 
 async fn handle_socket(
     socket: WebSocket,
     state: AppState,
+    client_id: Uuid,
     username: String,
-    _token: String,
+    session_id: Uuid,
+    room_scope: Option<String>,
 ) {
     let (sink, mut stream) = socket.split();
-    let client_id = Uuid::new_v4();
-    let (tx, mut rx) = mpsc::channel(32);
+    let (tx, mut rx) = mpsc::channel(state.config.channel_size);
+    state.clients.lock().await.insert(client_id, (session_id, tx.clone()));
+    telemetry::connection_opened(telemetry::Transport::WebSocket);
+
+    // Set once the client negotiated MessagePack; everything queued as JSON
+    // goes out binary from then on
+    let binary = Arc::new(AtomicBool::new(false));
 
     // Writing task for outgoing messages
     let mut sink_for_writing = sink;
+    let binary_for_writing = binary.clone();
     let writing_task = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
+            let msg = if binary_for_writing.load(Ordering::Relaxed) { encoding::to_binary(msg) } else { msg };
             if sink_for_writing.send(msg).await.is_err() {
                 break;
             }
         }
     });
 
-    // Reading loop for incoming messages
-    while let Some(item) = stream.next().await {
+    let client = SignalingClient { client_id, username: username.clone(), room_scope, tx: tx.clone() };
+
+    // Reading loop for incoming messages. Pings keep the connection busy so
+    // one that stays silent has gone away without closing.
+    let mut heartbeat = tokio::time::interval_at(tokio::time::Instant::now() + HEARTBEAT_INTERVAL, HEARTBEAT_INTERVAL);
+    let mut last_seen = tokio::time::Instant::now();
+    let mut budget = ws_limits::MessageBudget::default();
+    loop {
+        let item = tokio::select! {
+            item = stream.next() => item,
+            _ = heartbeat.tick() => {
+                if last_seen.elapsed() >= IDLE_TIMEOUT {
+                    info!("Dropping idle connection of user: {}", username);
+                    let _ = tx.send(Message::Close(None)).await;
+                    break;
+                }
+                let _ = tx.try_send(Message::Ping(Vec::new()));
+                continue;
+            }
+        };
+        let Some(item) = item else { break };
+        last_seen = tokio::time::Instant::now();
         let msg = if let Ok(msg) = item {
             msg
         } else {
@@ -129,175 +1147,2741 @@ async fn handle_socket(
             break;
         };
 
-        if let Message::Text(text) = msg {
-            if let Ok(sig_msg) = serde_json::from_str::<SignalingMessage>(&text) {
-                match &sig_msg {
-                    SignalingMessage::JoinRoom { room } => {
-                        if join_room(&state, room.clone(), client_id, username.clone(), tx.clone()).await.is_ok() {
-                            // Success
-                        } else {
-                            let _ = tx.send(Message::Text(r#"{"type":"error","message":"Room full"}"#.to_string())).await;
-                            continue;
-                        }
-                    }
-                    SignalingMessage::Offer { room, .. } | SignalingMessage::Answer { room, .. } | SignalingMessage::IceCandidate { room, .. } => {
-                        let room_str = room.as_str();
-                        if let Some((other_id, _)) = find_other_peer(&state, room_str, &client_id).await {
-                            if let Some(other_tx) = get_sender(&state, room_str, &other_id).await {
-                                let _ = other_tx.try_send(Message::Text(text.clone()));
-                            }
-                        } else {
-                            let _ = tx.send(Message::Text(r#"{"type":"error","message":"No peer in room"}"#.to_string())).await;
-                        }
-                    }
-                }
+        let size = match &msg {
+            Message::Text(text) => Some(text.len()),
+            Message::Binary(data) => Some(data.len()),
+            _ => None,
+        };
+        if let Some(size) = size {
+            let violation = if size > ws_limits::MAX_MESSAGE_BYTES {
+                Some("Message too large")
+            } else if !budget.take() {
+                Some("Too many messages")
+            } else {
+                None
+            };
+            if let Some(reason) = violation {
+                warn!("Closing connection of user {}: {}", username, reason);
+                let _ = tx.send(Message::Close(Some(CloseFrame { code: close_code::POLICY, reason: reason.into() }))).await;
+                break;
             }
         }
+
+        // Kicked clients are removed from the registry; stop serving them
+        if !state.clients.lock().await.contains_key(&client_id) {
+            break;
+        }
+
+        #[cfg(feature = "chaos")]
+        if state.chaos.lock().await.disconnect() {
+            warn!("Chaos: cutting connection of user {}", username);
+            break;
+        }
+
+        let text = match msg {
+            Message::Text(text) => text,
+            Message::Binary(data) if binary.load(Ordering::Relaxed) => match encoding::from_binary(&data) {
+                Some(text) => text,
+                None => {
+                    notify(&tx, serde_json::json!({"type": "error", "message": "Malformed message"}));
+                    continue;
+                }
+            },
+            _ => continue,
+        };
+        if handle_signal(&state, &client, &text, Some(&binary)).await.is_break() {
+            break;
+        }
     }
 
-    remove_from_rooms(&state, &client_id).await;
+    async {
+        state.clients.lock().await.remove(&client_id);
+        telemetry::connection_closed(telemetry::Transport::WebSocket);
+        state.mailbox.lock().await.close(&client_id);
+        hold_places(&state, client_id).await;
+    }
+    .instrument(tracing::info_span!("disconnect"))
+    .await;
     drop(tx); // Close channel to stop writing task
     let _ = writing_task.await;
 }
 
-async fn join_room(
-    state: &AppState,
-    room: String,
+/// Who a signaling message comes from and where the answers go, whatever
+/// the transport.
+struct SignalingClient {
     client_id: Uuid,
     username: String,
-    tx: tokio::sync::mpsc::Sender<Message>,
-) -> Result<(), ()> {
-    let mut rooms = state.rooms.lock().await;
-    let room_peers = rooms.entry(room.clone()).or_insert_with(HashMap::new);
-    if room_peers.len() >= 2 {
-        return Err(());
-    }
-    room_peers.insert(client_id, (username, tx));
-    if room_peers.len() == 2 {
-        // Notify both or send list
-        let peers: Vec<_> = room_peers.values().map(|(u, _)| u.clone()).collect();
-        for (_, tx) in room_peers.values() {
-            let _ = tx.try_send(Message::Text(serde_json::to_string(&serde_json::json!({"type": "peers", "peers": peers})).unwrap()));
-        }
-    }
-    Ok(())
+    /// Guests may only signal in the room their token is for
+    room_scope: Option<String>,
+    tx: mpsc::Sender<Message>,
 }
 
-async fn find_other_peer(
+/// Handles one signaling message from `client`. Transports that can carry
+/// MessagePack pass `binary` to learn whether it was negotiated. Breaks
+/// when the transport should close.
+async fn handle_signal(
     state: &AppState,
-    room: &str,
-    client_id: &Uuid,
-) -> Option<(Uuid, String)> {
-    let rooms = state.rooms.lock().await;
-    if let Some(room_peers) = rooms.get(room) {
-        for (id, (u, _)) in room_peers {
-            if id != client_id {
-                return Some((*id, u.clone()));
-            }
-        }
-    }
-    None
+    client: &SignalingClient,
+    text: &str,
+    binary: Option<&AtomicBool>,
+) -> ControlFlow<()> {
+    // Named and given a room once the message is read; traces show one
+    // span per message, inside the connection's
+    let span = tracing::info_span!("signal", otel.name = "signal", room = tracing::field::Empty);
+    dispatch_signal(state, client, text, binary).instrument(span).await
 }
 
-async fn get_sender(
+async fn dispatch_signal(
     state: &AppState,
-    room: &str,
-    client_id: &Uuid,
-) -> Option<mpsc::Sender<Message>> {
-    let rooms = state.rooms.lock().await;
-    if let Some(room_peers) = rooms.get(room) {
-        if let Some((_, tx)) = room_peers.get(client_id) {
-            Some(tx.clone())
-        } else {
-            None
+    client: &SignalingClient,
+    text: &str,
+    binary: Option<&AtomicBool>,
+) -> ControlFlow<()> {
+    let SignalingClient { client_id, username, room_scope, tx } = client;
+    let sig_msg = match serde_json::from_str::<SignalingMessage>(text) {
+        Ok(sig_msg) => sig_msg,
+        Err(_) => {
+            notify(tx, serde_json::json!({"type": "error", "message": unsupported_message(text)}));
+            return ControlFlow::Continue(());
         }
-    } else {
-        None
+    };
+    if room_scope.as_deref().is_some_and(|scope| sig_msg.room().is_some_and(|room| room != scope)) {
+        notify(tx, serde_json::json!({"type": "error", "message": "Guest access is limited to one room"}));
+        return ControlFlow::Continue(());
     }
-}
-
+    let span = tracing::Span::current();
+    span.record("otel.name", sig_msg.kind());
+    if let Some(room) = sig_msg.room() {
+        span.record("room", room);
+        // Rooms homed at another instance are handled there
+        if cluster::forward(state, client, room, text).await {
+            return ControlFlow::Continue(());
+        }
+    }
+    match &sig_msg {
+        SignalingMessage::Hello { version, capabilities, encodings } => {
+            if *version < MIN_PROTOCOL_VERSION {
+                let _ = tx.send(Message::Close(Some(CloseFrame {
+                    code: CLOSE_UNSUPPORTED_VERSION,
+                    reason: "Client too old, please reload".into(),
+                }))).await;
+                return ControlFlow::Break(());
+            }
+            let shared: Vec<_> = CAPABILITIES
+                .iter()
+                .filter(|c| **c != RELAY_CAPABILITY || state.chat_relay)
+                .filter(|c| capabilities.iter().any(|own| own == *c))
+                .collect();
+            let encoding = binary.and_then(|_| encoding::negotiate(encodings));
+            notify(tx, serde_json::json!({
+                "type": "welcome",
+                "version": PROTOCOL_VERSION.min(*version),
+                "capabilities": shared,
+                "encoding": encoding,
+            }));
+            // The welcome itself may already go out binary; clients
+            // that offered an encoding read both
+            if let Some(binary) = binary {
+                binary.store(encoding.is_some(), Ordering::Relaxed);
+            }
+            // Guests have no mailbox
+            if room_scope.is_none() && shared.contains(&&MAILBOX_CAPABILITY) {
+                open_mailbox(state, *client_id, username, tx).await;
+            }
+            if let Some(window) = current_maintenance(state).await {
+                notify(tx, window.notice());
+            }
+        }
+        SignalingMessage::TimeSync { sent } => {
+            notify(tx, serde_json::json!({
+                "type": "time",
+                "sent": sent,
+                "server": Utc::now().timestamp_millis(),
+            }));
+        }
+        SignalingMessage::JoinRoom { room, password, invite, resume } => {
+            let resumed = match resume {
+                Some(token) => resume_place(state, room, token, *client_id, username, tx).await,
+                None => false,
+            };
+            let paused = !resumed && current_maintenance(state).await.is_some_and(|w| w.blocks_joins(Utc::now()));
+            if paused {
+                notify(tx, serde_json::json!({
+                    "type": "error",
+                    "message": "Joining rooms is paused for scheduled maintenance",
+                }));
+            } else if !resumed {
+                let credentials = RoomCredentials { password: password.clone(), invite: invite.clone() };
+                join_room(state, room.clone(), credentials, *client_id, username.clone(), tx.clone()).await;
+            }
+        }
+        SignalingMessage::LeaveRoom { room } => {
+            if leave_room(state, room, client_id).await {
+                notify(tx, serde_json::json!({"type": "left", "room": room}));
+            } else {
+                notify(tx, serde_json::json!({"type": "error", "message": "You are not in this room"}));
+            }
+        }
+        SignalingMessage::AnswerKnock { room, id, admit } => {
+            answer_knock(state, room, client_id, *id, *admit).await;
+        }
+        SignalingMessage::LockRoom { room, locked } => {
+            lock_room(state, room, client_id, *locked).await;
+        }
+        SignalingMessage::SetRoomTopic { room, topic } => {
+            if let Err(message) = set_room_topic(state, room, username, client_id, topic).await {
+                notify(tx, serde_json::json!({"type": "error", "message": message}));
+            }
+        }
+        SignalingMessage::Kick { room, peer_id } => {
+            if let Err((_, message)) = kick_peer(state, room, username, Some(*client_id), *peer_id).await {
+                notify(tx, serde_json::json!({"type": "error", "message": message}));
+            }
+        }
+        SignalingMessage::Ban { room, username: target, duration } => {
+            if let Err((_, message)) = ban_user(state, room, username, Some(*client_id), target, *duration).await {
+                notify(tx, serde_json::json!({"type": "error", "message": message}));
+            }
+        }
+        SignalingMessage::MailAck { ids } => {
+            let receipts = state.mailbox.lock().await.acknowledge(username, ids);
+            for (sender, receipt) in receipts {
+                let event = serde_json::json!({"type": "mail_delivered", "receipts": [&receipt]});
+                if !send_to_readers(state, &sender, event).await {
+                    state.mailbox.lock().await.keep_receipt(&sender, receipt);
+                }
+            }
+        }
+        SignalingMessage::ChatRelay { .. } if !state.chat_relay => {
+            notify(tx, serde_json::json!({"type": "error", "message": "This server does not relay chat"}));
+        }
+        SignalingMessage::Offer { room, to, .. }
+        | SignalingMessage::Answer { room, to, .. }
+        | SignalingMessage::IceCandidate { room, to, .. }
+        | SignalingMessage::ChatRelay { room, to, .. } => {
+            let kind = sig_msg.kind();
+            match relay_target(state, room, client_id, *to, kind == "Offer").await {
+                Ok(peer_tx) => {
+                    // The receiver needs to know whom to answer
+                    let mut relayed: serde_json::Value = serde_json::from_str(text).unwrap_or_default();
+                    relayed["from"] = serde_json::json!(client_id);
+                    let relayed = Message::Text(relayed.to_string());
+                    #[cfg(feature = "chaos")]
+                    state.chaos.lock().await.deliver(&peer_tx, relayed);
+                    #[cfg(not(feature = "chaos"))]
+                    let _ = peer_tx.try_send(relayed);
+                    state.activity.lock().await.record_relay(room, text.len());
+                    telemetry::relayed(kind);
+                }
+                Err(message) => {
+                    let _ = tx.send(Message::Text(serde_json::json!({"type": "error", "message": message}).to_string())).await;
+                }
+            }
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+/// Why a text frame could not be handled, naming its type if it has one.
+fn unsupported_message(text: &str) -> String {
+    let parsed: Option<serde_json::Value> = serde_json::from_str(text).ok();
+    match parsed.as_ref().and_then(|v| v["type"].as_str()) {
+        Some(kind) => format!("Unsupported or malformed message of type {}", kind),
+        None => "Malformed message".to_string(),
+    }
+}
+
+fn notify(tx: &mpsc::Sender<Message>, event: serde_json::Value) {
+    let _ = tx.try_send(Message::Text(event.to_string()));
+}
+
+fn display_name(users: &HashMap<String, User>, username: &str) -> String {
+    users.get(username).map_or(username, |user| user.display_name(username)).to_string()
+}
+
+/// Sends every member the current membership, oldest member first, along
+/// with their own peer id. Ids are stable for as long as a connection stays
+/// in the room, so clients can pair up: whoever joined later makes the offer.
+/// Members come with their username too, which clients key petnames by.
+/// Members whose connection dropped are marked `away`. Each member gets their
+/// own `resume_token`. Rooms with their own STUN/TURN servers send them along
+/// as `ice_servers`; broadcast rooms name their `broadcaster` and count the
+/// `viewers`.
+fn announce_peers(name: &str, room: &rooms::Room, users: &HashMap<String, User>) {
+    let peers: Vec<_> = room
+        .members()
+        .map(|(id, (u, _))| {
+            let mut peer = serde_json::json!({"id": id, "name": display_name(users, u), "username": u});
+            if room.is_away(&id) {
+                peer["away"] = serde_json::json!(true);
+            }
+            peer
+        })
+        .collect();
+    for (id, (_, tx)) in &room.peers {
+        let mut event = serde_json::json!({
+            "type": "peers",
+            "room": name,
+            "you": id,
+            "peers": peers,
+            "resume_token": room.resume_token(id),
+        });
+        if !room.ice_servers.is_empty() {
+            event["ice_servers"] = serde_json::json!(room.ice_servers);
+        }
+        if room.broadcast {
+            event["broadcaster"] = serde_json::json!(room.broadcaster());
+            event["viewers"] = serde_json::json!(room.viewers());
+        }
+        if room.digests {
+            event["digests"] = serde_json::json!(true);
+        }
+        notify(tx, event);
+    }
+}
+
+/// Tells the other members that `id` entered (`peer_joined`) or left
+/// (`peer_left`) the room, also when their connection dropped and they did
+/// not resume in time.
+fn announce_presence(name: &str, room: &rooms::Room, users: &HashMap<String, User>, event: &str, id: Uuid, username: &str) {
+    let peer_name = display_name(users, username);
+    for (peer_id, (_, tx)) in &room.peers {
+        if *peer_id != id {
+            notify(tx, serde_json::json!({"type": event, "room": name, "id": id, "name": peer_name, "username": username}));
+        }
+    }
+}
+
+/// Tells every member who moderates the room, and whether that is a
+/// stand-in for the absent owner.
+fn announce_moderator(name: &str, room: &rooms::Room, users: &HashMap<String, User>) {
+    let Some((moderator, temporary)) = room.moderator() else { return };
+    let moderator_name = display_name(users, &room.peers[&moderator].0);
+    for (id, (_, tx)) in &room.peers {
+        notify(tx, serde_json::json!({
+            "type": "moderator",
+            "room": name,
+            "name": moderator_name,
+            "temporary": temporary,
+            "you": room.is_moderator(id),
+        }));
+    }
+}
+
+/// Lets in whoever may enter after the room changed and tells the rest of the
+/// queue where they stand.
+async fn settle_waiting(state: &AppState, name: &str, room: &mut rooms::Room, users: &HashMap<String, User>) {
+    let was_empty = room.peers.is_empty();
+    let entered = room.let_in_waiting();
+    if let Some(first) = entered.first().filter(|_| was_empty) {
+        state.webhooks.send(webhooks::RoomEvent::FirstPeerJoined, name, serde_json::json!({"username": first.username}));
+    }
+    for knock in &entered {
+        notify(&knock.tx, serde_json::json!({"type": "admitted", "room": name}));
+        if !room.topic.is_empty() {
+            notify(&knock.tx, room_updated(name, room, None));
+        }
+        if room.lobby && knock.username == room.owner {
+            brief_owner(name, room, users, &knock.tx);
+        }
+        state.activity.lock().await.record_join(name, room.peers.len());
+        announce_presence(name, room, users, "peer_joined", knock.client_id, &knock.username);
+    }
+    if !entered.is_empty() {
+        room.update_delegate(state.moderator_delegation);
+        announce_moderator(name, room, users);
+        announce_peers(name, room, users);
+    }
+    for (i, knock) in room.waiting.iter().enumerate() {
+        notify(&knock.tx, serde_json::json!({"type": "waiting", "room": name, "position": i + 1}));
+    }
+}
+
+/// What a `JoinRoom` brought to get into a password-protected room.
+struct RoomCredentials {
+    password: Option<String>,
+    invite: Option<String>,
+}
+
+/// Whether the credentials open the protected room `room`: an unexpired
+/// invite for it, or the right password.
+async fn check_room_credentials(keys: &jwt_keys::JwtKeys, room: &str, hash: String, credentials: RoomCredentials) -> bool {
+    let invited = credentials
+        .invite
+        .and_then(|token| keys.decode::<RoomInviteClaims>(&token))
+        .is_some_and(|claims| claims.purpose == ROOM_INVITE_PURPOSE && claims.room == room);
+    match credentials.password {
+        _ if invited => true,
+        Some(password) => verify_password(password, hash).await,
+        None => false,
+    }
+}
+
+/// Asks the owner of a lobby room, on each of their clients inside, to admit
+/// `knock`. Nobody is asked while the owner is away; they get the whole
+/// queue from [`brief_owner`] when they come in.
+fn request_admission(name: &str, room: &rooms::Room, users: &HashMap<String, User>, knock: &rooms::Knock) {
+    let display = display_name(users, &knock.username);
+    for (_, (_, tx)) in room.owner_clients() {
+        notify(tx, serde_json::json!({"type": "admit_request", "room": name, "id": knock.client_id, "name": display}));
+    }
+}
+
+/// Sends the owner who just entered a lobby room an `admit_request` for
+/// everyone still waiting to be admitted.
+fn brief_owner(name: &str, room: &rooms::Room, users: &HashMap<String, User>, tx: &tokio::sync::mpsc::Sender<Message>) {
+    for knock in room.waiting.iter().filter(|k| !k.admitted) {
+        let display = display_name(users, &knock.username);
+        notify(tx, serde_json::json!({"type": "admit_request", "room": name, "id": knock.client_id, "name": display}));
+    }
+}
+
+/// Joins the room, or knocks if it is full or locked: the members are asked
+/// to admit the newcomer, who waits in a queue meanwhile. In a lobby room
+/// everyone but the owner knocks, and only the owner is asked. Protected
+/// rooms turn away anyone but staff without valid credentials.
+async fn join_room(
+    state: &AppState,
+    room: String,
+    credentials: RoomCredentials,
+    client_id: Uuid,
+    username: String,
+    tx: tokio::sync::mpsc::Sender<Message>,
+) {
+    let protected = match state.rooms.lock().await.get(&room) {
+        Some(entry) if !entry.admits(&username) => {
+            notify(&tx, serde_json::json!({"type": "error", "message": "This room is private"}));
+            return;
+        }
+        Some(entry) if entry.is_banned(&username) => {
+            notify(&tx, serde_json::json!({"type": "banned", "room": room, "until": entry.bans[&username]}));
+            return;
+        }
+        Some(entry) if !entry.is_staff(&username) => entry.password_hash.clone(),
+        _ => None,
+    };
+    if let Some(hash) = protected {
+        // Checked without holding the rooms lock, bcrypt takes a while
+        let attempted = credentials.password.is_some() || credentials.invite.is_some();
+        if !check_room_credentials(&state.jwt_keys, &room, hash, credentials).await {
+            notify(&tx, serde_json::json!({"type": "password_required", "room": room, "rejected": attempted}));
+            return;
+        }
+    }
+
+    let mut rooms = state.rooms.lock().await;
+    let Some(entry) = rooms.get_mut(&room) else {
+        notify(&tx, serde_json::json!({"type": "error", "message": "No such room"}));
+        return;
+    };
+    if entry.peers.contains_key(&client_id) || entry.position(&client_id).is_some() {
+        return;
+    }
+    let mut users = state.users.lock().await;
+    if let Some(user) = users.get_mut(&username) {
+        user.rooms.visit(&room);
+        // Saved once the locks held here are released
+        let (state, username) = (state.clone(), username.clone());
+        tokio::spawn(async move { persist_user(&state, &username).await });
+    }
+
+    if entry.needs_knock(&username) {
+        if entry.is_full() {
+            notify(&tx, serde_json::json!({"type": "room_full", "room": room, "limit": entry.capacity}));
+        }
+        // The owner of a lobby room only ever waits for space
+        let admitted = entry.lobby && username == entry.owner;
+        entry.waiting.push_back(rooms::Knock {
+            client_id,
+            username: username.clone(),
+            tx: tx.clone(),
+            admitted,
+        });
+        notify(&tx, serde_json::json!({"type": "waiting", "room": room, "position": entry.waiting.len()}));
+        if admitted {
+            return;
+        }
+        if entry.lobby {
+            request_admission(&room, entry, &users, entry.waiting.back().expect("just queued"));
+            return;
+        }
+        let name = display_name(&users, &username);
+        for (_, peer_tx) in entry.peers.values() {
+            notify(peer_tx, serde_json::json!({"type": "knock", "room": room, "id": client_id, "name": name}));
+        }
+        return;
+    }
+
+    if entry.lobby && username == entry.owner {
+        brief_owner(&room, entry, &users, &tx);
+    }
+    if !entry.topic.is_empty() {
+        notify(&tx, room_updated(&room, entry, None));
+    }
+    entry.add_peer(client_id, (username.clone(), tx));
+    if entry.peers.len() == 1 {
+        state.webhooks.send(webhooks::RoomEvent::FirstPeerJoined, &room, serde_json::json!({"username": username}));
+    }
+    state.activity.lock().await.record_join(&room, entry.peers.len());
+    announce_presence(&room, entry, &users, "peer_joined", client_id, &username);
+    entry.update_delegate(state.moderator_delegation);
+    announce_moderator(&room, entry, &users);
+    announce_peers(&room, entry, &users);
+}
+
+async fn answer_knock(state: &AppState, room: &str, client_id: &Uuid, knock_id: Uuid, admit: bool) {
+    let mut rooms = state.rooms.lock().await;
+    let Some(entry) = rooms.get_mut(room).filter(|r| r.may_admit(client_id)) else {
+        return;
+    };
+    if admit {
+        let Some(knock) = entry.waiting.iter_mut().find(|k| k.client_id == knock_id) else { return };
+        knock.admitted = true;
+    } else {
+        let Some(knock) = entry.remove_knock(&knock_id) else { return };
+        notify(&knock.tx, serde_json::json!({"type": "knock_denied", "room": room}));
+    }
+    for (_, tx) in entry.peers.values() {
+        notify(tx, serde_json::json!({"type": "knock_resolved", "room": room, "id": knock_id, "admitted": admit}));
+    }
+    let users = state.users.lock().await;
+    settle_waiting(state, room, entry, &users).await;
+}
+
+/// Longest room topic, in characters.
+const MAX_TOPIC_LENGTH: usize = 200;
+
+/// The `room_updated` event for the room's current details; `by` is whoever
+/// just changed them, left out when catching up a newcomer.
+fn room_updated(name: &str, room: &rooms::Room, by: Option<&str>) -> serde_json::Value {
+    serde_json::json!({"type": "room_updated", "room": name, "topic": room.topic, "by": by})
+}
+
+async fn set_room_topic(state: &AppState, room: &str, username: &str, client_id: &Uuid, topic: &str) -> Result<(), &'static str> {
+    let topic = topic.trim();
+    if topic.chars().count() > MAX_TOPIC_LENGTH || topic.chars().any(char::is_control) {
+        return Err("Topics are at most 200 characters on one line");
+    }
+    let mut rooms = state.rooms.lock().await;
+    let entry = rooms.get_mut(room).ok_or("No such room")?;
+    if !entry.is_moderator(client_id) {
+        return Err("Only moderators can set the topic");
+    }
+    entry.topic = topic.to_string();
+    let by = display_name(&*state.users.lock().await, username);
+    let event = room_updated(room, entry, Some(&by));
+    for (_, tx) in entry.peers.values() {
+        notify(tx, event.clone());
+    }
+    let persistent = entry.persistent;
+    drop(rooms);
+    if persistent {
+        persist_rooms(state).await;
+    }
+    Ok(())
+}
+
+async fn lock_room(state: &AppState, room: &str, client_id: &Uuid, locked: bool) {
+    let mut rooms = state.rooms.lock().await;
+    let Some(entry) = rooms.get_mut(room).filter(|r| r.is_moderator(client_id)) else {
+        return;
+    };
+    entry.locked = locked;
+    for (_, tx) in entry.peers.values() {
+        notify(tx, serde_json::json!({"type": "room_locked", "room": room, "locked": locked}));
+    }
+    if !locked {
+        let users = state.users.lock().await;
+        settle_waiting(state, room, entry, &users).await;
+    }
+}
+
+/// Whether `actor` moderates the room: through the connection `actor_client`
+/// when acting over signaling, or as a user when acting over REST.
+fn moderates(entry: &rooms::Room, actor: &str, actor_client: Option<Uuid>) -> bool {
+    match actor_client {
+        Some(id) => entry.is_moderator(&id),
+        None => entry.can_edit(actor),
+    }
+}
+
+/// Takes the member `peer_id` out of the room and tells them with `kicked`.
+/// Their connection stays up for their other rooms, and they may come back
+/// unless they are also banned.
+async fn kick_peer(
+    state: &AppState,
+    room: &str,
+    actor: &str,
+    actor_client: Option<Uuid>,
+    peer_id: Uuid,
+) -> Result<(), (StatusCode, &'static str)> {
+    let mut rooms = state.rooms.lock().await;
+    let entry = rooms.get_mut(room).ok_or((StatusCode::NOT_FOUND, "No such room"))?;
+    let (target, tx) = entry.peers.get(&peer_id).cloned().ok_or((StatusCode::NOT_FOUND, "No such peer in room"))?;
+    if let Some(denied) = entry.removal_denied(actor, moderates(entry, actor, actor_client), &target) {
+        return Err((StatusCode::FORBIDDEN, denied));
+    }
+    notify(&tx, serde_json::json!({"type": "kicked", "room": room}));
+    let users = state.users.lock().await;
+    remove_from_room(state, room, entry, &users, &peer_id).await;
+    info!("User {} removed from room {} by {}", target, room, actor);
+    Ok(())
+}
+
+/// Bans `target` from the room for `duration` seconds, or for good, and
+/// closes their connections to it. Returns when the ban ends.
+async fn ban_user(
+    state: &AppState,
+    room: &str,
+    actor: &str,
+    actor_client: Option<Uuid>,
+    target: &str,
+    duration: Option<u64>,
+) -> Result<Option<DateTime<Utc>>, (StatusCode, &'static str)> {
+    if duration.is_some_and(|secs| secs == 0 || secs > MAX_BAN_SECS) {
+        return Err((StatusCode::BAD_REQUEST, "Bans last between a second and a year; leave out the duration to ban for good"));
+    }
+    let until = duration.map(|secs| Utc::now() + Duration::seconds(secs as i64));
+    let (clients, persistent) = {
+        let mut rooms = state.rooms.lock().await;
+        let entry = rooms.get_mut(room).ok_or((StatusCode::NOT_FOUND, "No such room"))?;
+        if let Some(denied) = entry.removal_denied(actor, moderates(entry, actor, actor_client), target) {
+            return Err((StatusCode::FORBIDDEN, denied));
+        }
+        entry.forget_expired_bans();
+        entry.bans.insert(target.to_string(), until);
+        (entry.clients_of(target), entry.persistent)
+    };
+    if persistent {
+        persist_rooms(state).await;
+    }
+    disconnect_clients(state, &clients, CLOSE_REMOVED, &format!("Banned from room {}", room)).await;
+    info!("User {} banned from room {} by {} until {:?}", target, room, actor, until);
+    Ok(until)
+}
+
+/// Where a negotiation message from `from` goes: the member `to`, or the
+/// only other member when `to` is left out. In broadcast rooms only the
+/// broadcaster makes offers, and viewers only signal the broadcaster, which
+/// is also where their messages without `to` go.
+async fn relay_target(
+    state: &AppState,
+    room: &str,
+    from: &Uuid,
+    to: Option<Uuid>,
+    offer: bool,
+) -> Result<mpsc::Sender<Message>, &'static str> {
+    let rooms = state.rooms.lock().await;
+    let entry = rooms.get(room).filter(|r| r.peers.contains_key(from)).ok_or("Not in room")?;
+    let broadcaster = entry.broadcaster();
+    if entry.broadcast && broadcaster != Some(*from) {
+        if offer {
+            return Err("Only the broadcaster makes offers in a broadcast room");
+        }
+        if to.is_some() && to != broadcaster {
+            return Err("Viewers only signal the broadcaster");
+        }
+    }
+    let target = match to {
+        Some(id) if id == *from => return Err("Cannot signal yourself"),
+        Some(id) => id,
+        None if entry.broadcast && broadcaster != Some(*from) => broadcaster.ok_or("The broadcaster is not here")?,
+        None => {
+            let mut others = entry.peers.keys().filter(|id| *id != from);
+            match (others.next(), others.next()) {
+                (Some(id), None) => *id,
+                (None, _) => return Err("No peer in room"),
+                (Some(_), Some(_)) => return Err("Several peers in room, name one with \"to\""),
+            }
+        }
+    };
+    if entry.is_away(&target) {
+        return Err("That peer lost their connection and may be back shortly");
+    }
+    entry.peers.get(&target).map(|(_, tx)| tx.clone()).ok_or("No such peer in room")
+}
+
+/// After the client's connection dropped, holds its places in rooms for
+/// [`RESUME_GRACE`] and tells the other members to wait for it with a
+/// `peer_away` event. Places nobody resumed in time are given up as if the
+/// client had left; waiting queues it gives up right away. Instances homing
+/// rooms it signaled in through this one hold its places there alike.
+async fn hold_places(state: &AppState, client_id: Uuid) {
+    state.cluster.client_gone(client_id).await;
+    {
+        let mut rooms = state.rooms.lock().await;
+        let users = state.users.lock().await;
+        for (name, entry) in rooms.iter_mut() {
+            if !entry.hold(&client_id) {
+                remove_from_room(state, name, entry, &users, &client_id).await;
+                continue;
+            }
+            let peer_name = display_name(&users, &entry.peers[&client_id].0);
+            for (peer_id, (_, tx)) in &entry.peers {
+                if *peer_id != client_id {
+                    notify(tx, serde_json::json!({
+                        "type": "peer_away",
+                        "room": name,
+                        "id": client_id,
+                        "name": peer_name,
+                        "grace": RESUME_GRACE.as_secs(),
+                    }));
+                }
+            }
+            announce_peers(name, entry, &users);
+        }
+    }
+    let state = state.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(RESUME_GRACE).await;
+        // Resumed places carry the new connection's id by now
+        remove_from_rooms(&state, &client_id).await;
+    });
+}
+
+/// Gives the client the place its resume `token` was issued for in `room`,
+/// telling the others with `peer_resumed` which id it had before; `false`
+/// if the token does not hold a place there, and the client should join
+/// normally. The connection the place had, if it is still open, is told it
+/// `left`.
+async fn resume_place(
+    state: &AppState,
+    room: &str,
+    token: &str,
+    client_id: Uuid,
+    username: &str,
+    tx: &mpsc::Sender<Message>,
+) -> bool {
+    let mut rooms = state.rooms.lock().await;
+    let Some(entry) = rooms.get_mut(room).filter(|r| !r.peers.contains_key(&client_id)) else {
+        return false;
+    };
+    let Some((previous, (_, replaced))) = entry.resume(token, client_id, (username.to_string(), tx.clone())) else {
+        return false;
+    };
+    info!("User {} resumed their place in room {}", username, room);
+    notify(&replaced, serde_json::json!({"type": "left", "room": room}));
+    if !entry.topic.is_empty() {
+        notify(tx, room_updated(room, entry, None));
+    }
+    let users = state.users.lock().await;
+    let peer_name = display_name(&users, username);
+    for (peer_id, (_, peer_tx)) in &entry.peers {
+        if *peer_id != client_id {
+            notify(peer_tx, serde_json::json!({
+                "type": "peer_resumed",
+                "room": room,
+                "id": client_id,
+                "previous": previous,
+                "name": peer_name,
+            }));
+        }
+    }
+    announce_moderator(room, entry, &users);
+    announce_peers(room, entry, &users);
+    true
+}
+
+/// Takes the client out of every room and waiting queue, letting the next
+/// waiting user in where a place opened up.
 async fn remove_from_rooms(state: &AppState, client_id: &Uuid) {
     let mut rooms = state.rooms.lock().await;
-    rooms.retain(|_, peers| {
-        peers.retain(|id, _| id != client_id);
-        !peers.is_empty()
+    let users = state.users.lock().await;
+    for (name, entry) in rooms.iter_mut() {
+        remove_from_room(state, name, entry, &users, client_id).await;
+    }
+}
+
+/// Takes the client out of one room on its request; `false` if it was
+/// neither in the room nor waiting to get in.
+async fn leave_room(state: &AppState, name: &str, client_id: &Uuid) -> bool {
+    let mut rooms = state.rooms.lock().await;
+    let users = state.users.lock().await;
+    match rooms.get_mut(name) {
+        Some(entry) => remove_from_room(state, name, entry, &users, client_id).await,
+        None => false,
+    }
+}
+
+/// Removes the client from the room's members or its knock queue and tells
+/// whoever is still there; `false` if it was in neither.
+async fn remove_from_room(
+    state: &AppState,
+    name: &str,
+    entry: &mut rooms::Room,
+    users: &HashMap<String, User>,
+    client_id: &Uuid,
+) -> bool {
+    let left = entry.remove_peer(client_id);
+    if let Some((username, _)) = &left {
+        announce_presence(name, entry, users, "peer_left", *client_id, username);
+        if entry.peers.is_empty() {
+            state.webhooks.send(webhooks::RoomEvent::Emptied, name, serde_json::json!({"last_username": username}));
+        }
+    }
+    let left = left.is_some();
+    let gave_up = entry.remove_knock(client_id).is_some();
+    if gave_up {
+        for (_, tx) in entry.peers.values() {
+            notify(tx, serde_json::json!({"type": "knock_resolved", "room": name, "id": client_id, "admitted": false}));
+        }
+    }
+    if left {
+        // Hands moderation to a stand-in if the owner just left
+        entry.update_delegate(state.moderator_delegation);
+        announce_moderator(name, entry, users);
+        announce_peers(name, entry, users);
+    }
+    if left || gave_up {
+        settle_waiting(state, name, entry, users).await;
+    }
+    left || gave_up
+}
+
+#[derive(Debug, Serialize)]
+struct TrendingRoom {
+    name: String,
+    /// Joins and relayed signaling messages over the last hour
+    joins: usize,
+    relays: usize,
+    peers: usize,
+    capacity: usize,
+    category: Option<String>,
+}
+
+const TRENDING_LIMIT: usize = 10;
+
+/// Public rooms with the most activity over the last hour, busiest first.
+/// Locked and unlisted rooms are left out.
+async fn trending_rooms(State(state): State<AppState>, AuthUser(_): AuthUser) -> impl IntoResponse {
+    let scores = state.activity.lock().await.trending();
+    let rooms = state.rooms.lock().await;
+    let list: Vec<TrendingRoom> = scores
+        .into_iter()
+        .filter_map(|score| rooms.get(&score.name).filter(|r| r.public && !r.locked).map(|entry| (score, entry)))
+        .take(TRENDING_LIMIT)
+        .map(|(score, entry)| TrendingRoom {
+            peers: entry.peers.len(),
+            capacity: entry.capacity,
+            category: entry.category.clone(),
+            name: score.name,
+            joins: score.joins,
+            relays: score.relays,
+        })
+        .collect();
+    Json(list)
+}
+
+const ROOMS_PER_PAGE: usize = 20;
+const MAX_ROOMS_PER_PAGE: usize = 100;
+const MAX_TAG_LENGTH: usize = 20;
+
+/// The room directory: occupancy of public rooms, so the lobby can show which
+/// are full before the user tries to join, filtered by search terms, tag and
+/// category and paginated.
+async fn list_rooms(
+    State(state): State<AppState>,
+    AuthUser(_): AuthUser,
+    Query(query): Query<RoomQuery>,
+) -> impl IntoResponse {
+    let terms: Vec<String> = query.q.split_whitespace().map(str::to_lowercase).collect();
+    let tag = query.tag.map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty());
+    let category = query.category.filter(|c| !c.is_empty());
+    let per_page = query.per_page.unwrap_or(ROOMS_PER_PAGE).clamp(1, MAX_ROOMS_PER_PAGE);
+    let page = query.page.unwrap_or(1).max(1);
+
+    // Unlisted rooms are only reachable by name
+    let mut public: Vec<RoomInfo> =
+        state.rooms.lock().await.iter().filter(|(_, r)| r.public).map(|(name, r)| RoomInfo::new(name, r)).collect();
+    // Those homed at other instances, unless one of ours has the name
+    for info in state.cluster.directory().await {
+        if !public.iter().any(|known| known.name == info.name) {
+            public.push(info);
+        }
+    }
+    let mut categories: Vec<String> = public.iter().filter_map(|r| r.category.clone()).collect();
+    categories.sort();
+    categories.dedup();
+    let mut tags: Vec<String> = public.iter().flat_map(|r| r.tags.iter().cloned()).collect();
+    tags.sort();
+    tags.dedup();
+
+    let mut list: Vec<RoomInfo> = public
+        .into_iter()
+        .filter(|entry| {
+            entry.matches(&terms)
+                && tag.iter().all(|t| entry.tags.contains(t))
+                && category.iter().all(|c| entry.category.as_ref() == Some(c))
+        })
+        .collect();
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let total = list.len();
+    let rooms = list.into_iter().skip((page - 1) * per_page).take(per_page).collect();
+    Json(RoomPage { rooms, total, page, per_page, categories, tags, max_capacity: state.max_room_capacity })
+}
+
+/// Creates a room owned by the caller. Rooms must exist before anyone can
+/// join them and stay around when empty, until the owner deletes them.
+async fn create_room(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Json(payload): Json<CreateRoomRequest>,
+) -> impl IntoResponse {
+    if let Err(errors) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, format!("Validation error: {:?}", errors)).into_response();
+    }
+    let name = payload.name.trim().to_string();
+    // Names end up in URL paths like /chat/<name> and /rooms/<name>
+    if name.is_empty() || name.contains('/') || name.chars().any(char::is_control) {
+        return (StatusCode::BAD_REQUEST, "Room names cannot be blank or contain '/'").into_response();
+    }
+    if name.starts_with(direct::ROOM_PREFIX) {
+        return (StatusCode::BAD_REQUEST, format!("Room names starting with {} are for direct messages", direct::ROOM_PREFIX))
+            .into_response();
+    }
+    let ceiling = state.max_room_capacity;
+    let capacity = payload.max_size.unwrap_or(rooms::DEFAULT_MAX_ROOM_CAPACITY.min(ceiling));
+    if !(rooms::MIN_ROOM_CAPACITY..=ceiling).contains(&capacity) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Rooms hold between {} and {} peers", rooms::MIN_ROOM_CAPACITY, ceiling),
+        )
+            .into_response();
+    }
+
+    if payload.digests && !payload.public {
+        return (StatusCode::BAD_REQUEST, "Only public rooms can have email digests").into_response();
+    }
+    if payload.ice_servers.len() > ice::MAX_ROOM_ICE_SERVERS {
+        return (StatusCode::BAD_REQUEST, format!("Rooms bring at most {} ICE servers", ice::MAX_ROOM_ICE_SERVERS))
+            .into_response();
+    }
+    if let Err(message) = payload.ice_servers.iter().try_for_each(ice::IceServer::check) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+
+    let password_hash = match payload.password {
+        Some(password) => Some(hash_password(password).await),
+        None => None,
+    };
+
+    // Registered before taking the rooms lock, which a slow bus would hold
+    // for every other request meanwhile
+    match state.cluster.claim(&name).await {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::CONFLICT, "Room already exists").into_response(),
+        Err(message) => return (StatusCode::SERVICE_UNAVAILABLE, message).into_response(),
+    }
+    let mut rooms = state.rooms.lock().await;
+    if rooms.contains_key(&name) {
+        // Ours already: the claim renewed it for that room, so it stays
+        return (StatusCode::CONFLICT, "Room already exists").into_response();
+    }
+    let mut room = rooms::Room::new(username.clone(), capacity);
+    room.description = payload.description.trim().to_string();
+    room.public = payload.public;
+    room.persistent = payload.persistent;
+    room.password_hash = password_hash;
+    room.lobby = payload.lobby;
+    room.broadcast = payload.broadcast;
+    room.digests = payload.digests;
+    room.ice_servers = payload.ice_servers;
+    let info = RoomInfo::new(&name, &room);
+    rooms.insert(name.clone(), room);
+    drop(rooms);
+    info!("Room {} created by user: {}", name, username);
+    state.webhooks.send(
+        webhooks::RoomEvent::Created,
+        &name,
+        serde_json::json!({"owner": username, "public": info.public, "capacity": info.capacity}),
+    );
+    if info.public {
+        state.cluster.list_room(&info).await;
+    }
+    if info.persistent {
+        persist_rooms(&state).await;
+    }
+    (StatusCode::CREATED, Json(info)).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct UserQuery {
+    #[serde(default)]
+    query: String,
+}
+
+/// A user as the directory lists them.
+#[derive(Debug, Serialize)]
+struct UserSummary {
+    username: String,
+    display_name: Option<String>,
+    avatar_url: Option<String>,
+}
+
+/// Users whose username or display name contains the query, to start a
+/// direct conversation with. Disabled accounts and the caller are left out.
+async fn search_users(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Query(query): Query<UserQuery>,
+) -> impl IntoResponse {
+    let query = query.query.trim().to_lowercase();
+    if query.chars().count() < direct::MIN_QUERY_LENGTH {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Search for at least {} characters", direct::MIN_QUERY_LENGTH),
+        )
+            .into_response();
+    }
+    let users = state.users.lock().await;
+    let mut found: Vec<UserSummary> = users
+        .iter()
+        .filter(|(name, user)| {
+            **name != username && !user.disabled && direct::matches(&query, name, user.profile.display_name.as_deref())
+        })
+        .map(|(name, user)| UserSummary {
+            username: name.clone(),
+            display_name: user.profile.display_name.clone(),
+            avatar_url: user.profile.avatar_url.clone(),
+        })
+        .collect();
+    found.sort_by(|a, b| a.username.cmp(&b.username));
+    found.truncate(direct::SEARCH_LIMIT);
+    Json(found).into_response()
+}
+
+/// The caller's direct-message room with `other`, created the first time
+/// either of them asks. Only the two of them may join; both moderate it,
+/// and it is kept while empty like a persistent room.
+async fn direct_message_room(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path(other): Path<String>,
+) -> impl IntoResponse {
+    if other == username {
+        return (StatusCode::BAD_REQUEST, "You cannot message yourself").into_response();
+    }
+    let active = state.users.lock().await.get(&other).is_some_and(|user| !user.disabled);
+    if !active {
+        return (StatusCode::NOT_FOUND, "No such user").into_response();
+    }
+    let name = direct::room_name(&username, &other);
+    if let Some(room) = state.rooms.lock().await.get(&name) {
+        return (StatusCode::OK, Json(RoomInfo::new(&name, room))).into_response();
+    }
+    let mut room = rooms::Room::new(username.clone(), rooms::MIN_ROOM_CAPACITY);
+    room.moderators = vec![other.clone()];
+    room.participants = vec![username.clone(), other.clone()];
+    room.public = false;
+    room.persistent = true;
+    let info = RoomInfo::new(&name, &room);
+    // Registered before taking the rooms lock, as in create_room
+    match state.cluster.claim(&name).await {
+        Ok(true) => {}
+        // The other user opened it on another instance, which signaling
+        // for it goes to
+        Ok(false) => return (StatusCode::OK, Json(info)).into_response(),
+        Err(message) => return (StatusCode::SERVICE_UNAVAILABLE, message).into_response(),
+    }
+    let mut rooms = state.rooms.lock().await;
+    // Both asked at once and the other request won; the claim is that room's
+    if let Some(room) = rooms.get(&name) {
+        return (StatusCode::OK, Json(RoomInfo::new(&name, room))).into_response();
+    }
+    rooms.insert(name.clone(), room);
+    drop(rooms);
+    info!("Direct-message room {} created by user: {}", name, username);
+    state.webhooks.send(
+        webhooks::RoomEvent::Created,
+        &name,
+        serde_json::json!({"owner": username, "public": false, "capacity": info.capacity}),
+    );
+    persist_rooms(&state).await;
+    (StatusCode::CREATED, Json(info)).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct PostLetterRequest {
+    /// Encrypted by the sender for the recipient; opaque to us
+    payload: String,
+    /// Seconds to keep the letter, [`mailbox::DEFAULT_TTL`] if left out
+    ttl: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct PostLetterResponse {
+    id: Uuid,
+    expires_at: DateTime<Utc>,
+}
+
+/// Leaves a letter for `recipient`. Connected clients of theirs get it at
+/// once, the others on their next connect; either way it is kept until one
+/// of them acknowledges it or it expires.
+async fn post_letter(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path(recipient): Path<String>,
+    Json(payload): Json<PostLetterRequest>,
+) -> impl IntoResponse {
+    if recipient == username {
+        return (StatusCode::BAD_REQUEST, "You cannot write to yourself").into_response();
+    }
+    if payload.payload.is_empty() || payload.payload.len() > mailbox::MAX_PAYLOAD_BYTES {
+        return (StatusCode::BAD_REQUEST, "Payload must be between 1 byte and 8 KiB").into_response();
+    }
+    let ttl = match payload.ttl.map(Duration::seconds) {
+        Some(ttl) if ttl <= Duration::zero() || ttl > mailbox::MAX_TTL => {
+            return (StatusCode::BAD_REQUEST, "ttl must be between 1 second and 30 days").into_response();
+        }
+        ttl => ttl.unwrap_or(mailbox::DEFAULT_TTL),
+    };
+    let active = state.users.lock().await.get(&recipient).is_some_and(|user| !user.disabled);
+    if !active {
+        return (StatusCode::NOT_FOUND, "No such user").into_response();
+    }
+    let letter = match state.mailbox.lock().await.post(&username, &recipient, payload.payload, ttl) {
+        Ok(letter) => letter,
+        Err(mailbox::PostError::Full) => {
+            return (StatusCode::INSUFFICIENT_STORAGE, "Their mailbox is full").into_response();
+        }
+    };
+    let response = PostLetterResponse { id: letter.id, expires_at: letter.expires_at };
+    send_to_readers(&state, &recipient, serde_json::json!({"type": "mail", "letters": [letter]})).await;
+    (StatusCode::CREATED, Json(response)).into_response()
+}
+
+/// Signs `client_id` up for `username`'s mail and hands it what has been
+/// waiting: letters, and receipts for letters of theirs that arrived.
+async fn open_mailbox(state: &AppState, client_id: Uuid, username: &str, tx: &mpsc::Sender<Message>) {
+    let mut mailbox = state.mailbox.lock().await;
+    mailbox.open(client_id, username);
+    let letters = mailbox.letters(username);
+    let receipts = mailbox.take_receipts(username);
+    drop(mailbox);
+    if !letters.is_empty() {
+        notify(tx, serde_json::json!({"type": "mail", "letters": letters}));
+    }
+    if !receipts.is_empty() {
+        notify(tx, serde_json::json!({"type": "mail_delivered", "receipts": receipts}));
+    }
+}
+
+/// Sends `event` to every connected client of `username` that reads mail;
+/// false if there is none.
+async fn send_to_readers(state: &AppState, username: &str, event: serde_json::Value) -> bool {
+    let readers = state.mailbox.lock().await.readers(username);
+    let clients = state.clients.lock().await;
+    let txs: Vec<_> = readers.iter().filter_map(|id| clients.get(id)).map(|(_, tx)| tx.clone()).collect();
+    drop(clients);
+    for tx in &txs {
+        notify(tx, event.clone());
+    }
+    !txs.is_empty()
+}
+
+/// How often empty rooms are checked against the retention policy.
+const ROOM_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Resolves on SIGTERM or Ctrl+C once signaling has wound down: new
+/// connections are refused, everyone connected gets a `server_shutdown`
+/// notice and [`SHUTDOWN_DRAIN`] to finish, then the rest are closed.
+async fn shutdown_signal(state: AppState) {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+
+    info!("Shutting down, draining signaling connections");
+    state.shutting_down.store(true, Ordering::Relaxed);
+    let clients: Vec<_> = state.clients.lock().await.values().map(|(_, tx)| tx.clone()).collect();
+    for tx in &clients {
+        notify(tx, serde_json::json!({"type": "server_shutdown", "reconnect_after": SHUTDOWN_RECONNECT_AFTER_SECS}));
+    }
+    let _ = tokio::time::timeout(SHUTDOWN_DRAIN, clients_gone(&state)).await;
+
+    let clients: Vec<_> = state.clients.lock().await.values().map(|(_, tx)| tx.clone()).collect();
+    for tx in clients {
+        let close = CloseFrame { code: close_code::RESTART, reason: "Server restarting".into() };
+        let _ = tx.send(Message::Close(Some(close))).await;
+    }
+    // A moment for the close frames to go out before the process exits
+    let _ = tokio::time::timeout(std::time::Duration::from_secs(1), clients_gone(&state)).await;
+}
+
+/// Sends `event` to every signaling client.
+async fn broadcast(state: &AppState, event: serde_json::Value) {
+    for (_, tx) in state.clients.lock().await.values() {
+        notify(tx, event.clone());
+    }
+}
+
+/// The maintenance window that is coming up or under way, if any.
+async fn current_maintenance(state: &AppState) -> Option<maintenance::MaintenanceWindow> {
+    let mut window = state.maintenance.lock().await;
+    if window.as_ref().is_some_and(|w| w.is_over(Utc::now())) {
+        *window = None;
+    }
+    window.clone()
+}
+
+async fn clients_gone(state: &AppState) {
+    while !state.clients.lock().await.is_empty() {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+}
+
+/// Background task removing rooms that stayed empty longer than the
+/// retention policy allows.
+async fn sweep_rooms(state: AppState) {
+    let mut interval = tokio::time::interval(ROOM_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let now = std::time::Instant::now();
+        let mut removed = Vec::new();
+        state.rooms.lock().await.retain(|name, room| {
+            let expired = room.expired(&state.room_retention, now);
+            if expired {
+                removed.push((name.clone(), room.persistent));
+            }
+            !expired
+        });
+        // A new room by the same name starts without their stats
+        let mut activity = state.activity.lock().await;
+        let mut digests = state.digests.lock().await;
+        for (name, _) in &removed {
+            activity.forget(name);
+            digests.forget_room(name);
+        }
+        drop(digests);
+        drop(activity);
+
+        let mut stats = state.room_sweeps.lock().await;
+        stats.sweeps += 1;
+        stats.last_sweep = Some(Utc::now());
+        for (name, persistent) in &removed {
+            info!("Room {} removed after staying empty", name);
+            stats.reclaimed += 1;
+            if *persistent {
+                stats.reclaimed_persistent += 1;
+            }
+        }
+        drop(stats);
+        if removed.iter().any(|(_, persistent)| *persistent) {
+            persist_rooms(&state).await;
+        }
+    }
+}
+
+/// Deletes an empty room. Only its owner may do this.
+async fn delete_room(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    let mut rooms = state.rooms.lock().await;
+    let Some(entry) = rooms.get(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if entry.owner != username {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    if !entry.peers.is_empty() || !entry.waiting.is_empty() {
+        return (StatusCode::CONFLICT, "Room is not empty").into_response();
+    }
+    let removed = rooms.remove(&name);
+    drop(rooms);
+    state.activity.lock().await.forget(&name);
+    state.digests.lock().await.forget_room(&name);
+    info!("Room {} deleted by user: {}", name, username);
+    if removed.is_some_and(|r| r.persistent) {
+        persist_rooms(&state).await;
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryRequest {
+    text: String,
+}
+
+/// Keeps a message just sent in a room with digests. Members post what
+/// they send, as the server does not see messages between peers; only
+/// those in the room now may, and in a broadcast room only its owner.
+async fn post_history(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path(name): Path<String>,
+    Json(payload): Json<HistoryRequest>,
+) -> impl IntoResponse {
+    let text = payload.text.trim();
+    if text.is_empty() || text.chars().count() > digests::MAX_MESSAGE_CHARS {
+        return (StatusCode::BAD_REQUEST, format!("Messages must be 1 to {} characters", digests::MAX_MESSAGE_CHARS))
+            .into_response();
+    }
+    let rooms = state.rooms.lock().await;
+    let Some(room) = rooms.get(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !room.digests {
+        return (StatusCode::CONFLICT, "This room has no digests").into_response();
+    }
+    let member = room.peers.values().any(|(peer, _)| *peer == username);
+    if !member || (room.broadcast && room.owner != username) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    drop(rooms);
+    let author = match state.users.lock().await.get(&username) {
+        Some(user) => user.display_name(&username).to_string(),
+        None => return StatusCode::UNAUTHORIZED.into_response(),
+    };
+    let entry = digests::Entry { at: Utc::now(), author, text: text.to_string() };
+    state.digests.lock().await.record(&name, entry);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DigestSubscription {
+    /// `None` when not subscribed
+    frequency: Option<digests::Frequency>,
+}
+
+/// How often the caller gets `name`'s digest, if at all.
+async fn get_digest(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    Json(DigestSubscription { frequency: state.digests.lock().await.frequency(&username, &name) })
+}
+
+/// Subscribes the caller to `name`'s digest by email, daily or weekly.
+/// Takes an email address on the account.
+async fn subscribe_digest(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path(name): Path<String>,
+    Json(payload): Json<DigestSubscription>,
+) -> impl IntoResponse {
+    let Some(frequency) = payload.frequency else {
+        return (StatusCode::BAD_REQUEST, "frequency must be daily or weekly").into_response();
+    };
+    if !state.rooms.lock().await.get(&name).is_some_and(|room| room.digests) {
+        return (StatusCode::NOT_FOUND, "This room has no digests").into_response();
+    }
+    let has_email = state.users.lock().await.get(&username).is_some_and(|user| user.email.is_some());
+    if !has_email {
+        return (StatusCode::BAD_REQUEST, "Add an email address to your account first").into_response();
+    }
+    state.digests.lock().await.subscribe(&username, &name, frequency);
+    info!("User {} subscribed to the digest of room {}", username, name);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn unsubscribe_digest(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    if state.digests.lock().await.unsubscribe(&username, &name) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UnsubscribeLinkRequest {
+    token: Uuid,
+}
+
+/// Follows the unsubscribe link at the bottom of a digest; the token is
+/// all it takes, so it works from any mail client without logging in.
+async fn unsubscribe_digest_link(
+    State(state): State<AppState>,
+    Json(payload): Json<UnsubscribeLinkRequest>,
+) -> impl IntoResponse {
+    match state.digests.lock().await.unsubscribe_token(&payload.token) {
+        Some(room) => Json(serde_json::json!({"room": room})).into_response(),
+        None => (StatusCode::NOT_FOUND, "This link was already used").into_response(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RoomStatsResponse {
+    room: String,
+    /// Members right now
+    peers: usize,
+    /// The last day, hour by hour, oldest first
+    hours: Vec<trending::HourStats>,
+}
+
+/// A room's joins, peak membership and relayed traffic over the last day.
+/// Only its owner may see them.
+async fn room_stats(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    let peers = match state.rooms.lock().await.get(&name) {
+        None => return StatusCode::NOT_FOUND.into_response(),
+        Some(room) if room.owner != username => {
+            return (StatusCode::FORBIDDEN, "Only the room's owner can see its stats").into_response();
+        }
+        Some(room) => room.peers.len(),
+    };
+    let hours = state.activity.lock().await.history(&name);
+    Json(RoomStatsResponse { room: name, peers, hours }).into_response()
+}
+
+/// Signs an invite into the room that stands in for its password. Only its
+/// owner or current moderator may do this.
+async fn create_invite(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path(name): Path<String>,
+    Json(payload): Json<CreateInviteRequest>,
+) -> impl IntoResponse {
+    let hours = payload.expires_in_hours.unwrap_or(DEFAULT_INVITE_TTL_HOURS);
+    if !(1..=MAX_INVITE_TTL_HOURS).contains(&hours) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Invites last between 1 and {} hours", MAX_INVITE_TTL_HOURS),
+        )
+            .into_response();
+    }
+    match state.rooms.lock().await.get(&name) {
+        None => return StatusCode::NOT_FOUND.into_response(),
+        Some(entry) if !entry.can_edit(&username) => return StatusCode::FORBIDDEN.into_response(),
+        Some(_) => {}
+    }
+
+    let expires_at = Utc::now() + Duration::hours(hours);
+    let claims = RoomInviteClaims {
+        room: name.clone(),
+        exp: expires_at.timestamp() as usize,
+        purpose: ROOM_INVITE_PURPOSE.to_string(),
+    };
+    let token = state.jwt_keys.encode(&claims);
+    info!("Invite to room {} created by user: {}", name, username);
+    (StatusCode::CREATED, Json(InviteResponse { token, expires_at })).into_response()
+}
+
+/// Sets the directory details of a room. Only its owner, appointed
+/// moderators or the stand-in moderator may do this.
+async fn update_room(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path(name): Path<String>,
+    Json(payload): Json<RoomDetailsRequest>,
+) -> impl IntoResponse {
+    if let Err(errors) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, format!("Validation error: {:?}", errors)).into_response();
+    }
+    let mut tags: Vec<String> = payload
+        .tags
+        .iter()
+        .map(|t| t.trim().to_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect();
+    if tags.iter().any(|t| t.len() > MAX_TAG_LENGTH) {
+        return (StatusCode::BAD_REQUEST, format!("Tags can be at most {} characters", MAX_TAG_LENGTH)).into_response();
+    }
+    tags.sort();
+    tags.dedup();
+
+    let mut rooms = state.rooms.lock().await;
+    let Some(entry) = rooms.get_mut(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !entry.can_edit(&username) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    entry.description = payload.description.trim().to_string();
+    entry.category = payload.category.map(|c| c.trim().to_string()).filter(|c| !c.is_empty());
+    entry.tags = tags;
+    let persistent = entry.persistent;
+    drop(rooms);
+    if persistent {
+        persist_rooms(&state).await;
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[derive(Debug, Deserialize, Validate)]
+struct ModeratorsRequest {
+    /// Usernames of registered users, at most 10
+    #[validate(length(max = 10))]
+    moderators: Vec<String>,
+}
+
+/// Replaces the room's appointed moderators. Only its owner may do this.
+async fn set_moderators(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path(name): Path<String>,
+    Json(payload): Json<ModeratorsRequest>,
+) -> impl IntoResponse {
+    if let Err(errors) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, format!("Validation error: {:?}", errors)).into_response();
+    }
+    let mut moderators: Vec<String> = Vec::new();
+    for moderator in payload.moderators.iter().map(|m| m.trim()).filter(|m| !m.is_empty()) {
+        if !moderators.iter().any(|m| m == moderator) {
+            moderators.push(moderator.to_string());
+        }
+    }
+
+    let mut rooms = state.rooms.lock().await;
+    let Some(entry) = rooms.get_mut(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if entry.owner != username {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let users = state.users.lock().await;
+    if let Some(unknown) = moderators.iter().find(|m| !users.contains_key(*m) || **m == entry.owner) {
+        return (StatusCode::BAD_REQUEST, format!("{} cannot be made a moderator", unknown)).into_response();
+    }
+    entry.moderators = moderators;
+    entry.update_delegate(state.moderator_delegation);
+    announce_moderator(&name, entry, &users);
+    let info = RoomInfo::new(&name, entry);
+    drop(users);
+    drop(rooms);
+    info!("Moderators of room {} set by user: {}", name, username);
+    if info.persistent {
+        persist_rooms(&state).await;
+    }
+    Json(info).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct KickRequest {
+    peer_id: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+struct BanRequest {
+    username: String,
+    /// Seconds; banned for good if left out
+    duration: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct BanInfo {
+    username: String,
+    /// `None` for a ban without end
+    until: Option<DateTime<Utc>>,
+}
+
+/// Removes a member from the room, like the `Kick` signaling message.
+async fn kick_from_room(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path(name): Path<String>,
+    Json(payload): Json<KickRequest>,
+) -> impl IntoResponse {
+    match kick_peer(&state, &name, &username, None, payload.peer_id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+/// Lists the room's current bans. Only its moderators may see them.
+async fn list_bans(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    let mut rooms = state.rooms.lock().await;
+    let Some(entry) = rooms.get_mut(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !entry.can_edit(&username) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    entry.forget_expired_bans();
+    let mut bans: Vec<BanInfo> = entry
+        .bans
+        .iter()
+        .map(|(username, until)| BanInfo { username: username.clone(), until: *until })
+        .collect();
+    bans.sort_by(|a, b| a.username.cmp(&b.username));
+    Json(bans).into_response()
+}
+
+/// Bans a user from the room, like the `Ban` signaling message.
+async fn add_ban(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path(name): Path<String>,
+    Json(payload): Json<BanRequest>,
+) -> impl IntoResponse {
+    let target = payload.username.trim();
+    match ban_user(&state, &name, &username, None, target, payload.duration).await {
+        Ok(until) => (StatusCode::CREATED, Json(BanInfo { username: target.to_string(), until })).into_response(),
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+/// Lifts a ban. Only the room's moderators may do this.
+async fn remove_ban(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path((name, banned)): Path<(String, String)>,
+) -> impl IntoResponse {
+    let mut rooms = state.rooms.lock().await;
+    let Some(entry) = rooms.get_mut(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !entry.can_edit(&username) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    if entry.bans.remove(&banned).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let persistent = entry.persistent;
+    drop(rooms);
+    info!("Ban of user {} in room {} lifted by {}", banned, name, username);
+    if persistent {
+        persist_rooms(&state).await;
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Saves persistent rooms if the server was given a file for them. Call
+/// without holding the rooms lock.
+async fn persist_rooms(state: &AppState) {
+    if let Some(store) = &state.room_store {
+        if let Err(err) = store.save(&state.rooms).await {
+            warn!("Could not save rooms: {}", err);
+        }
+    }
+}
+
+/// Saves the account as it is now, or deletes it once it is gone.
+async fn persist_user(state: &AppState, username: &str) {
+    if let Some(store) = &state.user_store {
+        if let Err(err) = store.save(&state.users, username).await {
+            warn!("Could not save account {}: {}", username, err);
+        }
+    }
+}
+
+async fn register(
+    State(state): State<AppState>,
+    Json(payload): Json<RegisterRequest>,
+) -> impl IntoResponse {
+    if let Err(errors) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, format!("Validation error: {:?}", errors)).into_response();
+    }
+
+    if payload.username.starts_with(guest::USERNAME_PREFIX) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Usernames starting with \"{}\" are reserved for guests", guest::USERNAME_PREFIX),
+        ).into_response();
+    }
+    if state.users.lock().await.contains_key(&payload.username) {
+        return (
+            StatusCode::BAD_REQUEST,
+            "User already exists",
+        ).into_response();
+    }
+    let password_hash = hash_password(payload.password.clone()).await;
+
+    let mut users = state.users.lock().await;
+    if users.contains_key(&payload.username) {
+        return (StatusCode::BAD_REQUEST, "User already exists").into_response();
+    }
+    users.insert(payload.username.clone(), User {
+        password_hash,
+        email: payload.email.clone(),
+        totp: None,
+        profile: Profile::default(),
+        rooms: AccountRooms::default(),
+        display: DisplaySettings::default(),
+        settings: AccountSettings::default(),
+        onboarding_completed: false,
+        disabled: false,
     });
+    drop(users);
+    persist_user(&state, &payload.username).await;
+    info!("User registered: {}", payload.username);
+    (StatusCode::CREATED, "User registered").into_response()
+}
+
+async fn login(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<LoginRequest>,
+) -> impl IntoResponse {
+    if let Err(errors) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, format!("Validation error: {:?}", errors)).into_response();
+    }
+
+    let ip = addr.ip();
+    if let Err(wait) = state.login_limiter.lock().await.check(ip, &payload.username) {
+        let retry_after = login_limiter::retry_after(wait);
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(RETRY_AFTER, retry_after.to_string())],
+            format!("Too many login attempts, retry in {}s", retry_after),
+        ).into_response();
+    }
+
+    let stored_hash = state.users.lock().await.get(&payload.username).map(|u| u.password_hash.clone());
+    let password_ok = match stored_hash {
+        Some(hash) => verify_password(payload.password.clone(), hash).await,
+        None => false,
+    };
+
+    let users = state.users.lock().await;
+    let Some(user) = users.get(&payload.username).filter(|_| password_ok) else {
+        drop(users);
+        state.login_limiter.lock().await.record_failure(ip, &payload.username);
+        return (StatusCode::UNAUTHORIZED, "Invalid credentials").into_response();
+    };
+    if user.disabled {
+        drop(users);
+        state.login_limiter.lock().await.release(ip, &payload.username);
+        return (StatusCode::FORBIDDEN, "Account disabled").into_response();
+    }
+    let totp_enabled = user.totp.as_ref().is_some_and(|t| t.enabled);
+    drop(users);
+    if totp_enabled {
+        match payload.totp_code.as_deref() {
+            None => {
+                state.login_limiter.lock().await.release(ip, &payload.username);
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    Json(serde_json::json!({ "totp_required": true })),
+                ).into_response();
+            }
+            Some(code) if !verify_second_factor(&state, &payload.username, code).await => {
+                state.login_limiter.lock().await.record_failure(ip, &payload.username);
+                return (StatusCode::UNAUTHORIZED, "Invalid second factor").into_response();
+            }
+            Some(_) => {}
+        }
+    }
+
+    let user_agent = headers.get(USER_AGENT).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let session_id = state.sessions.lock().await.create(&payload.username, Some(ip), user_agent);
+    let token = issue_token(&state, &payload.username, session_id).await;
+    state.login_limiter.lock().await.record_success(ip, &payload.username);
+    info!("User logged in: {}", payload.username);
+    Json(serde_json::json!({ "token": token })).into_response()
+}
+
+/// Starts handing this login to another device: the code goes into a QR
+/// code the new device scans. Replaces the caller's previous code.
+async fn start_qr_login(State(state): State<AppState>, AuthSession { username, session_id }: AuthSession) -> impl IntoResponse {
+    let (code, expires_at) = state.qr_logins.lock().await.start(&username, session_id);
+    info!("QR login handoff started by user: {}", username);
+    (StatusCode::CREATED, Json(serde_json::json!({ "code": code, "expires_at": expires_at }))).into_response()
+}
+
+/// Polled by the device showing the code, to learn when someone scanned it
+/// and from where.
+async fn qr_login_status(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path(code): Path<String>,
+) -> impl IntoResponse {
+    match state.qr_logins.lock().await.status(&code, &username) {
+        Some(status) => Json(status).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Called by the device that scanned the code. The secret it gets back is
+/// what it collects its token with once the signed-in device confirms.
+async fn claim_qr_login(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(code): Path<String>,
+) -> impl IntoResponse {
+    let user_agent = headers.get(USER_AGENT).and_then(|v| v.to_str().ok()).map(str::to_string);
+    match state.qr_logins.lock().await.claim(&code, Some(addr.ip()), user_agent) {
+        Some((username, claim)) => Json(serde_json::json!({ "claim": claim, "username": username })).into_response(),
+        None => (StatusCode::NOT_FOUND, "This code has expired or was already used").into_response(),
+    }
+}
+
+/// The signed-in device lets the device that scanned its code in, or not.
+async fn confirm_qr_login(
+    State(state): State<AppState>,
+    AuthSession { username, .. }: AuthSession,
+    Path(code): Path<String>,
+    Json(payload): Json<QrConfirmRequest>,
+) -> impl IntoResponse {
+    if !state.qr_logins.lock().await.answer(&code, &username, payload.approve) {
+        return (StatusCode::NOT_FOUND, "Nobody is waiting on this code").into_response();
+    }
+    info!("QR login handoff {} by user: {}", if payload.approve { "approved" } else { "denied" }, username);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Polled by the device that scanned the code: 202 until the signed-in
+/// device answers, then a token of its own session, or 403.
+async fn redeem_qr_login(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    Json(payload): Json<QrRedeemRequest>,
+) -> impl IntoResponse {
+    let redeemed = state.qr_logins.lock().await.redeem(&code, &payload.claim);
+    let (username, device) = match redeemed {
+        None => return (StatusCode::NOT_FOUND, "This code has expired or was already used").into_response(),
+        Some(qr_login::Redeemed::Pending) => return StatusCode::ACCEPTED.into_response(),
+        Some(qr_login::Redeemed::Denied) => return (StatusCode::FORBIDDEN, "The other device declined").into_response(),
+        Some(qr_login::Redeemed::Approved { username, device }) => (username, device),
+    };
+    match state.users.lock().await.get(&username) {
+        Some(user) if !user.disabled => {}
+        Some(_) => return (StatusCode::FORBIDDEN, "Account disabled").into_response(),
+        None => return StatusCode::NOT_FOUND.into_response(),
+    }
+    let session_id = state.sessions.lock().await.create(&username, device.ip, device.user_agent);
+    let token = issue_token(&state, &username, session_id).await;
+    info!("User logged in by QR code: {}", username);
+    Json(serde_json::json!({ "token": token })).into_response()
+}
+
+/// Mints a short-lived token with a generated name for someone without an
+/// account. It is scoped to `room`: signaling for any other room and all
+/// account endpoints reject it.
+async fn join_as_guest(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<GuestRequest>,
+) -> impl IntoResponse {
+    if let Err(errors) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, format!("Validation error: {:?}", errors)).into_response();
+    }
+
+    if !state.rooms.lock().await.contains_key(&payload.room) {
+        return (StatusCode::NOT_FOUND, "No such room").into_response();
+    }
+
+    let user_agent = headers.get(USER_AGENT).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let mut sessions = state.sessions.lock().await;
+    let username = loop {
+        let name = guest::nickname();
+        if !sessions.has_user(&name) {
+            break name;
+        }
+    };
+    let session_id = sessions.create(&username, Some(addr.ip()), user_agent);
+    drop(sessions);
+
+    let token = sign_token(&state, &username, session_id, guest::TOKEN_TTL, Some(payload.room.clone())).await;
+    info!("Guest {} admitted to room {}", username, payload.room);
+    Json(serde_json::json!({ "token": token, "username": username })).into_response()
 }
 
-async fn register(
+/// Replaces the password after checking the current one. Every other session
+/// is revoked and its sockets closed; the caller gets a fresh token.
+async fn change_password(
     State(state): State<AppState>,
-    Json(payload): Json<RegisterRequest>,
+    AuthSession { username, session_id }: AuthSession,
+    Json(payload): Json<ChangePasswordRequest>,
 ) -> impl IntoResponse {
     if let Err(errors) = payload.validate() {
         return (StatusCode::BAD_REQUEST, format!("Validation error: {:?}", errors)).into_response();
     }
 
-    let mut users = state.users.lock().await;
-    if users.contains_key(&payload.username) {
-        return (
-            StatusCode::BAD_REQUEST,
-            "User already exists",
-        ).into_response();
+    let Some(stored_hash) = state.users.lock().await.get(&username).map(|u| u.password_hash.clone()) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    if !verify_password(payload.current_password, stored_hash).await {
+        return (StatusCode::UNAUTHORIZED, "Current password is incorrect").into_response();
     }
-    users.insert(payload.username.clone(), payload.password.clone()); // Hash password in production
-    info!("User registered: {}", payload.username);
-    (StatusCode::CREATED, "User registered").into_response()
+
+    let new_hash = hash_password(payload.new_password).await;
+    match state.users.lock().await.get_mut(&username) {
+        Some(user) => user.password_hash = new_hash,
+        None => return StatusCode::UNAUTHORIZED.into_response(),
+    }
+    persist_user(&state, &username).await;
+    end_sessions(&state, &username, Some(session_id), "Password changed").await;
+
+    let token = issue_token(&state, &username, session_id).await;
+    info!("Password changed for user: {}", username);
+    Json(serde_json::json!({ "token": token })).into_response()
 }
 
-async fn login(
+/// Mails a reset link if the address belongs to an account. Always answers
+/// 202 so the endpoint cannot be used to probe for registered emails.
+async fn forgot_password(
     State(state): State<AppState>,
-    Json(payload): Json<LoginRequest>,
+    Json(payload): Json<ForgotPasswordRequest>,
+) -> impl IntoResponse {
+    let username = state
+        .users
+        .lock()
+        .await
+        .iter()
+        .find(|(_, u)| u.email.as_deref().is_some_and(|e| e.eq_ignore_ascii_case(&payload.email)))
+        .map(|(name, _)| name.clone());
+
+    if let Some(username) = username {
+        send_reset_link(&state, &username, &payload.email).await;
+        info!("Password reset requested for user: {}", username);
+    }
+    StatusCode::ACCEPTED
+}
+
+/// Mails `email` a single-use link for choosing a new password.
+async fn send_reset_link(state: &AppState, username: &str, email: &str) {
+    let jti = Uuid::new_v4();
+    let exp = (Utc::now() + Duration::minutes(PASSWORD_RESET_TTL_MINUTES)).timestamp() as usize;
+    let claims = PasswordResetClaims {
+        sub: username.to_string(),
+        exp,
+        jti,
+        purpose: PASSWORD_RESET_PURPOSE.to_string(),
+    };
+    let token = state.jwt_keys.encode(&claims);
+
+    let mut resets = state.password_resets.lock().await;
+    let now = Utc::now().timestamp() as usize;
+    resets.retain(|_, (_, exp)| *exp > now);
+    resets.insert(jti, (username.to_string(), exp));
+    drop(resets);
+
+    let link = format!("{}/reset-password?token={}", state.config.frontend_url, token);
+    state.mailer.send(
+        email,
+        "Reset your P2P Chat password",
+        &format!(
+            "Hi {},\n\nUse this link within {} minutes to choose a new password:\n{}\n\nIf you did not ask for this, ignore this email.",
+            username, PASSWORD_RESET_TTL_MINUTES, link
+        ),
+    );
+}
+
+async fn reset_password(
+    State(state): State<AppState>,
+    Json(payload): Json<ResetPasswordRequest>,
 ) -> impl IntoResponse {
     if let Err(errors) = payload.validate() {
         return (StatusCode::BAD_REQUEST, format!("Validation error: {:?}", errors)).into_response();
     }
 
-    let users = state.users.lock().await;
-    if let Some(stored_pass) = users.get(&payload.username) {
-        if stored_pass == &payload.password {
-            let claims = Claims {
-                sub: payload.username.clone(),
-                exp: (Utc::now() + Duration::hours(24)).timestamp() as usize,
-            };
-            let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(JWT_SECRET.as_ref())).unwrap();
-            info!("User logged in: {}", payload.username);
-            return Json(serde_json::json!({ "token": token })).into_response();
+    let claims = match state.jwt_keys.decode::<PasswordResetClaims>(&payload.token) {
+        Some(claims) if claims.purpose == PASSWORD_RESET_PURPOSE => claims,
+        _ => return (StatusCode::BAD_REQUEST, "Invalid or expired reset link").into_response(),
+    };
+    // Removing the id makes the token single-use
+    if state.password_resets.lock().await.remove(&claims.jti).is_none() {
+        return (StatusCode::BAD_REQUEST, "Reset link was already used").into_response();
+    }
+
+    let new_hash = hash_password(payload.new_password).await;
+    match state.users.lock().await.get_mut(&claims.sub) {
+        Some(user) => user.password_hash = new_hash,
+        None => return (StatusCode::BAD_REQUEST, "Invalid or expired reset link").into_response(),
+    }
+    persist_user(&state, &claims.sub).await;
+    end_sessions(&state, &claims.sub, None, "Password reset").await;
+    info!("Password reset for user: {}", claims.sub);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn delete_account(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+) -> impl IntoResponse {
+    if state.users.lock().await.remove(&username).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    persist_user(&state, &username).await;
+    end_sessions(&state, &username, None, "Account deleted").await;
+    // Reset links already mailed must not work for whoever takes the name next
+    state.password_resets.lock().await.retain(|_, (owner, _)| *owner != username);
+    state.mailbox.lock().await.remove_user(&username);
+    state.digests.lock().await.forget_user(&username);
+    // Nor do they inherit the account's rooms, direct conversations or standing
+    let closed: Vec<(String, rooms::Room)> = {
+        let mut rooms = state.rooms.lock().await;
+        let names: Vec<String> =
+            rooms.iter().filter(|(_, room)| room.belongs_to(&username)).map(|(name, _)| name.clone()).collect();
+        rooms.values_mut().for_each(|room| room.forget_user(&username));
+        names.into_iter().filter_map(|name| rooms.remove(&name).map(|room| (name, room))).collect()
+    };
+    for (name, room) in &closed {
+        let clients: Vec<_> = room.peers.keys().copied().chain(room.waiting.iter().map(|k| k.client_id)).collect();
+        disconnect_clients(&state, &clients, CLOSE_REMOVED, &format!("Room {} was closed", name)).await;
+        state.activity.lock().await.forget(name);
+        state.digests.lock().await.forget_room(name);
+    }
+    persist_rooms(&state).await;
+    info!("User deleted: {}", username);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[derive(Debug, Serialize)]
+struct SessionInfo {
+    id: Uuid,
+    #[serde(flatten)]
+    session: sessions::Session,
+    current: bool,
+}
+
+/// Where the caller is signed in, most recently used first.
+async fn list_sessions(
+    State(state): State<AppState>,
+    AuthSession { username, session_id }: AuthSession,
+) -> impl IntoResponse {
+    let list: Vec<SessionInfo> = user_sessions(&state, &username)
+        .await
+        .into_iter()
+        .map(|(id, session)| SessionInfo { id, session, current: id == session_id })
+        .collect();
+    Json(list)
+}
+
+/// Signs out one of the caller's sessions and closes its sockets.
+async fn revoke_session(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !user_sessions(&state, &username).await.iter().any(|(sid, _)| *sid == id) {
+        return StatusCode::NOT_FOUND;
+    }
+    state.sessions.lock().await.remove(&id);
+    state.cluster.end_sessions(&username, &[id], "Session revoked").await;
+    kick_sessions(&state, &[id], "Session revoked").await;
+    info!("Session {} revoked for user: {}", id, username);
+    StatusCode::NO_CONTENT
+}
+
+#[derive(Debug, Deserialize, Validate)]
+struct ApiKeyRequest {
+    #[validate(length(min = 1, max = 50))]
+    name: String,
+    #[validate(length(min = 1))]
+    scopes: Vec<api_keys::Scope>,
+}
+
+#[derive(Debug, Serialize)]
+struct CreatedApiKey {
+    #[serde(flatten)]
+    api_key: api_keys::ApiKey,
+    /// The only time the key itself is shown
+    key: String,
+}
+
+/// Creates a key for a bot acting as the caller. Only an interactive login
+/// can create, list or revoke keys.
+async fn create_api_key(
+    State(state): State<AppState>,
+    AuthSession { username, .. }: AuthSession,
+    Json(payload): Json<ApiKeyRequest>,
+) -> impl IntoResponse {
+    if let Err(errors) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, format!("Validation error: {:?}", errors)).into_response();
+    }
+    let mut scopes = Vec::new();
+    for scope in payload.scopes {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+
+    let mut keys = state.api_keys.lock().await;
+    if keys.for_user(&username).len() >= api_keys::MAX_KEYS_PER_USER {
+        return (StatusCode::CONFLICT, format!("You can have at most {} API keys", api_keys::MAX_KEYS_PER_USER))
+            .into_response();
+    }
+    let (api_key, key) = keys.create(&username, payload.name.trim().to_string(), scopes);
+    info!("API key {} created for user: {}", api_key.id, username);
+    (StatusCode::CREATED, Json(CreatedApiKey { api_key, key })).into_response()
+}
+
+async fn list_api_keys(
+    State(state): State<AppState>,
+    AuthSession { username, .. }: AuthSession,
+) -> impl IntoResponse {
+    Json(state.api_keys.lock().await.for_user(&username))
+}
+
+/// Deletes a key and closes the connections made with it.
+async fn revoke_api_key(
+    State(state): State<AppState>,
+    AuthSession { username, .. }: AuthSession,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !state.api_keys.lock().await.revoke(&username, &id) {
+        return StatusCode::NOT_FOUND;
+    }
+    kick_sessions(&state, &[id], "API key revoked").await;
+    info!("API key {} revoked for user: {}", id, username);
+    StatusCode::NO_CONTENT
+}
+
+async fn get_profile(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+) -> impl IntoResponse {
+    match state.users.lock().await.get(&username) {
+        Some(user) => Json(serde_json::json!({
+            "username": username,
+            "display_name": user.profile.display_name,
+            "avatar_url": user.profile.avatar_url,
+            "bio": user.profile.bio,
+        }))
+        .into_response(),
+        None => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+async fn update_profile(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Json(mut payload): Json<Profile>,
+) -> impl IntoResponse {
+    // Blank fields clear the value instead of failing validation
+    for field in [&mut payload.display_name, &mut payload.avatar_url, &mut payload.bio] {
+        *field = field.take().map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+    }
+    if let Err(errors) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, format!("Validation error: {:?}", errors)).into_response();
+    }
+
+    match state.users.lock().await.get_mut(&username) {
+        Some(user) => user.profile = payload.clone(),
+        None => return StatusCode::UNAUTHORIZED.into_response(),
+    }
+    persist_user(&state, &username).await;
+    Json(payload).into_response()
+}
+
+async fn get_display_settings(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+) -> impl IntoResponse {
+    match state.users.lock().await.get(&username) {
+        Some(user) => Json(user.display.clone()).into_response(),
+        None => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+async fn update_display_settings(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Json(payload): Json<DisplaySettings>,
+) -> impl IntoResponse {
+    if let Err(errors) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, format!("Validation error: {:?}", errors)).into_response();
+    }
+    match state.users.lock().await.get_mut(&username) {
+        Some(user) => user.display = payload.clone(),
+        None => return StatusCode::UNAUTHORIZED.into_response(),
+    }
+    persist_user(&state, &username).await;
+    Json(payload).into_response()
+}
+
+async fn get_account_settings(State(state): State<AppState>, AuthUser(username): AuthUser) -> impl IntoResponse {
+    match state.users.lock().await.get(&username) {
+        Some(user) => Json(user.settings.clone()).into_response(),
+        None => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Saves the settings if they are based on the latest version; otherwise
+/// answers 409 with the latest, for the client to merge its changes into
+/// and try again. The user's open connections hear about the new version.
+async fn update_account_settings(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Json(payload): Json<UpdateSettingsRequest>,
+) -> impl IntoResponse {
+    let size = serde_json::to_string(&payload.settings).map_or(usize::MAX, |json| json.len());
+    if size > MAX_SETTINGS_BYTES {
+        return (StatusCode::BAD_REQUEST, format!("Settings can be at most {} bytes", MAX_SETTINGS_BYTES)).into_response();
+    }
+    let saved = match state.users.lock().await.get_mut(&username) {
+        Some(user) if user.settings.version != payload.base_version => {
+            return (StatusCode::CONFLICT, Json(user.settings.clone())).into_response();
+        }
+        Some(user) => {
+            user.settings = AccountSettings { version: user.settings.version + 1, settings: payload.settings };
+            user.settings.clone()
+        }
+        None => return StatusCode::UNAUTHORIZED.into_response(),
+    };
+    persist_user(&state, &username).await;
+    let sessions: Vec<Uuid> = state.sessions.lock().await.for_user(&username).into_iter().map(|(id, _)| id).collect();
+    for (session_id, tx) in state.clients.lock().await.values() {
+        if sessions.contains(session_id) {
+            notify(tx, serde_json::json!({
+                "type": "settings_changed",
+                "version": saved.version,
+                "settings": saved.settings,
+            }));
+        }
+    }
+    Json(saved).into_response()
+}
+
+#[derive(Debug, Deserialize, Validate)]
+struct FeedbackRequest {
+    category: feedback::Category,
+    #[validate(length(min = 1, max = 2000))]
+    description: String,
+    #[validate(length(max = 4000))]
+    diagnostics: Option<String>,
+}
+
+/// Stores a report from the in-app feedback form for admins to review.
+async fn submit_feedback(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Json(payload): Json<FeedbackRequest>,
+) -> impl IntoResponse {
+    if let Err(errors) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, format!("Validation error: {:?}", errors)).into_response();
+    }
+    let diagnostics = payload.diagnostics.filter(|d| !d.trim().is_empty());
+    let result = state.feedback.lock().await.submit(&username, payload.category, payload.description.trim().to_string(), diagnostics);
+    match result {
+        Ok(id) => {
+            info!("Feedback {} submitted by user: {}", id, username);
+            StatusCode::CREATED.into_response()
+        }
+        Err(wait) => {
+            let retry_after = wait.as_secs() + 1;
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, retry_after.to_string())],
+                format!("Too much feedback at once, retry in {}s", retry_after),
+            ).into_response()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Onboarding {
+    completed: bool,
+}
+
+async fn get_onboarding(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+) -> impl IntoResponse {
+    match state.users.lock().await.get(&username) {
+        Some(user) => Json(Onboarding { completed: user.onboarding_completed }).into_response(),
+        None => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Records that the tour was finished or skipped so other devices skip it
+/// too; setting `completed` to false shows it again.
+async fn update_onboarding(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Json(payload): Json<Onboarding>,
+) -> impl IntoResponse {
+    match state.users.lock().await.get_mut(&username) {
+        Some(user) => user.onboarding_completed = payload.completed,
+        None => return StatusCode::UNAUTHORIZED.into_response(),
+    }
+    persist_user(&state, &username).await;
+    Json(payload).into_response()
+}
+
+async fn get_account_rooms(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+) -> impl IntoResponse {
+    match state.users.lock().await.get(&username) {
+        Some(user) => Json(user.rooms.clone()).into_response(),
+        None => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Replaces both lists, e.g. after starring a room or clearing the history.
+async fn update_account_rooms(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Json(mut payload): Json<AccountRooms>,
+) -> impl IntoResponse {
+    if let Err(errors) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, format!("Validation error: {:?}", errors)).into_response();
+    }
+    for list in [&mut payload.recent, &mut payload.favorites] {
+        let mut seen = HashSet::new();
+        *list = list
+            .iter()
+            .map(|r| r.trim().to_string())
+            .filter(|r| !r.is_empty() && seen.insert(r.clone()))
+            .collect();
+        if list.iter().any(|r| r.len() > MAX_ROOM_NAME_LENGTH) {
+            return (StatusCode::BAD_REQUEST, format!("Room names can be at most {} characters", MAX_ROOM_NAME_LENGTH))
+                .into_response();
         }
     }
-    (StatusCode::UNAUTHORIZED, "Invalid credentials").into_response()
+
+    match state.users.lock().await.get_mut(&username) {
+        Some(user) => user.rooms = payload.clone(),
+        None => return StatusCode::UNAUTHORIZED.into_response(),
+    }
+    persist_user(&state, &username).await;
+    Json(payload).into_response()
+}
+
+async fn totp_enroll(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+) -> impl IntoResponse {
+    let mut users = state.users.lock().await;
+    let Some(user) = users.get_mut(&username) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    if user.totp.as_ref().is_some_and(|t| t.enabled) {
+        return (StatusCode::BAD_REQUEST, "2FA already enabled").into_response();
+    }
+
+    let pending = totp::TotpState::generate();
+    let Some((secret, otpauth_uri)) = pending.provisioning(&username) else {
+        return (StatusCode::BAD_REQUEST, "Username cannot be used for 2FA").into_response();
+    };
+    user.totp = Some(pending);
+    drop(users);
+    persist_user(&state, &username).await;
+    Json(serde_json::json!({ "secret": secret, "otpauth_uri": otpauth_uri })).into_response()
+}
+
+async fn totp_confirm(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Json(payload): Json<TotpConfirmRequest>,
+) -> impl IntoResponse {
+    let (recovery_codes, recovery_hashes) = new_recovery_codes().await;
+    let mut users = state.users.lock().await;
+    let Some(totp) = users.get_mut(&username).and_then(|u| u.totp.as_mut()) else {
+        return (StatusCode::BAD_REQUEST, "2FA enrollment not started").into_response();
+    };
+    if totp.enabled {
+        return (StatusCode::BAD_REQUEST, "2FA already enabled").into_response();
+    }
+    if !totp.check_code(&username, &payload.code) {
+        return (StatusCode::UNAUTHORIZED, "Invalid code").into_response();
+    }
+
+    totp.enable(recovery_hashes);
+    drop(users);
+    persist_user(&state, &username).await;
+    info!("2FA enabled for user: {}", username);
+    Json(serde_json::json!({ "recovery_codes": recovery_codes })).into_response()
+}
+
+/// Turns 2FA off, given a current code or a recovery code. Enrolling again
+/// starts with a new secret.
+async fn totp_disable(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Json(payload): Json<TotpConfirmRequest>,
+) -> impl IntoResponse {
+    if !verify_second_factor(&state, &username, &payload.code).await {
+        return (StatusCode::UNAUTHORIZED, "Invalid code").into_response();
+    }
+    if let Some(user) = state.users.lock().await.get_mut(&username) {
+        user.totp = None;
+    }
+    persist_user(&state, &username).await;
+    info!("2FA disabled for user: {}", username);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Replaces the recovery codes, given a current code or one of the old
+/// recovery codes, and returns the new ones.
+async fn totp_regenerate_recovery_codes(
+    State(state): State<AppState>,
+    AuthUser(username): AuthUser,
+    Json(payload): Json<TotpConfirmRequest>,
+) -> impl IntoResponse {
+    if !verify_second_factor(&state, &username, &payload.code).await {
+        return (StatusCode::UNAUTHORIZED, "Invalid code").into_response();
+    }
+    let (recovery_codes, recovery_hashes) = new_recovery_codes().await;
+    let mut users = state.users.lock().await;
+    let Some(totp) = users.get_mut(&username).and_then(|u| u.totp.as_mut()).filter(|t| t.enabled) else {
+        return (StatusCode::BAD_REQUEST, "2FA not enabled").into_response();
+    };
+    totp.replace_recovery_codes(recovery_hashes);
+    drop(users);
+    persist_user(&state, &username).await;
+    info!("2FA recovery codes replaced for user: {}", username);
+    Json(serde_json::json!({ "recovery_codes": recovery_codes })).into_response()
 }
 
 #[tokio::main]
 async fn main() {
+    let layers = config::Layers::load().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    let config = config::Config::load(&layers).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    if let config::Command::Migrate { dry_run } = layers.command {
+        let Some(url) = &config.database_url else {
+            eprintln!("migrate needs {} or {}", config::DATABASE_URL.var, config::DATABASE_URL.key);
+            std::process::exit(1);
+        };
+        if let Err(err) = db::migrate_command(url, dry_run).await {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let profile = config.profile;
+    let trace_export = otel::layer().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    let exporting = trace_export.is_some();
     tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new("info"))
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::EnvFilter::new(&config.log_filter))
+        .with((config.log_format == config::LogFormat::Pretty).then(tracing_subscriber::fmt::layer))
+        .with((config.log_format == config::LogFormat::Json).then(|| tracing_subscriber::fmt::layer().json().flatten_event(true)))
+        .with(trace_export)
         .init();
+    info!("Running with the {} profile", profile.name());
+    if exporting {
+        info!("Exporting traces over OTLP");
+    }
+    #[cfg(feature = "chaos")]
+    warn!("Built with the chaos feature: admins can make signaling misbehave");
+    let mut user_store = None;
+    if let Some(url) = &config.database_url {
+        let database = db::connect(url).await.unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        });
+        db::migrate(&database).await.unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        });
+        info!("Database schema is up to date");
+        user_store = Some(user_store::UserStore::new(database));
+    }
+    let cors = profile.cors(&config.frontend_url).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    let trusted_proxies = proxy::TrustedProxies::load(&layers).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    let tls = config.tls.clone().map(|files| {
+        Arc::new(tls::Tls::new(files).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }))
+    });
+    #[cfg(unix)]
+    if let Some(tls) = &tls {
+        tokio::spawn(tls::reload_on_hangup(tls.clone()));
+    }
+
+    let jwt_keys = jwt_keys::JwtKeys::load(&layers).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    info!("JWT signing keys loaded: {:?}", jwt_keys);
+    let ice = ice::IceConfig::from_env().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    if !ice.has_turn() {
+        if profile.requires_turn() {
+            eprintln!("The {} profile needs a TURN server: set TURN_URLS and TURN_SECRET", profile.name());
+            std::process::exit(1);
+        }
+        warn!("No TURN server configured; peers behind symmetric NAT will not connect");
+    }
+    let room_retention = rooms::RetentionPolicy::from_env().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    info!("Room retention: {:?}", room_retention);
+    let max_room_capacity = rooms::capacity_ceiling_from_env().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    let webhooks = webhooks::Webhooks::from_env().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    if webhooks.is_enabled() {
+        info!("Room lifecycle webhooks enabled");
+    }
+    let metrics = telemetry::Metrics::install().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    if !metrics.is_protected() {
+        warn!("GET /metrics is open to anyone; set METRICS_TOKEN unless only Prometheus can reach the server");
+    }
 
-    let users = Arc::new(Mutex::new(HashMap::new()));
-    let rooms = Arc::new(Mutex::new(HashMap::new()));
+    let mailer = mail::load(&layers, profile).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    let bus = bus::load(&layers).await.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    let users = match &user_store {
+        Some(store) => store.load().await.unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }),
+        None => HashMap::new(),
+    };
+    if user_store.is_some() {
+        info!("{} accounts loaded", users.len());
+    }
+    let users = Arc::new(Mutex::new(users));
+    let room_store = room_store::RoomStore::from_env();
+    let rooms = match &room_store {
+        Some(store) => store.load().await.unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }),
+        None => HashMap::new(),
+    };
+    info!("{} persistent rooms loaded", rooms.len());
+    let rooms = Arc::new(Mutex::new(rooms));
     let state = AppState {
         users,
         rooms,
+        login_limiter: Arc::new(Mutex::new(login_limiter::LoginLimiter::default())),
+        clients: Arc::new(Mutex::new(HashMap::new())),
+        event_streams: Arc::new(Mutex::new(event_stream::EventStreams::default())),
+        shutting_down: Arc::new(AtomicBool::new(false)),
+        sessions: Arc::new(Mutex::new(sessions::SessionStore::default())),
+        password_resets: Arc::new(Mutex::new(HashMap::new())),
+        qr_logins: Arc::new(Mutex::new(qr_login::QrLoginStore::default())),
+        mailer,
+        jwt_keys: Arc::new(jwt_keys),
+        moderator_delegation: std::env::var("ROOM_MODERATOR_DELEGATION")
+            .map_or(true, |v| !matches!(v.as_str(), "0" | "false" | "off")),
+        max_room_capacity,
+        chat_relay: std::env::var("CHAT_RELAY").is_ok_and(|v| matches!(v.as_str(), "1" | "true" | "on")),
+        admins: Arc::new(
+            std::env::var("ADMIN_USERS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect(),
+        ),
+        activity: Arc::new(Mutex::new(trending::ActivityTracker::default())),
+        api_keys: Arc::new(Mutex::new(api_keys::ApiKeyStore::default())),
+        feedback: Arc::new(Mutex::new(feedback::FeedbackStore::default())),
+        ice: Arc::new(ice),
+        room_retention,
+        room_sweeps: Arc::new(Mutex::new(rooms::SweepStats::default())),
+        room_store: room_store.map(Arc::new),
+        user_store: user_store.map(Arc::new),
+        client_config: Arc::new(client_config::ClientConfig::from_env(&config.base_path)),
+        webhooks: Arc::new(webhooks),
+        mailbox: Arc::new(Mutex::new(mailbox::Mailbox::default())),
+        digests: Arc::new(Mutex::new(digests::Digests::default())),
+        config: Arc::new(config),
+        maintenance: Arc::new(Mutex::new(None)),
+        metrics: Arc::new(metrics),
+        cluster: Arc::new(cluster::Cluster::new(bus)),
+        #[cfg(feature = "chaos")]
+        chaos: Arc::new(Mutex::new(chaos::ChaosSettings::default())),
     };
+    tokio::spawn(sweep_rooms(state.clone()));
+    tokio::spawn(digests::send_due(state.clone()));
+    tokio::spawn(cluster::run(state.clone()));
+    let config = state.config.clone();
 
     let app = Router::new()
         .route("/", get(|| async { "Hello, P2P Chat Signaling Server!" }))
         .route("/ws", get(ws_handler))
+        .route("/events", get(signal_events))
+        .route("/metrics", get(telemetry::render))
+        .route("/ice-config", get(ice_config))
+        .route("/config.json", get(client_config))
+        .route("/rooms", get(list_rooms).post(create_room))
+        .route("/rooms/trending", get(trending_rooms))
+        .route("/rooms/:name", put(update_room).delete(delete_room))
+        .route("/rooms/:name/invites", post(create_invite))
+        .route("/rooms/:name/stats", get(room_stats))
+        .route("/rooms/:name/history", post(post_history))
+        .route("/rooms/:name/digest", get(get_digest).put(subscribe_digest).delete(unsubscribe_digest))
+        .route("/digests/unsubscribe", post(unsubscribe_digest_link))
+        .route("/rooms/:name/moderators", put(set_moderators))
+        .route("/rooms/:name/kick", post(kick_from_room))
+        .route("/rooms/:name/bans", get(list_bans).post(add_ban))
+        .route("/rooms/:name/bans/:username", delete(remove_ban))
+        .route("/users", get(search_users))
+        .route("/dm/:username", post(direct_message_room))
+        .route("/mailbox/:username", post(post_letter))
         .route("/register", post(register))
         .route("/login", post(login))
-        .layer(CorsLayer::permissive()) // For development; restrict in production
-        .layer(TraceLayer::new_for_http())
-        .layer(RequestBodyLimitLayer::new(1024 * 10)) // 10KB limit
-        .with_state(state);
-
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    info!("Server running on http://{}", addr);
-    println!("Server running on http://{}", addr);
-    println!("WebSocket available at ws://{}", addr);
-    // For WSS, configure TLS in production
-    let listener = TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
-}
\ No newline at end of file
+        .route("/guest", post(join_as_guest))
+        .route("/auth/qr/start", post(start_qr_login))
+        .route("/auth/qr/:code", get(qr_login_status))
+        .route("/auth/qr/:code/claim", post(claim_qr_login))
+        .route("/auth/qr/:code/confirm", post(confirm_qr_login))
+        .route("/auth/qr/:code/redeem", post(redeem_qr_login))
+        .route("/account", delete(delete_account))
+        .route("/account/password", post(change_password))
+        .route("/account/rooms", get(get_account_rooms).put(update_account_rooms))
+        .route("/account/display", get(get_display_settings).put(update_display_settings))
+        .route("/account/settings", get(get_account_settings).put(update_account_settings))
+        .route("/account/onboarding", get(get_onboarding).put(update_onboarding))
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/:id", delete(revoke_session))
+        .route("/profile", get(get_profile).put(update_profile))
+        .route("/apikeys", get(list_api_keys).post(create_api_key))
+        .route("/feedback", post(submit_feedback))
+        .route("/apikeys/:id", delete(revoke_api_key))
+        .route("/password/forgot", post(forgot_password))
+        .route("/password/reset", post(reset_password))
+        .route("/2fa/enroll", post(totp_enroll))
+        .route("/2fa/confirm", post(totp_confirm))
+        .route("/2fa/disable", post(totp_disable))
+        .route("/2fa/recovery-codes", post(totp_regenerate_recovery_codes))
+        .layer(RequestBodyLimitLayer::new(state.config.body_limit))
+        // Admin routes apply the same limit, but take larger backups
+        .nest("/admin", admin::routes(state.clone()))
+        // Signaling posts may be as big as a WebSocket message
+        .route(
+            "/signal",
+            post(post_signal).layer(RequestBodyLimitLayer::new(ws_limits::MAX_MESSAGE_BYTES)),
+        )
+        .route_layer(axum::middleware::from_fn(telemetry::track_http))
+        .layer(cors)
+        .layer(TraceLayer::new_for_http().make_span_with(otel::request_span))
+        .with_state(state.clone());
+    let app = if config.base_path.is_empty() {
+        app
+    } else {
+        info!("Serving under {}", config.base_path);
+        Router::new().nest(&config.base_path, app)
+    };
+    let app = app.layer(axum::middleware::map_request_with_state(Arc::new(trusted_proxies), proxy::real_client));
+
+    let acceptor = tls.as_ref().map(|tls| tls.acceptor());
+    let mut listeners = Vec::new();
+    for bind in &config.binds {
+        match listeners::open(bind, acceptor.as_ref()) {
+            Ok(opened) => listeners.extend(opened),
+            // The default IPv6 address is only a bonus
+            Err(err) if !config.binds_configured && matches!(bind, profile::Bind::Tcp(addr) if addr.is_ipv6()) => {
+                warn!("Not listening on {}: {}", bind, err)
+            }
+            Err(err) => {
+                eprintln!("Cannot listen on {}: {}", bind, err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(());
+    tokio::spawn(async move {
+        shutdown_signal(state).await;
+        let _ = shutdown_tx.send(());
+    });
+    let servers = listeners.into_iter().map(|(name, listener)| {
+        info!("Server running on {}", name);
+        println!("Server running on {}", name);
+        listeners::serve(listener, app.clone(), shutdown_rx.clone())
+    });
+    for result in futures::future::join_all(servers).await {
+        result.unwrap();
+    }
+    // Sockets systemd passed in are its to clean up
+    for bind in &config.binds {
+        if let profile::Bind::Unix(path) = bind {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    otel::shutdown();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(list: &str) -> jwt_keys::JwtKeys {
+        jwt_keys::JwtKeys::new(Some(list.to_string()), None, None).unwrap()
+    }
+
+    fn invite(keys: &jwt_keys::JwtKeys, room: &str, purpose: &str, expires_in: i64) -> RoomCredentials {
+        let claims = RoomInviteClaims {
+            room: room.to_string(),
+            exp: (Utc::now() + Duration::seconds(expires_in)).timestamp() as usize,
+            purpose: purpose.to_string(),
+        };
+        RoomCredentials { password: None, invite: Some(keys.encode(&claims)) }
+    }
+
+    fn password(password: &str) -> RoomCredentials {
+        RoomCredentials { password: Some(password.to_string()), invite: None }
+    }
+
+    #[tokio::test]
+    async fn protected_rooms_take_the_password() {
+        let keys = keys("a:secret");
+        let hash = bcrypt::hash("hunter2", 4).unwrap();
+        assert!(check_room_credentials(&keys, "lobby", hash.clone(), password("hunter2")).await);
+        assert!(!check_room_credentials(&keys, "lobby", hash.clone(), password("hunter3")).await);
+        assert!(!check_room_credentials(&keys, "lobby", hash, RoomCredentials { password: None, invite: None }).await);
+    }
+
+    #[tokio::test]
+    async fn invites_open_only_their_room_until_they_expire() {
+        let keys = keys("a:secret");
+        let hash = bcrypt::hash("hunter2", 4).unwrap();
+        let check = |credentials| check_room_credentials(&keys, "lobby", hash.clone(), credentials);
+        assert!(check(invite(&keys, "lobby", ROOM_INVITE_PURPOSE, 3600)).await);
+        assert!(!check(invite(&keys, "other", ROOM_INVITE_PURPOSE, 3600)).await, "for another room");
+        assert!(!check(invite(&keys, "lobby", ROOM_INVITE_PURPOSE, -3600)).await, "expired");
+        assert!(!check(invite(&keys, "lobby", PASSWORD_RESET_PURPOSE, 3600)).await, "not an invite");
+        let forged = invite(&self::keys("a:guessed"), "lobby", ROOM_INVITE_PURPOSE, 3600);
+        assert!(!check(forged).await, "signed with another secret");
+    }
+
+    #[tokio::test]
+    async fn a_bad_invite_falls_back_to_the_password() {
+        let keys = keys("a:secret");
+        let hash = bcrypt::hash("hunter2", 4).unwrap();
+        let mut credentials = invite(&keys, "other", ROOM_INVITE_PURPOSE, 3600);
+        credentials.password = Some("hunter2".to_string());
+        assert!(check_room_credentials(&keys, "lobby", hash, credentials).await);
+    }
+}
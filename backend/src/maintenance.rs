@@ -0,0 +1,73 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Longest window that can be scheduled, in seconds.
+const MAX_DURATION_SECS: u64 = 24 * 60 * 60;
+/// Furthest ahead a window can be scheduled.
+const MAX_LEAD: Duration = Duration::days(30);
+/// Longest stretch before the start that joins can be blocked, in seconds.
+const MAX_BLOCK_JOINS_BEFORE_SECS: u64 = 24 * 60 * 60;
+const MAX_MESSAGE_CHARS: usize = 300;
+
+/// A time the service is expected to be down, announced to every client so
+/// people are not caught mid-call.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    pub starts_at: DateTime<Utc>,
+    /// Expected length, in seconds
+    pub duration: u64,
+    /// Refuse new room joins from this many seconds before the start until
+    /// the end, so nobody starts a call that is about to be cut. Rejoining
+    /// a room after a dropped connection still works.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_joins_before: Option<u64>,
+    /// Shown to users next to the countdown, e.g. what is being upgraded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl MaintenanceWindow {
+    pub fn validate(&self) -> Result<(), String> {
+        let now = Utc::now();
+        if self.duration == 0 || self.duration > MAX_DURATION_SECS {
+            return Err(format!("duration must be from 1 to {} seconds", MAX_DURATION_SECS));
+        }
+        if self.block_joins_before.is_some_and(|before| before > MAX_BLOCK_JOINS_BEFORE_SECS) {
+            return Err(format!("block_joins_before must be at most {} seconds", MAX_BLOCK_JOINS_BEFORE_SECS));
+        }
+        if self.ends_at() <= now {
+            return Err("The window is already over".to_string());
+        }
+        if self.starts_at > now + MAX_LEAD {
+            return Err(format!("Maintenance can be scheduled at most {} days ahead", MAX_LEAD.num_days()));
+        }
+        if self.message.as_ref().is_some_and(|m| m.chars().count() > MAX_MESSAGE_CHARS) {
+            return Err(format!("message must be at most {} characters", MAX_MESSAGE_CHARS));
+        }
+        Ok(())
+    }
+
+    pub fn ends_at(&self) -> DateTime<Utc> {
+        self.starts_at + Duration::seconds(self.duration as i64)
+    }
+
+    pub fn is_over(&self, now: DateTime<Utc>) -> bool {
+        self.ends_at() <= now
+    }
+
+    pub fn blocks_joins(&self, now: DateTime<Utc>) -> bool {
+        let Some(before) = self.block_joins_before else { return false };
+        now >= self.starts_at - Duration::seconds(before as i64) && !self.is_over(now)
+    }
+
+    /// The `maintenance_notice` event announcing this window.
+    pub fn notice(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "maintenance_notice",
+            "starts_at": self.starts_at,
+            "duration": self.duration,
+            "joins_blocked_from": self.block_joins_before.map(|before| self.starts_at - Duration::seconds(before as i64)),
+            "message": self.message,
+        })
+    }
+}
@@ -0,0 +1,84 @@
+use std::env;
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http::HeaderMap,
+};
+use opentelemetry::{
+    global,
+    propagation::Extractor,
+    trace::TracerProvider as _,
+    KeyValue,
+};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, runtime, trace::Tracer, Resource};
+use tracing::Span;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Collector to send spans to over OTLP/gRPC, e.g. `http://localhost:4317`
+/// for Jaeger or Tempo; nothing is exported without it. The exporter reads
+/// the other standard `OTEL_*` variables too.
+const ENDPOINT_VAR: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+const SERVICE_NAME_VAR: &str = "OTEL_SERVICE_NAME";
+const SERVICE_NAME: &str = "p2p-chat-backend";
+
+/// A layer exporting every span the log filter lets through, if a collector
+/// is configured. Incoming `traceparent` headers are honoured from then on.
+pub fn layer<S>() -> Result<Option<OpenTelemetryLayer<S, Tracer>>, String>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    if env::var(ENDPOINT_VAR).ok().filter(|endpoint| !endpoint.is_empty()).is_none() {
+        return Ok(None);
+    }
+    let mut resource = Resource::default();
+    if env::var(SERVICE_NAME_VAR).is_err() {
+        resource = resource.merge(&Resource::new([KeyValue::new("service.name", SERVICE_NAME)]));
+    }
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+        .with_trace_config(opentelemetry_sdk::trace::Config::default().with_resource(resource))
+        .install_batch(runtime::Tokio)
+        .map_err(|err| format!("Cannot export traces to {}: {}", ENDPOINT_VAR, err))?;
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let tracer = provider.tracer(SERVICE_NAME);
+    global::set_tracer_provider(provider);
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Sends the spans still queued before the server exits.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+/// The span for one HTTP request, named after its route and continuing the
+/// caller's trace if it sent one. Only the path is recorded: signaling
+/// URLs may carry a token in the query.
+pub fn request_span(request: &Request) -> Span {
+    let route = request.extensions().get::<MatchedPath>().map(MatchedPath::as_str);
+    let span = tracing::info_span!(
+        "request",
+        otel.name = %format!("{} {}", request.method(), route.unwrap_or("unmatched")),
+        otel.kind = "server",
+        http.request.method = %request.method(),
+        http.route = route,
+        url.path = request.uri().path(),
+    );
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(&Headers(request.headers())));
+    span.set_parent(parent);
+    span
+}
+
+/// Reads trace context from request headers.
+struct Headers<'a>(&'a HeaderMap);
+
+impl Extractor for Headers<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
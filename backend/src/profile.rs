@@ -0,0 +1,160 @@
+use std::fmt;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+
+use axum::http::{header::{AUTHORIZATION, CONTENT_TYPE}, HeaderValue, Method};
+use tower_http::cors::CorsLayer;
+
+use crate::config::{Layers, Setting};
+
+/// Which bundle of defaults to run with.
+pub const PROFILE: Setting = Setting { key: "profile", var: "P2P_CHAT_PROFILE", flag: Some("--profile") };
+/// Where the frontend is served; reset links point there and, outside dev,
+/// it is the only origin CORS lets in.
+pub const FRONTEND_URL: Setting = Setting { key: "frontend_url", var: "FRONTEND_URL", flag: Some("--frontend-url") };
+/// Comma-separated addresses to listen on, e.g. `0.0.0.0:3000,[::]:3000`,
+/// see [`Bind`].
+pub const BIND_ADDR: Setting = Setting { key: "bind_addr", var: "BIND_ADDR", flag: Some("--bind") };
+/// `BIND_ADDR` entries naming a unix domain socket start with this.
+const UNIX_PREFIX: &str = "unix:";
+/// The `BIND_ADDR` entry for sockets passed in by systemd.
+const SYSTEMD: &str = "systemd";
+
+const DEV_FRONTEND_URL: &str = "http://127.0.0.1:3001";
+
+/// Bundled defaults for a kind of deployment. Every default can still be
+/// overridden one by one: `log`, `bind_addr`, `frontend_url`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    /// Verbose logs, any origin, listens on localhost only, over IPv4 and IPv6
+    Dev,
+    /// Like production, with more logging
+    Staging,
+    Prod,
+}
+
+/// Where connections come from, one per `BIND_ADDR` entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Bind {
+    Tcp(SocketAddr),
+    /// `unix:<path>`: a unix domain socket, for a reverse proxy on the same
+    /// host
+    Unix(PathBuf),
+    /// `systemd`: the sockets systemd opened for us with socket activation,
+    /// TCP or unix
+    Systemd,
+}
+
+impl Bind {
+    fn parse(entry: &str) -> Result<Bind, String> {
+        if entry == SYSTEMD {
+            return Ok(Bind::Systemd);
+        }
+        if let Some(path) = entry.strip_prefix(UNIX_PREFIX) {
+            return match path {
+                "" => Err(format!("{} has {:?} without a socket path", BIND_ADDR.var, entry)),
+                path => Ok(Bind::Unix(PathBuf::from(path))),
+            };
+        }
+        entry.parse().map(Bind::Tcp).map_err(|_| {
+            format!(
+                "{} has {:?}, not an address like 0.0.0.0:3000 or [::]:3000, {}<path> or {}",
+                BIND_ADDR.var, entry, UNIX_PREFIX, SYSTEMD
+            )
+        })
+    }
+}
+
+impl fmt::Display for Bind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bind::Tcp(addr) => write!(f, "{}", addr),
+            Bind::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+            Bind::Systemd => f.write_str(SYSTEMD),
+        }
+    }
+}
+
+impl Profile {
+    /// Defaults to `dev`.
+    pub fn load(layers: &Layers) -> Result<Self, String> {
+        match layers.get(&PROFILE).as_deref() {
+            None | Some("dev") => Ok(Profile::Dev),
+            Some("staging") => Ok(Profile::Staging),
+            Some("prod" | "production") => Ok(Profile::Prod),
+            Some(other) => Err(format!("Unknown profile {:?}, expected dev, staging or prod", other)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Dev => "dev",
+            Profile::Staging => "staging",
+            Profile::Prod => "prod",
+        }
+    }
+
+    /// Tracing filter used unless `log` is set.
+    pub fn log_filter(self) -> &'static str {
+        match self {
+            Profile::Dev => "debug,hyper=info,tower_http=debug",
+            Profile::Staging => "info",
+            Profile::Prod => "warn,p2p_chat_backend=info",
+        }
+    }
+
+    /// Where to listen, and whether `bind_addr` chose it. The defaults
+    /// cover IPv4 and IPv6; hosts without IPv6 only get the first.
+    pub fn binds(self, layers: &Layers) -> Result<(Vec<Bind>, bool), String> {
+        let Some(binds) = layers.get(&BIND_ADDR) else {
+            let defaults = match self {
+                Profile::Dev => [SocketAddr::from(([127, 0, 0, 1], 3000)), SocketAddr::from((Ipv6Addr::LOCALHOST, 3000))],
+                Profile::Staging | Profile::Prod => {
+                    [SocketAddr::from(([0, 0, 0, 0], 3000)), SocketAddr::from((Ipv6Addr::UNSPECIFIED, 3000))]
+                }
+            };
+            return Ok((defaults.into_iter().map(Bind::Tcp).collect(), false));
+        };
+        let binds = binds
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(Bind::parse)
+            .collect::<Result<Vec<Bind>, String>>()?;
+        if binds.is_empty() {
+            return Err(format!("{} lists no addresses", BIND_ADDR.var));
+        }
+        Ok((binds, true))
+    }
+
+    /// Deployments have to say where their frontend lives; dev assumes
+    /// `trunk serve`.
+    pub fn frontend_url(self, layers: &Layers) -> Result<String, String> {
+        match layers.get(&FRONTEND_URL).filter(|u| !u.is_empty()) {
+            Some(url) => Ok(url.trim_end_matches('/').to_string()),
+            None if self == Profile::Dev => Ok(DEV_FRONTEND_URL.to_string()),
+            None => Err(format!("{} must be set for the {} profile", FRONTEND_URL.var, self.name())),
+        }
+    }
+
+    /// Dev lets any origin in; elsewhere only the frontend may call the API
+    /// from a browser.
+    pub fn cors(self, frontend_url: &str) -> Result<CorsLayer, String> {
+        if self == Profile::Dev {
+            return Ok(CorsLayer::permissive());
+        }
+        let origin: HeaderValue = frontend_url
+            .parse()
+            .map_err(|_| format!("{} is not a valid origin", FRONTEND_URL.var))?;
+        Ok(CorsLayer::new()
+            .allow_origin(origin)
+            .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+            .allow_headers([AUTHORIZATION, CONTENT_TYPE]))
+    }
+
+    /// Production refuses to start without TURN: too many users sit behind
+    /// NATs that plain STUN cannot get through.
+    pub fn requires_turn(self) -> bool {
+        self == Profile::Prod
+    }
+}
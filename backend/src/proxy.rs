@@ -0,0 +1,184 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use axum::extract::{ConnectInfo, Request, State};
+
+use crate::config::{Layers, Setting};
+
+/// Comma-separated addresses or networks of the reverse proxies in front of
+/// us, e.g. `127.0.0.1,10.0.0.0/8,fd00::/8`. Only their `X-Forwarded-For`
+/// is believed.
+pub const TRUSTED_PROXIES: Setting =
+    Setting { key: "trusted_proxies", var: "TRUSTED_PROXIES", flag: Some("--trusted-proxies") };
+/// Path the proxy serves us under, e.g. `/chat`; every route moves below it.
+pub const BASE_PATH: Setting = Setting { key: "base_path", var: "BASE_PATH", flag: Some("--base-path") };
+
+/// An address, or a network in CIDR notation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    fn parse(entry: &str) -> Option<Network> {
+        let (addr, prefix) = match entry.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+            None => (entry.parse::<IpAddr>().ok()?, None),
+        };
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(bits);
+        (prefix <= bits).then_some(Network { addr: addr.to_canonical(), prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let mask = |bits: u32| if self.prefix == 0 { 0 } else { u128::MAX << (bits - u32::from(self.prefix)) };
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = mask(32) as u32;
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = mask(128);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The reverse proxies whose word we take for the client's address.
+#[derive(Debug, Default)]
+pub struct TrustedProxies {
+    networks: Vec<Network>,
+}
+
+impl TrustedProxies {
+    pub fn load(layers: &Layers) -> Result<Self, String> {
+        let networks = layers
+            .get(&TRUSTED_PROXIES)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                Network::parse(entry).ok_or_else(|| {
+                    format!("{} has {:?}, not an address or a network like 10.0.0.0/8", TRUSTED_PROXIES.var, entry)
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(TrustedProxies { networks })
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+
+    /// The client behind a request a trusted proxy at `peer` sent us. Each
+    /// proxy appends the address it was reached from to `X-Forwarded-For`,
+    /// so the list is read from the right, past the proxies we trust;
+    /// anything further left was written by the client and could be made up.
+    fn client(&self, peer: IpAddr, forwarded_for: &str) -> IpAddr {
+        let mut client = peer;
+        for hop in forwarded_for.rsplit(',') {
+            let Ok(ip) = hop.trim().parse::<IpAddr>() else { break };
+            client = ip.to_canonical();
+            if !self.trusts(client) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// Replaces the peer address handlers see with the client's, as trusted
+/// proxies report it, so login rate limits and the sessions list show
+/// clients rather than the proxy. Unix socket connections carry no address
+/// and can only come from this host, so they count as a trusted proxy.
+pub async fn real_client(State(proxies): State<Arc<TrustedProxies>>, mut request: Request) -> Request {
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| *addr);
+    let trusted = match peer {
+        Some(addr) => proxies.trusts(addr.ip()),
+        None => true,
+    };
+    if !trusted {
+        return request;
+    }
+    let peer = peer.unwrap_or_else(|| SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0));
+    let client = match request.headers().get("x-forwarded-for").and_then(|value| value.to_str().ok()) {
+        Some(forwarded_for) => SocketAddr::new(proxies.client(peer.ip(), forwarded_for), 0),
+        None => peer,
+    };
+    request.extensions_mut().insert(ConnectInfo(client));
+    request
+}
+
+/// `BASE_PATH` without a trailing slash; empty when the server owns the
+/// whole host.
+pub fn base_path(layers: &Layers) -> Result<String, String> {
+    let path = layers.get(&BASE_PATH).unwrap_or_default();
+    let path = path.trim().trim_end_matches('/');
+    if !path.is_empty() && !path.starts_with('/') {
+        return Err(format!("{} must start with a slash, like /chat", BASE_PATH.var));
+    }
+    Ok(path.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+
+    use super::*;
+
+    fn proxies(list: &str) -> TrustedProxies {
+        TrustedProxies { networks: list.split(',').map(|entry| Network::parse(entry).unwrap()).collect() }
+    }
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn networks_match_their_prefix() {
+        let net = Network::parse("10.1.0.0/16").unwrap();
+        assert!(net.contains(ip("10.1.200.3")));
+        assert!(!net.contains(ip("10.2.0.1")));
+        assert!(net.contains(ip("::ffff:10.1.0.9")), "IPv4-mapped addresses count as IPv4");
+        assert!(Network::parse("fd00::/8").unwrap().contains(ip("fd12::1")));
+        assert!(Network::parse("0.0.0.0/0").unwrap().contains(ip("203.0.113.7")));
+        assert!(!Network::parse("127.0.0.1").unwrap().contains(ip("127.0.0.2")), "a bare address is a /32");
+        assert_eq!(Network::parse("10.0.0.0/33"), None);
+        assert_eq!(Network::parse("10.0.0.0/x"), None);
+        assert_eq!(Network::parse("proxy.internal"), None);
+    }
+
+    #[test]
+    fn the_client_is_the_first_untrusted_hop_from_the_right() {
+        let proxies = proxies("10.0.0.0/8");
+        assert_eq!(proxies.client(ip("10.0.0.1"), "203.0.113.7, 10.0.0.2"), ip("203.0.113.7"));
+        // Made up by the client, which the first proxy appended after it
+        assert_eq!(proxies.client(ip("10.0.0.1"), "198.51.100.1, 203.0.113.7, 10.0.0.2"), ip("203.0.113.7"));
+        assert_eq!(proxies.client(ip("10.0.0.1"), "nonsense, 203.0.113.7"), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn a_chain_of_trusted_proxies_ends_at_the_leftmost() {
+        let proxies = proxies("10.0.0.0/8");
+        assert_eq!(proxies.client(ip("10.0.0.1"), "10.0.0.3, 10.0.0.2"), ip("10.0.0.3"));
+        assert_eq!(proxies.client(ip("10.0.0.1"), "garbage, 10.0.0.2"), ip("10.0.0.2"));
+    }
+
+    async fn seen_as(proxies: TrustedProxies, peer: &str, forwarded_for: &str) -> IpAddr {
+        let mut request = Request::builder().header("x-forwarded-for", forwarded_for).body(Body::empty()).unwrap();
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::new(ip(peer), 4000)));
+        let request = real_client(State(Arc::new(proxies)), request).await;
+        request.extensions().get::<ConnectInfo<SocketAddr>>().unwrap().0.ip()
+    }
+
+    #[tokio::test]
+    async fn only_trusted_peers_are_believed() {
+        assert_eq!(seen_as(proxies("10.0.0.0/8"), "10.0.0.1", "203.0.113.7").await, ip("203.0.113.7"));
+        assert_eq!(seen_as(proxies("10.0.0.0/8"), "198.51.100.1", "203.0.113.7").await, ip("198.51.100.1"));
+        assert_eq!(seen_as(TrustedProxies::default(), "127.0.0.1", "203.0.113.7").await, ip("127.0.0.1"));
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// How long a code can be scanned and confirmed.
+pub const HANDOFF_TTL: Duration = Duration::minutes(2);
+
+fn random_hex() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Shown on the signed-in device; nobody scanned it yet
+    Waiting,
+    /// Scanned by a new device, which waits for the signed-in one to confirm
+    Claimed,
+    Approved,
+    Denied,
+}
+
+/// The device that scanned a code.
+#[derive(Debug, Clone, Serialize)]
+pub struct Claimant {
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    /// Given to the device that scanned the code; only it can collect the
+    /// token, so seeing the code over someone's shoulder is not enough
+    #[serde(skip)]
+    secret: String,
+}
+
+/// Where a handoff stands, as the signed-in device sees it.
+#[derive(Debug, Serialize)]
+pub struct HandoffStatus {
+    pub state: Stage,
+    pub device: Option<Claimant>,
+}
+
+/// Login handed from a signed-in device to a new one, identified by the
+/// code in the QR code.
+#[derive(Debug)]
+struct Handoff {
+    username: String,
+    /// Session of the device showing the code
+    session_id: Uuid,
+    expires_at: DateTime<Utc>,
+    stage: Stage,
+    claimant: Option<Claimant>,
+}
+
+/// What the scanning device gets when it asks for its token.
+#[derive(Debug)]
+pub enum Redeemed {
+    /// Not confirmed yet
+    Pending,
+    Approved { username: String, device: Claimant },
+    Denied,
+}
+
+/// Open handoffs by code. Codes are single-use and expire after
+/// [`HANDOFF_TTL`].
+#[derive(Debug, Default)]
+pub struct QrLoginStore {
+    handoffs: HashMap<String, Handoff>,
+}
+
+impl QrLoginStore {
+    /// Starts a handoff from `session_id`, replacing the one it started
+    /// before, and returns its code and when it expires.
+    pub fn start(&mut self, username: &str, session_id: Uuid) -> (String, DateTime<Utc>) {
+        let now = Utc::now();
+        self.handoffs.retain(|_, h| h.expires_at > now && h.session_id != session_id);
+        let code = random_hex();
+        let expires_at = now + HANDOFF_TTL;
+        self.handoffs.insert(
+            code.clone(),
+            Handoff {
+                username: username.to_string(),
+                session_id,
+                expires_at,
+                stage: Stage::Waiting,
+                claimant: None,
+            },
+        );
+        (code, expires_at)
+    }
+
+    fn live(&mut self, code: &str) -> Option<&mut Handoff> {
+        self.handoffs.get_mut(code).filter(|h| h.expires_at > Utc::now())
+    }
+
+    /// The state of `username`'s handoff `code`.
+    pub fn status(&mut self, code: &str, username: &str) -> Option<HandoffStatus> {
+        let handoff = self.live(code).filter(|h| h.username == username)?;
+        Some(HandoffStatus { state: handoff.stage, device: handoff.claimant.clone() })
+    }
+
+    /// Claims the code for the scanning device and returns whose it is and
+    /// the secret the device redeems its token with. Fails for unknown,
+    /// expired or already scanned codes.
+    pub fn claim(&mut self, code: &str, ip: Option<IpAddr>, user_agent: Option<String>) -> Option<(String, String)> {
+        let handoff = self.live(code).filter(|h| h.stage == Stage::Waiting)?;
+        let secret = random_hex();
+        handoff.stage = Stage::Claimed;
+        handoff.claimant = Some(Claimant { ip, user_agent, secret: secret.clone() });
+        Some((handoff.username.clone(), secret))
+    }
+
+    /// The signed-in device's answer; false unless the code is `username`'s
+    /// and waits for one.
+    pub fn answer(&mut self, code: &str, username: &str, approve: bool) -> bool {
+        let Some(handoff) = self.live(code).filter(|h| h.username == username && h.stage == Stage::Claimed) else {
+            return false;
+        };
+        handoff.stage = if approve { Stage::Approved } else { Stage::Denied };
+        true
+    }
+
+    /// What the device holding `secret` gets. A final answer ends the
+    /// handoff.
+    pub fn redeem(&mut self, code: &str, secret: &str) -> Option<Redeemed> {
+        let handoff = self.live(code).filter(|h| h.claimant.as_ref().is_some_and(|c| c.secret == secret))?;
+        match handoff.stage {
+            Stage::Waiting | Stage::Claimed => Some(Redeemed::Pending),
+            Stage::Denied => {
+                self.handoffs.remove(code);
+                Some(Redeemed::Denied)
+            }
+            Stage::Approved => {
+                let handoff = self.handoffs.remove(code)?;
+                Some(Redeemed::Approved { username: handoff.username, device: handoff.claimant? })
+            }
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::ice::IceServer;
+use crate::rooms::Room;
+
+/// JSON file persistent rooms are kept in; without it they live in memory
+/// only, like everything else.
+const ROOMS_FILE_VAR: &str = "ROOMS_FILE";
+
+/// What survives a restart of a persistent room. Who is inside, the queue
+/// and the lock are live state and start over.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredRoom {
+    pub name: String,
+    owner: String,
+    #[serde(default)]
+    moderators: Vec<String>,
+    capacity: usize,
+    public: bool,
+    #[serde(default)]
+    topic: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    category: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    password_hash: Option<String>,
+    #[serde(default)]
+    lobby: bool,
+    #[serde(default)]
+    broadcast: bool,
+    #[serde(default)]
+    digests: bool,
+    #[serde(default)]
+    ice_servers: Vec<IceServer>,
+    #[serde(default)]
+    bans: HashMap<String, Option<DateTime<Utc>>>,
+    #[serde(default)]
+    participants: Vec<String>,
+}
+
+impl StoredRoom {
+    fn new(name: &str, room: &Room) -> Self {
+        StoredRoom {
+            name: name.to_string(),
+            owner: room.owner.clone(),
+            moderators: room.moderators.clone(),
+            capacity: room.capacity,
+            public: room.public,
+            topic: room.topic.clone(),
+            description: room.description.clone(),
+            category: room.category.clone(),
+            tags: room.tags.clone(),
+            password_hash: room.password_hash.clone(),
+            lobby: room.lobby,
+            broadcast: room.broadcast,
+            digests: room.digests,
+            ice_servers: room.ice_servers.clone(),
+            bans: room.bans.clone(),
+            participants: room.participants.clone(),
+        }
+    }
+
+    /// The room's name and the room, empty as after a restart.
+    pub fn into_room(self) -> (String, Room) {
+        let mut room = Room::new(self.owner, self.capacity);
+        room.moderators = self.moderators;
+        room.public = self.public;
+        room.persistent = true;
+        room.topic = self.topic;
+        room.description = self.description;
+        room.category = self.category;
+        room.tags = self.tags;
+        room.password_hash = self.password_hash;
+        room.lobby = self.lobby;
+        room.broadcast = self.broadcast;
+        room.digests = self.digests;
+        room.ice_servers = self.ice_servers;
+        room.bans = self.bans;
+        room.participants = self.participants;
+        room.forget_expired_bans();
+        (self.name, room)
+    }
+}
+
+/// The persistent rooms among `rooms`, as they are saved.
+pub fn persistent(rooms: &HashMap<String, Room>) -> Vec<StoredRoom> {
+    rooms.iter().filter(|(_, room)| room.persistent).map(|(name, room)| StoredRoom::new(name, room)).collect()
+}
+
+/// Writes persistent rooms to disk whenever they change.
+#[derive(Debug)]
+pub struct RoomStore {
+    path: PathBuf,
+    /// Serializes writes so an older snapshot never overwrites a newer one
+    write_lock: Mutex<()>,
+}
+
+impl RoomStore {
+    pub fn from_env() -> Option<Self> {
+        let path = env::var(ROOMS_FILE_VAR).ok().filter(|p| !p.is_empty())?;
+        Some(RoomStore { path: PathBuf::from(path), write_lock: Mutex::new(()) })
+    }
+
+    /// Reads the rooms saved by an earlier run. A missing file means none.
+    pub async fn load(&self) -> Result<HashMap<String, Room>, String> {
+        let data = match tokio::fs::read(&self.path).await {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(err) => return Err(format!("Cannot read {}: {}", self.path.display(), err)),
+        };
+        let stored: Vec<StoredRoom> =
+            serde_json::from_slice(&data).map_err(|err| format!("Cannot parse {}: {}", self.path.display(), err))?;
+        Ok(stored.into_iter().map(StoredRoom::into_room).collect())
+    }
+
+    /// Saves the persistent rooms among `rooms`, replacing the file
+    /// atomically.
+    pub async fn save(&self, rooms: &Mutex<HashMap<String, Room>>) -> std::io::Result<()> {
+        let _guard = self.write_lock.lock().await;
+        let stored = persistent(&*rooms.lock().await);
+        let data = serde_json::to_vec_pretty(&stored).expect("rooms serialize to JSON");
+        let tmp = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, &self.path).await
+    }
+}
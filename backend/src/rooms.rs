@@ -0,0 +1,587 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::time::{Duration, Instant};
+
+use axum::extract::ws::Message;
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::ice::IceServer;
+use crate::Peer;
+
+/// Most peers a room may allow unless `ROOM_CAPACITY_CEILING` says
+/// otherwise. Every member connects to every other one, so the connection
+/// count grows quadratically; keep this small.
+pub const DEFAULT_MAX_ROOM_CAPACITY: usize = 8;
+/// A room needs space for at least a conversation.
+pub const MIN_ROOM_CAPACITY: usize = 2;
+const CAPACITY_CEILING_VAR: &str = "ROOM_CAPACITY_CEILING";
+
+/// The server-wide limit on room capacity.
+pub fn capacity_ceiling_from_env() -> Result<usize, String> {
+    match env::var(CAPACITY_CEILING_VAR) {
+        Ok(value) => value
+            .parse()
+            .ok()
+            .filter(|n| *n >= MIN_ROOM_CAPACITY)
+            .ok_or_else(|| format!("{} must be a whole number of at least {}", CAPACITY_CEILING_VAR, MIN_ROOM_CAPACITY)),
+        Err(_) => Ok(DEFAULT_MAX_ROOM_CAPACITY),
+    }
+}
+
+/// Seconds an ordinary room may stay empty before it is removed.
+const EMPTY_TTL_VAR: &str = "ROOM_EMPTY_TTL_SECS";
+/// Days a persistent room may go unused before it is removed.
+const PERSISTENT_IDLE_VAR: &str = "PERSISTENT_ROOM_IDLE_DAYS";
+const DEFAULT_EMPTY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const DEFAULT_PERSISTENT_IDLE_DAYS: u64 = 90;
+
+/// How long empty rooms are kept before the sweeper removes them.
+#[derive(Debug, Clone, Copy)]
+pub struct RetentionPolicy {
+    pub empty_ttl: Duration,
+    pub persistent_idle: Duration,
+}
+
+impl RetentionPolicy {
+    pub fn from_env() -> Result<Self, String> {
+        let read = |var: &str| -> Result<Option<u64>, String> {
+            match env::var(var) {
+                Ok(value) => value
+                    .parse()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .map(Some)
+                    .ok_or_else(|| format!("{} must be a positive whole number", var)),
+                Err(_) => Ok(None),
+            }
+        };
+        Ok(RetentionPolicy {
+            empty_ttl: read(EMPTY_TTL_VAR)?.map_or(DEFAULT_EMPTY_TTL, Duration::from_secs),
+            persistent_idle: Duration::from_secs(
+                read(PERSISTENT_IDLE_VAR)?.unwrap_or(DEFAULT_PERSISTENT_IDLE_DAYS) * 24 * 60 * 60,
+            ),
+        })
+    }
+}
+
+/// What the room sweeper has removed since the server started.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SweepStats {
+    pub sweeps: u64,
+    pub reclaimed: u64,
+    /// Of `reclaimed`, how many were persistent rooms
+    pub reclaimed_persistent: u64,
+    pub last_sweep: Option<DateTime<Utc>>,
+}
+
+fn ban_active(until: &Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+    match until {
+        Some(until) => *until > now,
+        None => true,
+    }
+}
+
+fn resume_token() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Someone waiting outside a full or locked room, or in a room's lobby.
+#[derive(Debug, Clone)]
+pub struct Knock {
+    pub client_id: Uuid,
+    pub username: String,
+    pub tx: mpsc::Sender<Message>,
+    /// A member let them in; they enter as soon as there is space
+    pub admitted: bool,
+}
+
+#[derive(Debug)]
+pub struct Room {
+    pub peers: HashMap<Uuid, Peer>,
+    /// Client ids in the order they entered, longest-connected first
+    join_order: Vec<Uuid>,
+    /// Each member's resume token, which gets their place back from a new
+    /// connection, see [`Room::resume`]
+    resume_tokens: HashMap<Uuid, String>,
+    /// Members whose connection dropped; their place is held for a while
+    away: HashSet<Uuid>,
+    /// Newcomers must knock even when there is space
+    pub locked: bool,
+    /// Everyone but the owner waits in the lobby until the owner admits
+    /// them, also while the owner is away. Unlike the lock, chosen when the
+    /// room is created and kept.
+    pub lobby: bool,
+    /// The owner broadcasts to everyone else, who only watch; see
+    /// [`Room::broadcaster`]
+    pub broadcast: bool,
+    /// Members may post what they send for email digests, see
+    /// [`crate::digests`]
+    pub digests: bool,
+    /// Most viewers a broadcast room had at once since it was created
+    pub peak_viewers: usize,
+    pub waiting: VecDeque<Knock>,
+    /// Username of whoever created the room; they moderate it while present
+    pub owner: String,
+    /// Usernames the owner appointed to moderate alongside them
+    pub moderators: Vec<String>,
+    /// Banned usernames and when their ban ends; `None` is for good
+    pub bans: HashMap<String, Option<DateTime<Utc>>>,
+    /// Peers allowed at once, chosen by the owner
+    pub capacity: usize,
+    /// Listed in the directory and trending rooms; unlisted rooms are only
+    /// reachable by name
+    pub public: bool,
+    /// Kept while empty for much longer than ordinary rooms, see
+    /// [`RetentionPolicy`]
+    pub persistent: bool,
+    /// When someone last entered or left
+    last_used: Instant,
+    /// bcrypt hash of the password everyone but the owner must give to join,
+    /// unless they bring an invite token
+    pub password_hash: Option<String>,
+    /// Member moderating on the owner's behalf while the owner is away
+    delegate: Option<Uuid>,
+    /// STUN/TURN servers the creator brought, e.g. their own TURN server;
+    /// members use these instead of the server's when there are any
+    pub ice_servers: Vec<IceServer>,
+    /// What the room is talking about right now, shown atop the chat; set by
+    /// a moderator over signaling
+    pub topic: String,
+    /// Directory listing details, set by the moderator
+    pub description: String,
+    pub category: Option<String>,
+    pub tags: Vec<String>,
+    /// Direct-message rooms: the two users who may join; everyone else is
+    /// turned away. Empty for every other room.
+    pub participants: Vec<String>,
+}
+
+impl Room {
+    pub fn new(owner: String, capacity: usize) -> Self {
+        Room {
+            peers: HashMap::new(),
+            join_order: Vec::new(),
+            resume_tokens: HashMap::new(),
+            away: HashSet::new(),
+            locked: false,
+            lobby: false,
+            broadcast: false,
+            digests: false,
+            peak_viewers: 0,
+            waiting: VecDeque::new(),
+            owner,
+            moderators: Vec::new(),
+            bans: HashMap::new(),
+            capacity,
+            public: true,
+            persistent: false,
+            last_used: Instant::now(),
+            password_hash: None,
+            delegate: None,
+            ice_servers: Vec::new(),
+            topic: String::new(),
+            description: String::new(),
+            category: None,
+            tags: Vec::new(),
+            participants: Vec::new(),
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.peers.len() >= self.capacity
+    }
+
+    /// Whether `username` has to knock rather than walk in. The owner and
+    /// appointed moderators bypass the lock but not the capacity limit; only
+    /// the owner bypasses the lobby.
+    pub fn needs_knock(&self, username: &str) -> bool {
+        (self.lobby && username != self.owner)
+            || (!self.peers.is_empty() && (self.is_full() || (self.locked && !self.is_staff(username))))
+    }
+
+    /// The owner's clients inside the room.
+    pub fn owner_clients(&self) -> impl Iterator<Item = (&Uuid, &Peer)> {
+        self.peers.iter().filter(|(_, (username, _))| *username == self.owner)
+    }
+
+    /// Whether the client may answer knocks: a moderator, or in a lobby room
+    /// only the owner.
+    pub fn may_admit(&self, client_id: &Uuid) -> bool {
+        if self.lobby {
+            self.peers.get(client_id).is_some_and(|(username, _)| *username == self.owner)
+        } else {
+            self.is_moderator(client_id)
+        }
+    }
+
+    /// In a broadcast room, the owner's longest-connected client: the only
+    /// one that makes offers, to every other member.
+    pub fn broadcaster(&self) -> Option<Uuid> {
+        if !self.broadcast {
+            return None;
+        }
+        self.members().find(|(_, (username, _))| *username == self.owner).map(|(id, _)| id)
+    }
+
+    /// Members of a broadcast room other than the broadcaster.
+    pub fn viewers(&self) -> usize {
+        self.peers.len() - usize::from(self.broadcaster().is_some())
+    }
+
+    pub fn add_peer(&mut self, client_id: Uuid, peer: Peer) {
+        self.peers.insert(client_id, peer);
+        self.join_order.push(client_id);
+        self.resume_tokens.insert(client_id, resume_token());
+        self.last_used = Instant::now();
+        if self.broadcast {
+            self.peak_viewers = self.peak_viewers.max(self.viewers());
+        }
+    }
+
+    pub fn remove_peer(&mut self, client_id: &Uuid) -> Option<Peer> {
+        self.join_order.retain(|id| id != client_id);
+        self.resume_tokens.remove(client_id);
+        self.away.remove(client_id);
+        let peer = self.peers.remove(client_id);
+        if peer.is_some() {
+            self.last_used = Instant::now();
+        }
+        peer
+    }
+
+    /// The token that gets the member's place back after their connection
+    /// drops.
+    pub fn resume_token(&self, client_id: &Uuid) -> Option<&str> {
+        self.resume_tokens.get(client_id).map(String::as_str)
+    }
+
+    /// Holds the member's place after their connection dropped, until they
+    /// resume it or are removed; whatever is sent to them meanwhile goes
+    /// nowhere. False if they are not a member.
+    pub fn hold(&mut self, client_id: &Uuid) -> bool {
+        let Some((_, tx)) = self.peers.get_mut(client_id) else { return false };
+        // Lets go of the dropped connection so it can finish closing
+        *tx = mpsc::channel(1).0;
+        self.away.insert(*client_id);
+        true
+    }
+
+    pub fn is_away(&self, client_id: &Uuid) -> bool {
+        self.away.contains(client_id)
+    }
+
+    /// Hands the place `token` was issued for to the same user's connection
+    /// `client_id`, which keeps its seniority and gets a new token. Returns
+    /// the place's previous id and connection. A place still connected is
+    /// taken over too: its connection may not have noticed it dropped yet.
+    pub fn resume(&mut self, token: &str, client_id: Uuid, peer: Peer) -> Option<(Uuid, Peer)> {
+        let previous = self.resume_tokens.iter().find(|(_, t)| t.as_str() == token).map(|(id, _)| *id)?;
+        if self.peers[&previous].0 != peer.0 {
+            return None;
+        }
+        let replaced = self.peers.remove(&previous)?;
+        self.resume_tokens.remove(&previous);
+        self.away.remove(&previous);
+        self.peers.insert(client_id, peer);
+        self.resume_tokens.insert(client_id, resume_token());
+        for id in self.join_order.iter_mut().filter(|id| **id == previous) {
+            *id = client_id;
+        }
+        if self.delegate == Some(previous) {
+            self.delegate = Some(client_id);
+        }
+        self.last_used = Instant::now();
+        Some((previous, replaced))
+    }
+
+    /// Whether the room has been empty long enough under `policy` to be
+    /// removed. Rooms with someone inside or knocking never expire.
+    pub fn expired(&self, policy: &RetentionPolicy, now: Instant) -> bool {
+        let ttl = if self.persistent { policy.persistent_idle } else { policy.empty_ttl };
+        self.peers.is_empty() && self.waiting.is_empty() && now.duration_since(self.last_used) >= ttl
+    }
+
+    /// Members in the order they entered, longest-connected first.
+    pub fn members(&self) -> impl Iterator<Item = (Uuid, &Peer)> {
+        self.join_order.iter().map(|id| (*id, &self.peers[id]))
+    }
+
+    /// Whether `username` may join at all: anyone may, except into someone
+    /// else's direct-message room.
+    pub fn admits(&self, username: &str) -> bool {
+        self.participants.is_empty() || self.participants.iter().any(|p| p == username)
+    }
+
+    /// The owner or one of the appointed moderators.
+    pub fn is_staff(&self, username: &str) -> bool {
+        self.owner == username || self.moderators.iter().any(|m| m == username)
+    }
+
+    /// Whether `username` is banned right now. Expired bans are ignored
+    /// until [`Room::forget_expired_bans`] drops them.
+    pub fn is_banned(&self, username: &str) -> bool {
+        let now = Utc::now();
+        self.bans.get(username).is_some_and(|until| ban_active(until, now))
+    }
+
+    /// Whether the room goes when `username`'s account does: they own it
+    /// or it is one of their direct conversations.
+    pub fn belongs_to(&self, username: &str) -> bool {
+        self.owner == username || self.participants.iter().any(|p| p == username)
+    }
+
+    /// Drops a deleted account from the moderators and bans, so whoever
+    /// registers the name next starts afresh.
+    pub fn forget_user(&mut self, username: &str) {
+        self.moderators.retain(|m| m != username);
+        self.bans.remove(username);
+    }
+
+    pub fn forget_expired_bans(&mut self) {
+        let now = Utc::now();
+        self.bans.retain(|_, until| ban_active(until, now));
+    }
+
+    /// Why `actor` may not remove `target` from the room, or `None` if they
+    /// may. `actor_moderates` says whether the actor moderates right now.
+    /// Nobody removes the owner, and only the owner removes moderators.
+    pub fn removal_denied(&self, actor: &str, actor_moderates: bool, target: &str) -> Option<&'static str> {
+        if !actor_moderates {
+            Some("Only moderators can remove people")
+        } else if target == actor {
+            Some("You cannot remove yourself")
+        } else if target == self.owner {
+            Some("The owner cannot be removed")
+        } else if self.is_staff(target) && actor != self.owner {
+            Some("Only the owner can remove moderators")
+        } else {
+            None
+        }
+    }
+
+    /// Clients of `username` inside the room or knocking on it.
+    pub fn clients_of(&self, username: &str) -> Vec<Uuid> {
+        self.peers
+            .iter()
+            .filter(|(_, (u, _))| u == username)
+            .map(|(id, _)| *id)
+            .chain(self.waiting.iter().filter(|k| k.username == username).map(|k| k.client_id))
+            .collect()
+    }
+
+    /// The owner's client if they are present, otherwise the
+    /// longest-connected appointed moderator's.
+    fn staff_client(&self) -> Option<Uuid> {
+        let present = |username: &str| self.join_order.iter().copied().find(|id| self.peers[id].0 == username);
+        present(&self.owner).or_else(|| self.join_order.iter().copied().find(|id| self.is_staff(&self.peers[id].0)))
+    }
+
+    /// The client leading moderation and whether they only stand in because
+    /// no owner or appointed moderator is present.
+    pub fn moderator(&self) -> Option<(Uuid, bool)> {
+        match self.staff_client() {
+            Some(id) => Some((id, false)),
+            None => self.delegate.map(|id| (id, true)),
+        }
+    }
+
+    /// Whether the client may moderate: every present owner or appointed
+    /// moderator may, and the stand-in while none of them is around.
+    pub fn is_moderator(&self, client_id: &Uuid) -> bool {
+        self.peers.get(client_id).is_some_and(|(username, _)| self.is_staff(username))
+            || self.moderator().is_some_and(|(id, _)| id == *client_id)
+    }
+
+    /// Whether `username` may edit the room: its owner, an appointed
+    /// moderator, or whoever stands in for them.
+    pub fn can_edit(&self, username: &str) -> bool {
+        self.is_staff(username) || self.moderator().is_some_and(|(id, _)| self.peers[&id].0 == username)
+    }
+
+    /// Re-evaluates the stand-in after membership changed: while the owner
+    /// and appointed moderators are away the longest-connected member
+    /// moderates, if `delegation` is allowed; staff take over again on return.
+    pub fn update_delegate(&mut self, delegation: bool) {
+        self.delegate = if self.staff_client().is_some() || !delegation {
+            None
+        } else {
+            self.join_order.first().copied()
+        };
+    }
+
+    /// 1-based place of `client_id` in the waiting queue.
+    pub fn position(&self, client_id: &Uuid) -> Option<usize> {
+        self.waiting.iter().position(|k| k.client_id == *client_id).map(|i| i + 1)
+    }
+
+    pub fn remove_knock(&mut self, client_id: &Uuid) -> Option<Knock> {
+        let index = self.waiting.iter().position(|k| k.client_id == *client_id)?;
+        self.waiting.remove(index)
+    }
+
+    /// Moves waiting users in, in queue order, while there is space. Admitted
+    /// users always qualify; anyone does while the room is unlocked and has
+    /// no lobby, since they only knocked because it was full. Returns the
+    /// users who entered.
+    pub fn let_in_waiting(&mut self) -> Vec<Knock> {
+        if self.peers.is_empty() {
+            // Nobody is left to hold the room locked or to answer knocks
+            self.locked = false;
+        }
+        let mut entered = Vec::new();
+        while !self.is_full() {
+            let gated = self.locked || self.lobby;
+            let Some(index) = self.waiting.iter().position(|k| k.admitted || !gated) else { break };
+            let knock = self.waiting.remove(index).unwrap();
+            self.add_peer(knock.client_id, (knock.username.clone(), knock.tx.clone()));
+            entered.push(knock);
+        }
+        entered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(username: &str) -> Peer {
+        (username.to_string(), mpsc::channel(1).0)
+    }
+
+    fn knock(username: &str, admitted: bool) -> Knock {
+        Knock { client_id: Uuid::new_v4(), username: username.to_string(), tx: mpsc::channel(1).0, admitted }
+    }
+
+    fn join(room: &mut Room, username: &str) -> Uuid {
+        let id = Uuid::new_v4();
+        room.add_peer(id, peer(username));
+        id
+    }
+
+    #[test]
+    fn full_and_locked_rooms_take_knocks() {
+        let mut room = Room::new("owner".to_string(), 2);
+        room.moderators = vec!["mod".to_string()];
+        assert!(!room.needs_knock("alice"), "empty");
+        join(&mut room, "alice");
+        room.locked = true;
+        assert!(room.needs_knock("bob"));
+        assert!(!room.needs_knock("mod"), "staff bypass the lock");
+        join(&mut room, "bob");
+        assert!(room.needs_knock("owner"), "but not the capacity");
+
+        room.lobby = true;
+        room.locked = false;
+        let alice = room.members().next().unwrap().0;
+        room.remove_peer(&alice);
+        assert!(room.needs_knock("mod"), "only the owner skips the lobby");
+        assert!(!room.needs_knock("owner"));
+    }
+
+    #[test]
+    fn waiting_users_enter_in_order_when_there_is_space() {
+        let mut room = Room::new("owner".to_string(), 3);
+        join(&mut room, "owner");
+        room.locked = true;
+        room.waiting.extend([knock("alice", false), knock("bob", true), knock("carol", true)]);
+        let entered: Vec<String> = room.let_in_waiting().into_iter().map(|k| k.username).collect();
+        assert_eq!(entered, ["bob", "carol"], "only the admitted while locked, up to capacity");
+        let alice = room.waiting[0].client_id;
+        assert_eq!(room.position(&alice), Some(1));
+
+        let ids: Vec<Uuid> = room.members().map(|(id, _)| id).collect();
+        for id in &ids {
+            room.remove_peer(id);
+        }
+        let entered: Vec<String> = room.let_in_waiting().into_iter().map(|k| k.username).collect();
+        assert!(!room.locked, "nobody is left to keep it locked");
+        assert_eq!(entered, ["alice"]);
+    }
+
+    #[test]
+    fn a_held_place_is_resumed_with_its_seniority() {
+        let mut room = Room::new("owner".to_string(), 4);
+        let alice = join(&mut room, "alice");
+        join(&mut room, "bob");
+        let token = room.resume_token(&alice).unwrap().to_string();
+        assert!(room.hold(&alice));
+        assert!(room.is_away(&alice));
+
+        assert!(room.resume(&token, Uuid::new_v4(), peer("mallory")).is_none(), "another user's token");
+        let again = Uuid::new_v4();
+        assert_eq!(room.resume(&token, again, peer("alice")).map(|(id, _)| id), Some(alice));
+        assert_eq!(room.members().next().map(|(id, _)| id), Some(again));
+        assert!(!room.is_away(&again));
+        assert_ne!(room.resume_token(&again), Some(token.as_str()), "tokens are used once");
+        assert!(room.resume(&token, Uuid::new_v4(), peer("alice")).is_none());
+    }
+
+    #[test]
+    fn the_longest_connected_member_stands_in_for_absent_staff() {
+        let mut room = Room::new("owner".to_string(), 4);
+        let alice = join(&mut room, "alice");
+        let bob = join(&mut room, "bob");
+        room.update_delegate(true);
+        assert_eq!(room.moderator(), Some((alice, true)));
+        assert!(room.can_edit("alice") && !room.can_edit("bob"));
+
+        let owner = join(&mut room, "owner");
+        room.update_delegate(true);
+        assert_eq!(room.moderator(), Some((owner, false)));
+        assert!(!room.is_moderator(&alice));
+
+        room.remove_peer(&owner);
+        room.update_delegate(false);
+        assert_eq!(room.moderator(), None, "delegation is off");
+        assert!(!room.is_moderator(&bob));
+    }
+
+    #[test]
+    fn only_the_owner_removes_moderators() {
+        let mut room = Room::new("owner".to_string(), 4);
+        room.moderators = vec!["mod".to_string()];
+        assert_eq!(room.removal_denied("mod", true, "alice"), None);
+        assert!(room.removal_denied("alice", false, "bob").is_some());
+        assert!(room.removal_denied("mod", true, "mod").is_some());
+        assert!(room.removal_denied("mod", true, "owner").is_some());
+        assert!(room.removal_denied("mod", true, "mod2").is_none());
+        room.moderators.push("mod2".to_string());
+        assert!(room.removal_denied("mod", true, "mod2").is_some());
+        assert_eq!(room.removal_denied("owner", true, "mod2"), None);
+    }
+
+    #[test]
+    fn the_owner_broadcasts_to_viewers() {
+        let mut room = Room::new("owner".to_string(), 4);
+        room.broadcast = true;
+        join(&mut room, "alice");
+        let owner = join(&mut room, "owner");
+        join(&mut room, "owner");
+        join(&mut room, "bob");
+        assert_eq!(room.broadcaster(), Some(owner), "the owner's longest-connected client");
+        assert_eq!(room.viewers(), 3);
+        assert_eq!(room.peak_viewers, 3);
+        room.remove_peer(&owner);
+        assert_eq!(room.viewers(), 2);
+        assert_eq!(room.peak_viewers, 3);
+    }
+
+    #[test]
+    fn empty_rooms_expire_after_their_policy() {
+        let policy = RetentionPolicy { empty_ttl: Duration::from_secs(60), persistent_idle: Duration::from_secs(3600) };
+        let mut room = Room::new("owner".to_string(), 4);
+        let later = Instant::now() + Duration::from_secs(120);
+        assert!(room.expired(&policy, later));
+        room.persistent = true;
+        assert!(!room.expired(&policy, later));
+        room.persistent = false;
+        room.waiting.push_back(knock("alice", false));
+        assert!(!room.expired(&policy, later), "someone is knocking");
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Lifetime of an access token. A session unused for this long can only hold
+/// expired tokens, so it is dropped.
+pub const TOKEN_TTL: Duration = Duration::hours(24);
+
+/// A login: created on successful authentication and referenced by the `sid`
+/// claim of every token issued for it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    #[serde(skip)]
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+/// Active sessions by id. Removing a session revokes every token that names it.
+#[derive(Debug, Default)]
+pub struct SessionStore {
+    sessions: HashMap<Uuid, Session>,
+}
+
+impl SessionStore {
+    pub fn create(&mut self, username: &str, ip: Option<IpAddr>, user_agent: Option<String>) -> Uuid {
+        let now = Utc::now();
+        self.sessions.retain(|_, s| now - s.last_seen < TOKEN_TTL);
+
+        let id = Uuid::new_v4();
+        self.sessions.insert(
+            id,
+            Session {
+                username: username.to_string(),
+                created_at: now,
+                last_seen: now,
+                ip,
+                user_agent,
+            },
+        );
+        id
+    }
+
+    /// Whether `id` is a live session of `username`; marks it as just used.
+    pub fn touch(&mut self, id: &Uuid, username: &str) -> bool {
+        match self.sessions.get_mut(id) {
+            Some(session) if session.username == username => {
+                session.last_seen = Utc::now();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<&Session> {
+        self.sessions.get(id)
+    }
+
+    /// Takes up a session another instance created, marked as just used.
+    pub fn adopt(&mut self, id: Uuid, session: Session) {
+        self.sessions.insert(id, Session { last_seen: Utc::now(), ..session });
+    }
+
+    pub fn has_user(&self, username: &str) -> bool {
+        self.sessions.values().any(|s| s.username == username)
+    }
+
+    pub fn remove(&mut self, id: &Uuid) -> Option<Session> {
+        self.sessions.remove(id)
+    }
+
+    /// Removes all of `username`'s sessions except `keep` and returns their ids.
+    pub fn remove_user(&mut self, username: &str, keep: Option<Uuid>) -> Vec<Uuid> {
+        let ids: Vec<Uuid> = self
+            .sessions
+            .iter()
+            .filter(|(id, s)| s.username == username && Some(**id) != keep)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &ids {
+            self.sessions.remove(id);
+        }
+        ids
+    }
+
+    /// `username`'s sessions, most recently used first.
+    pub fn for_user(&self, username: &str) -> Vec<(Uuid, Session)> {
+        let mut list: Vec<(Uuid, Session)> = self
+            .sessions
+            .iter()
+            .filter(|(_, s)| s.username == username)
+            .map(|(id, s)| (*id, s.clone()))
+            .collect();
+        list.sort_by_key(|(_, s)| std::cmp::Reverse(s.last_seen));
+        list
+    }
+}
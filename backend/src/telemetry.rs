@@ -0,0 +1,134 @@
+use std::env;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+use crate::{bearer_token, AppState};
+
+/// Bearer token Prometheus must send to scrape `/metrics`; without it the
+/// endpoint is open, for servers only reachable from the scraper.
+const METRICS_TOKEN_VAR: &str = "METRICS_TOKEN";
+
+const HTTP_DURATION: &str = "p2p_chat_http_request_duration_seconds";
+const AUTH_FAILURES: &str = "p2p_chat_auth_failures_total";
+const SIGNALING_CONNECTIONS: &str = "p2p_chat_signaling_connections";
+const SIGNALING_RELAYED: &str = "p2p_chat_signaling_relayed_total";
+const ROOMS: &str = "p2p_chat_rooms";
+const ROOM_MEMBERS: &str = "p2p_chat_room_members";
+/// From 5 ms to 10 s; signaling and account calls are fast, logins spend
+/// most of their time in bcrypt.
+const HTTP_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+/// How often recorded histogram samples are folded into their buckets.
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The exporter and who may read it.
+pub struct Metrics {
+    handle: PrometheusHandle,
+    token: Option<String>,
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics").field("protected", &self.token.is_some()).finish()
+    }
+}
+
+impl Metrics {
+    /// Installs the Prometheus recorder, which every `metrics` macro in the
+    /// process reports to from then on.
+    pub fn install() -> Result<Self, String> {
+        let handle = PrometheusBuilder::new()
+            .set_buckets_for_metric(Matcher::Full(HTTP_DURATION.to_string()), HTTP_BUCKETS)
+            .and_then(PrometheusBuilder::install_recorder)
+            .map_err(|err| format!("Cannot set up metrics: {}", err))?;
+        let upkeep = handle.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                upkeep.run_upkeep();
+            }
+        });
+        let token = env::var(METRICS_TOKEN_VAR).ok().filter(|token| !token.is_empty());
+        Ok(Metrics { handle, token })
+    }
+
+    pub fn is_protected(&self) -> bool {
+        self.token.is_some()
+    }
+}
+
+/// Signaling transports, as the `transport` label.
+#[derive(Clone, Copy, Debug)]
+pub enum Transport {
+    WebSocket,
+    EventStream,
+}
+
+impl Transport {
+    pub fn label(self) -> &'static str {
+        match self {
+            Transport::WebSocket => "websocket",
+            Transport::EventStream => "sse",
+        }
+    }
+}
+
+pub fn connection_opened(transport: Transport) {
+    gauge!(SIGNALING_CONNECTIONS, "transport" => transport.label()).increment(1.0);
+}
+
+pub fn connection_closed(transport: Transport) {
+    gauge!(SIGNALING_CONNECTIONS, "transport" => transport.label()).decrement(1.0);
+}
+
+/// Counts a signaling message handed on to another peer, by its `type`.
+pub fn relayed(kind: &'static str) {
+    counter!(SIGNALING_RELAYED, "type" => kind).increment(1);
+}
+
+/// Times every routed request, labelled with its route rather than its
+/// path so room and user names do not each get a series, and counts the
+/// ones turned away for missing or bad credentials.
+pub async fn track_http(path: MatchedPath, request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = path.as_str().to_string();
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let status = response.status();
+    if status == StatusCode::UNAUTHORIZED {
+        counter!(AUTH_FAILURES, "route" => route.clone()).increment(1);
+    }
+    histogram!(
+        HTTP_DURATION,
+        "method" => method,
+        "route" => route,
+        "status" => status.as_str().to_string()
+    )
+    .record(started.elapsed().as_secs_f64());
+    response
+}
+
+/// `GET /metrics`: everything recorded so far in the Prometheus text
+/// format, with room gauges read at scrape time.
+pub async fn render(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    if let Some(token) = &state.metrics.token {
+        if bearer_token(&headers) != Some(token.as_str()) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+    {
+        let rooms = state.rooms.lock().await;
+        gauge!(ROOMS).set(rooms.len() as f64);
+        gauge!(ROOM_MEMBERS).set(rooms.values().map(|room| room.peers.len()).sum::<usize>() as f64);
+    }
+    ([("content-type", "text/plain; version=0.0.4")], state.metrics.handle.render()).into_response()
+}
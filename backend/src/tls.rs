@@ -0,0 +1,140 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use rustls::crypto::{aws_lc_rs, CryptoProvider};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::{Layers, Setting};
+
+/// PEM file with the certificate chain, leaf first.
+pub const CERT: Setting = Setting { key: "tls_cert", var: "TLS_CERT", flag: Some("--tls-cert") };
+/// PEM file with the certificate's private key.
+pub const KEY: Setting = Setting { key: "tls_key", var: "TLS_KEY", flag: Some("--tls-key") };
+
+/// Connections that have not finished the handshake by then are dropped, so
+/// idle sockets cannot pile up.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where the certificate and its key are read from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl TlsFiles {
+    /// `None` unless both files are named: without them we speak plain HTTP.
+    pub fn load(layers: &Layers) -> Result<Option<Self>, String> {
+        let cert = layers.get(&CERT).filter(|path| !path.is_empty());
+        let key = layers.get(&KEY).filter(|path| !path.is_empty());
+        match (cert, key) {
+            (Some(cert), Some(key)) => Ok(Some(TlsFiles { cert: cert.into(), key: key.into() })),
+            (None, None) => Ok(None),
+            _ => Err(format!("{} and {} must be set together", CERT.var, KEY.var)),
+        }
+    }
+
+    fn read(&self, provider: &CryptoProvider) -> Result<CertifiedKey, String> {
+        let open = |path: &PathBuf| {
+            File::open(path)
+                .map(BufReader::new)
+                .map_err(|err| format!("Cannot read {}: {}", path.display(), err))
+        };
+        let certs = rustls_pemfile::certs(&mut open(&self.cert)?)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("{} is not a PEM certificate chain: {}", self.cert.display(), err))?;
+        if certs.is_empty() {
+            return Err(format!("{} holds no certificate", self.cert.display()));
+        }
+        let key = rustls_pemfile::private_key(&mut open(&self.key)?)
+            .map_err(|err| format!("{} is not a PEM private key: {}", self.key.display(), err))?
+            .ok_or_else(|| format!("{} holds no private key", self.key.display()))?;
+        let key = provider
+            .key_provider
+            .load_private_key(key)
+            .map_err(|err| format!("Unusable private key in {}: {}", self.key.display(), err))?;
+        let certified = CertifiedKey::new(certs, key);
+        certified
+            .keys_match()
+            .map_err(|_| format!("{} is not the key of {}", self.key.display(), self.cert.display()))?;
+        Ok(certified)
+    }
+}
+
+/// Hands every handshake the certificate read last.
+#[derive(Debug)]
+struct Certificate {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for Certificate {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// TLS termination for the TCP listeners, with a certificate that can be
+/// swapped without a restart.
+pub struct Tls {
+    files: TlsFiles,
+    provider: Arc<CryptoProvider>,
+    certificate: Arc<Certificate>,
+    acceptor: TlsAcceptor,
+}
+
+impl Tls {
+    /// Reads the certificate and key, failing if either is missing or they
+    /// do not belong together. Only HTTP/1.1 is offered, as WebSocket
+    /// upgrades need it.
+    pub fn new(files: TlsFiles) -> Result<Self, String> {
+        let provider = Arc::new(aws_lc_rs::default_provider());
+        let certificate = Arc::new(Certificate { current: RwLock::new(Arc::new(files.read(&provider)?)) });
+        let mut config = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|err| format!("Cannot set up TLS: {}", err))?
+            .with_no_client_auth()
+            .with_cert_resolver(certificate.clone());
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(Tls { files, provider, certificate, acceptor: TlsAcceptor::from(Arc::new(config)) })
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        self.acceptor.clone()
+    }
+
+    /// Reads the files again, e.g. after a renewal. Broken files leave the
+    /// certificate in use alone.
+    pub fn reload(&self) -> Result<(), String> {
+        let certified = self.files.read(&self.provider)?;
+        *self.certificate.current.write().unwrap() = Arc::new(certified);
+        Ok(())
+    }
+}
+
+/// Reloads the certificate on every SIGHUP, which is what ACME clients like
+/// certbot can be told to send after renewing.
+#[cfg(unix)]
+pub async fn reload_on_hangup(tls: Arc<Tls>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    use tracing::{info, warn};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(err) => {
+            warn!("Cannot listen for SIGHUP, the TLS certificate will not be reloaded: {}", err);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match tls.reload() {
+            Ok(()) => info!("TLS certificate reloaded from {}", tls.files.cert.display()),
+            Err(err) => warn!("Keeping the old TLS certificate: {}", err),
+        }
+    }
+}
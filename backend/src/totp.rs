@@ -0,0 +1,217 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const ISSUER: &str = "P2P Chat";
+const RECOVERY_CODE_COUNT: usize = 8;
+const RECOVERY_CODE_LEN: usize = 10;
+/// bcrypt cost for recovery codes. Lower than for passwords: the codes are
+/// random, and a login may compare one against every stored hash.
+const RECOVERY_CODE_COST: u32 = 8;
+
+/// Second-factor state for a user. The secret is stored as soon as enrollment
+/// starts, but it is only enforced at login once `enabled` is set by a
+/// successful confirmation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpState {
+    secret: Vec<u8>,
+    pub enabled: bool,
+    /// bcrypt hashes of the unused recovery codes
+    recovery_codes: Vec<String>,
+}
+
+impl TotpState {
+    pub fn generate() -> Self {
+        TotpState {
+            // 160-bit secrets always satisfy the RFC length check
+            secret: Secret::generate_secret().to_bytes().unwrap(),
+            enabled: false,
+            recovery_codes: Vec::new(),
+        }
+    }
+
+    /// Rebuilds the state from the columns of the `users` table.
+    pub fn from_stored(secret_base32: &str, enabled: bool, recovery_codes: Vec<String>) -> Option<Self> {
+        let secret = Secret::Encoded(secret_base32.to_string()).to_bytes().ok()?;
+        Some(TotpState { secret, enabled, recovery_codes })
+    }
+
+    /// The secret as the `users` table keeps it.
+    pub fn secret_base32(&self) -> String {
+        Secret::Raw(self.secret.clone()).to_encoded().to_string()
+    }
+
+    fn totp(&self, username: &str) -> Option<TOTP> {
+        TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            self.secret.clone(),
+            Some(ISSUER.to_string()),
+            username.to_string(),
+        )
+        .ok()
+    }
+
+    /// Returns the base32 secret and the `otpauth://` URI for authenticator apps.
+    pub fn provisioning(&self, username: &str) -> Option<(String, String)> {
+        let totp = self.totp(username)?;
+        Some((totp.get_secret_base32(), totp.get_url()))
+    }
+
+    pub fn check_code(&self, username: &str, code: &str) -> bool {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        self.check_code_at(username, code, now)
+    }
+
+    /// Accepts the code for `time` and, for clocks that drift, the codes of
+    /// the 30-second steps either side of it.
+    fn check_code_at(&self, username: &str, code: &str, time: u64) -> bool {
+        self.totp(username).is_some_and(|totp| totp.check(code.trim(), time))
+    }
+
+    /// Marks the factor as enabled with the hashes of its first recovery
+    /// codes, see [`recovery_codes`].
+    pub fn enable(&mut self, recovery_hashes: Vec<String>) {
+        self.enabled = true;
+        self.recovery_codes = recovery_hashes;
+    }
+
+    /// Replaces every recovery code, used or not.
+    pub fn replace_recovery_codes(&mut self, recovery_hashes: Vec<String>) {
+        self.recovery_codes = recovery_hashes;
+    }
+
+    /// Hashes of the recovery codes not used yet, to check a code against
+    /// with [`find_recovery_code`] without holding the user table.
+    pub fn recovery_hashes(&self) -> Vec<String> {
+        self.recovery_codes.clone()
+    }
+
+    /// Uses up the recovery code with this hash. `false` if it is gone,
+    /// because another login used it first or the codes were replaced.
+    pub fn use_recovery_code(&mut self, hash: &str) -> bool {
+        match self.recovery_codes.iter().position(|stored| stored == hash) {
+            Some(pos) => {
+                self.recovery_codes.remove(pos);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A fresh set of recovery codes with their bcrypt hashes. The codes are
+/// shown to the user once; only the hashes are kept. Blocking: run it off
+/// the async executor.
+pub fn recovery_codes() -> (Vec<String>, Vec<String>) {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(RECOVERY_CODE_LEN)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect()
+        })
+        .collect();
+    // Hashing only fails for costs out of range
+    let hashes = codes.iter().map(|code| bcrypt::hash(code, RECOVERY_CODE_COST).unwrap()).collect();
+    (codes, hashes)
+}
+
+/// The hash among `hashes` that `code` matches, if any. Blocking like
+/// [`recovery_codes`].
+pub fn find_recovery_code(hashes: &[String], code: &str) -> Option<String> {
+    let code = code.trim().to_ascii_lowercase();
+    hashes.iter().find(|hash| bcrypt::verify(&code, hash).unwrap_or(false)).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER: &str = "alice";
+    const NOW: u64 = 1_700_000_000;
+
+    fn code_at(state: &TotpState, time: u64) -> String {
+        state.totp(USER).unwrap().generate(time)
+    }
+
+    #[test]
+    fn accepts_codes_within_one_step() {
+        let state = TotpState::generate();
+        for time in [NOW - 30, NOW, NOW + 30] {
+            assert!(state.check_code_at(USER, &code_at(&state, time), NOW), "code for {} refused", time);
+        }
+        assert!(state.check_code_at(USER, &format!(" {} ", code_at(&state, NOW)), NOW));
+    }
+
+    #[test]
+    fn rejects_codes_outside_the_window() {
+        let state = TotpState::generate();
+        for time in [NOW - 90, NOW + 90] {
+            let code = code_at(&state, time);
+            // Codes of distant steps may collide with a current one by chance
+            if [NOW - 30, NOW, NOW + 30].iter().all(|&t| code_at(&state, t) != code) {
+                assert!(!state.check_code_at(USER, &code, NOW), "code for {} accepted", time);
+            }
+        }
+        assert!(!state.check_code_at(USER, "", NOW));
+        assert!(!state.check_code_at(USER, "not a code", NOW));
+    }
+
+    #[test]
+    fn codes_are_bound_to_the_secret() {
+        let state = TotpState::generate();
+        let other = TotpState::generate();
+        let code = code_at(&other, NOW);
+        if code != code_at(&state, NOW) {
+            assert!(!state.check_code_at(USER, &code, NOW));
+        }
+    }
+
+    fn enabled() -> (TotpState, Vec<String>) {
+        let mut state = TotpState::generate();
+        let (codes, hashes) = recovery_codes();
+        state.enable(hashes);
+        (state, codes)
+    }
+
+    /// Logs in with a recovery code the way the login handler does.
+    fn use_code(state: &mut TotpState, code: &str) -> bool {
+        find_recovery_code(&state.recovery_hashes(), code).is_some_and(|hash| state.use_recovery_code(&hash))
+    }
+
+    #[test]
+    fn recovery_codes_work_once() {
+        let (mut state, codes) = enabled();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(codes.iter().all(|code| code.len() == RECOVERY_CODE_LEN));
+
+        assert!(use_code(&mut state, &codes[0].to_ascii_uppercase()));
+        assert!(!use_code(&mut state, &codes[0]));
+        assert!(use_code(&mut state, &codes[1]));
+        assert!(!use_code(&mut state, "0000000000"));
+    }
+
+    #[test]
+    fn only_hashes_are_kept() {
+        let (state, codes) = enabled();
+        let stored = serde_json::to_string(&state).unwrap();
+        assert!(codes.iter().all(|code| !stored.contains(code.as_str())));
+    }
+
+    #[test]
+    fn replacing_recovery_codes_drops_the_old_ones() {
+        let (mut state, old) = enabled();
+        let (new, hashes) = recovery_codes();
+        state.replace_recovery_codes(hashes);
+        if !new.contains(&old[0]) {
+            assert!(!use_code(&mut state, &old[0]));
+        }
+        assert!(use_code(&mut state, &new[0]));
+    }
+}
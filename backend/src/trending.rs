@@ -0,0 +1,160 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Only activity within this window counts towards "popular now".
+const WINDOW: Duration = Duration::from_secs(60 * 60);
+/// A join says more about interest than one relayed signaling message.
+const JOIN_WEIGHT: usize = 10;
+/// Hours of history kept per room for its owner's stats.
+pub const HISTORY_HOURS: i64 = 24;
+const SECS_PER_HOUR: i64 = 60 * 60;
+
+#[derive(Debug, Default)]
+struct RoomActivity {
+    joins: VecDeque<Instant>,
+    relays: VecDeque<Instant>,
+}
+
+impl RoomActivity {
+    fn prune(&mut self, cutoff: Instant) {
+        while self.joins.front().is_some_and(|t| *t < cutoff) {
+            self.joins.pop_front();
+        }
+        while self.relays.front().is_some_and(|t| *t < cutoff) {
+            self.relays.pop_front();
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.joins.is_empty() && self.relays.is_empty()
+    }
+}
+
+/// One hour of a room's activity.
+#[derive(Debug, Clone, Serialize)]
+pub struct HourStats {
+    pub start: DateTime<Utc>,
+    pub joins: usize,
+    /// Most members at once, as counted at each join that hour
+    pub peak_peers: usize,
+    /// Signaling messages relayed between members, chat relay included
+    pub relays: usize,
+    pub relay_bytes: usize,
+}
+
+impl HourStats {
+    fn new(start: DateTime<Utc>) -> Self {
+        HourStats { start, joins: 0, peak_peers: 0, relays: 0, relay_bytes: 0 }
+    }
+}
+
+/// Recent joins and relayed signaling messages per room, kept separately
+/// from the rooms themselves so activity outlives a room emptying out.
+/// Besides the last hour for trending, an hourly history of the last
+/// [`HISTORY_HOURS`] is kept for owners.
+#[derive(Debug, Default)]
+pub struct ActivityTracker {
+    rooms: HashMap<String, RoomActivity>,
+    history: HashMap<String, VecDeque<HourStats>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RoomScore {
+    pub name: String,
+    pub joins: usize,
+    pub relays: usize,
+    pub score: usize,
+}
+
+/// Start of the hour `now` falls in.
+fn hour_of(now: DateTime<Utc>) -> DateTime<Utc> {
+    let secs = now.timestamp();
+    DateTime::from_timestamp(secs - secs.rem_euclid(SECS_PER_HOUR), 0).unwrap_or(now)
+}
+
+impl ActivityTracker {
+    /// A member joined `room`, which now has `peers` members.
+    pub fn record_join(&mut self, room: &str, peers: usize) {
+        self.rooms.entry(room.to_string()).or_default().joins.push_back(Instant::now());
+        self.prune_history();
+        let hour = self.current_hour(room);
+        hour.joins += 1;
+        hour.peak_peers = hour.peak_peers.max(peers);
+    }
+
+    pub fn record_relay(&mut self, room: &str, bytes: usize) {
+        self.rooms.entry(room.to_string()).or_default().relays.push_back(Instant::now());
+        let hour = self.current_hour(room);
+        hour.relays += 1;
+        hour.relay_bytes += bytes;
+    }
+
+    fn current_hour(&mut self, room: &str) -> &mut HourStats {
+        let start = hour_of(Utc::now());
+        let hours = self.history.entry(room.to_string()).or_default();
+        if hours.back().map(|hour| hour.start) != Some(start) {
+            hours.push_back(HourStats::new(start));
+        }
+        hours.back_mut().unwrap()
+    }
+
+    fn prune_history(&mut self) {
+        let cutoff = hour_of(Utc::now()) - chrono::Duration::hours(HISTORY_HOURS - 1);
+        self.history.retain(|_, hours| {
+            while hours.front().is_some_and(|hour| hour.start < cutoff) {
+                hours.pop_front();
+            }
+            !hours.is_empty()
+        });
+    }
+
+    /// `room`'s activity hour by hour over the last [`HISTORY_HOURS`],
+    /// oldest first. Hours without any are listed too, as zeros.
+    pub fn history(&mut self, room: &str) -> Vec<HourStats> {
+        self.prune_history();
+        let now = hour_of(Utc::now());
+        let recorded = self.history.get(room);
+        (0..HISTORY_HOURS)
+            .rev()
+            .map(|ago| now - chrono::Duration::hours(ago))
+            .map(|start| {
+                recorded
+                    .and_then(|hours| hours.iter().find(|hour| hour.start == start))
+                    .cloned()
+                    .unwrap_or_else(|| HourStats::new(start))
+            })
+            .collect()
+    }
+
+    /// Drops a deleted room's history, so a new room by the same name
+    /// starts afresh.
+    pub fn forget(&mut self, room: &str) {
+        self.rooms.remove(room);
+        self.history.remove(room);
+    }
+
+    /// Rooms with activity in the last hour, busiest first.
+    pub fn trending(&mut self) -> Vec<RoomScore> {
+        if let Some(cutoff) = Instant::now().checked_sub(WINDOW) {
+            self.rooms.retain(|_, activity| {
+                activity.prune(cutoff);
+                !activity.is_empty()
+            });
+        }
+        let mut scores: Vec<RoomScore> = self
+            .rooms
+            .iter()
+            .map(|(name, activity)| RoomScore {
+                name: name.clone(),
+                joins: activity.joins.len(),
+                relays: activity.relays.len(),
+                score: activity.joins.len() * JOIN_WEIGHT + activity.relays.len(),
+            })
+            .collect();
+        scores.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+        scores
+    }
+}
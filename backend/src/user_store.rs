@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use sqlx::sqlite::{SqlitePool, SqliteRow};
+use sqlx::Row;
+use tokio::sync::Mutex;
+
+use crate::totp::TotpState;
+use crate::User;
+
+/// Keeps accounts in the `users` table of the database whenever they
+/// change. Without a database they live in memory only.
+#[derive(Debug)]
+pub struct UserStore {
+    pool: SqlitePool,
+    /// Serializes writes so an older snapshot never overwrites a newer one
+    write_lock: Mutex<()>,
+}
+
+fn to_json(value: &impl serde::Serialize) -> String {
+    serde_json::to_string(value).expect("account fields serialize to JSON")
+}
+
+fn from_json<T: serde::de::DeserializeOwned>(row: &SqliteRow, column: &str) -> Result<T, String> {
+    let username: String = row.get("username");
+    serde_json::from_str(row.get(column)).map_err(|err| format!("Cannot read {} of {}: {}", column, username, err))
+}
+
+fn read_user(row: &SqliteRow) -> Result<(String, User), String> {
+    let username: String = row.get("username");
+    let totp = match row.get::<Option<String>, _>("totp_secret") {
+        Some(secret) => {
+            let recovery_codes = from_json(row, "totp_recovery_codes")?;
+            let totp = TotpState::from_stored(&secret, row.get("totp_enabled"), recovery_codes)
+                .ok_or_else(|| format!("Invalid 2FA secret of {}", username))?;
+            Some(totp)
+        }
+        None => None,
+    };
+    let user = User {
+        password_hash: row.get("password_hash"),
+        email: row.get("email"),
+        totp,
+        profile: from_json(row, "profile")?,
+        rooms: from_json(row, "rooms")?,
+        display: from_json(row, "display")?,
+        settings: from_json(row, "settings")?,
+        onboarding_completed: row.get("onboarding_completed"),
+        disabled: row.get("disabled"),
+    };
+    Ok((username, user))
+}
+
+async fn write_user<'e, E>(executor: E, username: &str, user: &User) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Sqlite>,
+{
+    sqlx::query(
+        "INSERT INTO users (username, password_hash, email, totp_secret, totp_enabled, totp_recovery_codes, \
+         profile, rooms, display, settings, onboarding_completed, disabled) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) \
+         ON CONFLICT (username) DO UPDATE SET password_hash = excluded.password_hash, email = excluded.email, \
+         totp_secret = excluded.totp_secret, totp_enabled = excluded.totp_enabled, \
+         totp_recovery_codes = excluded.totp_recovery_codes, profile = excluded.profile, rooms = excluded.rooms, \
+         display = excluded.display, settings = excluded.settings, \
+         onboarding_completed = excluded.onboarding_completed, disabled = excluded.disabled",
+    )
+    .bind(username)
+    .bind(&user.password_hash)
+    .bind(&user.email)
+    .bind(user.totp.as_ref().map(TotpState::secret_base32))
+    .bind(user.totp.as_ref().is_some_and(|t| t.enabled))
+    .bind(to_json(&user.totp.as_ref().map(TotpState::recovery_hashes).unwrap_or_default()))
+    .bind(to_json(&user.profile))
+    .bind(to_json(&user.rooms))
+    .bind(to_json(&user.display))
+    .bind(to_json(&user.settings))
+    .bind(user.onboarding_completed)
+    .bind(user.disabled)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+impl UserStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        UserStore { pool, write_lock: Mutex::new(()) }
+    }
+
+    /// Reads the accounts saved by an earlier run.
+    pub async fn load(&self) -> Result<HashMap<String, User>, String> {
+        let rows = sqlx::query("SELECT * FROM users")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|err| format!("Cannot read accounts: {}", err))?;
+        rows.iter().map(read_user).collect()
+    }
+
+    /// Saves `username` as it is in `users`, or deletes it if it is gone.
+    pub async fn save(&self, users: &Mutex<HashMap<String, User>>, username: &str) -> Result<(), sqlx::Error> {
+        let _guard = self.write_lock.lock().await;
+        let user = users.lock().await.get(username).cloned();
+        match user {
+            Some(user) => write_user(&self.pool, username, &user).await,
+            None => {
+                sqlx::query("DELETE FROM users WHERE username = ?").bind(username).execute(&self.pool).await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Replaces every account with those in `users`, as after a restore.
+    pub async fn save_all(&self, users: &Mutex<HashMap<String, User>>) -> Result<(), sqlx::Error> {
+        let _guard = self.write_lock.lock().await;
+        let users = users.lock().await.clone();
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query("DELETE FROM users").execute(&mut *transaction).await?;
+        for (username, user) in &users {
+            write_user(&mut *transaction, username, user).await?;
+        }
+        transaction.commit().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AccountRooms, AccountSettings, DisplaySettings, Profile};
+
+    /// A fresh in-memory database. One connection, since each would open
+    /// a database of its own.
+    async fn store() -> UserStore {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+        crate::db::migrate(&pool).await.unwrap();
+        UserStore::new(pool)
+    }
+
+    fn user(email: Option<&str>) -> User {
+        User {
+            password_hash: "$2b$04$hash".to_string(),
+            email: email.map(str::to_string),
+            totp: None,
+            profile: Profile::default(),
+            rooms: AccountRooms::default(),
+            display: DisplaySettings::default(),
+            settings: AccountSettings::default(),
+            onboarding_completed: false,
+            disabled: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn accounts_survive_a_reload() {
+        let store = store().await;
+        let mut alice = user(Some("alice@example.com"));
+        let mut totp = TotpState::generate();
+        totp.enable(vec!["$2b$04$recovery".to_string()]);
+        alice.totp = Some(totp);
+        alice.profile.display_name = Some("Alice".to_string());
+        alice.disabled = true;
+        let users = Mutex::new(HashMap::from([("alice".to_string(), alice.clone())]));
+        store.save(&users, "alice").await.unwrap();
+
+        let loaded = store.load().await.unwrap();
+        let stored = &loaded["alice"];
+        assert_eq!(stored.email, alice.email);
+        assert_eq!(stored.profile.display_name.as_deref(), Some("Alice"));
+        assert!(stored.disabled);
+        let (totp, original) = (stored.totp.as_ref().unwrap(), alice.totp.as_ref().unwrap());
+        assert!(totp.enabled);
+        assert_eq!(totp.secret_base32(), original.secret_base32());
+        assert_eq!(totp.recovery_hashes(), original.recovery_hashes());
+    }
+
+    #[tokio::test]
+    async fn saving_a_removed_account_deletes_it() {
+        let store = store().await;
+        let users = Mutex::new(HashMap::from([("alice".to_string(), user(None))]));
+        store.save(&users, "alice").await.unwrap();
+        users.lock().await.remove("alice");
+        store.save(&users, "alice").await.unwrap();
+        assert!(store.load().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn accounts_may_share_an_email() {
+        let store = store().await;
+        let users = Mutex::new(HashMap::from([
+            ("alice".to_string(), user(Some("shared@example.com"))),
+            ("bob".to_string(), user(Some("Shared@example.com"))),
+        ]));
+        store.save_all(&users).await.unwrap();
+        assert_eq!(store.load().await.unwrap().len(), 2);
+
+        users.lock().await.remove("bob");
+        store.save_all(&users).await.unwrap();
+        assert_eq!(store.load().await.unwrap().keys().collect::<Vec<_>>(), ["alice"]);
+    }
+}
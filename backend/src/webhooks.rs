@@ -0,0 +1,223 @@
+use std::env;
+use std::time::Duration;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// Comma-separated URLs that receive every room lifecycle event.
+const WEBHOOK_URLS_VAR: &str = "WEBHOOK_URLS";
+/// Key the payloads are signed with; receivers need it to check them.
+const WEBHOOK_SECRET_VAR: &str = "WEBHOOK_SECRET";
+/// Comma-separated event names to send; all of them when unset.
+const WEBHOOK_EVENTS_VAR: &str = "WEBHOOK_EVENTS";
+
+const SIGNATURE_HEADER: &str = "X-P2P-Chat-Signature";
+const TIMESTAMP_HEADER: &str = "X-P2P-Chat-Timestamp";
+const EVENT_HEADER: &str = "X-P2P-Chat-Event";
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// A delivery that fails is retried after 1s, then 2s, ...
+const MAX_ATTEMPTS: u32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoomEvent {
+    Created,
+    /// The room had nobody in it and now has one peer
+    FirstPeerJoined,
+    /// The last peer left
+    Emptied,
+}
+
+impl RoomEvent {
+    const ALL: [RoomEvent; 3] = [RoomEvent::Created, RoomEvent::FirstPeerJoined, RoomEvent::Emptied];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RoomEvent::Created => "room.created",
+            RoomEvent::FirstPeerJoined => "room.first_peer_joined",
+            RoomEvent::Emptied => "room.emptied",
+        }
+    }
+}
+
+/// Room lifecycle events posted to operator-configured URLs, for dashboards,
+/// billing and the like. Each delivery is a JSON body signed with
+/// HMAC-SHA256 over `<timestamp>.<body>`: the timestamp goes in
+/// `X-P2P-Chat-Timestamp` and the hex signature, as `sha256=<hex>`, in
+/// `X-P2P-Chat-Signature`, so receivers can reject forged or replayed calls.
+/// Deliveries run in the background and never hold up signaling.
+#[derive(Debug)]
+pub struct Webhooks {
+    urls: Vec<String>,
+    secret: Vec<u8>,
+    events: Vec<RoomEvent>,
+    client: reqwest::Client,
+}
+
+impl Webhooks {
+    /// Reads the endpoints from the environment; none are configured unless
+    /// `WEBHOOK_URLS` is set. URLs without a secret, or unknown event names,
+    /// are configuration errors.
+    pub fn from_env() -> Result<Self, String> {
+        let list = |var: &str| -> Vec<String> {
+            env::var(var)
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        let urls = list(WEBHOOK_URLS_VAR);
+        let secret = env::var(WEBHOOK_SECRET_VAR).ok().filter(|s| !s.is_empty());
+        if !urls.is_empty() && secret.is_none() {
+            return Err(format!("{} is set but {} is not", WEBHOOK_URLS_VAR, WEBHOOK_SECRET_VAR));
+        }
+        if let Some(url) = urls.iter().find(|u| !u.starts_with("http://") && !u.starts_with("https://")) {
+            return Err(format!("{} must hold http(s) URLs, got {}", WEBHOOK_URLS_VAR, url));
+        }
+        let names = list(WEBHOOK_EVENTS_VAR);
+        let events = if names.is_empty() {
+            RoomEvent::ALL.to_vec()
+        } else {
+            names
+                .iter()
+                .map(|name| {
+                    RoomEvent::ALL.into_iter().find(|e| e.name() == name).ok_or_else(|| {
+                        let known: Vec<_> = RoomEvent::ALL.iter().map(|e| e.name()).collect();
+                        format!("Unknown event {} in {}; expected {}", name, WEBHOOK_EVENTS_VAR, known.join(", "))
+                    })
+                })
+                .collect::<Result<_, _>>()?
+        };
+        let client = reqwest::Client::builder()
+            .timeout(DELIVERY_TIMEOUT)
+            .build()
+            .map_err(|err| format!("Could not set up the webhook client: {}", err))?;
+        Ok(Webhooks { urls, secret: secret.unwrap_or_default().into_bytes(), events, client })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.urls.is_empty()
+    }
+
+    /// Posts `event` for `room` to every endpoint, with `data` as the
+    /// event's details. Returns right away.
+    pub fn send(&self, event: RoomEvent, room: &str, data: Value) {
+        if !self.is_enabled() || !self.events.contains(&event) {
+            return;
+        }
+        let now = Utc::now();
+        let body = serde_json::json!({
+            "id": Uuid::new_v4(),
+            "event": event.name(),
+            "room": room,
+            "occurred_at": now,
+            "data": data,
+        })
+        .to_string();
+        let timestamp = now.timestamp().to_string();
+        let signature = format!("sha256={}", self.sign(&timestamp, &body));
+        for url in &self.urls {
+            let request = self
+                .client
+                .post(url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(EVENT_HEADER, event.name())
+                .header(TIMESTAMP_HEADER, &timestamp)
+                .header(SIGNATURE_HEADER, &signature)
+                .body(body.clone());
+            let url = url.clone();
+            tokio::spawn(async move {
+                for attempt in 1..=MAX_ATTEMPTS {
+                    let Some(request) = request.try_clone() else { return };
+                    match request.send().await.and_then(|resp| resp.error_for_status()) {
+                        Ok(_) => {
+                            debug!("Webhook {} delivered to {}", event.name(), url);
+                            return;
+                        }
+                        Err(err) if attempt < MAX_ATTEMPTS => {
+                            debug!("Webhook {} to {} failed, retrying: {}", event.name(), url, err);
+                            tokio::time::sleep(Duration::from_secs(1 << (attempt - 1))).await;
+                        }
+                        Err(err) => warn!("Webhook {} to {} failed {} times: {}", event.name(), url, attempt, err),
+                    }
+                }
+            });
+        }
+    }
+
+    /// Hex HMAC-SHA256 of `<timestamp>.<body>`.
+    fn sign(&self, timestamp: &str, body: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(timestamp.as_bytes());
+        mac.update(b".");
+        mac.update(body.as_bytes());
+        mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "whsec-test";
+    const TIMESTAMP: &str = "1760000000";
+    const BODY: &str = r#"{"event":"room.created","room":"lobby"}"#;
+
+    fn webhooks(secret: &str) -> Webhooks {
+        Webhooks {
+            urls: vec!["https://hooks.example.com/p2p".to_string()],
+            secret: secret.as_bytes().to_vec(),
+            events: RoomEvent::ALL.to_vec(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// What a receiver does with the two headers and the raw body.
+    fn verify(secret: &str, timestamp: &str, body: &str, signature: &str) -> bool {
+        let Some(hex) = signature.strip_prefix("sha256=") else { return false };
+        let Ok(bytes) = (0..hex.len())
+            .step_by(2)
+            .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()).ok_or(()))
+            .collect::<Result<Vec<u8>, ()>>()
+        else {
+            return false;
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(format!("{}.{}", timestamp, body).as_bytes());
+        mac.verify_slice(&bytes).is_ok()
+    }
+
+    #[test]
+    fn signs_timestamp_dot_body() {
+        // HMAC-SHA256("whsec-test", "1760000000.<body>"), computed independently.
+        assert_eq!(
+            webhooks(SECRET).sign(TIMESTAMP, BODY),
+            "595390ad50a5d293c9d4a4145e2e82cf7131702a988d80f68869275098a04d6a"
+        );
+    }
+
+    #[test]
+    fn receivers_accept_genuine_deliveries() {
+        let signature = format!("sha256={}", webhooks(SECRET).sign(TIMESTAMP, BODY));
+        assert!(verify(SECRET, TIMESTAMP, BODY, &signature));
+    }
+
+    #[test]
+    fn receivers_reject_altered_deliveries() {
+        let signature = format!("sha256={}", webhooks(SECRET).sign(TIMESTAMP, BODY));
+        assert!(!verify(SECRET, TIMESTAMP, &BODY.replace("lobby", "vault"), &signature));
+        assert!(!verify(SECRET, "1760000300", BODY, &signature), "a replay with a fresh timestamp");
+        assert!(!verify("another-secret", TIMESTAMP, BODY, &signature));
+        assert!(!verify(SECRET, TIMESTAMP, BODY, signature.trim_start_matches("sha256=")));
+    }
+
+    #[test]
+    fn signatures_depend_on_the_secret() {
+        assert_ne!(webhooks(SECRET).sign(TIMESTAMP, BODY), webhooks("another-secret").sign(TIMESTAMP, BODY));
+    }
+}
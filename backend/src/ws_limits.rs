@@ -0,0 +1,39 @@
+use std::time::Instant;
+
+/// Largest text frame a client may send. Relayed chat with a scribble is
+/// the biggest legitimate message and stays well below this.
+pub const MAX_MESSAGE_BYTES: usize = 64 * 1024;
+/// Messages a connection may send in one go, e.g. a batch of ICE candidates.
+const BURST: f64 = 60.0;
+/// Sustained messages per second once the burst is used up.
+const PER_SECOND: f64 = 20.0;
+
+/// Per-connection message budget: a token bucket that holds up to `BURST`
+/// tokens and refills at `PER_SECOND`. Each message takes one.
+#[derive(Debug)]
+pub struct MessageBudget {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Default for MessageBudget {
+    fn default() -> Self {
+        MessageBudget { tokens: BURST, refilled: Instant::now() }
+    }
+}
+
+impl MessageBudget {
+    /// Takes a token for one message; `false` if the connection is over
+    /// its budget.
+    pub fn take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * PER_SECOND).min(BURST);
+        self.refilled = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
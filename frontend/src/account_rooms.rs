@@ -0,0 +1,79 @@
+use leptos::*;
+use leptos_router::use_navigate;
+use serde::{Deserialize, Serialize};
+
+use crate::{api, petnames};
+
+/// Recently visited and starred rooms, stored on the server so they follow
+/// the user across devices. Both lists are most recent first.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AccountRooms {
+    #[serde(default)]
+    pub recent: Vec<String>,
+    #[serde(default)]
+    pub favorites: Vec<String>,
+}
+
+impl AccountRooms {
+    pub fn is_favorite(&self, room: &str) -> bool {
+        self.favorites.iter().any(|r| r == room)
+    }
+
+    pub fn toggle_favorite(&mut self, room: &str) {
+        if self.is_favorite(room) {
+            self.favorites.retain(|r| r != room);
+        } else {
+            self.favorites.insert(0, room.to_string());
+        }
+    }
+
+    /// Favorites first, then recent rooms that are not starred, each with
+    /// whether it is a favorite.
+    pub fn quick_access(&self) -> Vec<(String, bool)> {
+        let favorites = self.favorites.iter().map(|r| (r.clone(), true));
+        let recent = self.recent.iter().filter(|r| !self.is_favorite(r)).map(|r| (r.clone(), false));
+        favorites.chain(recent).collect()
+    }
+}
+
+/// `None` when signed out or signed in as a guest, who have no saved rooms.
+pub async fn load() -> Option<AccountRooms> {
+    api::get("/account/rooms").await.ok().filter(|resp| resp.ok())?.json()
+}
+
+pub async fn save(rooms: &AccountRooms) -> Option<AccountRooms> {
+    api::put_json("/account/rooms", rooms).await.ok().filter(|resp| resp.ok())?.json()
+}
+
+/// Chips for the user's favorite and recent rooms; renders nothing until
+/// they have any.
+#[component]
+pub fn QuickRooms() -> impl IntoView {
+    let navigate = use_navigate();
+    let (rooms, set_rooms) = create_signal(AccountRooms::default());
+    spawn_local(async move {
+        if let Some(loaded) = load().await {
+            set_rooms.set(loaded);
+        }
+    });
+
+    view! {
+        <Show when=move || rooms.with(|r| !r.quick_access().is_empty())>
+            <div class="quick-rooms">
+                {
+                    let navigate = navigate.clone();
+                    move || rooms.with(AccountRooms::quick_access).into_iter().map(|(name, favorite)| {
+                        let navigate = navigate.clone();
+                        let target = format!("/chat/{}", name);
+                        view! {
+                            <button type="button" class="room-chip" class:favorite=favorite on:click=move |_| navigate(&target, Default::default())>
+                                {if favorite { "★ " } else { "" }}
+                                {move || petnames::room_label(&name)}
+                            </button>
+                        }
+                    }).collect_view()
+                }
+            </div>
+        </Show>
+    }
+}
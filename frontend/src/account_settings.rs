@@ -0,0 +1,287 @@
+use std::cell::RefCell;
+
+use leptos::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use wasm_bindgen::JsCast;
+
+use crate::{api, ice};
+use crate::error::{self, AppError, CryptoError};
+use crate::vault::{self, Sealed};
+
+/// Saves that run into someone else's newer version are merged and retried
+/// this often before giving up.
+const MAX_SAVE_ATTEMPTS: usize = 3;
+const THEME_KEY: &str = "theme";
+const NOTIFICATIONS_KEY: &str = "notifications";
+const SYNC_DRAFTS_KEY: &str = "sync_drafts";
+/// The only key of the document the server stores: the settings, encrypted.
+const SEALED_KEY: &str = "sealed";
+const THEMES: &[(&str, &str)] = &[("system", "Same as the system"), ("light", "Light"), ("dark", "Dark")];
+
+/// Preferences stored with the account and shared by all of the user's
+/// devices (`GET/PUT /account/settings`): one JSON object that any part of
+/// the app can keep keys in, with a version the server bumps on every save.
+/// The server only ever sees it encrypted with a key derived from the
+/// user's password (see [`vault`]).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AccountSettings {
+    pub version: u64,
+    pub settings: Map<String, Value>,
+}
+
+#[derive(Serialize)]
+struct UpdateSettings<'a> {
+    base_version: u64,
+    settings: &'a Map<String, Value>,
+}
+
+thread_local! {
+    /// Created on first use, normally by [`init`] when the app starts
+    static STORE: RefCell<Option<RwSignal<AccountSettings>>> = const { RefCell::new(None) };
+}
+
+fn store() -> RwSignal<AccountSettings> {
+    STORE.with(|s| *s.borrow_mut().get_or_insert_with(|| create_rw_signal(AccountSettings::default())))
+}
+
+/// Keeps the page's theme in line with the settings and loads them.
+pub fn init() {
+    let store = store();
+    create_effect(move |_| apply_theme(&store.with(theme_of)));
+    load();
+}
+
+/// The server's document; signed-out visitors and guests have none.
+async fn fetch() -> Option<AccountSettings> {
+    api::get("/account/settings").await.ok().filter(|resp| resp.ok()).and_then(|r| r.json())
+}
+
+fn sealed_of(doc: &AccountSettings) -> Option<Result<Sealed, CryptoError>> {
+    let sealed = doc.settings.get(SEALED_KEY)?;
+    Some(serde_json::from_value(sealed.clone()).map_err(|err| CryptoError::Failed(err.to_string())))
+}
+
+/// Decrypts a document from the server. Ones saved before settings were
+/// encrypted are taken as they are, and sealed by the next save.
+async fn reveal(doc: AccountSettings, key: Option<&vault::Key>) -> Result<AccountSettings, AppError> {
+    let Some(sealed) = sealed_of(&doc) else { return Ok(doc) };
+    let plaintext = sealed?.open(key.ok_or(CryptoError::Locked)?).await?;
+    let settings = serde_json::from_slice(&plaintext).map_err(|err| CryptoError::Failed(err.to_string()))?;
+    Ok(AccountSettings { version: doc.version, settings })
+}
+
+async fn conceal(settings: &Map<String, Value>, key: &vault::Key) -> Result<Map<String, Value>, AppError> {
+    let plaintext = serde_json::to_vec(settings).map_err(|err| CryptoError::Failed(err.to_string()))?;
+    let sealed = serde_json::to_value(Sealed::seal(key, &plaintext).await?).unwrap_or_default();
+    Ok(Map::from_iter([(SEALED_KEY.to_string(), sealed)]))
+}
+
+/// Takes decrypted settings unless ours are newer.
+fn adopt(incoming: AccountSettings) {
+    let store = store();
+    if incoming.version >= store.with_untracked(|s| s.version) {
+        store.set(incoming);
+    }
+}
+
+/// Replaces the settings with the signed-in user's, on a device that has
+/// its key from an earlier sign-in.
+pub fn load() {
+    spawn_local(async move {
+        let Some(doc) = fetch().await else { return };
+        match reveal(doc, vault::current().as_ref()).await {
+            Ok(settings) => store().set(settings),
+            Err(err) => error::report(err),
+        }
+    });
+}
+
+/// Derives this device's key from the password just signed in with, then
+/// loads the settings. Settings sealed under a password that was reset
+/// since cannot be read by anyone any more, so they start over.
+pub async fn unlock(password: String) {
+    let Some(doc) = fetch().await else { return };
+    let unlocked = async {
+        let salt = match sealed_of(&doc) {
+            Some(sealed) => sealed?.salt().ok_or(CryptoError::Locked)?,
+            None => vault::new_salt()?,
+        };
+        let key = vault::derive(&password, salt).await?;
+        match reveal(doc.clone(), Some(&key)).await {
+            Err(AppError::Crypto(CryptoError::Failed(err))) => {
+                log::warn!("Synced settings were sealed with an older password, starting over: {}", err);
+                let key = vault::derive(&password, vault::new_salt()?).await?;
+                vault::keep(&key);
+                Ok(AccountSettings { version: doc.version, settings: Map::new() })
+            }
+            revealed => {
+                vault::keep(&key);
+                revealed
+            }
+        }
+    };
+    match unlocked.await {
+        Ok(settings) => store().set(settings),
+        Err(err) => error::report(err),
+    }
+}
+
+/// Takes settings pushed by the server, unless ours are newer.
+pub fn receive(incoming: AccountSettings) {
+    spawn_local(async move {
+        match reveal(incoming, vault::current().as_ref()).await {
+            Ok(settings) => adopt(settings),
+            Err(err) => error::record(&err),
+        }
+    });
+}
+
+/// Whether the user wants desktop notifications.
+pub fn notifications_enabled() -> bool {
+    store().with_untracked(notifications_of)
+}
+
+/// Whether unsent drafts are shared with the user's other devices.
+pub fn drafts_synced() -> bool {
+    store().with_untracked(drafts_synced_of)
+}
+
+/// The value under `key`, tracked, for parts of the app that keep their
+/// own keys in the settings.
+pub fn get(key: &str) -> Option<Value> {
+    store().with(|s| s.settings.get(key).cloned())
+}
+
+/// Every key starting with `prefix`, and its value, tracked.
+pub fn keys_with_prefix(prefix: &str) -> Vec<(String, Value)> {
+    store().with(|s| {
+        s.settings.iter().filter(|(key, _)| key.starts_with(prefix)).map(|(k, v)| (k.clone(), v.clone())).collect()
+    })
+}
+
+/// Saves `changes` on top of the latest settings, sealed with `key`; `null`
+/// removes a key. If another device saved first, its version is taken and
+/// only our changed keys are applied over it before trying again.
+async fn save(changes: Map<String, Value>, key: &vault::Key) -> Result<(), String> {
+    let store = store();
+    for _ in 0..MAX_SAVE_ATTEMPTS {
+        let base = store.get_untracked();
+        let mut settings = base.settings.clone();
+        for (key, value) in &changes {
+            match value {
+                Value::Null => settings.remove(key),
+                value => settings.insert(key.clone(), value.clone()),
+            };
+        }
+        let sealed = conceal(&settings, key).await.map_err(|err| err.to_string())?;
+        let body = UpdateSettings { base_version: base.version, settings: &sealed };
+        let resp = api::put_json("/account/settings", &body).await.map_err(|err| err.to_string())?;
+        match resp.json::<AccountSettings>() {
+            Some(saved) if resp.ok() => {
+                adopt(AccountSettings { version: saved.version, settings });
+                return Ok(());
+            }
+            // Sealed with this device's key, which `key` may be replacing
+            Some(latest) if resp.status == 409 => {
+                adopt(reveal(latest, vault::current().as_ref()).await.map_err(|err| err.to_string())?);
+            }
+            _ => return Err(resp.body),
+        }
+    }
+    Err("Your settings keep changing on another device; try again.".to_string())
+}
+
+pub async fn set(key: &str, value: Value) -> Result<(), String> {
+    let mut changes = Map::new();
+    changes.insert(key.to_string(), value);
+    update(changes).await
+}
+
+/// Like [`set`], for several keys in one save.
+pub async fn update(changes: Map<String, Value>) -> Result<(), String> {
+    let vault_key = vault::current().ok_or_else(|| CryptoError::Locked.to_string())?;
+    save(changes, &vault_key).await
+}
+
+/// Seals the settings again with a key from the new password, after it was
+/// changed. The old key stays in use until the server has the new
+/// ciphertext.
+pub async fn rekey(new_password: &str) -> Result<(), String> {
+    let salt = vault::new_salt().map_err(|err| err.to_string())?;
+    let key = vault::derive(new_password, salt).await.map_err(|err| err.to_string())?;
+    save(Map::new(), &key).await?;
+    ice::reseal_own_servers(&key).await;
+    vault::keep(&key);
+    Ok(())
+}
+
+/// Notifications are on unless turned off.
+fn notifications_of(settings: &AccountSettings) -> bool {
+    settings.settings.get(NOTIFICATIONS_KEY).and_then(Value::as_bool).unwrap_or(true)
+}
+
+/// Drafts stay on the device unless turned on.
+fn drafts_synced_of(settings: &AccountSettings) -> bool {
+    settings.settings.get(SYNC_DRAFTS_KEY).and_then(Value::as_bool).unwrap_or(false)
+}
+
+fn theme_of(settings: &AccountSettings) -> String {
+    settings.settings.get(THEME_KEY).and_then(Value::as_str).unwrap_or(THEMES[0].0).to_string()
+}
+
+/// The stylesheet picks its colors by the `data-theme` attribute.
+fn apply_theme(theme: &str) {
+    if let Some(root) = web_sys::window()
+        .and_then(|w| w.document())
+        .and_then(|d| d.document_element())
+        .and_then(|el| el.dyn_into::<web_sys::HtmlElement>().ok())
+    {
+        let _ = root.set_attribute("data-theme", theme);
+    }
+}
+
+/// Settings page section for the preferences that follow the account;
+/// changes save right away.
+#[component]
+pub fn SyncedPreferences() -> impl IntoView {
+    let store = store();
+    let (notice, set_notice) = create_signal::<Option<String>>(None);
+    let on_change = create_action(move |(key, value): &(&'static str, Value)| {
+        let (key, value) = (*key, value.clone());
+        async move {
+            set_notice.set(set(key, value).await.err());
+        }
+    });
+
+    view! {
+        <section>
+            <h3>"On all your devices"</h3>
+            <label>
+                "Theme "
+                <select on:change=move |ev| on_change.dispatch((THEME_KEY, Value::from(event_target_value(&ev))))>
+                    {THEMES.iter().map(|(value, label)| view! {
+                        <option value=*value selected=move || store.with(|s| theme_of(s) == *value)>{*label}</option>
+                    }).collect_view()}
+                </select>
+            </label>
+            <label>
+                <input
+                    type="checkbox"
+                    prop:checked=move || store.with(notifications_of)
+                    on:change=move |ev| on_change.dispatch((NOTIFICATIONS_KEY, Value::from(event_target_checked(&ev))))
+                />
+                "Desktop notifications for reminders and timers"
+            </label>
+            <label>
+                <input
+                    type="checkbox"
+                    prop:checked=move || store.with(drafts_synced_of)
+                    on:change=move |ev| on_change.dispatch((SYNC_DRAFTS_KEY, Value::from(event_target_checked(&ev))))
+                />
+                "Continue unsent messages on your other devices"
+            </label>
+            {move || notice.get().map(|n| view! { <p class="notice">{n}</p> })}
+        </section>
+    }
+}
@@ -0,0 +1,102 @@
+use serde::{de::DeserializeOwned, Serialize};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+
+use crate::config;
+use crate::error::{self, AppError, SignalingError};
+
+const TOKEN_KEY: &str = "jwt";
+
+pub struct ApiResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl ApiResponse {
+    pub fn ok(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Option<T> {
+        serde_json::from_str(&self.body).ok()
+    }
+}
+
+/// Sends a request to the signaling server, attaching the stored JWT (if any)
+/// as a bearer token. Only failing to get an answer is an error, and it is
+/// recorded for diagnostics; error statuses are left to the caller.
+pub async fn request<B: Serialize>(
+    method: &str,
+    path: &str,
+    body: Option<&B>,
+) -> Result<ApiResponse, AppError> {
+    send(method, path, body).await.map_err(|err| {
+        let err = SignalingError::Unreachable(format!("{} {}: {:?}", method, path, err)).into();
+        error::record(&err);
+        err
+    })
+}
+
+async fn send<B: Serialize>(method: &str, path: &str, body: Option<&B>) -> Result<ApiResponse, JsValue> {
+    let opts = web_sys::RequestInit::new();
+    opts.set_method(method);
+    if let Some(body) = body {
+        let json = serde_json::to_string(body).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        opts.set_body(&JsValue::from_str(&json));
+    }
+
+    let request = web_sys::Request::new_with_str_and_init(&format!("{}{}", config::api_base(), path), &opts)?;
+    request.headers().set("Content-Type", "application/json")?;
+    if let Some(token) = stored_token() {
+        request.headers().set("Authorization", &format!("Bearer {}", token))?;
+    }
+
+    let window = web_sys::window().unwrap();
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_request(&request))
+        .await?
+        .dyn_into()?;
+    let body = JsFuture::from(response.text()?).await?.as_string().unwrap_or_default();
+    Ok(ApiResponse {
+        status: response.status(),
+        body,
+    })
+}
+
+pub async fn get(path: &str) -> Result<ApiResponse, AppError> {
+    request::<()>("GET", path, None).await
+}
+
+pub async fn put_json<B: Serialize>(path: &str, body: &B) -> Result<ApiResponse, AppError> {
+    request("PUT", path, Some(body)).await
+}
+
+pub async fn delete(path: &str) -> Result<ApiResponse, AppError> {
+    request::<()>("DELETE", path, None).await
+}
+
+pub async fn post_json<B: Serialize>(path: &str, body: &B) -> Result<ApiResponse, AppError> {
+    request("POST", path, Some(body)).await
+}
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+pub fn stored_token() -> Option<String> {
+    local_storage()?
+        .get_item(TOKEN_KEY)
+        .ok()?
+        .filter(|token| !token.is_empty())
+}
+
+pub fn store_token(token: &str) {
+    if let Some(storage) = local_storage() {
+        let _ = storage.set_item(TOKEN_KEY, token);
+    }
+}
+
+pub fn clear_token() {
+    if let Some(storage) = local_storage() {
+        let _ = storage.remove_item(TOKEN_KEY);
+    }
+}
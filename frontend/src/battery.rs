@@ -0,0 +1,87 @@
+use leptos::*;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+/// Per-device opt-out; battery saving is on unless this is "0".
+const ENABLED_KEY: &str = "battery_saver";
+/// Charge level at or below which we count the battery as low.
+const LOW_LEVEL: f64 = 0.2;
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+pub fn enabled() -> bool {
+    local_storage()
+        .and_then(|s| s.get_item(ENABLED_KEY).ok().flatten())
+        .as_deref()
+        != Some("0")
+}
+
+pub fn set_enabled(enabled: bool) {
+    if let Some(storage) = local_storage() {
+        let _ = storage.set_item(ENABLED_KEY, if enabled { "1" } else { "0" });
+    }
+}
+
+fn is_low(battery: &JsValue) -> bool {
+    let field = |name: &str| js_sys::Reflect::get(battery, &JsValue::from_str(name)).unwrap_or(JsValue::UNDEFINED);
+    let charging = field("charging").as_bool().unwrap_or(true);
+    let level = field("level").as_f64().unwrap_or(1.0);
+    !charging && level <= LOW_LEVEL
+}
+
+/// Whether the device is running low on battery and not charging, kept up
+/// to date from the Battery Status API. Stays `false` where the API is
+/// missing or the user turned battery saving off.
+pub fn watch_low() -> ReadSignal<bool> {
+    let (low, set_low) = create_signal(false);
+    if !enabled() {
+        return low;
+    }
+    let Some(window) = web_sys::window() else { return low };
+    let Ok(get_battery) = js_sys::Reflect::get(&window.navigator(), &JsValue::from_str("getBattery")) else {
+        return low;
+    };
+    let Some(get_battery) = get_battery.dyn_ref::<js_sys::Function>() else { return low };
+    let Ok(promise) = get_battery.call0(&window.navigator()) else { return low };
+    spawn_local(async move {
+        let Ok(battery) = JsFuture::from(js_sys::Promise::from(promise)).await else { return };
+        set_low.set(is_low(&battery));
+        let target: web_sys::EventTarget = battery.clone().unchecked_into();
+        let closure = Closure::wrap(Box::new(move || {
+            set_low.set(is_low(&battery));
+        }) as Box<dyn FnMut()>);
+        for event in ["levelchange", "chargingchange"] {
+            let _ = target.add_event_listener_with_callback(event, closure.as_ref().unchecked_ref());
+        }
+        closure.forget();
+    });
+    low
+}
+
+/// Settings page section.
+#[component]
+pub fn BatterySaverSetting() -> impl IntoView {
+    let (on, set_on) = create_signal(enabled());
+
+    view! {
+        <section>
+            <h3>"Battery"</h3>
+            <label>
+                <input
+                    type="checkbox"
+                    prop:checked=on
+                    on:change=move |ev| {
+                        let checked = event_target_checked(&ev);
+                        set_enabled(checked);
+                        set_on.set(checked);
+                    }
+                />
+                "Save battery when it runs low"
+            </label>
+            <p class="hint">"Below 20% and not charging, the lobby refreshes every 30 seconds instead of every 5."</p>
+        </section>
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{RtcDataChannel, RtcDataChannelState, RtcIceCandidateInit, RtcPeerConnection, RtcSessionDescriptionInit};
+
+use crate::frames;
+use crate::signaling::SignalingMessage;
+use crate::transport::Transport;
+
+/// Our part in a broadcast room, where the owner sends and everyone else
+/// watches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Broadcaster,
+    Viewer,
+}
+
+struct Connection {
+    pc: RtcPeerConnection,
+    dc: RtcDataChannel,
+}
+
+/// The broadcaster's connections, one per viewer. Viewers keep the chat
+/// page's single connection, to the broadcaster; what they send back (acks,
+/// keepalives) is not listened to.
+#[derive(Default)]
+pub struct Audience {
+    viewers: HashMap<String, Connection>,
+}
+
+impl Audience {
+    /// Offers a connection to each of `ids` we are not connected to yet and
+    /// closes the ones to viewers no longer among them. `on_open` runs when
+    /// a viewer's channel opens.
+    pub fn sync(
+        &mut self,
+        room: &str,
+        ids: &[String],
+        config: &web_sys::RtcConfiguration,
+        transport: &Transport,
+        on_open: impl Fn() + Clone + 'static,
+    ) {
+        self.viewers.retain(|id, viewer| {
+            let stays = ids.contains(id);
+            if !stays {
+                viewer.dc.close();
+                viewer.pc.close();
+            }
+            stays
+        });
+        for id in ids {
+            if self.viewers.contains_key(id) {
+                continue;
+            }
+            if let Some(viewer) = connect(room, id, config, transport, on_open.clone()) {
+                self.viewers.insert(id.clone(), viewer);
+            }
+        }
+    }
+
+    /// Applies a viewer's answer to our offer. Descriptions and candidates
+    /// travel as the JSON of their browser objects.
+    pub fn answer(&self, from: &str, sdp: &str) {
+        if let (Some(viewer), Ok(desc)) = (self.viewers.get(from), js_sys::JSON::parse(sdp)) {
+            let _ = viewer.pc.set_remote_description(desc.unchecked_ref::<RtcSessionDescriptionInit>());
+        }
+    }
+
+    pub fn candidate(&self, from: &str, candidate: &str) {
+        if let (Some(viewer), Ok(init)) = (self.viewers.get(from), js_sys::JSON::parse(candidate)) {
+            let _ = viewer.pc.add_ice_candidate_with_opt_rtc_ice_candidate_init(Some(init.unchecked_ref::<RtcIceCandidateInit>()));
+        }
+    }
+
+    /// Sends `data` to every viewer whose channel is open; how many got it.
+    pub fn send(&self, data: &str) -> usize {
+        self.viewers
+            .values()
+            .filter(|v| v.dc.ready_state() == RtcDataChannelState::Open)
+            .filter(|v| frames::send(&v.dc, data))
+            .count()
+    }
+
+    pub fn close(&mut self) {
+        for (_, viewer) in self.viewers.drain() {
+            viewer.dc.close();
+            viewer.pc.close();
+        }
+    }
+}
+
+/// A connection to viewer `id`, offered right away.
+fn connect(
+    room: &str,
+    id: &str,
+    config: &web_sys::RtcConfiguration,
+    transport: &Transport,
+    on_open: impl Fn() + 'static,
+) -> Option<Connection> {
+    let pc = RtcPeerConnection::new_with_configuration(config).ok()?;
+    let dc_init = web_sys::RtcDataChannelInit::new();
+    dc_init.set_ordered(true);
+    dc_init.set_max_retransmits(0);
+    let dc = pc.create_data_channel_with_data_channel_dict("chat", &dc_init);
+    let onopen = Closure::wrap(Box::new(move |_ev| on_open()) as Box<dyn FnMut(web_sys::Event)>);
+    dc.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+    onopen.forget();
+
+    let (candidate_transport, candidate_room, to) = (transport.clone(), room.to_string(), id.to_string());
+    let onicecandidate = Closure::wrap(Box::new(move |ev: web_sys::RtcPeerConnectionIceEvent| {
+        let Some(init) = ev.candidate().map(|c| c.to_json()) else { return };
+        let Some(candidate) = js_sys::JSON::stringify(&init).ok().and_then(|json| json.as_string()) else { return };
+        candidate_transport.send(&SignalingMessage::IceCandidate {
+            room: candidate_room.clone(),
+            candidate,
+            to: Some(to.clone()),
+            from: None,
+        });
+    }) as Box<dyn FnMut(web_sys::RtcPeerConnectionIceEvent)>);
+    pc.set_onicecandidate(Some(onicecandidate.as_ref().unchecked_ref()));
+    onicecandidate.forget();
+
+    let (offer_pc, offer_transport, room, to) = (pc.clone(), transport.clone(), room.to_string(), id.to_string());
+    wasm_bindgen_futures::spawn_local(async move {
+        let Ok(offer) = JsFuture::from(offer_pc.create_offer()).await else { return };
+        let Some(sdp) = js_sys::JSON::stringify(&offer).ok().and_then(|json| json.as_string()) else { return };
+        if JsFuture::from(offer_pc.set_local_description(offer.unchecked_ref())).await.is_err() {
+            return;
+        }
+        offer_transport.send(&SignalingMessage::Offer { room, sdp, to: Some(to), from: None });
+    });
+    Some(Connection { pc, dc })
+}
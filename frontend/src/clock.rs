@@ -0,0 +1,50 @@
+use std::cell::Cell;
+
+/// Round trips per sync; the one with the shortest round trip wins, since
+/// it leaves the least room for asymmetric delays.
+pub const SYNC_ROUNDS: u32 = 5;
+
+thread_local! {
+    /// Milliseconds to add to the local clock to get the server's
+    static OFFSET: Cell<f64> = const { Cell::new(0.0) };
+    static BEST_ROUND_TRIP: Cell<f64> = const { Cell::new(f64::INFINITY) };
+    static ROUNDS: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Starts a new sync, e.g. after reconnecting. The current offset stays in
+/// use until a better sample comes in.
+pub fn start_sync() {
+    ROUNDS.with(|r| r.set(0));
+    BEST_ROUND_TRIP.with(|b| b.set(f64::INFINITY));
+}
+
+/// Takes in the answer to a probe sent at local time `sent` that the server
+/// stamped with `server` and that came back at `received`. Returns whether
+/// another probe should follow.
+pub fn record(sent: f64, server: f64, received: f64) -> bool {
+    let round_trip = received - sent;
+    if round_trip >= 0.0 && round_trip < BEST_ROUND_TRIP.with(Cell::get) {
+        BEST_ROUND_TRIP.with(|b| b.set(round_trip));
+        // Assume the answer took as long as the probe
+        OFFSET.with(|o| o.set(server - (sent + received) / 2.0));
+    }
+    let rounds = ROUNDS.with(|r| {
+        r.set(r.get() + 1);
+        r.get()
+    });
+    rounds < SYNC_ROUNDS
+}
+
+/// Milliseconds since the epoch by the server's clock, as far as we know it.
+pub fn now() -> f64 {
+    js_sys::Date::now() + OFFSET.with(Cell::get)
+}
+
+/// [`now`] as an ISO 8601 timestamp.
+pub fn now_iso() -> String {
+    iso(now())
+}
+
+pub fn iso(millis: f64) -> String {
+    js_sys::Date::new(&wasm_bindgen::JsValue::from_f64(millis)).to_iso_string().into()
+}
@@ -0,0 +1,115 @@
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use crate::account_settings;
+
+/// Slash commands handled locally by the composer instead of being sent to the peer.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SlashCommand {
+    /// `/remind me in 20m to stretch`
+    Remind { delay_ms: u32, text: String },
+    /// `/timer 5m`
+    Timer { delay_ms: u32 },
+}
+
+/// Returns `None` when `input` is not a slash command, otherwise the parsed
+/// command or a usage message for the user.
+pub fn parse(input: &str) -> Option<Result<SlashCommand, String>> {
+    let input = input.trim();
+    let rest = input.strip_prefix('/')?;
+    let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    let args = args.trim();
+
+    let parsed = match name {
+        "remind" => parse_remind(args)
+            .ok_or_else(|| "Usage: /remind me in <duration> to <text>".to_string()),
+        "timer" => parse_duration(args)
+            .map(|delay_ms| SlashCommand::Timer { delay_ms })
+            .ok_or_else(|| "Usage: /timer <duration>, e.g. /timer 5m".to_string()),
+        _ => Err(format!("Unknown command: /{}", name)),
+    };
+    Some(parsed)
+}
+
+fn parse_remind(args: &str) -> Option<SlashCommand> {
+    let args = args.strip_prefix("me")?.trim_start().strip_prefix("in")?.trim_start();
+    let (duration, text) = args.split_once(" to ")?;
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    Some(SlashCommand::Remind {
+        delay_ms: parse_duration(duration.trim())?,
+        text: text.to_string(),
+    })
+}
+
+/// Parses durations like `90s`, `20m`, `2h` or `1h30m` into milliseconds.
+pub fn parse_duration(input: &str) -> Option<u32> {
+    let mut total: u64 = 0;
+    let mut digits = String::new();
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let value: u64 = digits.parse().ok()?;
+        digits.clear();
+        let unit_ms = match c {
+            's' => 1_000,
+            'm' => 60_000,
+            'h' => 3_600_000,
+            _ => return None,
+        };
+        total += value * unit_ms;
+    }
+    if !digits.is_empty() || total == 0 {
+        return None;
+    }
+    // setTimeout takes an i32 delay; anything longer would fire immediately
+    u32::try_from(total).ok().filter(|ms| *ms <= i32::MAX as u32)
+}
+
+/// Formats milliseconds back into the compact `1h30m` style used by the commands.
+pub fn format_duration(ms: u32) -> String {
+    let secs = ms / 1_000;
+    let (h, m, s) = (secs / 3_600, secs / 60 % 60, secs % 60);
+    let mut out = String::new();
+    if h > 0 {
+        out.push_str(&format!("{}h", h));
+    }
+    if m > 0 {
+        out.push_str(&format!("{}m", m));
+    }
+    if s > 0 || out.is_empty() {
+        out.push_str(&format!("{}s", s));
+    }
+    out
+}
+
+/// Runs `callback` once after `delay_ms`, independent of the peer connection.
+pub fn schedule(delay_ms: u32, callback: impl FnOnce() + 'static) {
+    let closure = Closure::once_into_js(callback);
+    let _ = web_sys::window()
+        .unwrap()
+        .set_timeout_with_callback_and_timeout_and_arguments_0(closure.unchecked_ref(), delay_ms as i32);
+}
+
+/// Asks for notification permission up front so the reminder can be shown
+/// when it fires, even if the tab is in the background.
+pub fn request_notification_permission() {
+    if web_sys::Notification::permission() == web_sys::NotificationPermission::Default {
+        let _ = web_sys::Notification::request_permission();
+    }
+}
+
+pub fn notify(title: &str, body: &str) {
+    if !account_settings::notifications_enabled()
+        || web_sys::Notification::permission() != web_sys::NotificationPermission::Granted
+    {
+        return;
+    }
+    let options = web_sys::NotificationOptions::new();
+    options.set_body(body);
+    let _ = web_sys::Notification::new_with_options(title, &options);
+}
@@ -0,0 +1,168 @@
+use std::cell::RefCell;
+
+use serde::Deserialize;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+/// Deployment profile the bundle was built for, e.g.
+/// `P2P_CHAT_PROFILE=prod trunk build --release`. Defaults to `dev`.
+const PROFILE: Option<&str> = option_env!("P2P_CHAT_PROFILE");
+/// Build-time override of the profile's API base URL.
+const API_BASE_OVERRIDE: Option<&str> = option_env!("P2P_CHAT_API_BASE");
+/// Path a reverse proxy serves the backend under, e.g. `/chat`, matching
+/// its `BASE_PATH`; the runtime config is looked for there too.
+const BASE_PATH: Option<&str> = option_env!("P2P_CHAT_BASE_PATH");
+/// Where `trunk serve` finds the backend during development.
+const DEV_API_BASE: &str = "http://localhost:3000";
+/// Served by the backend (or next to the bundle) so one build works for
+/// every deployment; `trunk serve` proxies it in development.
+const RUNTIME_CONFIG_PATH: &str = "/config.json";
+const DEFAULT_ICE_CONFIG_PATH: &str = "/ice-config";
+const DEFAULT_BRAND_NAME: &str = "P2P Chat";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    Dev,
+    Staging,
+    Prod,
+}
+
+impl Profile {
+    pub fn current() -> Self {
+        match PROFILE {
+            Some("prod" | "production") => Profile::Prod,
+            Some("staging") => Profile::Staging,
+            _ => Profile::Dev,
+        }
+    }
+
+    pub fn log_level(self) -> log::Level {
+        match self {
+            Profile::Dev => log::Level::Debug,
+            Profile::Staging => log::Level::Info,
+            Profile::Prod => log::Level::Warn,
+        }
+    }
+}
+
+/// Server features worth knowing before a socket is open.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub struct Features {
+    #[serde(default)]
+    pub chat_relay: bool,
+    #[serde(default)]
+    pub moderator_delegation: bool,
+    #[serde(default)]
+    pub turn: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Branding {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub logo_url: Option<String>,
+}
+
+/// Contents of `/config.json`; everything is optional.
+#[derive(Debug, Default, Deserialize)]
+struct RuntimeConfig {
+    #[serde(default)]
+    api_base: Option<String>,
+    #[serde(default)]
+    ws_url: Option<String>,
+    #[serde(default)]
+    ice_config_path: Option<String>,
+    #[serde(default)]
+    features: Features,
+    #[serde(default)]
+    branding: Branding,
+}
+
+thread_local! {
+    static RUNTIME: RefCell<RuntimeConfig> = RefCell::new(RuntimeConfig::default());
+}
+
+/// Reads `/config.json` if the deployment serves one. Called once before
+/// the app mounts; a missing or broken file leaves the built-in defaults.
+pub async fn load() {
+    let Some(window) = web_sys::window() else { return };
+    let path = format!("{}{}", base_path(), RUNTIME_CONFIG_PATH);
+    let Ok(response) = JsFuture::from(window.fetch_with_str(&path)).await else { return };
+    let Ok(response) = response.dyn_into::<web_sys::Response>() else { return };
+    if !response.ok() {
+        return;
+    }
+    let Ok(text) = response.text() else { return };
+    let Some(text) = JsFuture::from(text).await.ok().and_then(|t| t.as_string()) else { return };
+    match serde_json::from_str::<RuntimeConfig>(&text) {
+        Ok(config) => RUNTIME.with(|r| *r.borrow_mut() = config),
+        Err(err) => log::warn!("Ignoring {}: {}", path, err),
+    }
+}
+
+fn base_path() -> &'static str {
+    BASE_PATH.unwrap_or_default().trim_end_matches('/')
+}
+
+fn origin() -> String {
+    web_sys::window().and_then(|w| w.location().origin().ok()).unwrap_or_default()
+}
+
+/// Base URL of the backend, without a trailing slash: `/config.json` first,
+/// then the build-time override, then the profile's default. Staging and
+/// production expect the backend behind the same origin as the app, under
+/// the base path if there is one. A bare path, as the backend sends when
+/// it only knows its base path, is taken to be on our own origin.
+pub fn api_base() -> String {
+    let base = RUNTIME
+        .with(|r| r.borrow().api_base.clone())
+        .or_else(|| API_BASE_OVERRIDE.map(str::to_string))
+        .unwrap_or_else(|| match Profile::current() {
+            Profile::Dev => format!("{}{}", DEV_API_BASE, base_path()),
+            Profile::Staging | Profile::Prod => format!("{}{}", origin(), base_path()),
+        });
+    let base = base.trim_end_matches('/');
+    if base.starts_with('/') {
+        return format!("{}{}", origin(), base);
+    }
+    base.to_string()
+}
+
+/// The signaling WebSocket: as configured, or on the same host as the API.
+pub fn ws_url() -> String {
+    if let Some(url) = RUNTIME.with(|r| r.borrow().ws_url.clone()) {
+        return url;
+    }
+    let base = api_base();
+    // Parsed rather than patched as text, so IPv6 hosts like `[::1]`, ports
+    // and path prefixes all survive
+    let Ok(url) = web_sys::Url::new(&base) else {
+        return format!("{}/ws", base);
+    };
+    url.set_protocol(if url.protocol() == "https:" { "wss:" } else { "ws:" });
+    url.set_pathname(&format!("{}/ws", url.pathname().trim_end_matches('/')));
+    url.href()
+}
+
+/// Path of the STUN/TURN endpoint under [`api_base`].
+pub fn ice_config_path() -> String {
+    RUNTIME
+        .with(|r| r.borrow().ice_config_path.clone())
+        .unwrap_or_else(|| DEFAULT_ICE_CONFIG_PATH.to_string())
+}
+
+pub fn features() -> Features {
+    RUNTIME.with(|r| r.borrow().features)
+}
+
+/// Name shown in the header and the page title.
+pub fn brand_name() -> String {
+    RUNTIME
+        .with(|r| r.borrow().branding.name.clone())
+        .unwrap_or_else(|| DEFAULT_BRAND_NAME.to_string())
+}
+
+pub fn brand_logo() -> Option<String> {
+    RUNTIME.with(|r| r.borrow().branding.logo_url.clone())
+}
@@ -0,0 +1,99 @@
+use leptos::*;
+use wasm_bindgen::JsValue;
+
+/// Kept per device rather than with the account: whether data is scarce
+/// depends on the connection, not the person.
+const ENABLED_KEY: &str = "data_saver";
+/// Set once the user answered the suggestion, so it is not shown again.
+const SUGGESTED_KEY: &str = "data_saver_suggested";
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+fn flag(key: &str) -> bool {
+    local_storage()
+        .and_then(|s| s.get_item(key).ok().flatten())
+        .is_some_and(|v| v == "1")
+}
+
+fn set_flag(key: &str, value: bool) {
+    if let Some(storage) = local_storage() {
+        let _ = storage.set_item(key, if value { "1" } else { "0" });
+    }
+}
+
+pub fn enabled() -> bool {
+    flag(ENABLED_KEY)
+}
+
+pub fn set_enabled(enabled: bool) {
+    set_flag(ENABLED_KEY, enabled);
+}
+
+/// Whether the Network Information API says the connection is metered:
+/// the browser's own data saver is on or we are on cellular. Browsers
+/// without the API never count as metered.
+pub fn connection_is_metered() -> bool {
+    let Some(window) = web_sys::window() else { return false };
+    let Ok(connection) = js_sys::Reflect::get(&window.navigator(), &JsValue::from_str("connection")) else {
+        return false;
+    };
+    if connection.is_undefined() {
+        return false;
+    }
+    let field = |name: &str| js_sys::Reflect::get(&connection, &JsValue::from_str(name)).unwrap_or(JsValue::UNDEFINED);
+    field("saveData").as_bool() == Some(true) || field("type").as_string().as_deref() == Some("cellular")
+}
+
+/// Banner offering to turn data saver on when the connection looks metered.
+#[component]
+pub fn DataSaverSuggestion() -> impl IntoView {
+    let (visible, set_visible) = create_signal(!enabled() && !flag(SUGGESTED_KEY) && connection_is_metered());
+    let answer = move |turn_on: bool| {
+        set_flag(SUGGESTED_KEY, true);
+        if turn_on {
+            set_enabled(true);
+        }
+        set_visible.set(false);
+    };
+
+    view! {
+        <Show when=move || visible.get()>
+            <div class="data-saver-suggestion" role="status">
+                "You seem to be on a metered connection. Turn on data saver to refresh less often?"
+                <button type="button" on:click=move |_| answer(true)>"Turn on"</button>
+                <button type="button" on:click=move |_| answer(false)>"No thanks"</button>
+            </div>
+        </Show>
+    }
+}
+
+/// Settings page section.
+#[component]
+pub fn DataSaverSetting() -> impl IntoView {
+    let (on, set_on) = create_signal(enabled());
+    let metered = connection_is_metered();
+
+    view! {
+        <section>
+            <h3>"Data saver"</h3>
+            <label>
+                <input
+                    type="checkbox"
+                    prop:checked=on
+                    on:change=move |ev| {
+                        let checked = event_target_checked(&ev);
+                        set_enabled(checked);
+                        set_on.set(checked);
+                    }
+                />
+                "Use less data on this device"
+            </label>
+            <p class="hint">"The lobby refreshes every 30 seconds instead of every 5, and popular rooms load only once."</p>
+            {move || (metered && !on.get()).then(|| view! {
+                <p class="hint">"Your connection looks metered."</p>
+            })}
+        </section>
+    }
+}
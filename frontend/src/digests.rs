@@ -0,0 +1,119 @@
+use leptos::*;
+use leptos_router::use_query_map;
+use serde::{Deserialize, Serialize};
+
+use crate::api;
+
+/// How often `GET /rooms/:name/digest` says we get the room by email;
+/// `None` when we do not.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Subscription {
+    frequency: Option<String>,
+}
+
+fn path(room: &str) -> String {
+    format!("/rooms/{}", String::from(js_sys::encode_uri_component(room)))
+}
+
+/// Hands a message we just sent to the server, for the digests of a room
+/// that has them. Peers get it directly; this copy is only for email.
+pub fn record(room: &str, text: &str) {
+    let path = format!("{}/history", path(room));
+    let body = serde_json::json!({"text": text});
+    spawn_local(async move {
+        match api::post_json(&path, &body).await {
+            Ok(resp) if resp.ok() => {}
+            Ok(resp) => log::warn!("Message left out of the digest: {}", resp.body),
+            Err(err) => log::warn!("Message left out of the digest: {}", err),
+        }
+    });
+}
+
+/// Lets a member of a room with digests get them by email, and says that
+/// messages there are kept on the server.
+#[component]
+pub fn DigestSubscription(room: String) -> impl IntoView {
+    let path = format!("{}/digest", path(&room));
+    let (frequency, set_frequency) = create_signal(String::new());
+    let (notice, set_notice) = create_signal::<Option<String>>(None);
+    {
+        let path = path.clone();
+        spawn_local(async move {
+            if let Ok(resp) = api::get(&path).await {
+                let current = resp.json::<Subscription>().and_then(|s| s.frequency);
+                set_frequency.set(current.unwrap_or_default());
+            }
+        });
+    }
+    let change = create_action(move |choice: &String| {
+        let (path, choice) = (path.clone(), choice.clone());
+        async move {
+            let result = if choice.is_empty() {
+                api::delete(&path).await
+            } else {
+                api::put_json(&path, &Subscription { frequency: Some(choice.clone()) }).await
+            };
+            match result {
+                Ok(resp) if resp.ok() || (choice.is_empty() && resp.status == 404) => {
+                    set_frequency.set(choice);
+                    set_notice.set(None);
+                }
+                Ok(resp) => set_notice.set(Some(resp.body)),
+                Err(err) => set_notice.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    view! {
+        <div class="digest">
+            <p class="hint">"Messages in this room are kept on the server for a week, for email digests."</p>
+            <label>
+                "Email digest "
+                <select
+                    prop:value=frequency
+                    on:change=move |ev| change.dispatch(event_target_value(&ev))
+                >
+                    <option value="">"Off"</option>
+                    <option value="daily">"Daily"</option>
+                    <option value="weekly">"Weekly"</option>
+                </select>
+            </label>
+            {move || notice.get().map(|n| view! { <span class="notice">{n}</span> })}
+        </div>
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Unsubscribed {
+    room: String,
+}
+
+/// Where the unsubscribe link of a digest leads; works without logging in.
+#[component]
+pub fn UnsubscribePage() -> impl IntoView {
+    let query = use_query_map();
+    let token = query.with_untracked(|q| q.get("token").cloned().unwrap_or_default());
+    let (outcome, set_outcome) = create_signal::<Option<Result<String, String>>>(None);
+    spawn_local(async move {
+        let result = match api::post_json("/digests/unsubscribe", &serde_json::json!({"token": token})).await {
+            Ok(resp) if resp.ok() => {
+                let room = resp.json::<Unsubscribed>().map(|u| u.room).unwrap_or_default();
+                Ok(format!("You will no longer get digests of {}.", room))
+            }
+            Ok(resp) => Err(resp.body),
+            Err(err) => Err(err.to_string()),
+        };
+        set_outcome.set(Some(result));
+    });
+
+    view! {
+        <div class="auth-form">
+            <h2>"Email digests"</h2>
+            {move || match outcome.get() {
+                None => view! { <p>"Unsubscribing…"</p> }.into_view(),
+                Some(Ok(done)) => view! { <p class="notice">{done}</p> }.into_view(),
+                Some(Err(err)) => view! { <p class="error">{err}</p> }.into_view(),
+            }}
+        </div>
+    }
+}
@@ -0,0 +1,146 @@
+use leptos::*;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsCast;
+
+use crate::api;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Density {
+    Compact,
+    #[default]
+    Cozy,
+}
+
+/// Display preferences from `GET /account/display`, stored with the account.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DisplaySettings {
+    /// Text size in percent of the default, 75 to 200
+    pub font_scale: u16,
+    pub high_contrast: bool,
+    pub reduced_transparency: bool,
+    pub density: Density,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        DisplaySettings {
+            font_scale: 100,
+            high_contrast: false,
+            reduced_transparency: false,
+            density: Density::default(),
+        }
+    }
+}
+
+/// Applies the settings to the document. The stylesheet reads
+/// `--font-scale` and `--message-spacing`, and switches to the high-contrast
+/// and opaque variants of its color variables through the `data-contrast`
+/// and `data-transparency` attributes.
+pub fn apply(settings: &DisplaySettings) {
+    let Some(root) = web_sys::window()
+        .and_then(|w| w.document())
+        .and_then(|d| d.document_element())
+        .and_then(|el| el.dyn_into::<web_sys::HtmlElement>().ok())
+    else {
+        return;
+    };
+    let style = root.style();
+    let _ = style.set_property("--font-scale", &format!("{}", settings.font_scale as f64 / 100.0));
+    let spacing = match settings.density {
+        Density::Compact => "0.25rem",
+        Density::Cozy => "0.75rem",
+    };
+    let _ = style.set_property("--message-spacing", spacing);
+    let _ = root.set_attribute("data-contrast", if settings.high_contrast { "high" } else { "normal" });
+    let _ = root.set_attribute("data-transparency", if settings.reduced_transparency { "reduced" } else { "normal" });
+}
+
+/// Loads and applies the signed-in user's settings; signed-out visitors and
+/// guests keep the defaults.
+pub fn load_and_apply() {
+    spawn_local(async move {
+        if let Some(settings) = load().await {
+            apply(&settings);
+        }
+    });
+}
+
+pub async fn load() -> Option<DisplaySettings> {
+    api::get("/account/display").await.ok().filter(|resp| resp.ok())?.json()
+}
+
+/// Settings page section; changes apply right away and are saved on submit.
+#[component]
+pub fn DisplaySettingsForm() -> impl IntoView {
+    let (settings, set_settings) = create_signal(DisplaySettings::default());
+    let (notice, set_notice) = create_signal::<Option<String>>(None);
+    spawn_local(async move {
+        if let Some(loaded) = load().await {
+            set_settings.set(loaded);
+        }
+    });
+    create_effect(move |_| settings.with(apply));
+
+    let on_save = create_action(move |()| {
+        let body = settings.get();
+        async move {
+            match api::put_json("/account/display", &body).await {
+                Ok(resp) if resp.ok() => set_notice.set(Some("Display settings saved.".to_string())),
+                Ok(resp) => set_notice.set(Some(resp.body)),
+                Err(err) => set_notice.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    view! {
+        <section>
+            <h3>"Display"</h3>
+            <form on:submit=move |ev| {
+                ev.prevent_default();
+                on_save.dispatch(());
+            }>
+                <label>
+                    "Text size " {move || format!("{}%", settings.with(|s| s.font_scale))}
+                    <input
+                        type="range"
+                        min="75"
+                        max="200"
+                        step="5"
+                        prop:value=move || settings.with(|s| s.font_scale.to_string())
+                        on:input=move |ev| {
+                            if let Ok(scale) = event_target_value(&ev).parse() {
+                                set_settings.update(|s| s.font_scale = scale);
+                            }
+                        }
+                    />
+                </label>
+                <label>
+                    <input
+                        type="checkbox"
+                        prop:checked=move || settings.with(|s| s.high_contrast)
+                        on:change=move |ev| set_settings.update(|s| s.high_contrast = event_target_checked(&ev))
+                    />
+                    "High contrast"
+                </label>
+                <label>
+                    <input
+                        type="checkbox"
+                        prop:checked=move || settings.with(|s| s.reduced_transparency)
+                        on:change=move |ev| set_settings.update(|s| s.reduced_transparency = event_target_checked(&ev))
+                    />
+                    "Reduce transparency"
+                </label>
+                <select on:change=move |ev| {
+                    let density = if event_target_value(&ev) == "compact" { Density::Compact } else { Density::Cozy };
+                    set_settings.update(|s| s.density = density);
+                }>
+                    <option value="cozy" selected=move || settings.with(|s| s.density == Density::Cozy)>"Cozy messages"</option>
+                    <option value="compact" selected=move || settings.with(|s| s.density == Density::Compact)>"Compact messages"</option>
+                </select>
+                <button type="submit">"Save display settings"</button>
+            </form>
+            {move || notice.get().map(|n| view! { <p class="notice">{n}</p> })}
+        </section>
+    }
+}
@@ -0,0 +1,149 @@
+use std::cell::RefCell;
+use std::time::Duration;
+
+use leptos::leptos_dom::helpers::TimeoutHandle;
+use leptos::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{account_settings, clock, ulid};
+
+/// Local drafts and synced ones use the same key: `draft:<room>`.
+const KEY_PREFIX: &str = "draft:";
+/// Names this browser in the drafts it saves, so a draft coming back over
+/// settings sync can be told apart from one written elsewhere.
+const DEVICE_KEY: &str = "device_id";
+/// Typing is synced once it pauses this long.
+const SYNC_DELAY: Duration = Duration::from_secs(2);
+/// Synced settings are capped at 8 KB, sealed, so only short drafts in a
+/// few rooms are shared; longer ones stay on this device.
+const MAX_SYNCED_CHARS: usize = 1000;
+const MAX_SYNCED_DRAFTS: usize = 3;
+
+/// An unsent message, saved as it is typed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Draft {
+    pub text: String,
+    /// Milliseconds since the epoch by the server's clock, which the
+    /// devices share; the newest draft wins
+    pub saved_at: f64,
+    pub device: String,
+}
+
+impl Draft {
+    pub fn new(text: String) -> Self {
+        Draft { text, saved_at: clock::now(), device: device_id() }
+    }
+
+    pub fn from_other_device(&self) -> bool {
+        self.device != device_id()
+    }
+}
+
+thread_local! {
+    /// The room whose draft is waiting for typing to pause, and its timer
+    static PENDING: RefCell<Option<(String, TimeoutHandle)>> = const { RefCell::new(None) };
+}
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+fn key(room: &str) -> String {
+    format!("{}{}", KEY_PREFIX, room)
+}
+
+fn device_id() -> String {
+    let Some(storage) = local_storage() else { return String::new() };
+    if let Some(id) = storage.get_item(DEVICE_KEY).ok().flatten() {
+        return id;
+    }
+    let id = ulid::new();
+    let _ = storage.set_item(DEVICE_KEY, &id);
+    id
+}
+
+fn local(room: &str) -> Option<Draft> {
+    let json = local_storage()?.get_item(&key(room)).ok().flatten()?;
+    serde_json::from_str(&json).ok()
+}
+
+/// Keeps `draft` on this device; `None` drops it.
+pub fn keep(room: &str, draft: Option<&Draft>) {
+    let Some(storage) = local_storage() else { return };
+    let _ = match draft {
+        Some(draft) => storage.set_item(&key(room), &serde_json::to_string(draft).unwrap_or_default()),
+        None => storage.remove_item(&key(room)),
+    };
+}
+
+/// The draft for `room` other devices shared, tracked.
+pub fn synced(room: &str) -> Option<Draft> {
+    account_settings::get(&key(room)).and_then(|value| serde_json::from_value(value).ok())
+}
+
+/// The draft to put back in `room`'s message box: the newest of this
+/// device's and a shared one.
+pub fn load(room: &str) -> Option<Draft> {
+    let shared = account_settings::drafts_synced().then(|| untrack(|| synced(room))).flatten();
+    match (local(room), shared) {
+        (Some(local), Some(shared)) if shared.saved_at > local.saved_at => Some(shared),
+        (local, shared) => local.or(shared),
+    }
+}
+
+/// Shares `draft` with the user's other devices once typing pauses, if
+/// they turned that on; `None` withdraws it, at once. Drafts too long to
+/// share are withdrawn too, so a stale shorter one does not win elsewhere.
+pub fn sync(room: &str, draft: Option<Draft>) {
+    if !account_settings::drafts_synced() {
+        return;
+    }
+    // A room left mid-sentence still gets its draft shared
+    PENDING.with(|p| {
+        if let Some((_, handle)) = p.borrow_mut().take_if(|(pending, _)| *pending == room) {
+            handle.clear();
+        }
+    });
+    let draft = draft.filter(|d| d.text.chars().count() <= MAX_SYNCED_CHARS);
+    let Some(draft) = draft else {
+        spawn_local(share(room.to_string(), None));
+        return;
+    };
+    let pending_room = room.to_string();
+    let handle = set_timeout_with_handle(
+        {
+            let room = room.to_string();
+            move || {
+                PENDING.with(|p| p.borrow_mut().take_if(|(pending, _)| *pending == room));
+                spawn_local(share(room, Some(draft)));
+            }
+        },
+        SYNC_DELAY,
+    );
+    if let Ok(handle) = handle {
+        PENDING.with(|p| *p.borrow_mut() = Some((pending_room, handle)));
+    }
+}
+
+/// Saves the draft over settings sync, making room for it by dropping
+/// the oldest shared drafts of other rooms.
+async fn share(room: String, draft: Option<Draft>) {
+    let mut changes = Map::new();
+    if draft.is_some() {
+        let mut others: Vec<(String, f64)> = account_settings::keys_with_prefix(KEY_PREFIX)
+            .into_iter()
+            .filter(|(name, _)| *name != key(&room))
+            .map(|(name, value)| (name, value.get("saved_at").and_then(Value::as_f64).unwrap_or_default()))
+            .collect();
+        others.sort_by(|a, b| b.1.total_cmp(&a.1));
+        for (stale, _) in others.into_iter().skip(MAX_SYNCED_DRAFTS - 1) {
+            changes.insert(stale, Value::Null);
+        }
+    }
+    let value = draft.and_then(|d| serde_json::to_value(d).ok()).unwrap_or(Value::Null);
+    changes.insert(key(&room), value);
+    if let Err(err) = account_settings::update(changes).await {
+        log::warn!("Could not share the draft for {}: {}", room, err);
+    }
+}
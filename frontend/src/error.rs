@@ -0,0 +1,137 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use leptos::*;
+use thiserror::Error;
+
+use crate::clock;
+
+/// Errors kept for the feedback form's diagnostics.
+const LOG_LIMIT: usize = 50;
+/// How long a toast stays up.
+const TOAST_DURATION: std::time::Duration = std::time::Duration::from_secs(6);
+
+/// Anything that goes wrong in the client, by the part of it that failed.
+/// `Display` is the message for the user; `Debug` goes to the log.
+#[derive(Clone, Debug, Error)]
+pub enum AppError {
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+    #[error(transparent)]
+    Signaling(#[from] SignalingError),
+    #[error(transparent)]
+    WebRtc(#[from] WebRtcError),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error(transparent)]
+    Crypto(#[from] CryptoError),
+}
+
+#[derive(Clone, Debug, Error)]
+pub enum AuthError {
+    /// The session was revoked or the account disabled while connected
+    #[error("You were signed out: {0}")]
+    SignedOut(String),
+}
+
+#[derive(Clone, Debug, Error)]
+pub enum SignalingError {
+    /// A request to the server's API did not get an answer
+    #[error("Could not reach the server")]
+    Unreachable(String),
+    /// The WebSocket reported an error; it reconnects on its own
+    #[error("Lost the connection to the server")]
+    Connection,
+    /// An `error` event from the server
+    #[error("{0}")]
+    Server(String),
+}
+
+#[derive(Clone, Debug, Error)]
+pub enum WebRtcError {
+    #[error("The connection to your peer failed")]
+    DataChannel,
+    #[error("Could not reconnect to your peer: {0}")]
+    IceRestart(String),
+}
+
+#[derive(Clone, Debug, Error)]
+pub enum StorageError {
+    #[error("Too many unsent messages; this one was not queued")]
+    OutboxFull,
+    /// Local storage refused a write, usually because it is full
+    #[error("Could not save unsent messages on this device")]
+    WriteFailed(String),
+}
+
+#[derive(Clone, Debug, Error)]
+pub enum CryptoError {
+    /// No `crypto.getRandomValues`; message ids lose their random part
+    #[error("This browser has no secure random numbers")]
+    NoRandomSource,
+    /// Synced data was encrypted with a password this device has no key for
+    #[error("Sign in again to unlock your synced settings")]
+    Locked,
+    /// WebCrypto refused, or the ciphertext did not check out
+    #[error("Could not encrypt or decrypt your synced settings")]
+    Failed(String),
+}
+
+/// A message shown briefly in the corner of the page.
+#[derive(Clone, Debug, PartialEq)]
+struct Toast {
+    id: u64,
+    message: String,
+}
+
+thread_local! {
+    static LOG: RefCell<VecDeque<String>> = const { RefCell::new(VecDeque::new()) };
+    /// Set by [`Toasts`] once it is on the page
+    static TOASTS: RefCell<Option<RwSignal<Vec<Toast>>>> = const { RefCell::new(None) };
+    static NEXT_TOAST: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Logs the error and keeps it for diagnostics, without bothering the user,
+/// e.g. when the page already shows it some other way.
+pub fn record(err: &AppError) {
+    log::error!("{:?}", err);
+    LOG.with(|l| {
+        let mut l = l.borrow_mut();
+        if l.len() == LOG_LIMIT {
+            l.pop_front();
+        }
+        l.push_back(format!("{} {:?}", clock::now_iso(), err));
+    });
+}
+
+/// Logs the error and tells the user about it in a toast.
+pub fn report(err: AppError) {
+    record(&err);
+    let Some(toasts) = TOASTS.with(|t| *t.borrow()) else { return };
+    let id = NEXT_TOAST.with(|n| n.replace(n.get() + 1));
+    toasts.update(|t| t.push(Toast { id, message: err.to_string() }));
+    set_timeout(move || toasts.update(|t| t.retain(|toast| toast.id != id)), TOAST_DURATION);
+}
+
+/// Recent errors, oldest first, one per line.
+pub fn recent() -> Option<String> {
+    LOG.with(|l| {
+        let l = l.borrow();
+        (!l.is_empty()).then(|| l.iter().cloned().collect::<Vec<_>>().join("\n"))
+    })
+}
+
+/// Where reported errors show up; mounted once by the app.
+#[component]
+pub fn Toasts() -> impl IntoView {
+    let toasts = create_rw_signal(Vec::<Toast>::new());
+    TOASTS.with(|t| *t.borrow_mut() = Some(toasts));
+    on_cleanup(|| TOASTS.with(|t| *t.borrow_mut() = None));
+    view! {
+        <div class="toasts" role="status" aria-live="polite">
+            <For each=move || toasts.get() key=|toast| toast.id let:toast>
+                <p class="toast">{toast.message}</p>
+            </For>
+        </div>
+    }
+}
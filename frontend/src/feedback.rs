@@ -0,0 +1,101 @@
+use leptos::*;
+use serde::Serialize;
+
+use crate::{api, error};
+
+/// Connection details the current page can offer to attach to feedback.
+/// The chat page fills it from its connection panel; elsewhere it is empty.
+#[derive(Clone, Copy)]
+pub struct Diagnostics(pub RwSignal<Option<String>>);
+
+const CATEGORIES: &[(&str, &str)] = &[
+    ("bug", "Something is broken"),
+    ("connection", "Connection problem"),
+    ("idea", "Idea or suggestion"),
+    ("other", "Other"),
+];
+
+#[derive(Clone, Debug, Serialize)]
+struct FeedbackRequest {
+    category: String,
+    description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diagnostics: Option<String>,
+}
+
+/// Header button opening the feedback form.
+#[component]
+pub fn FeedbackButton() -> impl IntoView {
+    let diagnostics = use_context::<Diagnostics>().map(|d| d.0);
+    let (open, set_open) = create_signal(false);
+    let (category, set_category) = create_signal(CATEGORIES[0].0.to_string());
+    let (description, set_description) = create_signal("".to_string());
+    let (attach, set_attach) = create_signal(true);
+    let (notice, set_notice) = create_signal::<Option<String>>(None);
+    // The page's own details followed by the errors recorded so far
+    let available = move || {
+        let details = diagnostics.and_then(|d| d.get());
+        match (details, error::recent()) {
+            (Some(details), Some(errors)) => Some(format!("{}\n\nRecent errors:\n{}", details, errors)),
+            (details, errors) => details.or(errors.map(|e| format!("Recent errors:\n{}", e))),
+        }
+    };
+
+    let on_submit = create_action(move |()| {
+        let body = FeedbackRequest {
+            category: category.get(),
+            description: description.get(),
+            diagnostics: if attach.get() { available() } else { None },
+        };
+        async move {
+            match api::post_json("/feedback", &body).await {
+                Ok(resp) if resp.ok() => {
+                    set_description.set("".to_string());
+                    set_open.set(false);
+                    set_notice.set(Some("Thanks for the feedback!".to_string()));
+                }
+                Ok(resp) => set_notice.set(Some(resp.body)),
+                Err(err) => set_notice.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    view! {
+        <div class="feedback">
+            <button type="button" on:click=move |_| {
+                set_notice.set(None);
+                set_open.update(|o| *o = !*o);
+            }>"Feedback"</button>
+            <Show when=move || open.get()>
+                <form class="feedback-form" on:submit=move |ev| {
+                    ev.prevent_default();
+                    on_submit.dispatch(());
+                }>
+                    <select on:change=move |ev| set_category.set(event_target_value(&ev))>
+                        {CATEGORIES.iter().map(|(value, label)| view! {
+                            <option value=*value selected=move || category.with(|c| c == value)>{*label}</option>
+                        }).collect_view()}
+                    </select>
+                    <textarea
+                        placeholder="What happened, or what would you like to see?"
+                        maxlength="2000"
+                        prop:value=description
+                        on:input=move |ev| set_description.set(event_target_value(&ev))
+                    />
+                    {move || available().map(|details| view! {
+                        <label title=details>
+                            <input
+                                type="checkbox"
+                                prop:checked=attach
+                                on:change=move |ev| set_attach.set(event_target_checked(&ev))
+                            />
+                            "Attach diagnostics"
+                        </label>
+                    })}
+                    <button type="submit">"Send"</button>
+                </form>
+            </Show>
+            {move || notice.get().map(|n| view! { <p class="notice">{n}</p> })}
+        </div>
+    }
+}
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use web_sys::RtcDataChannel;
+
+use crate::{outbox, ulid};
+
+/// Payloads up to this size go out as a single data channel message, which
+/// every browser delivers whole.
+const MAX_MESSAGE_BYTES: usize = 16 * 1024;
+/// Room left in each part for the frame around the data.
+const FRAME_OVERHEAD: usize = 128;
+/// Nothing bigger is ever queued, so nothing bigger is reassembled.
+const MAX_PAYLOAD_BYTES: usize = outbox::MAX_QUEUED_BYTES;
+
+/// A payload too big for one message travels as a transaction: `begin`,
+/// its parts in order, then `commit`. The receiver only acts on a committed
+/// transaction with every part, so a channel closing halfway, or a part
+/// lost on our unreliable channel, leaves nothing half-applied; the missing
+/// ack makes the outbox send the whole payload again.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "frame", rename_all = "snake_case")]
+enum Frame {
+    Begin { tx: String, parts: usize },
+    Part { tx: String, seq: usize, data: String },
+    Commit { tx: String },
+}
+
+/// How frames start when serialized, telling them from plain payloads.
+const FRAME_PREFIX: &str = "{\"frame\":";
+
+/// Splits `data` at character boundaries into pieces of at most `max` bytes.
+fn split(data: &str, max: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(max);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (piece, tail) = rest.split_at(end);
+        pieces.push(piece);
+        rest = tail;
+    }
+    pieces
+}
+
+/// Sends `data` over `dc`, as a transaction if it is too big for one
+/// message. False if any message could not be sent.
+pub fn send(dc: &RtcDataChannel, data: &str) -> bool {
+    if data.len() <= MAX_MESSAGE_BYTES {
+        return dc.send_with_str(data).is_ok();
+    }
+    let tx = ulid::new();
+    let pieces = split(data, MAX_MESSAGE_BYTES - FRAME_OVERHEAD);
+    let send_frame = |frame: &Frame| serde_json::to_string(frame).is_ok_and(|f| dc.send_with_str(&f).is_ok());
+    send_frame(&Frame::Begin { tx: tx.clone(), parts: pieces.len() })
+        && pieces.iter().enumerate().all(|(seq, piece)| {
+            send_frame(&Frame::Part { tx: tx.clone(), seq, data: piece.to_string() })
+        })
+        && send_frame(&Frame::Commit { tx })
+}
+
+struct Transaction {
+    tx: String,
+    parts: Vec<Option<String>>,
+    bytes: usize,
+}
+
+/// Puts transactions from one data channel back together. At most one is
+/// open at a time, since a sender writes each one out in a single go; a new
+/// `begin` means the previous transaction was abandoned.
+#[derive(Default)]
+pub struct Reassembler {
+    open: Option<Transaction>,
+}
+
+impl Reassembler {
+    /// The payload `data` completes: `data` itself if it is not a frame, the
+    /// whole payload on a commit with every part, otherwise nothing.
+    pub fn receive(&mut self, data: String) -> Option<String> {
+        if !data.starts_with(FRAME_PREFIX) {
+            return Some(data);
+        }
+        match serde_json::from_str(&data).ok()? {
+            Frame::Begin { tx, parts } => {
+                self.open = (parts * MAX_MESSAGE_BYTES <= MAX_PAYLOAD_BYTES)
+                    .then(|| Transaction { tx, parts: vec![None; parts], bytes: 0 });
+                None
+            }
+            Frame::Part { tx, seq, data } => {
+                let open = self.open.as_mut().filter(|t| t.tx == tx)?;
+                open.bytes += data.len();
+                if open.bytes > MAX_PAYLOAD_BYTES {
+                    self.open = None;
+                    return None;
+                }
+                if let Some(slot) = open.parts.get_mut(seq) {
+                    *slot = Some(data);
+                }
+                None
+            }
+            Frame::Commit { tx } => {
+                let open = self.open.take().filter(|t| t.tx == tx)?;
+                open.parts.into_iter().collect::<Option<Vec<_>>>().map(|parts| parts.concat())
+            }
+        }
+    }
+
+    /// Drops a transaction the channel closed in the middle of.
+    pub fn reset(&mut self) {
+        self.open = None;
+    }
+}
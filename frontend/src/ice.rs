@@ -0,0 +1,270 @@
+use leptos::*;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+use crate::error::{self, CryptoError};
+use crate::vault::{self, Sealed};
+use crate::{api, config};
+
+/// Used when the server cannot be asked, e.g. while offline.
+const FALLBACK_STUN_URL: &str = "stun:stun.l.google.com:19302";
+/// The user's own servers, kept on this device only and sealed with the
+/// synced-settings key since TURN credentials are secrets.
+const OWN_SERVERS_KEY: &str = "own_ice_servers";
+
+/// One STUN or TURN server from `GET /ice-config`, or brought by the room
+/// (see [`SignalingMessage::Peers`](crate::signaling::SignalingMessage::Peers)).
+/// TURN entries from the server come with short-lived credentials.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IceServer {
+    urls: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    credential: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct IceConfig {
+    ice_servers: Vec<IceServer>,
+}
+
+async fn load() -> Vec<IceServer> {
+    let config = match api::get(&config::ice_config_path()).await {
+        Ok(resp) if resp.ok() => resp.json::<IceConfig>(),
+        _ => None,
+    };
+    config.map(|c| c.ice_servers).unwrap_or_else(|| {
+        vec![IceServer { urls: vec![FALLBACK_STUN_URL.to_string()], username: None, credential: None }]
+    })
+}
+
+/// Servers the user brings, and whether they replace the deployment's
+/// rather than add to them.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct OwnServers {
+    pub servers: Vec<IceServer>,
+    pub replace: bool,
+}
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+/// The user's own servers on this device, if any. [`CryptoError::Locked`]
+/// if they were sealed with a key this device no longer has.
+pub async fn own_servers() -> Result<Option<OwnServers>, CryptoError> {
+    let Some(stored) = local_storage().and_then(|s| s.get_item(OWN_SERVERS_KEY).ok().flatten()) else {
+        return Ok(None);
+    };
+    let sealed: Sealed = serde_json::from_str(&stored).map_err(|err| CryptoError::Failed(err.to_string()))?;
+    let key = vault::current().ok_or(CryptoError::Locked)?;
+    let plaintext = sealed.open(&key).await?;
+    serde_json::from_slice(&plaintext).map(Some).map_err(|err| CryptoError::Failed(err.to_string()))
+}
+
+pub async fn keep_own_servers(own: &OwnServers) -> Result<(), CryptoError> {
+    let key = vault::current().ok_or(CryptoError::Locked)?;
+    let plaintext = serde_json::to_vec(own).map_err(|err| CryptoError::Failed(err.to_string()))?;
+    let sealed = Sealed::seal(&key, &plaintext).await?;
+    if let Some(storage) = local_storage() {
+        let _ = storage.set_item(OWN_SERVERS_KEY, &serde_json::to_string(&sealed).unwrap_or_default());
+    }
+    Ok(())
+}
+
+pub fn forget_own_servers() {
+    if let Some(storage) = local_storage() {
+        let _ = storage.remove_item(OWN_SERVERS_KEY);
+    }
+}
+
+/// Seals the user's own servers with `key`, a new key replacing the current
+/// one after a password change.
+pub async fn reseal_own_servers(key: &vault::Key) {
+    let resealed = async {
+        let Some(own) = own_servers().await? else { return Ok(()) };
+        let plaintext = serde_json::to_vec(&own).map_err(|err| CryptoError::Failed(err.to_string()))?;
+        let sealed = Sealed::seal(key, &plaintext).await?;
+        if let Some(storage) = local_storage() {
+            let _ = storage.set_item(OWN_SERVERS_KEY, &serde_json::to_string(&sealed).unwrap_or_default());
+        }
+        Ok::<_, CryptoError>(())
+    };
+    if let Err(err) = resealed.await {
+        error::record(&err.into());
+    }
+}
+
+/// Why browsers would refuse `servers`, which would make the peer
+/// connection fail to construct.
+fn check(servers: &[IceServer]) -> Result<(), String> {
+    for server in servers {
+        for url in &server.urls {
+            match url.split_once(':') {
+                Some(("stun" | "stuns", rest)) if !rest.is_empty() => {}
+                Some(("turn" | "turns", rest)) if !rest.is_empty() => {
+                    if server.username.is_none() || server.credential.is_none() {
+                        return Err("TURN servers need a username and password".to_string());
+                    }
+                }
+                _ => return Err(format!("{} is not a stun:, stuns:, turn: or turns: URL", url)),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Servers from a form: one URL per line, STUN ones on their own, TURN ones
+/// together with the username and credential.
+pub fn from_form(urls: &str, username: &str, credential: &str) -> Vec<IceServer> {
+    let (turn, stun): (Vec<String>, Vec<String>) = urls
+        .lines()
+        .map(str::trim)
+        .filter(|u| !u.is_empty())
+        .map(str::to_string)
+        .partition(|u| u.starts_with("turn:") || u.starts_with("turns:"));
+    let mut servers = Vec::new();
+    if !stun.is_empty() {
+        servers.push(IceServer { urls: stun, username: None, credential: None });
+    }
+    if !turn.is_empty() {
+        let filled = |s: &str| (!s.trim().is_empty()).then(|| s.trim().to_string());
+        servers.push(IceServer { urls: turn, username: filled(username), credential: filled(credential) });
+    }
+    servers
+}
+
+/// The form fields [`from_form`] makes `servers` from.
+fn to_form(servers: &[IceServer]) -> (String, String, String) {
+    let urls = servers.iter().flat_map(|s| s.urls.iter().cloned()).collect::<Vec<_>>().join("\n");
+    let turn = servers.iter().find(|s| s.username.is_some());
+    let username = turn.and_then(|s| s.username.clone()).unwrap_or_default();
+    let credential = turn.and_then(|s| s.credential.clone()).unwrap_or_default();
+    (urls, username, credential)
+}
+
+/// Peer connection settings with the servers the signaling server hands out,
+/// fetched fresh each time since TURN credentials expire, and the user's
+/// own, which add to them or replace them.
+pub async fn rtc_configuration() -> web_sys::RtcConfiguration {
+    let mut servers = load().await;
+    match own_servers().await {
+        Ok(Some(own)) if own.replace && !own.servers.is_empty() => servers = own.servers,
+        Ok(Some(own)) => servers.extend(own.servers),
+        Ok(None) => {}
+        Err(err) => error::record(&err.into()),
+    }
+    configuration(&servers)
+}
+
+/// Peer connection settings with exactly `servers`.
+pub fn configuration(servers: &[IceServer]) -> web_sys::RtcConfiguration {
+    let entries = js_sys::Array::new();
+    for server in servers {
+        let entry = web_sys::RtcIceServer::new();
+        let urls: js_sys::Array = server.urls.iter().map(|u| JsValue::from_str(u)).collect();
+        entry.set_urls(&urls);
+        if let Some(username) = &server.username {
+            entry.set_username(username);
+        }
+        if let Some(credential) = &server.credential {
+            entry.set_credential(credential);
+        }
+        entries.push(&entry);
+    }
+    let config = web_sys::RtcConfiguration::new();
+    config.set_ice_servers(&entries);
+    config
+}
+
+/// Settings page section for STUN/TURN servers of the user's own, e.g. a
+/// TURN server they run. Rooms that bring servers still use theirs.
+#[component]
+pub fn OwnServersSetting() -> impl IntoView {
+    let (urls, set_urls) = create_signal(String::new());
+    let (username, set_username) = create_signal(String::new());
+    let (credential, set_credential) = create_signal(String::new());
+    let (replace, set_replace) = create_signal(false);
+    let (notice, set_notice) = create_signal::<Option<String>>(None);
+    let locked = || "Sign in with your password again to unlock your servers.".to_string();
+
+    spawn_local(async move {
+        match own_servers().await {
+            Ok(Some(own)) => {
+                let (u, n, c) = to_form(&own.servers);
+                set_urls.set(u);
+                set_username.set(n);
+                set_credential.set(c);
+                set_replace.set(own.replace);
+            }
+            Ok(None) => {}
+            Err(CryptoError::Locked) => set_notice.set(Some(locked())),
+            Err(err) => set_notice.set(Some(err.to_string())),
+        }
+    });
+
+    let on_save = create_action(move |()| {
+        let own = OwnServers {
+            servers: from_form(&urls.get(), &username.get(), &credential.get()),
+            replace: replace.get(),
+        };
+        async move {
+            if let Err(message) = check(&own.servers) {
+                set_notice.set(Some(message));
+                return;
+            }
+            let saved = if own.servers.is_empty() {
+                forget_own_servers();
+                Ok(())
+            } else {
+                keep_own_servers(&own).await
+            };
+            set_notice.set(Some(match saved {
+                Ok(()) => "Saved. New connections use these servers.".to_string(),
+                Err(CryptoError::Locked) => locked(),
+                Err(err) => err.to_string(),
+            }));
+        }
+    });
+
+    view! {
+        <section class="own-ice">
+            <h3>"Your STUN/TURN servers"</h3>
+            <form on:submit=move |ev| {
+                ev.prevent_default();
+                on_save.dispatch(());
+            }>
+                <textarea
+                    placeholder="One stun:, turn: or turns: URL per line"
+                    rows="3"
+                    prop:value=urls
+                    on:input=move |ev| set_urls.set(event_target_value(&ev))
+                ></textarea>
+                <input
+                    type="text"
+                    placeholder="TURN username"
+                    prop:value=username
+                    on:input=move |ev| set_username.set(event_target_value(&ev))
+                />
+                <input
+                    type="password"
+                    placeholder="TURN password"
+                    prop:value=credential
+                    on:input=move |ev| set_credential.set(event_target_value(&ev))
+                />
+                <label>
+                    <input
+                        type="checkbox"
+                        prop:checked=replace
+                        on:change=move |ev| set_replace.set(event_target_checked(&ev))
+                    />
+                    "Use only these, not the server's"
+                </label>
+                <button type="submit">"Save"</button>
+            </form>
+            <p class="hint">"Kept on this device only, encrypted with a key from your password. Leave the URLs empty to remove them."</p>
+            {move || notice.get().map(|n| view! { <p class="notice">{n}</p> })}
+        </section>
+    }
+}
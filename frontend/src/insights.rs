@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+
+use leptos::*;
+use wasm_bindgen::{JsCast, JsValue};
+
+use crate::{language, locale, Message};
+
+pub const CHART_WIDTH: f64 = 480.0;
+pub const CHART_HEIGHT: f64 = 160.0;
+
+/// Aggregates computed from a room's messages, entirely in the browser.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RoomStats {
+    /// Message count per `YYYY-MM-DD` day, in date order
+    pub per_day: BTreeMap<String, u32>,
+    /// Message count per local hour of day
+    pub per_hour: [u32; 24],
+    pub media_count: u32,
+    /// Mean time between a message and the other party's next reply
+    pub avg_response_ms: Option<f64>,
+    /// Received messages per detected language, e.g. `de`
+    pub per_language: BTreeMap<String, u32>,
+}
+
+fn parse_time(timestamp: &str) -> Option<js_sys::Date> {
+    let date = js_sys::Date::new(&JsValue::from_str(timestamp));
+    (!date.get_time().is_nan()).then_some(date)
+}
+
+fn day_key(date: &js_sys::Date) -> String {
+    format!("{:04}-{:02}-{:02}", date.get_full_year(), date.get_month() + 1, date.get_date())
+}
+
+pub fn compute(messages: &[Message]) -> RoomStats {
+    let mut stats = RoomStats::default();
+    let mut response_total = 0.0;
+    let mut response_count = 0u32;
+    let mut previous: Option<(&str, f64)> = None;
+
+    for msg in messages.iter().filter(|m| m.sender != "system") {
+        if msg.scribble.is_some() {
+            stats.media_count += 1;
+        }
+        if let Some(lang) = &msg.lang {
+            *stats.per_language.entry(lang.clone()).or_default() += 1;
+        }
+        let Some(date) = parse_time(&msg.timestamp) else { continue };
+        *stats.per_day.entry(day_key(&date)).or_default() += 1;
+        stats.per_hour[date.get_hours() as usize] += 1;
+
+        let time = date.get_time();
+        if let Some((sender, at)) = previous {
+            if sender != msg.sender {
+                response_total += time - at;
+                response_count += 1;
+            }
+        }
+        previous = Some((&msg.sender, time));
+    }
+
+    if response_count > 0 {
+        stats.avg_response_ms = Some(response_total / response_count as f64);
+    }
+    stats
+}
+
+/// "German 12, English 3": languages the other side wrote in, most used
+/// first.
+pub fn languages(stats: &RoomStats) -> Option<String> {
+    let mut languages: Vec<_> = stats.per_language.iter().collect();
+    languages.sort_by(|a, b| b.1.cmp(a.1));
+    let listed: Vec<String> = languages
+        .into_iter()
+        .map(|(lang, count)| format!("{} {}", language::name(lang), locale::number(*count as f64, 0)))
+        .collect();
+    (!listed.is_empty()).then(|| listed.join(", "))
+}
+
+/// Busiest hour of the day, if any messages were sent.
+pub fn busiest_hour(stats: &RoomStats) -> Option<usize> {
+    let (hour, count) = stats.per_hour.iter().enumerate().max_by_key(|(_, c)| **c)?;
+    (*count > 0).then_some(hour)
+}
+
+pub fn to_csv(stats: &RoomStats) -> String {
+    let mut csv = String::from("metric,key,value\n");
+    for (day, count) in &stats.per_day {
+        csv.push_str(&format!("messages_per_day,{},{}\n", day, count));
+    }
+    for (hour, count) in stats.per_hour.iter().enumerate() {
+        csv.push_str(&format!("messages_per_hour,{},{}\n", hour, count));
+    }
+    for (lang, count) in &stats.per_language {
+        csv.push_str(&format!("messages_per_language,{},{}\n", lang, count));
+    }
+    csv.push_str(&format!("media_count,,{}\n", stats.media_count));
+    if let Some(avg) = stats.avg_response_ms {
+        csv.push_str(&format!("avg_response_seconds,,{:.1}\n", avg / 1000.0));
+    }
+    csv
+}
+
+fn download_csv(filename: &str, csv: &str) {
+    let parts = js_sys::Array::of1(&JsValue::from_str(csv));
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type("text/csv");
+    let Ok(blob) = web_sys::Blob::new_with_str_sequence_and_options(&parts, &options) else { return };
+    let Ok(url) = web_sys::Url::create_object_url_with_blob(&blob) else { return };
+
+    let document = web_sys::window().unwrap().document().unwrap();
+    if let Some(link) = document
+        .create_element("a")
+        .ok()
+        .and_then(|el| el.dyn_into::<web_sys::HtmlAnchorElement>().ok())
+    {
+        link.set_href(&url);
+        link.set_download(filename);
+        link.click();
+    }
+    let _ = web_sys::Url::revoke_object_url(&url);
+}
+
+/// Bar chart of `values` on `canvas`, `labels` under the bars.
+pub fn draw_bars(canvas: &HtmlElement<html::Canvas>, labels: &[String], values: &[u32]) {
+    let Some(ctx) = canvas
+        .get_context("2d")
+        .ok()
+        .flatten()
+        .and_then(|c| c.dyn_into::<web_sys::CanvasRenderingContext2d>().ok())
+    else {
+        return;
+    };
+    ctx.clear_rect(0.0, 0.0, CHART_WIDTH, CHART_HEIGHT);
+    if values.is_empty() {
+        return;
+    }
+
+    let max = values.iter().copied().max().unwrap_or(0).max(1) as f64;
+    let label_space = 14.0;
+    let slot = CHART_WIDTH / values.len() as f64;
+    ctx.set_font("10px sans-serif");
+    for (i, value) in values.iter().enumerate() {
+        let height = (CHART_HEIGHT - label_space) * (*value as f64 / max);
+        let x = i as f64 * slot;
+        ctx.set_fill_style_str("#4a7bd0");
+        ctx.fill_rect(x + 1.0, CHART_HEIGHT - label_space - height, (slot - 2.0).max(1.0), height);
+        if let Some(label) = labels.get(i) {
+            ctx.set_fill_style_str("#555");
+            let _ = ctx.fill_text(label, x + 1.0, CHART_HEIGHT - 2.0);
+        }
+    }
+}
+
+/// Per-room statistics panel with canvas charts and CSV export.
+#[component]
+pub fn RoomInsights(
+    #[prop(into)] room: Signal<String>,
+    #[prop(into)] messages: Signal<Vec<Message>>,
+) -> impl IntoView {
+    let stats = create_memo(move |_| messages.with(|m| compute(m)));
+    let per_day_ref = create_node_ref::<html::Canvas>();
+    let per_hour_ref = create_node_ref::<html::Canvas>();
+
+    create_effect(move |_| {
+        let stats = stats.get();
+        if let Some(canvas) = per_day_ref.get() {
+            // Day labels leave out the year to fit under the bars; the
+            // keys are local dates, so parse them as local midnight
+            let labels: Vec<String> = stats.per_day.keys().map(|d| locale::month_day(&format!("{}T00:00", d))).collect();
+            let values: Vec<u32> = stats.per_day.values().copied().collect();
+            draw_bars(&canvas, &labels, &values);
+        }
+        if let Some(canvas) = per_hour_ref.get() {
+            let labels: Vec<String> = (0..24).map(|h| if h % 6 == 0 { locale::hour(h) } else { String::new() }).collect();
+            draw_bars(&canvas, &labels, &stats.per_hour);
+        }
+    });
+
+    let export = move |_| {
+        let filename = format!("{}-insights.csv", room.get());
+        download_csv(&filename, &to_csv(&stats.get()));
+    };
+
+    view! {
+        <div class="insights">
+            <h3>"Room insights"</h3>
+            <ul class="insights-summary">
+                <li>"Media shared: " {move || stats.with(|s| locale::number(s.media_count as f64, 0))}</li>
+                <li>"Languages: " {move || stats.with(languages).unwrap_or_else(|| "—".to_string())}</li>
+                <li>"Busiest hour: " {move || stats.with(busiest_hour).map(locale::hour).unwrap_or_else(|| "—".to_string())}</li>
+                <li>"Average response time: " {move || stats.with(|s| s.avg_response_ms).map(|ms| format!("{}s", locale::number(ms / 1000.0, 0))).unwrap_or_else(|| "—".to_string())}</li>
+            </ul>
+            <h4>"Messages per day"</h4>
+            <canvas node_ref=per_day_ref width=CHART_WIDTH height=CHART_HEIGHT/>
+            <h4>"Messages by hour"</h4>
+            <canvas node_ref=per_hour_ref width=CHART_WIDTH height=CHART_HEIGHT/>
+            <button type="button" on:click=export>"Export CSV"</button>
+        </div>
+    }
+}
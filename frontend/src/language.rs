@@ -0,0 +1,133 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+
+use crate::locale;
+
+/// Shorter texts ("ok", "haha") say too little about their language.
+const MIN_DETECT_CHARS: usize = 12;
+/// Per room, in localStorage: translate incoming messages on arrival.
+const AUTO_TRANSLATE_PREFIX: &str = "auto_translate:";
+/// The detector names languages by ISO 639-3; tags and the Translator API
+/// want ISO 639-1 where there is one.
+const ISO_639_1: &[(&str, &str)] = &[
+    ("afr", "af"), ("aka", "ak"), ("amh", "am"), ("ara", "ar"), ("aze", "az"), ("bel", "be"), ("ben", "bn"),
+    ("bul", "bg"), ("cat", "ca"), ("ces", "cs"), ("cmn", "zh"), ("dan", "da"), ("deu", "de"), ("ell", "el"),
+    ("eng", "en"), ("epo", "eo"), ("est", "et"), ("fin", "fi"), ("fra", "fr"), ("guj", "gu"), ("heb", "he"),
+    ("hin", "hi"), ("hrv", "hr"), ("hun", "hu"), ("hye", "hy"), ("ind", "id"), ("ita", "it"), ("jav", "jv"),
+    ("jpn", "ja"), ("kan", "kn"), ("kat", "ka"), ("khm", "km"), ("kor", "ko"), ("lat", "la"), ("lav", "lv"),
+    ("lit", "lt"), ("mal", "ml"), ("mar", "mr"), ("mkd", "mk"), ("mya", "my"), ("nep", "ne"), ("nld", "nl"),
+    ("nob", "nb"), ("ori", "or"), ("pan", "pa"), ("pes", "fa"), ("pol", "pl"), ("por", "pt"), ("ron", "ro"),
+    ("rus", "ru"), ("sin", "si"), ("slk", "sk"), ("slv", "sl"), ("sna", "sn"), ("spa", "es"), ("srp", "sr"),
+    ("swe", "sv"), ("tam", "ta"), ("tel", "te"), ("tgl", "tl"), ("tha", "th"), ("tuk", "tk"), ("tur", "tr"),
+    ("ukr", "uk"), ("urd", "ur"), ("uzb", "uz"), ("vie", "vi"), ("yid", "yi"), ("zul", "zu"),
+];
+
+thread_local! {
+    /// Translators the browser made, by (source, target); making one can
+    /// mean downloading a model, so they are kept for the session
+    static TRANSLATORS: RefCell<HashMap<(String, String), JsValue>> = RefCell::new(HashMap::new());
+}
+
+/// The probable language of `text` as a BCP 47 tag, e.g. `de`, if the
+/// detector is confident. Runs entirely in the browser.
+pub fn detect(text: &str) -> Option<String> {
+    if text.chars().filter(|c| c.is_alphabetic()).count() < MIN_DETECT_CHARS {
+        return None;
+    }
+    let info = whatlang::detect(text).filter(whatlang::Info::is_reliable)?;
+    let code = info.lang().code();
+    Some(ISO_639_1.iter().find(|(long, _)| *long == code).map_or(code, |(_, short)| *short).to_string())
+}
+
+/// The language the user reads, from the browser's locale: `de` for `de-CH`.
+pub fn own() -> String {
+    primary(&locale::current())
+}
+
+fn primary(tag: &str) -> String {
+    tag.split(['-', '_']).next().unwrap_or_default().to_lowercase()
+}
+
+/// Whether a message tagged `lang` is in another language than the user's.
+pub fn is_foreign(lang: &str) -> bool {
+    primary(lang) != own()
+}
+
+/// Human name of `lang` in the user's language, e.g. "German".
+pub fn name(lang: &str) -> String {
+    display_name(lang).unwrap_or_else(|| lang.to_string())
+}
+
+fn display_name(lang: &str) -> Option<String> {
+    let intl = js_sys::Reflect::get(&js_sys::global(), &"Intl".into()).ok()?;
+    let constructor: js_sys::Function = js_sys::Reflect::get(&intl, &"DisplayNames".into()).ok()?.dyn_into().ok()?;
+    let options = js_sys::Object::new();
+    js_sys::Reflect::set(&options, &"type".into(), &"language".into()).ok()?;
+    let args = js_sys::Array::of2(&js_sys::Array::of1(&locale::current().into()), &options);
+    let names = js_sys::Reflect::construct(&constructor, &args).ok()?;
+    let of: js_sys::Function = js_sys::Reflect::get(&names, &"of".into()).ok()?.dyn_into().ok()?;
+    of.call1(&names, &lang.into()).ok()?.as_string()
+}
+
+fn translator_api() -> Option<JsValue> {
+    js_sys::Reflect::get(&js_sys::global(), &"Translator".into()).ok().filter(|api| !api.is_undefined())
+}
+
+/// Whether the browser can translate on the device (the Translator API).
+/// Nothing is sent anywhere to translate; browsers without it offer no
+/// translations.
+pub fn can_translate() -> bool {
+    translator_api().is_some()
+}
+
+async fn call(target: &JsValue, method: &str, arg: &JsValue) -> Result<JsValue, JsValue> {
+    let function: js_sys::Function = js_sys::Reflect::get(target, &method.into())?.dyn_into()?;
+    let promise: js_sys::Promise = function.call1(target, arg)?.dyn_into()?;
+    JsFuture::from(promise).await
+}
+
+async fn translator(source: &str, target: &str) -> Result<JsValue, String> {
+    let key = (source.to_string(), target.to_string());
+    if let Some(translator) = TRANSLATORS.with(|t| t.borrow().get(&key).cloned()) {
+        return Ok(translator);
+    }
+    let api = translator_api().ok_or("This browser cannot translate")?;
+    let options = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&options, &"sourceLanguage".into(), &source.into());
+    let _ = js_sys::Reflect::set(&options, &"targetLanguage".into(), &target.into());
+    let availability = call(&api, "availability", &options).await.ok().and_then(|a| a.as_string());
+    if availability.as_deref() == Some("unavailable") {
+        return Err(format!("Cannot translate from {} to {} here", name(source), name(target)));
+    }
+    let translator = call(&api, "create", &options).await.map_err(|_| "Could not start translating".to_string())?;
+    TRANSLATORS.with(|t| t.borrow_mut().insert(key, translator.clone()));
+    Ok(translator)
+}
+
+/// Translates `text`, written in `source`, into the user's language.
+pub async fn translate(text: &str, source: &str) -> Result<String, String> {
+    let translator = translator(source, &own()).await?;
+    let translated = call(&translator, "translate", &text.into()).await.map_err(|_| "Translation failed".to_string())?;
+    translated.as_string().ok_or_else(|| "Translation failed".to_string())
+}
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+/// Whether incoming messages in `room` are translated as they arrive;
+/// kept per device, like the choice of language it follows.
+pub fn auto_translate(room: &str) -> bool {
+    local_storage()
+        .and_then(|s| s.get_item(&format!("{}{}", AUTO_TRANSLATE_PREFIX, room)).ok().flatten())
+        .is_some_and(|v| v == "1")
+}
+
+pub fn set_auto_translate(room: &str, enabled: bool) {
+    let Some(storage) = local_storage() else { return };
+    let key = format!("{}{}", AUTO_TRANSLATE_PREFIX, room);
+    let _ = if enabled { storage.set_item(&key, "1") } else { storage.remove_item(&key) };
+}
@@ -1,32 +1,348 @@
+mod account_rooms;
+mod account_settings;
+mod api;
+mod battery;
+mod broadcast;
+mod clock;
+mod commands;
+mod config;
+mod data_saver;
+mod digests;
+mod display;
+mod drafts;
+mod error;
+mod feedback;
+mod frames;
+mod ice;
+mod insights;
+mod language;
+mod locale;
+mod maintenance;
+mod message;
+mod outbox;
+mod palette;
+mod petnames;
+mod people;
+mod qr_login;
+mod room_stats;
+mod scribble;
+mod signaling;
+mod status;
+mod tour;
+mod transport;
+mod ulid;
+mod vault;
+
+use std::collections::HashMap;
+
 use leptos::*;
 use leptos_meta::*;
 use leptos_router::*;
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Message {
-    pub content: String,
-    pub sender: String,
-    pub timestamp: String,
+use account_rooms::{AccountRooms, QuickRooms};
+use commands::SlashCommand;
+use error::{AuthError, SignalingError, StorageError, WebRtcError};
+use insights::RoomInsights;
+pub use message::Message;
+use message::{MessageStatus, PeerMessage};
+use outbox::{Outbox, OutboxFull};
+use palette::CommandPalette;
+use people::PeopleSearch;
+use scribble::{Scribble, ScribblePad, ScribbleView};
+use signaling::{PeerInfo, SignalingMessage};
+use status::{ConnectionStatus, PeerState, RoutePanel, SignalingState, StatusIndicator};
+use transport::Transport;
+
+#[derive(Serialize)]
+struct Credentials {
+    username: String,
+    password: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    totp_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ForgotPassword {
+    email: String,
+}
+
+#[derive(Serialize)]
+struct ResetPassword {
+    token: String,
+    new_password: String,
+}
+
+#[derive(Deserialize)]
+struct LoginResponse {
+    token: String,
+}
+
+#[derive(Serialize)]
+struct GuestRequest {
+    room: String,
+}
+
+#[derive(Deserialize)]
+struct GuestLogin {
+    token: String,
+    username: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct Profile {
+    #[serde(default)]
+    display_name: Option<String>,
+    #[serde(default)]
+    avatar_url: Option<String>,
+    #[serde(default)]
+    bio: Option<String>,
 }
 
+#[derive(Serialize)]
+struct ChangePassword {
+    current_password: String,
+    new_password: String,
+}
+
+#[derive(Deserialize)]
+struct TotpChallenge {
+    totp_required: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+struct SessionInfo {
+    id: String,
+    created_at: String,
+    last_seen: String,
+    #[serde(default)]
+    ip: Option<String>,
+    #[serde(default)]
+    user_agent: Option<String>,
+    current: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+struct ApiKeyInfo {
+    id: String,
+    name: String,
+    scopes: Vec<String>,
+    created_at: String,
+    #[serde(default)]
+    last_used: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+struct NewApiKey {
+    name: String,
+    scopes: Vec<String>,
+}
+
+/// Returned once by `POST /apikeys`; the key is never shown again.
+#[derive(Deserialize)]
+struct CreatedApiKey {
+    key: String,
+}
+
+/// Scopes a bot's API key can be given, with what they allow.
+const API_KEY_SCOPES: &[(&str, &str)] = &[
+    ("signaling", "Join rooms and relay messages"),
+    ("rooms", "Browse and edit rooms"),
+    ("profile", "Profile and saved rooms"),
+];
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+struct RoomInfo {
+    name: String,
+    peers: usize,
+    capacity: usize,
+    full: bool,
+    #[serde(default)]
+    locked: bool,
+    #[serde(default)]
+    waiting: usize,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    category: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Joining takes a password or an invite link
+    #[serde(default)]
+    protected: bool,
+    /// Everyone but the owner waits for the owner to let them in
+    #[serde(default)]
+    lobby: bool,
+    /// Set by a moderator from the chat; empty for none
+    #[serde(default)]
+    topic: String,
+    /// Only the owner sends; `viewers` watch
+    #[serde(default)]
+    broadcast: bool,
+    #[serde(default)]
+    viewers: Option<usize>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+struct RoomPage {
+    rooms: Vec<RoomInfo>,
+    total: usize,
+    page: usize,
+    per_page: usize,
+    categories: Vec<String>,
+    tags: Vec<String>,
+    /// Largest room the server lets us create
+    #[serde(default)]
+    max_capacity: Option<usize>,
+}
+
+impl RoomPage {
+    fn page_count(&self) -> usize {
+        self.total.div_ceil(self.per_page.max(1)).max(1)
+    }
+}
+
+/// A room from `GET /rooms/trending`, with its activity over the last hour.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+struct TrendingRoom {
+    name: String,
+    joins: usize,
+    relays: usize,
+    peers: usize,
+    capacity: usize,
+    #[serde(default)]
+    category: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct NewRoom {
+    name: String,
+    description: String,
+    max_size: usize,
+    /// Listed in the directory; unlisted rooms are joined by name only
+    public: bool,
+    /// Kept for months while empty instead of a day
+    persistent: bool,
+    /// Everyone but us has to give it to join
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+    /// We let everyone in ourselves
+    lobby: bool,
+    /// Only we send; everyone else watches
+    broadcast: bool,
+    /// Messages are kept on the server for members' email digests
+    digests: bool,
+    /// Our own STUN/TURN servers, used by members instead of the server's
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ice_servers: Vec<ice::IceServer>,
+}
+
+/// Most peers a room can be created for until the directory tells us the
+/// server's actual limit.
+const MAX_ROOM_SIZE: usize = 8;
+
+impl Default for NewRoom {
+    fn default() -> Self {
+        NewRoom {
+            name: String::new(),
+            description: String::new(),
+            max_size: MAX_ROOM_SIZE,
+            public: true,
+            persistent: false,
+            password: None,
+            lobby: false,
+            broadcast: false,
+            digests: false,
+            ice_servers: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+struct RoomDetails {
+    description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    tags: Vec<String>,
+}
+
+/// A signed link into a password-protected room, from `POST /rooms/{name}/invites`.
+#[derive(Clone, Debug, Deserialize)]
+struct Invite {
+    token: String,
+}
+
+impl RoomInfo {
+    /// Joining means knocking and waiting for a member, or in a lobby room
+    /// the owner, to let us in.
+    fn needs_knock(&self) -> bool {
+        self.lobby || (self.peers > 0 && (self.full || self.locked))
+    }
+}
+
+/// How often the lobby refreshes room occupancy.
+const LOBBY_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+/// With data saver on or a low battery the lobby only refreshes on every
+/// this many ticks, i.e. every 30 seconds.
+const SLOW_POLL_TICKS: u32 = 6;
+/// How long the data channel gets to open before chat falls back to the
+/// server relay, where the server offers one.
+const P2P_TIMEOUT_MS: u32 = 15_000;
+/// How often we ping our peer over an open data channel.
+const KEEPALIVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+/// Silence after which a peer that pings is considered unreachable.
+const LIVENESS_TIMEOUT_MS: f64 = 15_000.0;
+/// How often relative message times are brought up to date.
+const CLOCK_TICK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+/// WebSockets that may close before the server welcomes us before we
+/// signal over the event stream instead.
+const WEBSOCKET_ATTEMPTS: usize = 3;
+/// Pause before trying a WebSocket that failed again.
+const SIGNALING_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+
 #[component]
 fn App() -> impl IntoView {
+    // Right-to-left languages mirror the whole layout, chat bubbles included
+    let locale = locale::current();
+    display::load_and_apply();
+    account_settings::init();
+    provide_context(feedback::Diagnostics(create_rw_signal(None)));
     view! {
+        <Html lang=locale.clone() dir=locale::direction(&locale)/>
         <Stylesheet id="leptos" href="/pkg/p2p_chat_frontend.css"/>
-        <Title text="P2P Chat"/>
+        <Title text=config::brand_name()/>
         <Link rel="shortcut icon" type_="image/ico" href="/favicon.ico"/>
         <Router fallback=|| view! { <div>"Not Found"</div> }>
             <header>
-                <h1>"P2P Chat App"</h1>
+                <h1>
+                    {config::brand_logo().map(|src| view! { <img class="brand-logo" src=src alt=""/> })}
+                    {config::brand_name()}
+                </h1>
+                <nav>
+                    <a href="/rooms">"Rooms"</a>
+                    <a href="/settings">"Settings"</a>
+                </nav>
+                <feedback::FeedbackButton/>
             </header>
+            <CommandPalette/>
+            <data_saver::DataSaverSuggestion/>
+            <error::Toasts/>
             <main>
                 <Routes>
                     <Route path="/" view=HomePage/>
                     <Route path="/login" view=LoginPage/>
+                    <Route path="/login/qr/:code" view=qr_login::QrLoginPage/>
                     <Route path="/register" view=RegisterPage/>
+                    <Route path="/forgot-password" view=ForgotPasswordPage/>
+                    <Route path="/reset-password" view=ResetPasswordPage/>
+                    <Route path="/digests/unsubscribe" view=digests::UnsubscribePage/>
+                    <Route path="/rooms" view=LobbyPage/>
+                    <Route path="/rooms/:name/stats" view=room_stats::RoomStatsPage/>
                     <Route path="/chat/:room" view=ChatPage/>
+                    <Route path="/settings" view=SettingsPage/>
                 </Routes>
             </main>
         </Router>
@@ -36,15 +352,61 @@ fn App() -> impl IntoView {
 #[component]
 fn HomePage() -> impl IntoView {
     let navigate = use_navigate();
+    let (guest_room, set_guest_room) = create_signal("".to_string());
+    let (error, set_error) = create_signal::<Option<String>>(None);
+
+    // Guests get a short-lived token that only works for this one room
+    let guest_navigate = navigate.clone();
+    let on_join_as_guest = create_action(move |()| {
+        let room = guest_room.get().trim().to_string();
+        let navigate = guest_navigate.clone();
+        async move {
+            if room.is_empty() {
+                return;
+            }
+            match api::post_json("/guest", &GuestRequest { room: room.clone() }).await {
+                Ok(resp) if resp.ok() => {
+                    if let Some(GuestLogin { token, username }) = resp.json() {
+                        api::store_token(&token);
+                        console::log_1(&format!("Joining {} as {}", room, username).into());
+                        navigate(&format!("/chat/{}", room), Default::default());
+                    }
+                }
+                Ok(resp) => set_error.set(Some(resp.body)),
+                Err(err) => set_error.set(Some(err.to_string())),
+            }
+        }
+    });
 
     view! {
         <div class="home">
             <h2>"Welcome to P2P Chat"</h2>
             <p>"Secure peer-to-peer messaging with end-to-end encryption."</p>
             <div class="buttons">
-                <button on:click=move |_| navigate("/login", false).unwrap()>"Login"</button>
-                <button on:click=move |_| navigate("/register", false).unwrap()>"Register"</button>
+                <button on:click={
+                    let navigate = navigate.clone();
+                    move |_| navigate("/login", Default::default())
+                }>"Login"</button>
+                <button on:click={
+                    let navigate = navigate.clone();
+                    move |_| navigate("/register", Default::default())
+                }>"Register"</button>
             </div>
+            <QuickRooms/>
+            <form class="guest-join" on:submit=move |ev| {
+                ev.prevent_default();
+                on_join_as_guest.dispatch(());
+            }>
+                <h3>"No account?"</h3>
+                <input
+                    type="text"
+                    placeholder="Room name"
+                    prop:value=guest_room
+                    on:input=move |ev| set_guest_room.set(event_target_value(&ev))
+                />
+                <button type="submit">"Join as guest"</button>
+            </form>
+            {move || error.get().map(|e| view! { <p class="error">{e}</p> })}
         </div>
     }
 }
@@ -54,17 +416,42 @@ fn LoginPage() -> impl IntoView {
     let navigate = use_navigate();
     let (username, set_username) = create_signal("".to_string());
     let (password, set_password) = create_signal("".to_string());
+    let (totp_code, set_totp_code) = create_signal("".to_string());
+    let (totp_required, set_totp_required) = create_signal(false);
+    let (error, set_error) = create_signal::<Option<String>>(None);
+    let (handoff_code, set_handoff_code) = create_signal("".to_string());
+    let code_navigate = navigate.clone();
 
     let on_submit = create_action(move |()| {
-        let username = username.get();
-        let password = password.get();
+        let credentials = Credentials {
+            username: username.get(),
+            password: password.get(),
+            totp_code: totp_required.get().then(|| totp_code.get()),
+            email: None,
+        };
+        let navigate = navigate.clone();
         async move {
-            // In real impl, call backend /login
-            if username == "test" && password == "test" {
-                // Store JWT in localStorage
-                set_username.set("".to_string());
-                set_password.set("".to_string());
-                navigate("/chat/testroom", false).unwrap();
+            let password = credentials.password.clone();
+            match api::post_json("/login", &credentials).await {
+                Ok(resp) if resp.ok() => {
+                    if let Some(LoginResponse { token }) = resp.json() {
+                        api::store_token(&token);
+                        set_username.set("".to_string());
+                        set_password.set("".to_string());
+                        set_totp_code.set("".to_string());
+                        set_totp_required.set(false);
+                        display::load_and_apply();
+                        spawn_local(account_settings::unlock(password));
+                        navigate("/rooms", Default::default());
+                    }
+                }
+                Ok(resp) if resp.json::<TotpChallenge>().is_some_and(|c| c.totp_required) => {
+                    // Password accepted; ask for the second factor and resubmit
+                    set_error.set(None);
+                    set_totp_required.set(true);
+                }
+                Ok(resp) => set_error.set(Some(resp.body)),
+                Err(err) => set_error.set(Some(err.to_string())),
             }
         }
     });
@@ -72,24 +459,64 @@ fn LoginPage() -> impl IntoView {
     view! {
         <div class="auth-form">
             <h2>"Login"</h2>
-            <form on:submit=|ev| on_submit.dispatch(ev)>
+            <form on:submit=move |ev| {
+                ev.prevent_default();
+                on_submit.dispatch(());
+            }>
                 <input
                     type="text"
                     placeholder="Username"
                     prop:value=username
+                    prop:disabled=totp_required
                     on:input=move |ev| set_username.set(event_target_value(&ev))
                 />
                 <input
                     type="password"
                     placeholder="Password"
                     prop:value=password
+                    prop:disabled=totp_required
                     on:input=move |ev| set_password.set(event_target_value(&ev))
                 />
-                <button type="submit">"Login"</button>
+                <Show when=move || totp_required.get()>
+                    <p>"Enter the code from your authenticator app, or a recovery code."</p>
+                    <input
+                        type="text"
+                        inputmode="numeric"
+                        autocomplete="one-time-code"
+                        placeholder="Authentication code"
+                        prop:value=totp_code
+                        on:input=move |ev| set_totp_code.set(event_target_value(&ev))
+                    />
+                </Show>
+                <button type="submit">{move || if totp_required.get() { "Verify" } else { "Login" }}</button>
             </form>
+            {move || error.get().map(|e| view! { <p class="error">{e}</p> })}
+            <details>
+                <summary>"Log in with a code from another device"</summary>
+                <p>"On a device where you are logged in, open Settings and show a QR code. Scan it, or type its code here."</p>
+                <form on:submit=move |ev| {
+                    ev.prevent_default();
+                    let code = handoff_code.get().replace(' ', "");
+                    if !code.is_empty() {
+                        code_navigate(&format!("/login/qr/{}", code), Default::default());
+                    }
+                }>
+                    <input
+                        type="text"
+                        placeholder="Code"
+                        autocomplete="off"
+                        prop:value=handoff_code
+                        on:input=move |ev| set_handoff_code.set(event_target_value(&ev))
+                    />
+                    <button type="submit">"Continue"</button>
+                </form>
+            </details>
             <p>
                 <a href="/register">"Don't have an account? Register"</a>
             </p>
+            <p>
+                <a href="/forgot-password">"Forgot your password?"</a>
+            </p>
         </div>
     }
 }
@@ -99,16 +526,27 @@ fn RegisterPage() -> impl IntoView {
     let navigate = use_navigate();
     let (username, set_username) = create_signal("".to_string());
     let (password, set_password) = create_signal("".to_string());
+    let (email, set_email) = create_signal("".to_string());
+    let (error, set_error) = create_signal::<Option<String>>(None);
 
     let on_submit = create_action(move |()| {
-        let username = username.get();
-        let password = password.get();
+        let email = email.get();
+        let credentials = Credentials {
+            username: username.get(),
+            password: password.get(),
+            totp_code: None,
+            email: (!email.trim().is_empty()).then(|| email.trim().to_string()),
+        };
+        let navigate = navigate.clone();
         async move {
-            // In real impl, call backend /register
-            if !username.is_empty() && !password.is_empty() {
-                set_username.set("".to_string());
-                set_password.set("".to_string());
-                navigate("/login", false).unwrap();
+            match api::post_json("/register", &credentials).await {
+                Ok(resp) if resp.ok() => {
+                    set_username.set("".to_string());
+                    set_password.set("".to_string());
+                    navigate("/login", Default::default());
+                }
+                Ok(resp) => set_error.set(Some(resp.body)),
+                Err(err) => set_error.set(Some(err.to_string())),
             }
         }
     });
@@ -116,7 +554,10 @@ fn RegisterPage() -> impl IntoView {
     view! {
         <div class="auth-form">
             <h2>"Register"</h2>
-            <form on:submit=|ev| on_submit.dispatch(ev)>
+            <form on:submit=move |ev| {
+                ev.prevent_default();
+                on_submit.dispatch(());
+            }>
                 <input
                     type="text"
                     placeholder="Username"
@@ -129,8 +570,15 @@ fn RegisterPage() -> impl IntoView {
                     prop:value=password
                     on:input=move |ev| set_password.set(event_target_value(&ev))
                 />
+                <input
+                    type="email"
+                    placeholder="Email (optional, for password resets)"
+                    prop:value=email
+                    on:input=move |ev| set_email.set(event_target_value(&ev))
+                />
                 <button type="submit">"Register"</button>
             </form>
+            {move || error.get().map(|e| view! { <p class="error">{e}</p> })}
             <p>
                 <a href="/login">"Already have an account? Login"</a>
             </p>
@@ -138,19 +586,880 @@ fn RegisterPage() -> impl IntoView {
     }
 }
 
+#[component]
+fn ForgotPasswordPage() -> impl IntoView {
+    let (email, set_email) = create_signal("".to_string());
+    let (notice, set_notice) = create_signal::<Option<String>>(None);
+
+    let on_submit = create_action(move |()| {
+        let body = ForgotPassword { email: email.get() };
+        async move {
+            match api::post_json("/password/forgot", &body).await {
+                Ok(resp) if resp.ok() => set_notice.set(Some(
+                    "If an account uses that address, a reset link is on its way.".to_string(),
+                )),
+                Ok(resp) => set_notice.set(Some(resp.body)),
+                Err(err) => set_notice.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    view! {
+        <div class="auth-form">
+            <h2>"Forgot password"</h2>
+            <form on:submit=move |ev| {
+                ev.prevent_default();
+                on_submit.dispatch(());
+            }>
+                <input
+                    type="email"
+                    placeholder="Email"
+                    prop:value=email
+                    on:input=move |ev| set_email.set(event_target_value(&ev))
+                />
+                <button type="submit">"Send reset link"</button>
+            </form>
+            {move || notice.get().map(|n| view! { <p class="notice">{n}</p> })}
+        </div>
+    }
+}
+
+#[component]
+fn ResetPasswordPage() -> impl IntoView {
+    let navigate = use_navigate();
+    let query = use_query_map();
+    let token = move || query.with(|q| q.get("token").cloned().unwrap_or_default());
+    let (new_password, set_new_password) = create_signal("".to_string());
+    let (error, set_error) = create_signal::<Option<String>>(None);
+
+    let on_submit = create_action(move |()| {
+        let body = ResetPassword {
+            token: token(),
+            new_password: new_password.get(),
+        };
+        let navigate = navigate.clone();
+        async move {
+            match api::post_json("/password/reset", &body).await {
+                Ok(resp) if resp.ok() => navigate("/login", Default::default()),
+                Ok(resp) => set_error.set(Some(resp.body)),
+                Err(err) => set_error.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    view! {
+        <div class="auth-form">
+            <h2>"Choose a new password"</h2>
+            <form on:submit=move |ev| {
+                ev.prevent_default();
+                on_submit.dispatch(());
+            }>
+                <input
+                    type="password"
+                    placeholder="New password"
+                    prop:value=new_password
+                    on:input=move |ev| set_new_password.set(event_target_value(&ev))
+                />
+                <button type="submit">"Reset password"</button>
+            </form>
+            {move || error.get().map(|e| view! { <p class="error">{e}</p> })}
+        </div>
+    }
+}
+
+#[component]
+fn LobbyPage() -> impl IntoView {
+    let navigate = use_navigate();
+    let (directory, set_directory) = create_signal(RoomPage::default());
+    let (trending, set_trending) = create_signal::<Vec<TrendingRoom>>(Vec::new());
+    let (room_name, set_room_name) = create_signal("testroom".to_string());
+    let (search, set_search) = create_signal("".to_string());
+    let (tag, set_tag) = create_signal::<Option<String>>(None);
+    let (category, set_category) = create_signal::<Option<String>>(None);
+    let (page, set_page) = create_signal(1usize);
+    let (error, set_error) = create_signal::<Option<String>>(None);
+
+    let refresh = move || {
+        let mut path = format!(
+            "/rooms?page={}&q={}",
+            page.get_untracked(),
+            String::from(js_sys::encode_uri_component(&search.get_untracked()))
+        );
+        if let Some(tag) = tag.get_untracked() {
+            path.push_str(&format!("&tag={}", String::from(js_sys::encode_uri_component(&tag))));
+        }
+        if let Some(category) = category.get_untracked() {
+            path.push_str(&format!("&category={}", String::from(js_sys::encode_uri_component(&category))));
+        }
+        spawn_local(async move {
+            match api::get(&path).await {
+                Ok(resp) if resp.ok() => {
+                    if let Some(result) = resp.json::<RoomPage>() {
+                        set_directory.set(result);
+                        set_error.set(None);
+                    }
+                }
+                Ok(resp) => set_error.set(Some(resp.body)),
+                Err(err) => set_error.set(Some(err.to_string())),
+            }
+        });
+    };
+    let refresh_trending = move || {
+        spawn_local(async move {
+            if let Ok(resp) = api::get("/rooms/trending").await {
+                if let Some(list) = resp.json::<Vec<TrendingRoom>>() {
+                    set_trending.set(list);
+                }
+            }
+        });
+    };
+    refresh_trending();
+    // Reload right away when a filter changes, and keep polling for occupancy
+    create_effect(move |_| {
+        search.track();
+        tag.track();
+        category.track();
+        page.track();
+        refresh();
+    });
+    // Data saver and a low battery poll less often; data saver also loads
+    // trending rooms only once
+    let saving_data = data_saver::enabled();
+    let low_battery = battery::watch_low();
+    let ticks = store_value(0u32);
+    let poll = move || {
+        ticks.update_value(|t| *t = t.wrapping_add(1));
+        if (saving_data || low_battery.get_untracked()) && ticks.get_value() % SLOW_POLL_TICKS != 0 {
+            return;
+        }
+        refresh();
+        if !saving_data {
+            refresh_trending();
+        }
+    };
+    if let Ok(handle) = set_interval_with_handle(poll, LOBBY_POLL_INTERVAL) {
+        on_cleanup(move || handle.clear());
+    }
+    // Any filter change starts over at the first page
+    let filter = move |f: &dyn Fn()| {
+        f();
+        set_page.set(1);
+    };
+
+    let occupancy = move |name: &str| directory.with(|d| d.rooms.iter().find(|r| r.name == name).cloned());
+    let typed_room_knock = move || room_name.with(|name| occupancy(name.trim()).is_some_and(|r| r.needs_knock()));
+
+    // Full or locked rooms are still joinable: the chat page knocks and waits
+    let join = {
+        let navigate = navigate.clone();
+        move |name: String| {
+            if !name.is_empty() {
+                navigate(&format!("/chat/{}", name), Default::default());
+            }
+        }
+    };
+    let join_typed = join.clone();
+    let join_trending = join.clone();
+    let join_created = join.clone();
+
+    // Rooms must be created before anyone can join; the creator owns it
+    let (new_room, set_new_room) = create_signal(NewRoom::default());
+    let (ice_urls, set_ice_urls) = create_signal(String::new());
+    let (turn_username, set_turn_username) = create_signal(String::new());
+    let (turn_credential, set_turn_credential) = create_signal(String::new());
+    let on_create_room = create_action(move |()| {
+        let mut body = new_room.get();
+        body.ice_servers = ice::from_form(&ice_urls.get(), &turn_username.get(), &turn_credential.get());
+        let join = join_created.clone();
+        async move {
+            match api::post_json("/rooms", &body).await {
+                Ok(resp) if resp.ok() => {
+                    set_new_room.set(NewRoom::default());
+                    set_ice_urls.set(String::new());
+                    set_turn_username.set(String::new());
+                    set_turn_credential.set(String::new());
+                    join(body.name.trim().to_string());
+                }
+                Ok(resp) => set_error.set(Some(resp.body)),
+                Err(err) => set_error.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    view! {
+        <div class="lobby">
+            <h2>"Rooms"</h2>
+            <QuickRooms/>
+            <form on:submit=move |ev| {
+                ev.prevent_default();
+                join_typed(room_name.get().trim().to_string());
+            }>
+                <input
+                    type="text"
+                    placeholder="Room name"
+                    prop:value=room_name
+                    on:input=move |ev| set_room_name.set(event_target_value(&ev))
+                />
+                <button type="submit">{move || if typed_room_knock() { "Knock" } else { "Join" }}</button>
+                <span class="occupancy">
+                    {move || room_name.with(|name| match occupancy(name.trim()) {
+                        Some(r) if r.full => format!("Room full, {} waiting", r.waiting),
+                        Some(r) if r.locked => format!("Locked, {}/{} in room", r.peers, r.capacity),
+                        Some(r) => format!("{}/{} in room", r.peers, r.capacity),
+                        None => "".to_string(),
+                    })}
+                </span>
+            </form>
+            <details class="create-room">
+                <summary>"Create a room"</summary>
+                <form on:submit=move |ev| {
+                    ev.prevent_default();
+                    on_create_room.dispatch(());
+                }>
+                    <input
+                        type="text"
+                        placeholder="Room name"
+                        maxlength="64"
+                        prop:value=move || new_room.with(|r| r.name.clone())
+                        on:input=move |ev| set_new_room.update(|r| r.name = event_target_value(&ev))
+                    />
+                    <input
+                        type="text"
+                        placeholder="Description"
+                        maxlength="200"
+                        prop:value=move || new_room.with(|r| r.description.clone())
+                        on:input=move |ev| set_new_room.update(|r| r.description = event_target_value(&ev))
+                    />
+                    <label>
+                        "Max size "
+                        <input
+                            type="number"
+                            min="2"
+                            max=move || directory.with(|d| d.max_capacity.unwrap_or(MAX_ROOM_SIZE))
+                            prop:value=move || new_room.with(|r| r.max_size.to_string())
+                            on:input=move |ev| {
+                                if let Ok(size) = event_target_value(&ev).parse() {
+                                    set_new_room.update(|r| r.max_size = size);
+                                }
+                            }
+                        />
+                    </label>
+                    <label>
+                        <input
+                            type="checkbox"
+                            prop:checked=move || new_room.with(|r| r.public)
+                            on:change=move |ev| set_new_room.update(|r| r.public = event_target_checked(&ev))
+                        />
+                        "List in the directory"
+                    </label>
+                    <label title="Other rooms are removed after a day without anyone in them">
+                        <input
+                            type="checkbox"
+                            prop:checked=move || new_room.with(|r| r.persistent)
+                            on:change=move |ev| set_new_room.update(|r| r.persistent = event_target_checked(&ev))
+                        />
+                        "Keep while empty"
+                    </label>
+                    <label title="People wait until you let them in, also while you are away">
+                        <input
+                            type="checkbox"
+                            prop:checked=move || new_room.with(|r| r.lobby)
+                            on:change=move |ev| set_new_room.update(|r| r.lobby = event_target_checked(&ev))
+                        />
+                        "Admit people myself"
+                    </label>
+                    <label title="Only you send messages; everyone else watches">
+                        <input
+                            type="checkbox"
+                            prop:checked=move || new_room.with(|r| r.broadcast)
+                            on:change=move |ev| set_new_room.update(|r| r.broadcast = event_target_checked(&ev))
+                        />
+                        "Broadcast"
+                    </label>
+                    <label title="Messages are kept on the server for a week so members can get them by email; listed rooms only">
+                        <input
+                            type="checkbox"
+                            prop:disabled=move || !new_room.with(|r| r.public)
+                            prop:checked=move || new_room.with(|r| r.public && r.digests)
+                            on:change=move |ev| set_new_room.update(|r| r.digests = event_target_checked(&ev))
+                        />
+                        "Email digests"
+                    </label>
+                    <input
+                        type="password"
+                        placeholder="Password (optional)"
+                        minlength="4"
+                        maxlength="100"
+                        prop:value=move || new_room.with(|r| r.password.clone().unwrap_or_default())
+                        on:input=move |ev| {
+                            let value = event_target_value(&ev);
+                            set_new_room.update(|r| r.password = (!value.is_empty()).then_some(value));
+                        }
+                    />
+                    <details class="room-ice">
+                        <summary>"Own STUN/TURN servers"</summary>
+                        <textarea
+                            placeholder="One stun:, turn: or turns: URL per line"
+                            rows="3"
+                            prop:value=ice_urls
+                            on:input=move |ev| set_ice_urls.set(event_target_value(&ev))
+                        ></textarea>
+                        <input
+                            type="text"
+                            placeholder="TURN username"
+                            prop:value=turn_username
+                            on:input=move |ev| set_turn_username.set(event_target_value(&ev))
+                        />
+                        <input
+                            type="password"
+                            placeholder="TURN password"
+                            prop:value=turn_credential
+                            on:input=move |ev| set_turn_credential.set(event_target_value(&ev))
+                        />
+                    </details>
+                    <button type="submit">"Create"</button>
+                </form>
+            </details>
+            <PeopleSearch/>
+            <Show when=move || trending.with(|t| !t.is_empty())>
+                <section class="trending">
+                    <h3>"Popular now"</h3>
+                    <ul class="trending-list">
+                        <For
+                            each=move || trending.get()
+                            key=|room| room.name.clone()
+                            children={
+                                let join = join_trending.clone();
+                                move |room| {
+                                    let join = join.clone();
+                                    let name = room.name.clone();
+                                    view! {
+                                        <li>
+                                            <button type="button" class="room-chip" on:click=move |_| join(name.clone())>
+                                                {let name = room.name.clone(); move || petnames::room_label(&name)}
+                                            </button>
+                                            {room.category.clone().map(|c| view! { <span class="category">{c}</span> })}
+                                            <span class="occupancy">{format!("{}/{}", room.peers, room.capacity)}</span>
+                                            <span class="activity">
+                                                {format!("{} joins, {} messages in the last hour", room.joins, room.relays)}
+                                            </span>
+                                        </li>
+                                    }
+                                }
+                            }
+                        />
+                    </ul>
+                </section>
+            </Show>
+            <div class="directory-filters">
+                <input
+                    type="search"
+                    placeholder="Search rooms"
+                    prop:value=search
+                    on:input=move |ev| filter(&|| set_search.set(event_target_value(&ev)))
+                />
+                <select on:change=move |ev| {
+                    let value = event_target_value(&ev);
+                    filter(&|| set_category.set((!value.is_empty()).then(|| value.clone())));
+                }>
+                    <option value="" selected=move || category.with(Option::is_none)>"All categories"</option>
+                    {move || directory.with(|d| d.categories.clone()).into_iter().map(|c| {
+                        let selected = category.with(|current| current.as_ref() == Some(&c));
+                        view! { <option value=c.clone() selected=selected>{c.clone()}</option> }
+                    }).collect_view()}
+                </select>
+                <div class="tag-filter">
+                    {move || directory.with(|d| d.tags.clone()).into_iter().map(|t| {
+                        let active = tag.with(|current| current.as_ref() == Some(&t));
+                        let toggled = (!active).then(|| t.clone());
+                        view! {
+                            <button type="button" class="tag" class:active=active on:click=move |_| {
+                                filter(&|| set_tag.set(toggled.clone()));
+                            }>{format!("#{}", t)}</button>
+                        }
+                    }).collect_view()}
+                </div>
+            </div>
+            <ul class="room-list">
+                <For
+                    each=move || directory.with(|d| d.rooms.clone())
+                    key=|room| room.name.clone()
+                    children=move |room| {
+                        let join = join.clone();
+                        let name = room.name.clone();
+                        let tags = room.tags.iter().map(|t| view! { <span class="tag">{format!("#{}", t)}</span> }).collect_view();
+                        view! {
+                            <li class:full=room.full class:locked=room.locked>
+                                <span class="room-name">{let name = room.name.clone(); move || petnames::room_label(&name)}</span>
+                                {room.protected.then(|| view! { <span class="protected" title="Needs a password or invite link">"🔒"</span> })}
+                                {room.lobby.then(|| view! { <span class="lobby" title="The owner lets people in">"Lobby"</span> })}
+                                {room.broadcast.then(|| view! {
+                                    <span class="broadcast" title="Only the owner sends messages">
+                                        {format!("Broadcast · {} watching", room.viewers.unwrap_or_default())}
+                                    </span>
+                                })}
+                                {room.category.clone().map(|c| view! { <span class="category">{c}</span> })}
+                                <span class="occupancy">{format!("{}/{}", room.peers, room.capacity)}</span>
+                                {(room.waiting > 0).then(|| view! { <span class="waiting">{format!("{} waiting", room.waiting)}</span> })}
+                                <button type="button" on:click=move |_| join(name.clone())>
+                                    {if room.needs_knock() { "Knock" } else { "Join" }}
+                                </button>
+                                {(!room.description.is_empty()).then(|| view! { <p class="room-description">{room.description.clone()}</p> })}
+                                {(!room.topic.is_empty()).then(|| view! { <p class="room-topic">{room.topic.clone()}</p> })}
+                                <div class="room-tags">{tags}</div>
+                            </li>
+                        }
+                    }
+                />
+            </ul>
+            <div class="pagination">
+                <button
+                    type="button"
+                    prop:disabled=move || page.get() <= 1
+                    on:click=move |_| set_page.update(|p| *p = p.saturating_sub(1).max(1))
+                >"Previous"</button>
+                <span>{move || directory.with(|d| format!("Page {} of {} ({} rooms)", d.page.max(1), d.page_count(), d.total))}</span>
+                <button
+                    type="button"
+                    prop:disabled=move || directory.with(|d| page.get() >= d.page_count())
+                    on:click=move |_| set_page.update(|p| *p += 1)
+                >"Next"</button>
+            </div>
+            {move || error.get().map(|e| view! { <p class="error">{e}</p> })}
+            <tour::OnboardingTour/>
+        </div>
+    }
+}
+
+#[component]
+fn SettingsPage() -> impl IntoView {
+    let navigate = use_navigate();
+    let (error, set_error) = create_signal::<Option<String>>(None);
+    let (current_password, set_current_password) = create_signal("".to_string());
+    let (new_password, set_new_password) = create_signal("".to_string());
+    let (password_notice, set_password_notice) = create_signal::<Option<String>>(None);
+    let (profile, set_profile) = create_signal(Profile::default());
+    let (profile_notice, set_profile_notice) = create_signal::<Option<String>>(None);
+    let (sessions, set_sessions) = create_signal::<Vec<SessionInfo>>(Vec::new());
+    let (tour_notice, set_tour_notice) = create_signal::<Option<String>>(None);
+
+    spawn_local(async move {
+        if let Ok(resp) = api::get("/profile").await {
+            if let Some(loaded) = resp.json::<Profile>() {
+                set_profile.set(loaded);
+            }
+        }
+    });
+
+    let load_sessions = move || {
+        spawn_local(async move {
+            if let Ok(resp) = api::get("/sessions").await {
+                if let Some(list) = resp.json::<Vec<SessionInfo>>() {
+                    set_sessions.set(list);
+                }
+            }
+        });
+    };
+    load_sessions();
+
+    let on_revoke_session = create_action(move |session: &SessionInfo| {
+        let session = session.clone();
+        let navigate = use_navigate();
+        async move {
+            match api::delete(&format!("/sessions/{}", session.id)).await {
+                Ok(resp) if resp.ok() && session.current => {
+                    api::clear_token();
+                    vault::forget();
+                    navigate("/login", Default::default());
+                }
+                Ok(resp) if resp.ok() => load_sessions(),
+                Ok(resp) => set_error.set(Some(resp.body)),
+                Err(err) => set_error.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    let (api_keys, set_api_keys) = create_signal::<Vec<ApiKeyInfo>>(Vec::new());
+    let (new_api_key, set_new_api_key) = create_signal(NewApiKey::default());
+    let (created_key, set_created_key) = create_signal::<Option<String>>(None);
+    let load_api_keys = move || {
+        spawn_local(async move {
+            if let Ok(resp) = api::get("/apikeys").await {
+                if let Some(list) = resp.json::<Vec<ApiKeyInfo>>() {
+                    set_api_keys.set(list);
+                }
+            }
+        });
+    };
+    load_api_keys();
+
+    let on_create_api_key = create_action(move |()| {
+        let body = new_api_key.get();
+        async move {
+            match api::post_json("/apikeys", &body).await {
+                Ok(resp) if resp.ok() => {
+                    set_created_key.set(resp.json::<CreatedApiKey>().map(|created| created.key));
+                    set_new_api_key.set(NewApiKey::default());
+                    load_api_keys();
+                }
+                Ok(resp) => set_error.set(Some(resp.body)),
+                Err(err) => set_error.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    let on_revoke_api_key = create_action(move |id: &String| {
+        let path = format!("/apikeys/{}", id);
+        async move {
+            match api::delete(&path).await {
+                Ok(resp) if resp.ok() => load_api_keys(),
+                Ok(resp) => set_error.set(Some(resp.body)),
+                Err(err) => set_error.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    let on_save_profile = create_action(move |()| {
+        let body = profile.get();
+        async move {
+            match api::put_json("/profile", &body).await {
+                Ok(resp) if resp.ok() => {
+                    if let Some(saved) = resp.json::<Profile>() {
+                        set_profile.set(saved);
+                    }
+                    set_profile_notice.set(Some("Profile saved.".to_string()));
+                }
+                Ok(resp) => set_profile_notice.set(Some(resp.body)),
+                Err(err) => set_profile_notice.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    let on_change_password = create_action(move |()| {
+        let body = ChangePassword {
+            current_password: current_password.get(),
+            new_password: new_password.get(),
+        };
+        async move {
+            match api::post_json("/account/password", &body).await {
+                Ok(resp) if resp.ok() => {
+                    // Every other session was signed out; keep this one with the new token
+                    if let Some(LoginResponse { token }) = resp.json() {
+                        api::store_token(&token);
+                    }
+                    set_current_password.set("".to_string());
+                    set_new_password.set("".to_string());
+                    let notice = match account_settings::rekey(&body.new_password).await {
+                        Ok(()) => "Password changed. Other sessions were signed out.".to_string(),
+                        Err(err) => format!("Password changed, but your synced settings could not be re-encrypted: {}", err),
+                    };
+                    set_password_notice.set(Some(notice));
+                    load_sessions();
+                }
+                Ok(resp) => set_password_notice.set(Some(resp.body)),
+                Err(err) => set_password_notice.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    let on_delete_account = create_action(move |()| {
+        let navigate = navigate.clone();
+        async move {
+            match api::delete("/account").await {
+                Ok(resp) if resp.ok() => {
+                    api::clear_token();
+                    vault::forget();
+                    navigate("/", Default::default());
+                }
+                Ok(resp) => set_error.set(Some(resp.body)),
+                Err(err) => set_error.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    let confirm_delete = move |_| {
+        let confirmed = web_sys::window()
+            .unwrap()
+            .confirm_with_message("Delete your account? This cannot be undone.")
+            .unwrap_or(false);
+        if confirmed {
+            on_delete_account.dispatch(());
+        }
+    };
+
+    view! {
+        <div class="settings">
+            <h2>"Settings"</h2>
+            <section>
+                <h3>"Profile"</h3>
+                <form on:submit=move |ev| {
+                    ev.prevent_default();
+                    on_save_profile.dispatch(());
+                }>
+                    <input
+                        type="text"
+                        placeholder="Display name"
+                        prop:value=move || profile.with(|p| p.display_name.clone().unwrap_or_default())
+                        on:input=move |ev| set_profile.update(|p| p.display_name = Some(event_target_value(&ev)))
+                    />
+                    <input
+                        type="url"
+                        placeholder="Avatar URL"
+                        prop:value=move || profile.with(|p| p.avatar_url.clone().unwrap_or_default())
+                        on:input=move |ev| set_profile.update(|p| p.avatar_url = Some(event_target_value(&ev)))
+                    />
+                    <textarea
+                        placeholder="Bio"
+                        maxlength="280"
+                        prop:value=move || profile.with(|p| p.bio.clone().unwrap_or_default())
+                        on:input=move |ev| set_profile.update(|p| p.bio = Some(event_target_value(&ev)))
+                    />
+                    <button type="submit">"Save profile"</button>
+                </form>
+                {move || profile_notice.get().map(|n| view! { <p class="notice">{n}</p> })}
+            </section>
+            <display::DisplaySettingsForm/>
+            <account_settings::SyncedPreferences/>
+            <petnames::PetnameSettings/>
+            <qr_login::ShareLogin/>
+            <data_saver::DataSaverSetting/>
+            <ice::OwnServersSetting/>
+            <battery::BatterySaverSetting/>
+            <section>
+                <h3>"Tour"</h3>
+                <button type="button" on:click=move |_| {
+                    tour::restart();
+                    set_tour_notice.set(Some("The tour will start next time you open the lobby.".to_string()));
+                }>"Show the tour again"</button>
+                {move || tour_notice.get().map(|n| view! { <p class="notice">{n}</p> })}
+            </section>
+            <section>
+                <h3>"Change password"</h3>
+                <form on:submit=move |ev| {
+                    ev.prevent_default();
+                    on_change_password.dispatch(());
+                }>
+                    <input
+                        type="password"
+                        placeholder="Current password"
+                        prop:value=current_password
+                        on:input=move |ev| set_current_password.set(event_target_value(&ev))
+                    />
+                    <input
+                        type="password"
+                        placeholder="New password"
+                        prop:value=new_password
+                        on:input=move |ev| set_new_password.set(event_target_value(&ev))
+                    />
+                    <button type="submit">"Change password"</button>
+                </form>
+                {move || password_notice.get().map(|n| view! { <p class="notice">{n}</p> })}
+            </section>
+            <section>
+                <h3>"Sessions"</h3>
+                <ul class="session-list">
+                    <For
+                        each=move || sessions.get()
+                        key=|session| session.id.clone()
+                        children=move |session| {
+                            let label = session.user_agent.clone().unwrap_or_else(|| "Unknown device".to_string());
+                            let details = format!(
+                                "{} · signed in {} · last active {}",
+                                session.ip.clone().unwrap_or_else(|| "unknown address".to_string()),
+                                locale::date_time(&session.created_at),
+                                locale::date_time(&session.last_seen),
+                            );
+                            let current = session.current;
+                            view! {
+                                <li class:current=current>
+                                    <span class="session-device">{label}</span>
+                                    {current.then(|| view! { <span class="badge">"This device"</span> })}
+                                    <span class="session-details">{details}</span>
+                                    <button type="button" on:click=move |_| on_revoke_session.dispatch(session.clone())>
+                                        {if current { "Sign out" } else { "Revoke" }}
+                                    </button>
+                                </li>
+                            }
+                        }
+                    />
+                </ul>
+            </section>
+            <section>
+                <h3>"API keys"</h3>
+                <p>"Let bots join rooms and relay messages as you, without signing in."</p>
+                <form on:submit=move |ev| {
+                    ev.prevent_default();
+                    on_create_api_key.dispatch(());
+                }>
+                    <input
+                        type="text"
+                        placeholder="Key name"
+                        prop:value=move || new_api_key.with(|k| k.name.clone())
+                        on:input=move |ev| set_new_api_key.update(|k| k.name = event_target_value(&ev))
+                    />
+                    {API_KEY_SCOPES.iter().map(|(scope, label)| {
+                        let scope = scope.to_string();
+                        let checked_scope = scope.clone();
+                        view! {
+                            <label>
+                                <input
+                                    type="checkbox"
+                                    prop:checked=move || new_api_key.with(|k| k.scopes.contains(&checked_scope))
+                                    on:change=move |ev| {
+                                        let checked = event_target_checked(&ev);
+                                        set_new_api_key.update(|k| {
+                                            k.scopes.retain(|s| *s != scope);
+                                            if checked {
+                                                k.scopes.push(scope.clone());
+                                            }
+                                        });
+                                    }
+                                />
+                                {*label}
+                            </label>
+                        }
+                    }).collect_view()}
+                    <button type="submit">"Create key"</button>
+                </form>
+                {move || created_key.get().map(|key| view! {
+                    <p class="notice">"Copy this key now, it will not be shown again: " <code>{key}</code></p>
+                })}
+                <ul class="api-key-list">
+                    <For
+                        each=move || api_keys.get()
+                        key=|api_key| api_key.id.clone()
+                        children=move |api_key| {
+                            let details = format!(
+                                "{} · created {} · {}",
+                                api_key.scopes.join(", "),
+                                locale::date_time(&api_key.created_at),
+                                api_key.last_used.as_deref().map_or("never used".to_string(), |t| format!("last used {}", locale::date_time(t))),
+                            );
+                            let id = api_key.id.clone();
+                            view! {
+                                <li>
+                                    <span class="api-key-name">{api_key.name.clone()}</span>
+                                    <span class="api-key-details">{details}</span>
+                                    <button type="button" on:click=move |_| on_revoke_api_key.dispatch(id.clone())>"Revoke"</button>
+                                </li>
+                            }
+                        }
+                    />
+                </ul>
+            </section>
+            <section class="danger-zone">
+                <h3>"Delete account"</h3>
+                <p>"Removes your account, signs you out everywhere and leaves all rooms."</p>
+                <button class="danger" on:click=confirm_delete>"Delete account"</button>
+            </section>
+            {move || error.get().map(|e| view! { <p class="error">{e}</p> })}
+        </div>
+    }
+}
+
 #[component]
 fn ChatPage() -> impl IntoView {
     let params = use_params_map();
     let room = move || params.with(|p| p.get("room").cloned().unwrap_or_default());
+    // For socket callbacks, which outlive a room switch
+    let current_room = move || params.with_untracked(|p| p.get("room").cloned().unwrap_or_default());
 
-    let (messages, set_messages) = create_signal::<Vec<Message>, _>(vec![]);
-    let (input, set_input) = create_signal("".to_string());
-    let (connection_status, set_connection_status) = create_signal("Disconnected".to_string());
+    // Unsent messages from a previous visit are restored from the outbox
+    let outbox = create_rw_signal(Outbox::load(&params.with_untracked(|p| p.get("room").cloned().unwrap_or_default())));
+    let (messages, set_messages) = create_signal::<Vec<Message>, _>(
+        outbox.with_untracked(|o| o.payloads().filter_map(Message::from_queued).collect()),
+    );
+    // What was being typed here last time, on this device or another
+    let initial_draft = drafts::load(&current_room());
+    let (input, set_input) = create_signal(initial_draft.as_ref().map(|d| d.text.clone()).unwrap_or_default());
+    // Set while the message box holds, untouched, a draft typed elsewhere
+    let (draft_elsewhere, set_draft_elsewhere) = create_signal(initial_draft.is_some_and(|d| d.from_other_device()));
+    // Translations of received messages by id, or why one failed, and
+    // whether this room translates them as they arrive
+    let (translations, set_translations) = create_signal::<HashMap<String, Result<String, String>>>(HashMap::new());
+    let (auto_translate, set_auto_translate) = create_signal(language::auto_translate(&current_room()));
+    let connection_status = create_rw_signal(ConnectionStatus::default());
+    // Which way the data channel goes once connected, for the route panel
+    let (route, set_route) = create_signal::<Option<status::Route>>(None);
     let (data_channel, set_data_channel) = create_signal<Option<web_sys::RtcDataChannel>>(None);
     let (peer_connection, set_peer_connection) = create_signal<Option<web_sys::RtcPeerConnection>>(None);
-    let (ws, set_ws) = create_signal<Option<web_sys::WebSocket>>(None);
-    let (is_initiator, set_is_initiator) = create_signal(false);
-    let (queued_messages, set_queued_messages) = create_signal::<Vec<String>, _>(vec![]);
+    let (transport, set_transport) = create_signal::<Option<Transport>>(None);
+    // WebSockets in a row that closed before the server welcomed us, and a
+    // tick that tries again
+    let websocket_failures = store_value(0usize);
+    let (signaling_retry, set_signaling_retry) = create_signal(0u32);
+    // Seconds the server asked us to wait when it announced a shutdown
+    let shutdown_notice = store_value::<Option<u64>>(None);
+    let (maintenance_window, set_maintenance_window) = create_signal::<Option<maintenance::Window>>(None);
+    // The member our one peer connection is paired with
+    let (remote_peer, set_remote_peer) = create_signal::<Option<String>>(None);
+    // Set once chat to the partner goes through the server instead
+    let (relay, set_relay) = create_signal::<Option<RelayRoute>>(None);
+    // Whether the server said it relays chat
+    let (relay_offered, set_relay_offered) = create_signal(config::features().chat_relay);
+    // Whether we made the offer to our partner, and so restart ICE when the
+    // connection fails
+    let we_offer = store_value(false);
+    // Everyone in the room but us, oldest member first
+    let (members, set_members) = create_signal::<Vec<PeerInfo>>(Vec::new());
+    // Outside a full or locked room: where we stand in the queue
+    let (admission, set_admission) = create_signal::<Option<String>>(None);
+    // Set when we had to queue because the room is at its member limit
+    let (room_limit, set_room_limit) = create_signal::<Option<usize>>(None);
+    // Last error from the signaling server, e.g. for a room that does not exist
+    let (signal_error, set_signal_error) = create_signal::<Option<String>>(None);
+    // People knocking on our room, as (id, display name)
+    let (knocks, set_knocks) = create_signal::<Vec<(String, String)>>(Vec::new());
+    let (room_locked, set_room_locked) = create_signal(false);
+    // Broadcast rooms: our role, how many watch, and as the broadcaster
+    // our connections to them
+    let (broadcast_role, set_broadcast_role) = create_signal::<Option<broadcast::Role>>(None);
+    let (viewers, set_viewers) = create_signal(0usize);
+    // The room keeps messages for email digests, so we hand ours over too
+    let (room_digests, set_room_digests) = create_signal(false);
+    let audience = store_value(broadcast::Audience::default());
+    on_cleanup(move || {
+        audience.try_update_value(broadcast::Audience::close);
+    });
+    // The room's topic and who set it
+    let (topic, set_topic) = create_signal::<(String, Option<String>)>((String::new(), None));
+    let (topic_input, set_topic_input) = create_signal(String::new());
+    // Display name of the room's moderator, whether they are standing in for
+    // the owner, and whether that is us
+    let (moderator, set_moderator) = create_signal::<Option<(String, bool, bool)>>(None);
+    let is_moderator = move || moderator.with(|m| m.as_ref().is_some_and(|(_, _, you)| *you));
+    // The owner moderates whenever they are in; others only stand in
+    let is_owner = move || moderator.with(|m| m.as_ref().is_some_and(|(_, temporary, you)| *you && !*temporary));
+    // Invite links carry a token that stands in for the room's password
+    let query = use_query_map();
+    let invite = store_value(query.with_untracked(|q| q.get("invite").cloned()));
+    let (room_password, set_room_password) = create_signal::<Option<String>>(None);
+    // Set while the room wants a password: whether the last attempt failed
+    let (password_prompt, set_password_prompt) = create_signal::<Option<bool>>(None);
+    let (password_input, set_password_input) = create_signal("".to_string());
+    // The room we are in and the token that gets our place in it back from
+    // a new connection if this one drops
+    let resume_token = store_value::<Option<(String, String)>>(None);
+    let join_message = move |room: String| SignalingMessage::JoinRoom {
+        resume: resume_token.with_value(|t| t.as_ref().filter(|(r, _)| *r == room).map(|(_, token)| token.clone())),
+        room,
+        password: room_password.get_untracked(),
+        invite: invite.get_value(),
+    };
+
+    let send_signal = move |msg: SignalingMessage| {
+        if let Some(transport) = transport.get_untracked() {
+            transport.send(&msg);
+        }
+    };
+
+    let set_status = move |id: &str, status: MessageStatus| set_message_status(set_messages, id, status);
+
+    // Offer the connection panel's details to the feedback form while we are here
+    if let Some(feedback::Diagnostics(diagnostics)) = use_context() {
+        create_effect(move |_| diagnostics.set(Some(connection_status.with(|s| s.diagnostics()))));
+        on_cleanup(move || diagnostics.set(None));
+    }
 
     // Get JWT from localStorage
     let jwt = use_memo(move || {
@@ -159,17 +1468,155 @@ fn ChatPage() -> impl IntoView {
         storage.get_item("jwt").unwrap().unwrap_or_default()
     });
 
-    // Initialize peer connection
-    create_effect(move |_| {
-        let config = web_sys::RtcConfiguration::new(&js_sys::Array::new());
-        let ice_server = web_sys::RtcIceServer::new("stun:stun.l.google.com:19302");
-        config.ice_servers(&js_sys::Array::of1(&ice_server.into()));
+    // Initialize peer connection. The STUN/TURN servers are fetched once per
+    // visit; signaling waits for them so the first pairing has a connection.
+    // A room that brings its own servers overrides them.
+    let rtc_config = store_value::<Option<web_sys::RtcConfiguration>>(None);
+    let room_ice = store_value(Vec::<ice::IceServer>::new());
+    let peer_config = move || {
+        room_ice
+            .with_value(|servers| (!servers.is_empty()).then(|| ice::configuration(servers)))
+            .or_else(|| rtc_config.get_value())
+            .unwrap_or_else(web_sys::RtcConfiguration::new)
+    };
+    let (ice_ready, set_ice_ready) = create_signal(false);
+    let new_peer_connection = move || {
+        let config = peer_config();
         let pc = web_sys::RtcPeerConnection::new_with_configuration(&config).unwrap();
         set_peer_connection.set(Some(pc));
+    };
+    spawn_local(async move {
+        rtc_config.set_value(Some(ice::rtc_configuration().await));
+        new_peer_connection();
+        set_ice_ready.set(true);
     });
 
     let pc = peer_connection;
 
+    // A peer that loses power or network leaves its data channel looking
+    // open, so both sides ping every KEEPALIVE_INTERVAL and treat silence
+    // as unreachable. Peers that never pinged us predate this and are not
+    // timed out. `reachable_as` is the state to go back to.
+    let last_heard = store_value::<Option<f64>>(None);
+    let reachable_as = store_value(PeerState::Connected);
+    let heard_from_peer = move |data: &str| {
+        let ping = matches!(serde_json::from_str::<PeerMessage>(data), Ok(PeerMessage::Ping));
+        if ping || last_heard.get_value().is_some() {
+            last_heard.set_value(Some(js_sys::Date::now()));
+        }
+        if connection_status.with_untracked(|s| s.peer == PeerState::Unreachable) {
+            console::log_1(&"Peer reachable again".into());
+            connection_status.update(|s| s.peer = reachable_as.get_value());
+            flush_outbox(outbox, data_channel, relay, connection_status, set_messages);
+        }
+    };
+    if let Ok(handle) = set_interval_with_handle(
+        move || {
+            let Some(dc) = data_channel.get_untracked().filter(|dc| dc.ready_state() == RtcDataChannelState::Open) else {
+                return;
+            };
+            let _ = dc.send_with_str(&serde_json::to_string(&PeerMessage::Ping).unwrap());
+            let silent = last_heard.get_value().is_some_and(|at| js_sys::Date::now() - at > LIVENESS_TIMEOUT_MS);
+            if silent && connection_status.with_untracked(|s| matches!(s.peer, PeerState::Connected | PeerState::RelayFallback)) {
+                console::log_1(&"Peer unreachable".into());
+                connection_status.update(|s| {
+                    reachable_as.set_value(s.peer);
+                    s.peer = PeerState::Unreachable;
+                });
+            }
+        },
+        KEEPALIVE_INTERVAL,
+    ) {
+        on_cleanup(move || handle.clear());
+    }
+
+    // Our partner left: close the connection and start over with a fresh one,
+    // ready to pair with whoever is next in the room
+    let tear_down_peer = move || {
+        if let Some(dc) = data_channel.get_untracked() {
+            // Its close handler would report the peer as disconnected
+            dc.set_onclose(None);
+            dc.close();
+        }
+        if let Some(pc) = peer_connection.get_untracked() {
+            pc.close();
+        }
+        set_data_channel.set(None);
+        set_remote_peer.set(None);
+        set_relay.set(None);
+        last_heard.set_value(None);
+        set_route.set(None);
+        new_peer_connection();
+        connection_status.update(|s| s.peer = PeerState::Waiting);
+    };
+
+    // The detected language is the source; the user's own the target
+    let translate = move |id: String, text: String, lang: String| {
+        spawn_local(async move {
+            let translated = language::translate(&text, &lang).await;
+            set_translations.update(|t| {
+                t.insert(id, translated);
+            });
+        });
+    };
+
+    // The language our partner last wrote in, if it is not ours
+    let foreign_language = move || {
+        messages.with(|msgs| {
+            msgs.iter().rev().filter(|m| m.sender == "peer").find_map(|m| m.lang.clone()).filter(|lang| language::is_foreign(lang))
+        })
+    };
+
+    // A payload from our partner, by data channel or server relay; the ack
+    // goes back the same way
+    let receive_payload = move |data: &str, reply: &dyn Fn(&str)| {
+        let Ok(payload) = serde_json::from_str::<PeerMessage>(data) else { return };
+        let message = match payload {
+            PeerMessage::Ack { id } => {
+                set_status(&id, MessageStatus::Delivered);
+                return;
+            }
+            PeerMessage::Ping => return,
+            PeerMessage::Text { id, content, sent_at } => Message::received(id, content, None, sent_at),
+            PeerMessage::Scribble { id, scribble, sent_at } => {
+                Message::received(id, "".to_string(), Some(scribble), sent_at)
+            }
+        };
+        let ack = PeerMessage::Ack { id: message.id.clone() };
+        reply(&serde_json::to_string(&ack).unwrap());
+        if let Some(lang) = message.lang.clone().filter(|lang| auto_translate.get_untracked() && language::is_foreign(lang)) {
+            if language::can_translate() && translations.with_untracked(|t| !t.contains_key(&message.id)) {
+                translate(message.id.clone(), message.content.clone(), lang);
+            }
+        }
+        // A retried message can arrive twice if only the ack was lost
+        set_messages.update(|msgs| {
+            if !msgs.iter().any(|m| m.id == message.id) {
+                msgs.push(message);
+            }
+        });
+    };
+
+    // Sends chat to `partner` through the server from now on, unless the
+    // data channel is open after all or the server does not relay
+    let start_relay = move |partner: String| {
+        let open = data_channel.get_untracked().is_some_and(|dc| dc.ready_state() == RtcDataChannelState::Open);
+        if open || !relay_offered.get_untracked() || relay.with_untracked(Option::is_some) {
+            return;
+        }
+        console::log_1(&"No peer connection, relaying chat through the server".into());
+        set_relay.set(Some(RelayRoute { transport, room: room(), to: partner }));
+        connection_status.update(|s| s.peer = PeerState::ServerRelay);
+        flush_outbox(outbox, data_channel, relay, connection_status, set_messages);
+    };
+    let schedule_relay_fallback = move |partner: String| {
+        commands::schedule(P2P_TIMEOUT_MS, move || {
+            if remote_peer.get_untracked().as_deref() == Some(partner.as_str()) {
+                start_relay(partner);
+            }
+        });
+    };
+
     // Create data channel
     let create_data_channel = move || {
         if let Some(pc) = pc() {
@@ -180,30 +1627,38 @@ fn ChatPage() -> impl IntoView {
             let dc = pc.create_data_channel_with_data_channel_init(&label, &dc_init).unwrap();
             dc.set_binary_type(web_sys::RtcDataChannelBinaryType::Arraybuffer);
             dc.set_onopen(Some(wasm_bindgen::closure::Closure::wrap(Box::new(move |_ev| {
-                set_connection_status.set("Connected".to_string());
-                console::log_1(&"Data channel open".into());
-                // Send queued messages
-                set_queued_messages.update(|q| {
-                    for msg in q.drain(..) {
-                        dc.send_with_str(&msg);
+                connection_status.update(|s| {
+                    if !matches!(s.peer, PeerState::Connected | PeerState::RelayFallback) {
+                        s.peer = PeerState::Connected;
                     }
                 });
+                console::log_1(&"Data channel open".into());
+                last_heard.set_value(None);
+                set_relay.set(None);
+                // Send queued messages
+                flush_outbox(outbox, data_channel, relay, connection_status, set_messages);
             }) as Box<dyn FnMut(web_sys::RtcDataChannelEvent)>).forget()));
+            // Large payloads arrive in parts; a closed channel discards a
+            // half-received one
+            let reassembler = std::rc::Rc::new(std::cell::RefCell::new(frames::Reassembler::default()));
+            let closed_reassembler = reassembler.clone();
             dc.set_onclose(Some(wasm_bindgen::closure::Closure::wrap(Box::new(move |_ev| {
-                set_connection_status.set("Disconnected".to_string());
+                closed_reassembler.borrow_mut().reset();
+                connection_status.update(|s| s.peer = PeerState::Disconnected);
                 console::log_1(&"Data channel closed".into());
             }) as Box<dyn FnMut(web_sys::RtcDataChannelEvent)>).forget()));
+            let ack_dc = dc.clone();
             dc.set_onmessage(Some(wasm_bindgen::closure::Closure::wrap(Box::new(move |ev| {
-                if let Ok(data) = ev.data().as_string() {
-                    set_messages.update(|msgs| msgs.push(Message {
-                        content: data.clone(),
-                        sender: "peer".to_string(),
-                        timestamp: js_sys::Date::new_0().to_string(),
-                    }));
-                }
+                let Some(data) = ev.data().as_string() else { return };
+                heard_from_peer(&data);
+                let Some(data) = reassembler.borrow_mut().receive(data) else { return };
+                receive_payload(&data, &|ack: &str| {
+                    let _ = ack_dc.send_with_str(ack);
+                });
             }) as Box<dyn FnMut(web_sys::MessageEvent)>).forget()));
             dc.set_onerror(Some(wasm_bindgen::closure::Closure::wrap(Box::new(move |_ev| {
-                console::error_1(&"Data channel error".into());
+                // The status panel already shows the peer as disconnected
+                error::record(&WebRtcError::DataChannel.into());
             }) as Box<dyn FnMut(web_sys::RtcDataChannelEvent)>).forget()));
             set_data_channel.set(Some(dc));
         }
@@ -213,14 +1668,19 @@ fn ChatPage() -> impl IntoView {
     create_effect(move |_| {
         if let Some(pc) = pc() {
             let room = room();
-            let ws = ws();
+            let transport = transport();
             let closure = Closure::wrap(Box::new(move |ev: web_sys::RtcPeerConnectionIceEvent| {
                 if let Some(candidate) = ev.candidate() {
                     let candidate_init = RtcIceCandidateInit::new(&candidate.to_json().unwrap());
                     let candidate_str = JSON::stringify(&candidate_init).unwrap().as_string().unwrap();
-                    let ice_msg = serde_wasm_bindgen::to_value(&SignalingMessage::IceCandidate { room: room.clone(), candidate: candidate_str }).unwrap();
-                    if let Some(ws) = ws.as_ref() {
-                        let _ = ws.send_with_json(&ice_msg);
+                    let ice_msg = SignalingMessage::IceCandidate {
+                        room: room.clone(),
+                        candidate: candidate_str,
+                        to: remote_peer.get_untracked(),
+                        from: None,
+                    };
+                    if let Some(transport) = transport.as_ref() {
+                        transport.send(&ice_msg);
                     }
                 }
             }) as Box<dyn FnMut(web_sys::RtcPeerConnectionIceEvent)>);
@@ -232,76 +1692,345 @@ fn ChatPage() -> impl IntoView {
     // Connection state handler
     create_effect(move |_| {
         if let Some(pc) = pc() {
-            let set_status = set_connection_status;
-            let closure = Closure::wrap(Box::new(move |ev: web_sys::Event| {
-                let state = pc.connection_state();
-                set_status.set(state.as_string().unwrap_or("Unknown".to_string()));
+            let state_pc = pc.clone();
+            let closure = Closure::wrap(Box::new(move |_ev: web_sys::Event| {
+                let peer = PeerState::from_connection_state(state_pc.connection_state());
+                // While relaying, only a working connection is news
+                if relay.with_untracked(Option::is_some) && peer != PeerState::Connected {
+                    return;
+                }
+                connection_status.update(|s| s.peer = peer);
+                if peer == PeerState::Connected {
+                    // Distinguish a direct path from a TURN relay once connected
+                    let pc = state_pc.clone();
+                    spawn_local(async move {
+                        let route = status::selected_route(&pc).await;
+                        if route.as_ref().is_some_and(|r| r.local.kind == "relay") {
+                            connection_status.update(|s| s.peer = PeerState::RelayFallback);
+                        }
+                        set_route.set(route);
+                    });
+                } else {
+                    set_route.set(None);
+                }
             }) as Box<dyn FnMut(web_sys::Event)>);
             pc.set_onconnectionstatechange(Some(closure.as_ref().unchecked_ref()));
             closure.forget();
+
+            let gathering_pc = pc.clone();
+            let closure = Closure::wrap(Box::new(move |_ev: web_sys::Event| {
+                if gathering_pc.ice_gathering_state() == web_sys::RtcIceGatheringState::Gathering {
+                    connection_status.update(|s| {
+                        if matches!(s.peer, PeerState::Waiting | PeerState::Connecting) {
+                            s.peer = PeerState::Gathering;
+                        }
+                    });
+                }
+            }) as Box<dyn FnMut(web_sys::Event)>);
+            pc.set_onicegatheringstatechange(Some(closure.as_ref().unchecked_ref()));
+            closure.forget();
         }
     });
 
-    // Connect to signaling server
+    // Connect to signaling server, over the event stream once WebSockets
+    // keep failing on the way
     let connect_signaling = move |jwt: String, room_name: String| {
-        let ws_url = format!("ws://localhost:3000/ws?token={}", jwt);
-        let ws = web_sys::WebSocket::new(&ws_url).unwrap();
-        ws.set_onopen(Some(wasm_bindgen::closure::Closure::wrap(Box::new(move |_ev| {
-            let join_msg = serde_wasm_bindgen::to_value(&SignalingMessage::JoinRoom { room: room_name.clone() }).unwrap();
-            ws.send_with_json(&join_msg).unwrap();
-            console::log_1(&"Joined room".into());
-        }) as Box<dyn FnMut(web_sys::Event)>).forget()));
-        ws.set_onmessage(Some(wasm_bindgen::closure::Closure::wrap(Box::new(move |ev: web_sys::MessageEvent| {
-            if let Ok(json_str) = ev.data().as_string() {
-                if let Ok(msg) = serde_json::from_str::<SignalingMessage>(&json_str) {
-                    match msg {
-                        SignalingMessage::Peers { peers } => {
-                            if peers.len() == 2 {
+        let kind = if websocket_failures.get_value() >= WEBSOCKET_ATTEMPTS {
+            console::log_1(&"WebSockets keep failing, signaling over an event stream".into());
+            transport::Kind::EventStream
+        } else {
+            transport::Kind::WebSocket
+        };
+        let welcomed = std::rc::Rc::new(std::cell::Cell::new(false));
+        let welcomed_on_close = welcomed.clone();
+        let handlers = transport::Handlers {
+            on_open: Box::new(move |transport: &Transport| {
+                connection_status.update(|s| s.signaling = SignalingState::Connected);
+                // Every connection starts out in JSON until the welcome says otherwise
+                signaling::use_encoding(None);
+                let hello = SignalingMessage::Hello {
+                    version: signaling::PROTOCOL_VERSION,
+                    capabilities: signaling::CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+                    encodings: signaling::ENCODINGS.iter().map(|e| e.to_string()).collect(),
+                };
+                transport.send(&hello);
+                transport.send(&join_message(room_name.clone()));
+                console::log_1(&"Joined room".into());
+            }),
+            on_message: Box::new(move |msg: SignalingMessage| {
+                // Stragglers from a room we just switched away from
+                if msg.room().is_some_and(|r| r != current_room()) {
+                    return;
+                }
+                match msg {
+                    SignalingMessage::PeerJoined { id, name, username, .. } => {
+                        set_members.update(|m| {
+                            if !m.iter().any(|p| p.id == id) {
+                                m.push(PeerInfo { id, name, username, away: false });
+                            }
+                        });
+                    }
+                    SignalingMessage::PeerLeft { id, .. } => {
+                        set_members.update(|m| m.retain(|p| p.id != id));
+                        if remote_peer.get_untracked().as_deref() == Some(id.as_str()) {
+                            tear_down_peer();
+                        }
+                    }
+                    // Our connection to them may well outlive the blip, so it
+                    // stays up until they are back or gone for good
+                    SignalingMessage::PeerAway { id, name, grace, .. } => {
+                        console::log_1(&format!("{} lost their connection, waiting up to {}s", name, grace).into());
+                        set_members.update(|m| {
+                            if let Some(peer) = m.iter_mut().find(|p| p.id == id) {
+                                peer.away = true;
+                            }
+                        });
+                    }
+                    SignalingMessage::PeerResumed { id, previous, .. } => {
+                        set_members.update(|m| {
+                            if let Some(peer) = m.iter_mut().find(|p| p.id == previous) {
+                                peer.id = id.clone();
+                                peer.away = false;
+                            }
+                        });
+                        if remote_peer.get_untracked().as_deref() == Some(previous.as_str()) {
+                            set_remote_peer.set(Some(id.clone()));
+                        }
+                        set_relay.update(|route| {
+                            if let Some(route) = route.as_mut().filter(|r| r.to == previous) {
+                                route.to = id.clone();
+                            }
+                        });
+                    }
+                    SignalingMessage::Peers { you, peers, ice_servers, broadcaster, viewers, digests, resume_token: token, .. } => {
+                        resume_token.set_value(token.map(|token| (current_room(), token)));
+                        set_room_digests.set(digests);
+                        set_members.set(peers.iter().filter(|p| p.id != you).cloned().collect());
+                        // Switch servers before the connection gathers candidates
+                        if room_ice.with_value(|current| *current != ice_servers) {
+                            room_ice.set_value(ice_servers);
+                            if let Some(pc) = pc.get_untracked() {
+                                if let Err(err) = pc.set_configuration(&peer_config()) {
+                                    console::log_1(&format!("Cannot use the room's ICE servers: {:?}", err).into());
+                                }
+                            }
+                        }
+                        let role = viewers.map(|_| {
+                            if broadcaster.as_deref() == Some(you.as_str()) {
+                                broadcast::Role::Broadcaster
+                            } else {
+                                broadcast::Role::Viewer
+                            }
+                        });
+                        set_broadcast_role.set(role);
+                        set_viewers.set(viewers.unwrap_or_default());
+                        // The broadcaster connects to every viewer instead of one partner
+                        if role == Some(broadcast::Role::Broadcaster) {
+                            let ids: Vec<String> = peers.iter().filter(|p| p.id != you).map(|p| p.id.clone()).collect();
+                            if let Some(transport) = transport.get_untracked() {
+                                let on_open = move || connection_status.update(|s| s.peer = PeerState::Connected);
+                                audience.update_value(|a| a.sync(&current_room(), &ids, &peer_config(), &transport, on_open));
+                            }
+                            return;
+                        }
+                        // Rooms can hold more members, but this page keeps a single
+                        // connection: pair with the longest-connected other member,
+                        // or in a broadcast room with the broadcaster, who makes the
+                        // offer. Of other pairs, whoever joined later makes it.
+                        if remote_peer.get_untracked().is_some_and(|id| !peers.iter().any(|p| p.id == id)) {
+                            set_remote_peer.set(None);
+                        }
+                        if remote_peer.get_untracked().is_none() {
+                            let position = |id: &str| peers.iter().position(|p| p.id == id);
+                            let partner = match role {
+                                Some(_) => peers.iter().find(|p| broadcaster.as_ref() == Some(&p.id)),
+                                None => peers.iter().find(|p| p.id != you),
+                            };
+                            if let Some(partner) = partner {
+                                set_remote_peer.set(Some(partner.id.clone()));
                                 create_data_channel();
-                                if is_initiator() {
-                                    create_offer(room_name.clone());
+                                // The broadcaster does not take relayed chat
+                                if role.is_none() {
+                                    schedule_relay_fallback(partner.id.clone());
+                                }
+                                we_offer.set_value(role.is_none() && position(&partner.id) < position(&you));
+                                if we_offer.get_value() {
+                                    create_offer(current_room(), false);
                                 }
                             }
                         }
-                        SignalingMessage::Offer { room: _, sdp } => {
-                            handle_offer(sdp, room_name.clone());
+                    }
+                    SignalingMessage::Offer { sdp, from, .. } => {
+                        if from.is_some() && from == remote_peer.get_untracked() {
+                            handle_offer(sdp, current_room());
                         }
-                        SignalingMessage::Answer { room: _, sdp } => {
+                    }
+                    SignalingMessage::Answer { sdp, from, .. } => {
+                        if from.is_some() && from == remote_peer.get_untracked() {
                             handle_answer(sdp);
+                        } else if let Some(from) = from.filter(|_| broadcast_role.get_untracked() == Some(broadcast::Role::Broadcaster)) {
+                            audience.with_value(|a| a.answer(&from, &sdp));
                         }
-                        SignalingMessage::IceCandidate { room: _, candidate } => {
+                    }
+                    SignalingMessage::IceCandidate { candidate, from, .. } => {
+                        if from.is_some() && from == remote_peer.get_untracked() {
                             handle_ice_candidate(&candidate);
+                        } else if let Some(from) = from.filter(|_| broadcast_role.get_untracked() == Some(broadcast::Role::Broadcaster)) {
+                            audience.with_value(|a| a.candidate(&from, &candidate));
                         }
-                        SignalingMessage::Error { message } => {
-                            console::error_1(&message.into());
+                    }
+                    SignalingMessage::ChatRelay { payload, from, .. } => {
+                        let Some(partner) = from.filter(|id| remote_peer.get_untracked().as_ref() == Some(id)) else {
+                            return;
+                        };
+                        // Our partner gave up on the peer connection, so do we
+                        start_relay(partner);
+                        receive_payload(&payload, &|ack: &str| {
+                            if let Some(route) = relay.get_untracked() {
+                                route.send(ack);
+                            }
+                        });
+                    }
+                    SignalingMessage::Welcome { version, capabilities, encoding } => {
+                        welcomed.set(true);
+                        // Any maintenance still on is announced again right after
+                        set_maintenance_window.set(None);
+                        if kind == transport::Kind::WebSocket {
+                            websocket_failures.set_value(0);
+                        }
+                        console::log_1(&format!("Signaling protocol v{} with {}", version, capabilities.join(", ")).into());
+                        signaling::use_encoding(encoding.as_deref());
+                        set_relay_offered.set(capabilities.iter().any(|c| c == "relay"));
+                        if capabilities.iter().any(|c| c == "time_sync") {
+                            clock::start_sync();
+                            send_signal(SignalingMessage::TimeSync { sent: js_sys::Date::now() });
                         }
-                        _ => {}
                     }
+                    SignalingMessage::Time { sent, server } => {
+                        if clock::record(sent, server, js_sys::Date::now()) {
+                            send_signal(SignalingMessage::TimeSync { sent: js_sys::Date::now() });
+                        }
+                    }
+                    SignalingMessage::Error { message } => {
+                        error::record(&SignalingError::Server(message.clone()).into());
+                        set_signal_error.set(Some(message));
+                    }
+                    SignalingMessage::RoomFull { limit, .. } => set_room_limit.set(Some(limit)),
+                    SignalingMessage::ServerShutdown { reconnect_after } => {
+                        console::log_1(&"The signaling server is shutting down".into());
+                        shutdown_notice.set_value(Some(reconnect_after));
+                    }
+                    SignalingMessage::MaintenanceNotice { starts_at, duration, joins_blocked_from, message } => {
+                        let window = maintenance::Window::from_notice(starts_at, duration, joins_blocked_from.as_deref(), message);
+                        set_maintenance_window.set(window);
+                    }
+                    SignalingMessage::MaintenanceCancelled => set_maintenance_window.set(None),
+                    SignalingMessage::SettingsChanged { version, settings } => {
+                        account_settings::receive(account_settings::AccountSettings { version, settings });
+                    }
+                    SignalingMessage::Waiting { position, .. } => {
+                        set_admission.set(Some(match room_limit.get_untracked() {
+                            Some(limit) => format!(
+                                "The room is full ({} people at most). Waiting for a spot, position {} in line",
+                                limit, position
+                            ),
+                            None => format!("Waiting to be let in, position {} in line", position),
+                        }));
+                    }
+                    SignalingMessage::PasswordRequired { rejected, .. } => set_password_prompt.set(Some(rejected)),
+                    SignalingMessage::Kicked { .. } => {
+                        tear_down_peer();
+                        resume_token.set_value(None);
+                        set_members.set(Vec::new());
+                        set_knocks.set(Vec::new());
+                        set_moderator.set(None);
+                        set_signal_error.set(Some("A moderator removed you from this room.".to_string()));
+                    }
+                    SignalingMessage::Banned { until, .. } => {
+                        set_signal_error.set(Some(match until {
+                            Some(until) => format!("You are banned from this room until {}.", until),
+                            None => "You are banned from this room.".to_string(),
+                        }));
+                    }
+                    SignalingMessage::Admitted { .. } => {
+                        set_admission.set(None);
+                        set_room_limit.set(None);
+                    }
+                    SignalingMessage::KnockDenied { .. } => {
+                        set_admission.set(Some("The room declined your request to join.".to_string()));
+                    }
+                    SignalingMessage::Knock { id, name, .. } | SignalingMessage::AdmitRequest { id, name, .. } => {
+                        set_knocks.update(|k| k.push((id, name)));
+                    }
+                    SignalingMessage::KnockResolved { id, .. } => {
+                        set_knocks.update(|k| k.retain(|(knock_id, _)| *knock_id != id));
+                    }
+                    SignalingMessage::RoomLocked { locked, .. } => set_room_locked.set(locked),
+                    SignalingMessage::RoomUpdated { topic, by, .. } => {
+                        set_topic_input.set(topic.clone());
+                        set_topic.set((topic, by));
+                    }
+                    SignalingMessage::Moderator { name, temporary, you, .. } => {
+                        set_moderator.set(Some((name, temporary, you)));
+                    }
+                    _ => {}
                 }
-            }
-        }) as Box<dyn FnMut(web_sys::MessageEvent)>).forget()));
-        ws.set_onclose(Some(wasm_bindgen::closure::Closure::wrap(Box::new(move |_ev| {
-            set_connection_status.set("Disconnected".to_string());
-            console::log_1(&"Signaling disconnected".into());
-        }) as Box<dyn FnMut(web_sys::CloseEvent)>).forget()));
-        ws.set_onerror(Some(wasm_bindgen::closure::Closure::wrap(Box::new(move |_ev| {
-            console::error_1(&"Signaling error".into());
-        }) as Box<dyn FnMut(web_sys::Event)>).forget()));
-        set_ws.set(Some(ws));
+            }),
+            on_close: Box::new(move |code: u16, reason: String| {
+                if code == signaling::CLOSE_UNSUPPORTED_VERSION {
+                    set_signal_error.set(Some("This page is out of date. Reload it to keep chatting.".to_string()));
+                } else if code == signaling::CLOSE_REMOVED {
+                    set_signal_error.set(Some(format!("{}.", reason)));
+                } else if code == signaling::CLOSE_SIGNED_OUT {
+                    error::report(AuthError::SignedOut(reason).into());
+                } else if let Some(delay) = maintenance_window
+                    .with_untracked(|window| window.as_ref().and_then(|w| w.reconnect_delay(clock::now())))
+                {
+                    // Down for maintenance: wait it out instead of retrying
+                    // or giving up on the WebSocket
+                    shutdown_notice.set_value(None);
+                    set_timeout(move || set_signaling_retry.update(|n| *n += 1), delay);
+                } else if code == signaling::CLOSE_SERVER_RESTART || shutdown_notice.get_value().is_some() {
+                    // Spread out so the whole room does not come back at once
+                    let reconnect_after = shutdown_notice.get_value().unwrap_or(SIGNALING_RETRY_DELAY.as_secs());
+                    shutdown_notice.set_value(None);
+                    let delay = reconnect_after as f64 * (1.0 + js_sys::Math::random());
+                    set_timeout(
+                        move || set_signaling_retry.update(|n| *n += 1),
+                        std::time::Duration::from_secs_f64(delay),
+                    );
+                } else if kind == transport::Kind::WebSocket && !welcomed_on_close.get() {
+                    // Likely a proxy on the way; a few tries, then the event stream
+                    websocket_failures.update_value(|n| *n += 1);
+                    set_timeout(move || set_signaling_retry.update(|n| *n += 1), SIGNALING_RETRY_DELAY);
+                }
+                // The "online" listener below rejoins once the network is back
+                connection_status.update(|s| s.signaling = SignalingState::Reconnecting);
+                console::log_1(&"Signaling disconnected".into());
+            }),
+            on_error: Box::new(move || error::record(&SignalingError::Connection.into())),
+        };
+        set_transport.set(Transport::connect(kind, &jwt, handlers));
     };
 
-    let create_offer = move |room_name: String| {
+    let create_offer = move |room_name: String, ice_restart: bool| {
         if let Some(pc) = pc() {
             let options = RtcOfferAnswerOptions::new();
+            options.set_ice_restart(ice_restart);
             let promise = pc.create_offer_with_rtc_offer_options(&options);
             spawn_local(async move {
                 let result = JsFuture::from(promise).await;
                 if let Ok(sdp_obj) = result.dyn_into::<js_sys::Object>() {
                     let sdp_str = JSON::stringify(&sdp_obj).unwrap().as_string().unwrap();
                     let _ = pc.set_local_description_with_type(&web_sys::RtcSdpDescription::new(&sdp_str).unwrap(), RtcSdpType::Offer);
-                    let offer_msg = serde_wasm_bindgen::to_value(&SignalingMessage::Offer { room: room_name, sdp: sdp_str }).unwrap();
-                    if let Some(ws) = ws() {
-                        ws.send_with_json(&offer_msg).unwrap();
+                    let offer_msg = SignalingMessage::Offer {
+                        room: room_name,
+                        sdp: sdp_str,
+                        to: remote_peer.get_untracked(),
+                        from: None,
+                    };
+                    if let Some(transport) = transport() {
+                        transport.send(&offer_msg);
                     }
                 }
             });
@@ -319,9 +2048,14 @@ fn ChatPage() -> impl IntoView {
                 if let Ok(sdp_obj) = result.dyn_into::<js_sys::Object>() {
                     let sdp_str = JSON::stringify(&sdp_obj).unwrap().as_string().unwrap();
                     let _ = pc.set_local_description(&web_sys::RtcSdpDescription::new(&sdp_str).unwrap());
-                    let answer_msg = serde_wasm_bindgen::to_value(&SignalingMessage::Answer { room: room_name, sdp: sdp_str }).unwrap();
-                    if let Some(ws) = ws() {
-                        ws.send_with_json(&answer_msg).unwrap();
+                    let answer_msg = SignalingMessage::Answer {
+                        room: room_name,
+                        sdp: sdp_str,
+                        to: remote_peer.get_untracked(),
+                        from: None,
+                    };
+                    if let Some(transport) = transport() {
+                        transport.send(&answer_msg);
                     }
                 }
             });
@@ -342,14 +2076,121 @@ fn ChatPage() -> impl IntoView {
         }
     };
 
-    // Connect on mount
+    // An ICE restart finds a new path for the same connection, so the data
+    // channel, the outbox and verification all carry over. The TURN
+    // credentials are renewed with it in case they are what expired, unless
+    // the room brought its own servers.
+    let restarting_ice = store_value(false);
+    let restart_ice = move || {
+        let signaling_up = connection_status.with_untracked(|s| s.signaling == SignalingState::Connected);
+        if remote_peer.get_untracked().is_none() || !signaling_up || restarting_ice.get_value() {
+            return;
+        }
+        restarting_ice.set_value(true);
+        spawn_local(async move {
+            if room_ice.with_value(Vec::is_empty) {
+                rtc_config.set_value(Some(ice::rtc_configuration().await));
+            }
+            if let Some(pc) = pc.get_untracked() {
+                if let Err(err) = pc.set_configuration(&peer_config()) {
+                    error::record(&WebRtcError::IceRestart(format!("{:?}", err)).into());
+                }
+            }
+            console::log_1(&"Restarting ICE".into());
+            create_offer(room(), true);
+            restarting_ice.set_value(false);
+        });
+    };
+
+    // A failed connection, or one to a peer that stopped answering, is
+    // retried by whoever made the offer, so the two sides do not both
+    // restart at once
     create_effect(move |_| {
+        let broken = matches!(connection_status.with(|s| s.peer), PeerState::Failed | PeerState::Unreachable);
+        if broken && we_offer.get_value() {
+            restart_ice();
+        }
+    });
+
+    // Switching networks, e.g. from Wi-Fi to cellular, strands the current
+    // candidate pair. Browsers with the Network Information API tell us.
+    use_effect(move || {
+        let connection = web_sys::window()
+            .and_then(|w| js_sys::Reflect::get(&w.navigator(), &JsValue::from_str("connection")).ok())
+            .filter(|c| !c.is_undefined())
+            .map(|c| c.unchecked_into::<web_sys::EventTarget>());
+        let closure = Closure::wrap(Box::new(move || {
+            console::log_1(&"Network changed".into());
+            restart_ice();
+        }) as Box<dyn FnMut()>);
+        if let Some(connection) = &connection {
+            let _ = connection.add_event_listener_with_callback("change", closure.as_ref().unchecked_ref());
+        }
+        move || {
+            if let Some(connection) = &connection {
+                let _ = connection.remove_event_listener_with_callback("change", closure.as_ref().unchecked_ref());
+            }
+        }
+    });
+
+    // Switching rooms keeps the connection: leave the old room, drop what
+    // belonged to it and join the new one
+    let switch_room = move |from: String, to: String| {
+        if let Some(transport) = transport.get_untracked() {
+            transport.send(&SignalingMessage::LeaveRoom { room: from });
+        }
+        tear_down_peer();
+        resume_token.set_value(None);
+        set_members.set(Vec::new());
+        set_knocks.set(Vec::new());
+        set_moderator.set(None);
+        set_room_locked.set(false);
+        set_topic.set((String::new(), None));
+        set_topic_input.set(String::new());
+        audience.update_value(broadcast::Audience::close);
+        set_broadcast_role.set(None);
+        set_viewers.set(0);
+        set_room_digests.set(false);
+        set_admission.set(None);
+        set_room_limit.set(None);
+        set_signal_error.set(None);
+        set_password_prompt.set(None);
+        set_room_password.set(None);
+        invite.set_value(query.with_untracked(|q| q.get("invite").cloned()));
+        let draft = drafts::load(&to);
+        set_input.set(draft.as_ref().map(|d| d.text.clone()).unwrap_or_default());
+        set_draft_elsewhere.set(draft.is_some_and(|d| d.from_other_device()));
+        set_translations.set(HashMap::new());
+        set_auto_translate.set(language::auto_translate(&to));
+        outbox.set(Outbox::load(&to));
+        set_messages.set(outbox.with_untracked(|o| o.payloads().filter_map(Message::from_queued).collect()));
+        if let Some(transport) = transport.get_untracked() {
+            transport.send(&join_message(to));
+        }
+    };
+
+    // Connect on mount, once the peer connection exists, and move the open
+    // connection along when the room in the address changes
+    create_effect(move |joined: Option<Option<String>>| {
+        if !ice_ready.get() {
+            return None;
+        }
         let room_name = room();
-        if let Some(jwt_val) = jwt() {
-            if !jwt_val.is_empty() {
-                connect_signaling(jwt_val, room_name);
+        signaling_retry.track();
+        let open = transport.get_untracked().is_some_and(|t| t.is_open());
+        match joined.flatten() {
+            Some(previous) if open => {
+                if previous != room_name {
+                    switch_room(previous, room_name.clone());
+                }
+            }
+            _ => {
+                if let Some(jwt_val) = jwt().filter(|jwt| !jwt.is_empty()) {
+                    connect_signaling(jwt_val, room_name.clone());
+                }
             }
         }
+        Some(room_name)
     });
 
     // Reconnection logic
@@ -370,65 +2211,639 @@ fn ChatPage() -> impl IntoView {
         }
     });
 
+    // Every payload goes through the outbox so ordering is kept and nothing
+    // is lost on reload. The message's status follows the outcome; the
+    // peer's ack marks it delivered.
+    let send_payload = move |payload: PeerMessage| {
+        let id = payload.id().to_string();
+        // A broadcast goes out live to whoever watches; nobody waits for a queue
+        if broadcast_role.get_untracked() == Some(broadcast::Role::Broadcaster) {
+            let sent = audience.with_value(|a| a.send(&serde_json::to_string(&payload).unwrap()));
+            set_status(&id, if sent > 0 { MessageStatus::Sent } else { MessageStatus::Failed });
+            return;
+        }
+        if outbox.try_update(|o| o.push(payload)).unwrap_or(Err(OutboxFull)).is_err() {
+            error::report(StorageError::OutboxFull.into());
+            set_status(&id, MessageStatus::Failed);
+            return;
+        }
+        flush_outbox(outbox, data_channel, relay, connection_status, set_messages);
+    };
+
+    let send_message = move |message: Message| {
+        let payload = message.payload();
+        set_messages.update(|msgs| msgs.push(message));
+        send_payload(payload);
+    };
+
+    let retry = move |id: String| {
+        let payload = messages.with(|msgs| msgs.iter().find(|m| m.id == id).map(Message::payload));
+        if let Some(payload) = payload {
+            set_status(&id, MessageStatus::Pending);
+            send_payload(payload);
+        }
+    };
+
+    // Drafts are kept as they are typed and dropped once sent
+    let edit_draft = move |text: String| {
+        let room = current_room();
+        let draft = (!text.is_empty()).then(|| drafts::Draft::new(text.clone()));
+        drafts::keep(&room, draft.as_ref());
+        drafts::sync(&room, draft);
+        set_draft_elsewhere.set(false);
+        set_input.set(text);
+    };
+
+    // A newer draft from another device replaces ours, last writer wins.
+    // One withdrawn there, because it was sent, only clears ours if we
+    // had not touched it.
+    create_effect(move |previous: Option<String>| {
+        let room = room();
+        let shared = drafts::synced(&room);
+        // Loading the draft of a room we just entered is up to `switch_room`
+        if previous.as_ref() != Some(&room) || !account_settings::drafts_synced() {
+            return room;
+        }
+        match shared {
+            Some(draft) if draft.from_other_device() => {
+                if drafts::load(&room).is_none_or(|ours| draft.saved_at >= ours.saved_at) {
+                    drafts::keep(&room, Some(&draft));
+                    set_input.set(draft.text);
+                    set_draft_elsewhere.set(true);
+                }
+            }
+            None if draft_elsewhere.get_untracked() => {
+                drafts::keep(&room, None);
+                set_input.set(String::new());
+                set_draft_elsewhere.set(false);
+            }
+            _ => {}
+        }
+        room
+    });
+
     let on_send = create_action(move |()| {
         let content = input.get();
         async move {
+            if let Some(command) = commands::parse(&content) {
+                run_command(command, set_messages);
+                edit_draft(String::new());
+                return;
+            }
             if !content.is_empty() {
-                if let Some(dc) = data_channel() {
-                    match dc.ready_state() {
-                        RtcDataChannelState::Open => {
-                            dc.send_with_str(&content);
-                        }
-                        _ => {
-                            set_queued_messages.update(|q| q.push(content.clone()));
-                            console::log_1(&format!("Queued: {}", content).into());
-                        }
-                    }
+                if room_digests.get_untracked() {
+                    digests::record(&current_room(), &content);
                 }
-                set_messages.update(|msgs| {
-                    msgs.push(Message {
-                        content: content.clone(),
-                        sender: "me".to_string(),
-                        timestamp: js_sys::Date::new_0().to_string(),
-                    });
-                });
-                set_input.set("".to_string());
+                send_message(Message::outgoing(content, None));
+                edit_draft(String::new());
+            }
+        }
+    });
+
+    let (show_insights, set_show_insights) = create_signal(false);
+    // Refreshes the "sent 2 minutes ago" hints on message times
+    let clock_tick = create_trigger();
+    if let Ok(handle) = set_interval_with_handle(move || clock_tick.notify(), CLOCK_TICK_INTERVAL) {
+        on_cleanup(move || handle.clear());
+    }
+    let (scribbling, set_scribbling) = create_signal(false);
+    let on_send_scribble = move |scribble: Scribble| {
+        send_message(Message::outgoing("".to_string(), Some(scribble)));
+        set_scribbling.set(false);
+    };
+
+    // Directory listing details, editable by the moderator
+    let (details, set_details) = create_signal(RoomDetails::default());
+    let (details_notice, set_details_notice) = create_signal::<Option<String>>(None);
+    let on_save_details = create_action(move |()| {
+        let body = details.get();
+        let path = format!("/rooms/{}", String::from(js_sys::encode_uri_component(&room())));
+        async move {
+            match api::put_json(&path, &body).await {
+                Ok(resp) if resp.ok() => set_details_notice.set(Some("Room details saved.".to_string())),
+                Ok(resp) => set_details_notice.set(Some(resp.body)),
+                Err(err) => set_details_notice.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    // Only the owner may appoint moderators; the server says so to anyone else
+    let (moderators_input, set_moderators_input) = create_signal("".to_string());
+    let on_save_moderators = create_action(move |()| {
+        let moderators: Vec<String> = moderators_input
+            .get()
+            .split(',')
+            .map(|m| m.trim().to_string())
+            .filter(|m| !m.is_empty())
+            .collect();
+        let path = format!("/rooms/{}/moderators", String::from(js_sys::encode_uri_component(&room())));
+        async move {
+            match api::put_json(&path, &serde_json::json!({ "moderators": moderators })).await {
+                Ok(resp) if resp.ok() => set_details_notice.set(Some("Moderators saved.".to_string())),
+                Ok(resp) if resp.status == 403 => {
+                    set_details_notice.set(Some("Only the room's owner can appoint moderators.".to_string()))
+                }
+                Ok(resp) => set_details_notice.set(Some(resp.body)),
+                Err(err) => set_details_notice.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    let on_password_submit = move |ev: ev::SubmitEvent| {
+        ev.prevent_default();
+        set_room_password.set(Some(password_input.get_untracked()));
+        set_password_prompt.set(None);
+        send_signal(join_message(room()));
+    };
+
+    let (invite_link, set_invite_link) = create_signal::<Option<String>>(None);
+    let on_create_invite = create_action(move |()| {
+        let name = String::from(js_sys::encode_uri_component(&room()));
+        async move {
+            match api::post_json(&format!("/rooms/{}/invites", name), &serde_json::json!({})).await {
+                Ok(resp) if resp.ok() => {
+                    let origin = web_sys::window().and_then(|w| w.location().origin().ok()).unwrap_or_default();
+                    set_invite_link.set(resp.json::<Invite>().map(|i| format!("{}/chat/{}?invite={}", origin, name, i.token)));
+                }
+                Ok(resp) => set_details_notice.set(Some(resp.body)),
+                Err(err) => set_details_notice.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    let kick = move |peer_id: String| {
+        send_signal(SignalingMessage::Kick { room: room(), peer_id });
+    };
+    let (ban_username, set_ban_username) = create_signal("".to_string());
+    // Seconds, or empty for a ban without end
+    let (ban_duration, set_ban_duration) = create_signal("".to_string());
+    let on_ban_submit = move |ev: ev::SubmitEvent| {
+        ev.prevent_default();
+        let username = ban_username.get_untracked().trim().to_string();
+        if username.is_empty() {
+            return;
+        }
+        let duration = ban_duration.get_untracked().parse().ok();
+        send_signal(SignalingMessage::Ban { room: room(), username, duration });
+        set_ban_username.set("".to_string());
+    };
+
+    let answer_knock = move |id: String, admit: bool| {
+        send_signal(SignalingMessage::AnswerKnock { room: room(), id, admit });
+    };
+
+    // Starred rooms; stays `None` for guests, who have no saved rooms
+    let (saved_rooms, set_saved_rooms) = create_signal::<Option<AccountRooms>>(None);
+    spawn_local(async move {
+        set_saved_rooms.set(account_rooms::load().await);
+    });
+    let is_favorite = move || saved_rooms.with(|r| r.as_ref().is_some_and(|r| r.is_favorite(&room())));
+    let on_toggle_favorite = create_action(move |()| {
+        let name = room();
+        async move {
+            // Start from the server's copy so changes made on another device survive
+            let Some(mut rooms) = account_rooms::load().await else { return };
+            rooms.toggle_favorite(&name);
+            if let Some(saved) = account_rooms::save(&rooms).await {
+                set_saved_rooms.set(Some(saved));
             }
         }
     });
 
     view! {
         <div class="chat">
-            <h2>"Chat Room: " {room}</h2>
-            <div class="status">"Connection: " {connection_status}</div>
+            <h2 title=move || petnames::room(&room()).map(|_| room())>"Chat Room: " {move || petnames::room_label(&room())}</h2>
+            {move || broadcast_role.get().map(|role| view! {
+                <p class="broadcast">
+                    {match role {
+                        broadcast::Role::Broadcaster => format!("Broadcasting to {} viewers", viewers.get()),
+                        broadcast::Role::Viewer => format!("Watching a broadcast with {} viewers", viewers.get()),
+                    }}
+                </p>
+            })}
+            {move || topic.with(|(text, by)| (!text.is_empty()).then(|| view! {
+                <p class="topic" title=by.as_ref().map(|by| format!("Set by {}", by))>{text.clone()}</p>
+            }))}
+            {move || room_digests.get().then(|| view! { <digests::DigestSubscription room=room()/> })}
+            <Show when=move || saved_rooms.with(Option::is_some)>
+                <button
+                    type="button"
+                    class="favorite-toggle"
+                    title="Favorite rooms show up on the home page and in the Ctrl+K switcher"
+                    on:click=move |_| on_toggle_favorite.dispatch(())
+                >
+                    {move || if is_favorite() { "★ Starred" } else { "☆ Star" }}
+                </button>
+                {move || view! { <petnames::PetnameEditor target=petnames::Target::Room(room())/> }}
+            </Show>
+            <StatusIndicator status=connection_status/>
+            <RoutePanel route=route/>
+            <p class="members">
+                {move || members.with(|m| if m.is_empty() {
+                    "Nobody else is here yet".to_string()
+                } else {
+                    let names: Vec<_> = m
+                        .iter()
+                        .map(|p| {
+                            let name = petnames::person_label(p.username.as_deref(), &p.name);
+                            if p.away { format!("{} (reconnecting…)", name) } else { name }
+                        })
+                        .collect();
+                    format!("In the room: {}", names.join(", "))
+                })}
+            </p>
+            <Show when=is_moderator>
+                <ul class="member-actions">
+                    <For
+                        each=move || members.get()
+                        key=|p| p.id.clone()
+                        children=move |p| {
+                            let id = p.id.clone();
+                            view! {
+                                <li>
+                                    {move || petnames::person_label(p.username.as_deref(), &p.name)}
+                                    <button type="button" title="They can join again" on:click=move |_| kick(id.clone())>"Remove"</button>
+                                </li>
+                            }
+                        }
+                    />
+                </ul>
+            </Show>
+            {move || admission.get().map(|text| view! { <p class="admission">{text}</p> })}
+            <maintenance::MaintenanceBanner window=maintenance_window/>
+            {move || signal_error.get().map(|e| view! { <p class="error">{e}</p> })}
+            {move || password_prompt.get().map(|rejected| view! {
+                <form class="room-password" on:submit=on_password_submit>
+                    <p>{if rejected { "Wrong password or expired invite, try again." } else { "This room is protected. Enter its password to join." }}</p>
+                    <input
+                        type="password"
+                        placeholder="Room password"
+                        prop:value=password_input
+                        on:input=move |ev| set_password_input.set(event_target_value(&ev))
+                    />
+                    <button type="submit">"Join"</button>
+                </form>
+            })}
+            {move || moderator.get().map(|(name, temporary, you)| {
+                let who = if you { "You".to_string() } else { name };
+                let note = if temporary { " (standing in for the owner)" } else { "" };
+                view! { <p class="moderator">"Moderator: " {who} {note}</p> }
+            })}
+            <Show when=is_moderator>
+                <button
+                    type="button"
+                    title="Locked rooms make newcomers knock"
+                    on:click=move |_| send_signal(SignalingMessage::LockRoom { room: room(), locked: !room_locked.get() })
+                >
+                    {move || if room_locked.get() { "Unlock room" } else { "Lock room" }}
+                </button>
+                <form class="room-topic" on:submit=move |ev| {
+                    ev.prevent_default();
+                    send_signal(SignalingMessage::SetRoomTopic { room: room(), topic: topic_input.get() });
+                }>
+                    <input
+                        type="text"
+                        placeholder="Topic"
+                        maxlength="200"
+                        prop:value=topic_input
+                        on:input=move |ev| set_topic_input.set(event_target_value(&ev))
+                    />
+                    <button type="submit">"Set topic"</button>
+                </form>
+                <details class="room-details">
+                    <summary>"Directory listing"</summary>
+                    <form on:submit=move |ev| {
+                        ev.prevent_default();
+                        on_save_details.dispatch(());
+                    }>
+                        <input
+                            type="text"
+                            placeholder="Description"
+                            maxlength="200"
+                            prop:value=move || details.with(|d| d.description.clone())
+                            on:input=move |ev| set_details.update(|d| d.description = event_target_value(&ev))
+                        />
+                        <input
+                            type="text"
+                            placeholder="Category"
+                            maxlength="30"
+                            prop:value=move || details.with(|d| d.category.clone().unwrap_or_default())
+                            on:input=move |ev| {
+                                let value = event_target_value(&ev);
+                                set_details.update(|d| d.category = (!value.trim().is_empty()).then_some(value));
+                            }
+                        />
+                        <input
+                            type="text"
+                            placeholder="Tags, comma separated"
+                            prop:value=move || details.with(|d| d.tags.join(", "))
+                            on:change=move |ev| set_details.update(|d| {
+                                d.tags = event_target_value(&ev)
+                                    .split(',')
+                                    .map(|t| t.trim().to_string())
+                                    .filter(|t| !t.is_empty())
+                                    .collect();
+                            })
+                        />
+                        <button type="submit">"Save"</button>
+                    </form>
+                    <form on:submit=move |ev| {
+                        ev.prevent_default();
+                        on_save_moderators.dispatch(());
+                    }>
+                        <input
+                            type="text"
+                            placeholder="Moderators: usernames, comma separated"
+                            title="Replaces the current list. Moderators can lock the room, answer knocks and edit its listing."
+                            prop:value=moderators_input
+                            on:input=move |ev| set_moderators_input.set(event_target_value(&ev))
+                        />
+                        <button type="submit">"Set moderators"</button>
+                    </form>
+                    {move || details_notice.get().map(|n| view! { <p class="notice">{n}</p> })}
+                </details>
+                <button
+                    type="button"
+                    title="Anyone with the link can join for a day without the room's password"
+                    on:click=move |_| on_create_invite.dispatch(())
+                >"Create invite link"</button>
+                {move || invite_link.get().map(|link| view! { <input class="invite-link" type="text" readonly prop:value=link/> })}
+                <form class="ban" on:submit=on_ban_submit>
+                    <input
+                        type="text"
+                        placeholder="Username to ban"
+                        prop:value=ban_username
+                        on:input=move |ev| set_ban_username.set(event_target_value(&ev))
+                    />
+                    <select on:change=move |ev| set_ban_duration.set(event_target_value(&ev))>
+                        <option value="3600">"For an hour"</option>
+                        <option value="86400">"For a day"</option>
+                        <option value="604800">"For a week"</option>
+                        <option value="" selected>"For good"</option>
+                    </select>
+                    <button type="submit">"Ban"</button>
+                </form>
+            </Show>
+            <ul class="knocks">
+                <For
+                    each=move || knocks.get()
+                    key=|(id, _)| id.clone()
+                    children=move |(id, name)| {
+                        let deny_id = id.clone();
+                        view! {
+                            <li class="knock">
+                                {format!("{} wants to join", name)}
+                                <Show when=is_moderator>
+                                    <button type="button" on:click={
+                                        let id = id.clone();
+                                        move |_| answer_knock(id.clone(), true)
+                                    }>"Admit"</button>
+                                    <button type="button" on:click={
+                                        let deny_id = deny_id.clone();
+                                        move |_| answer_knock(deny_id.clone(), false)
+                                    }>"Deny"</button>
+                                </Show>
+                            </li>
+                        }
+                    }
+                />
+            </ul>
+            <button type="button" on:click=move |_| set_show_insights.update(|open| *open = !*open)>"Insights"</button>
+            <Show when=is_owner>
+                <a href=move || format!("/rooms/{}/stats", room())>"Room stats"</a>
+            </Show>
+            <Show when=move || show_insights.get()>
+                <RoomInsights room=Signal::derive(room) messages=messages/>
+            </Show>
+            {move || language::can_translate().then(foreign_language).flatten().map(|lang| view! {
+                <label class="auto-translate">
+                    <input
+                        type="checkbox"
+                        prop:checked=auto_translate
+                        on:change=move |ev| {
+                            let enabled = event_target_checked(&ev);
+                            language::set_auto_translate(&room(), enabled);
+                            set_auto_translate.set(enabled);
+                        }
+                    />
+                    {format!("Translate messages in {} as they arrive", language::name(&lang))}
+                </label>
+            })}
             <div class="messages">
                 <For
                     each=messages
-                    key=|msg| msg.timestamp.clone()
-                    view=move |msg| view! {
-                        <div class=move || if msg.sender == "me" { "message sent" } else { "message received" }>
-                            <strong>{msg.sender}:</strong> {msg.content}
-                            <small>{msg.timestamp}</small>
-                        </div>
+                    key=|msg| msg.id.clone()
+                    view=move |msg| {
+                        let id = msg.id.clone();
+                        let outgoing = msg.sender == "me";
+                        // Rows are keyed by id, so read the live status from the list
+                        let status = move || {
+                            messages.with(|msgs| msgs.iter().find(|m| m.id == id).map(|m| m.status))
+                                .unwrap_or_default()
+                        };
+                        let retry_id = msg.id.clone();
+                        // Received messages in another language can be translated
+                        let foreign = msg.lang.clone().filter(|lang| language::is_foreign(lang));
+                        let translation = {
+                            let id = msg.id.clone();
+                            move || translations.with(|t| t.get(&id).cloned())
+                        };
+                        let translation_shown = translation.clone();
+                        let on_translate = {
+                            let (id, text) = (msg.id.clone(), msg.content.clone());
+                            move |lang: String| translate(id.clone(), text.clone(), lang)
+                        };
+                        view! {
+                            <div
+                                class=move || if outgoing { "message sent" } else { "message received" }
+                                class:pending=move || outgoing && status() == MessageStatus::Pending
+                                class:failed=move || outgoing && status() == MessageStatus::Failed
+                            >
+                                <strong>{msg.sender}:</strong> <span dir="auto" lang=msg.lang.clone()>{msg.content}</span>
+                                {foreign.clone().map(|lang| view! {
+                                    <small class="message-lang" title=language::name(&lang)>{lang.to_uppercase()}</small>
+                                })}
+                                {foreign.filter(|_| language::can_translate()).map(|lang| view! {
+                                    <Show when=move || translation_shown().is_none()>
+                                        <button type="button" class="translate" on:click={
+                                            let (lang, on_translate) = (lang.clone(), on_translate.clone());
+                                            move |_| on_translate(lang.clone())
+                                        }>"Translate"</button>
+                                    </Show>
+                                })}
+                                {move || translation().map(|translated| match translated {
+                                    Ok(text) => view! { <p class="translation" lang=language::own() dir="auto">{text}</p> },
+                                    Err(err) => view! { <p class="translation error">{err}</p> },
+                                })}
+                                {msg.scribble.map(|scribble| view! { <ScribbleView scribble/> })}
+                                <small title={
+                                    let timestamp = msg.timestamp.clone();
+                                    move || {
+                                        clock_tick.track();
+                                        format!("Sent {}", locale::relative(&timestamp, clock::now()))
+                                    }
+                                }>{locale::time(&msg.timestamp)}</small>
+                                <Show when=move || outgoing>
+                                    <small class="message-status">{move || status().label()}</small>
+                                </Show>
+                                <Show when=move || outgoing && status() == MessageStatus::Failed>
+                                    <button type="button" class="retry" on:click={
+                                        let retry_id = retry_id.clone();
+                                        move |_| retry(retry_id.clone())
+                                    }>"Retry"</button>
+                                </Show>
+                            </div>
+                        }
                     }
                 />
             </div>
-            <form on:submit=|ev| on_send.dispatch(ev) prevent_default=true>
-                <input
-                    type="text"
-                    placeholder="Type your message..."
-                    prop:value=input
-                    on:input=move |ev| set_input.set(event_target_value(&ev))
-                />
-                <button type="submit">"Send"</button>
-            </form>
-            <div class="queued">"Queued messages: " {queued_messages.get().len()}</div>
+            <Show
+                when=move || broadcast_role.get() != Some(broadcast::Role::Viewer)
+                fallback=|| view! { <p class="read-only">"Only the broadcaster sends messages in this room."</p> }
+            >
+                <form on:submit=|ev| on_send.dispatch(ev) prevent_default=true>
+                    <input
+                        type="text"
+                        placeholder="Type your message..."
+                        dir="auto"
+                        prop:value=input
+                        on:input=move |ev| edit_draft(event_target_value(&ev))
+                    />
+                    <button type="button" title="Draw a scribble" on:click=move |_| set_scribbling.update(|open| *open = !*open)>"✎"</button>
+                    <button type="submit">"Send"</button>
+                </form>
+                <Show when=move || draft_elsewhere.get()>
+                    <small class="draft-origin">"Draft from another device"</small>
+                </Show>
+            </Show>
+            <Show when=move || scribbling.get()>
+                <ScribblePad on_send=on_send_scribble on_close=move |_| set_scribbling.set(false)/>
+            </Show>
+            <div class="queued">{move || outbox.with(queue_summary)}</div>
         </div>
     }
 }
 
+fn set_message_status(set_messages: WriteSignal<Vec<Message>>, id: &str, status: MessageStatus) {
+    set_messages.update(|msgs| {
+        if let Some(msg) = msgs.iter_mut().find(|m| m.id == id) {
+            msg.status = status;
+        }
+    });
+}
+
+/// Where chat goes once the peer connection has given up: through the
+/// signaling socket, addressed to our partner.
+#[derive(Clone)]
+struct RelayRoute {
+    transport: ReadSignal<Option<Transport>>,
+    room: String,
+    to: String,
+}
+
+impl RelayRoute {
+    fn send(&self, payload: &str) -> bool {
+        let Some(transport) = self.transport.get_untracked().filter(Transport::is_open) else {
+            return false;
+        };
+        let msg = SignalingMessage::ChatRelay {
+            room: self.room.clone(),
+            payload: payload.to_string(),
+            to: Some(self.to.clone()),
+            from: None,
+        };
+        transport.send(&msg)
+    }
+}
+
+/// Sends whatever the outbox allows over the open data channel, or the
+/// server relay if chat fell back to it, and, if the head of the queue is
+/// backing off, schedules the next attempt.
+fn flush_outbox(
+    outbox: RwSignal<Outbox>,
+    data_channel: ReadSignal<Option<web_sys::RtcDataChannel>>,
+    relay: ReadSignal<Option<RelayRoute>>,
+    connection_status: RwSignal<ConnectionStatus>,
+    set_messages: WriteSignal<Vec<Message>>,
+) {
+    // An unreachable peer would not ack, so messages wait as pending
+    let dc = data_channel
+        .get_untracked()
+        .filter(|dc| dc.ready_state() == RtcDataChannelState::Open)
+        .filter(|_| connection_status.with_untracked(|s| s.peer != PeerState::Unreachable));
+    let updates = match (dc, relay.get_untracked()) {
+        (Some(dc), _) => outbox.try_update(|o| o.flush(|data| frames::send(&dc, data))),
+        (None, Some(route)) => outbox.try_update(|o| o.flush(|data| route.send(data))),
+        (None, None) => return,
+    }
+    .unwrap_or_default();
+    for (id, status) in updates {
+        set_message_status(set_messages, &id, status);
+    }
+
+    let retry_in = outbox.with_untracked(|o| if o.retry_scheduled { None } else { o.next_retry_in() });
+    if let Some(delay) = retry_in {
+        outbox.update(|o| o.retry_scheduled = true);
+        commands::schedule(delay, move || {
+            outbox.update(|o| o.retry_scheduled = false);
+            flush_outbox(outbox, data_channel, relay, connection_status, set_messages);
+        });
+    }
+}
+
+fn queue_summary(outbox: &Outbox) -> String {
+    if outbox.is_empty() {
+        return String::new();
+    }
+    let mut summary = format!(
+        "{} queued ({:.1} of {} KB)",
+        outbox.len(),
+        outbox.bytes() as f64 / 1024.0,
+        outbox::MAX_QUEUED_BYTES / 1024
+    );
+    match outbox.next_retry_in() {
+        Some(ms) => summary.push_str(&format!(" · retrying in {}s", ms.div_ceil(1000))),
+        None => summary.push_str(" · waiting for connection"),
+    }
+    summary
+}
+
+/// Executes a slash command locally. Reminders and timers are scheduled in
+/// this tab, so they fire whether or not the peer is connected.
+fn run_command(command: Result<SlashCommand, String>, set_messages: WriteSignal<Vec<Message>>) {
+    let push_system = move |content: String| {
+        set_messages.update(|msgs| msgs.push(Message::system(content)));
+    };
+
+    match command {
+        Err(usage) => push_system(usage),
+        Ok(SlashCommand::Remind { delay_ms, text }) => {
+            commands::request_notification_permission();
+            push_system(format!("Reminder set for {}: {}", commands::format_duration(delay_ms), text));
+            commands::schedule(delay_ms, move || {
+                commands::notify("Reminder", &text);
+                push_system(format!("Reminder: {}", text));
+            });
+        }
+        Ok(SlashCommand::Timer { delay_ms }) => {
+            let label = commands::format_duration(delay_ms);
+            commands::request_notification_permission();
+            push_system(format!("Timer started: {}", label));
+            commands::schedule(delay_ms, move || {
+                commands::notify("Timer finished", &label);
+                push_system(format!("Timer finished: {}", label));
+            });
+        }
+    }
+}
+
 fn main() {
     console_error_panic_hook::set_once();
-    console_log::init_with_level(log::Level::Info).expect("error initializing log");
-    mount_to_body(|cx| view! { cx, <App/> })
+    console_log::init_with_level(config::Profile::current().log_level()).expect("error initializing log");
+    // The API base may come from /config.json, so read it before anything
+    // talks to the backend
+    spawn_local(async {
+        config::load().await;
+        mount_to_body(|cx| view! { cx, <App/> })
+    });
 }
\ No newline at end of file
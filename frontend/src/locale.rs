@@ -0,0 +1,105 @@
+use wasm_bindgen::JsValue;
+
+/// Languages written right to left; the layout is mirrored for them.
+const RTL_LANGUAGES: &[&str] = &["ar", "ckb", "dv", "fa", "he", "ks", "ps", "sd", "ug", "ur", "yi"];
+
+/// The browser's preferred locale, e.g. `de-CH`.
+pub fn current() -> String {
+    web_sys::window()
+        .and_then(|w| w.navigator().language())
+        .unwrap_or_else(|| "en-US".to_string())
+}
+
+pub fn is_rtl(locale: &str) -> bool {
+    let language = locale.split(['-', '_']).next().unwrap_or_default().to_lowercase();
+    RTL_LANGUAGES.contains(&language.as_str())
+}
+
+/// Value for the `dir` attribute of the document.
+pub fn direction(locale: &str) -> &'static str {
+    if is_rtl(locale) {
+        "rtl"
+    } else {
+        "ltr"
+    }
+}
+
+fn options(entries: &[(&str, &str)]) -> js_sys::Object {
+    let options = js_sys::Object::new();
+    for (key, value) in entries {
+        let _ = js_sys::Reflect::set(&options, &JsValue::from_str(key), &JsValue::from_str(value));
+    }
+    options
+}
+
+fn locales() -> js_sys::Array {
+    js_sys::Array::of1(&JsValue::from_str(&current()))
+}
+
+fn format_date(timestamp: &str, entries: &[(&str, &str)]) -> String {
+    let date = js_sys::Date::new(&JsValue::from_str(timestamp));
+    if date.get_time().is_nan() {
+        return timestamp.to_string();
+    }
+    js_sys::Intl::DateTimeFormat::new(&locales(), &options(entries))
+        .format()
+        .call1(&JsValue::UNDEFINED, &date)
+        .ok()
+        .and_then(|s| s.as_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+/// Date and time, e.g. for when a session started.
+pub fn date_time(timestamp: &str) -> String {
+    format_date(timestamp, &[("dateStyle", "medium"), ("timeStyle", "short")])
+}
+
+/// Just the time of day, for message bubbles.
+pub fn time(timestamp: &str) -> String {
+    format_date(timestamp, &[("timeStyle", "short")])
+}
+
+/// How long ago `timestamp` was as of `now` (milliseconds since the
+/// epoch), e.g. "2 minutes ago", in the largest unit that fits.
+pub fn relative(timestamp: &str, now: f64) -> String {
+    let date = js_sys::Date::new(&JsValue::from_str(timestamp));
+    if date.get_time().is_nan() {
+        return timestamp.to_string();
+    }
+    let seconds = ((now - date.get_time()) / 1000.0).max(0.0);
+    let (value, unit) = match seconds {
+        s if s < 60.0 => (s, "second"),
+        s if s < 3600.0 => (s / 60.0, "minute"),
+        s if s < 86400.0 => (s / 3600.0, "hour"),
+        s => (s / 86400.0, "day"),
+    };
+    js_sys::Intl::RelativeTimeFormat::new(&locales(), &options(&[("numeric", "auto")]))
+        .format(-value.floor(), unit)
+        .into()
+}
+
+/// An hour of the day such as `14:00` or `2 PM`, whichever the locale uses.
+pub fn hour(hour: usize) -> String {
+    format_date(&format!("2000-01-01T{:02}:00", hour), &[("hour", "numeric")])
+}
+
+/// Month and day without the year, for chart labels.
+pub fn month_day(timestamp: &str) -> String {
+    format_date(timestamp, &[("month", "short"), ("day", "numeric")])
+}
+
+/// A number with the locale's digit grouping and decimal separator.
+pub fn number(value: f64, max_fraction_digits: u32) -> String {
+    let options = options(&[]);
+    let _ = js_sys::Reflect::set(
+        &options,
+        &JsValue::from_str("maximumFractionDigits"),
+        &JsValue::from_f64(max_fraction_digits as f64),
+    );
+    js_sys::Intl::NumberFormat::new(&locales(), &options)
+        .format()
+        .call1(&JsValue::UNDEFINED, &JsValue::from_f64(value))
+        .ok()
+        .and_then(|s| s.as_string())
+        .unwrap_or_else(|| value.to_string())
+}
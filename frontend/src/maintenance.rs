@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+use leptos::*;
+
+use crate::{clock, locale};
+
+/// Losing the server this long before the window counts as the
+/// maintenance starting early.
+const EARLY_START_MS: f64 = 60_000.0;
+/// Reconnects after the window are spread over this long, so every client
+/// does not come back in the same second.
+const RECONNECT_SPREAD_MS: f64 = 30_000.0;
+/// After the window, a server that is still down is retried this often
+/// (plus up to as much again at random) for a while, rather than treated
+/// like a broken connection.
+const OVERRUN_RETRY_MS: f64 = 15_000.0;
+const OVERRUN_MS: f64 = 30.0 * 60_000.0;
+const COUNTDOWN_TICK: Duration = Duration::from_secs(1);
+
+/// Scheduled downtime the server announced, by the server's clock.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Window {
+    starts_at: String,
+    start_ms: f64,
+    end_ms: f64,
+    joins_blocked_from: Option<f64>,
+    message: Option<String>,
+}
+
+fn millis(timestamp: &str) -> Option<f64> {
+    let millis = js_sys::Date::parse(timestamp);
+    (!millis.is_nan()).then_some(millis)
+}
+
+impl Window {
+    /// From a `maintenance_notice`; `None` if its times do not parse.
+    pub fn from_notice(starts_at: String, duration: u64, joins_blocked_from: Option<&str>, message: Option<String>) -> Option<Self> {
+        let start_ms = millis(&starts_at)?;
+        Some(Window {
+            starts_at,
+            start_ms,
+            end_ms: start_ms + duration as f64 * 1000.0,
+            joins_blocked_from: joins_blocked_from.and_then(millis),
+            message,
+        })
+    }
+
+    /// How long to wait before reconnecting when the server goes away at
+    /// `now`, if that is down to this window: until it ends, then at a
+    /// relaxed pace while it overruns. `None` if the window has nothing to
+    /// do with it.
+    pub fn reconnect_delay(&self, now: f64) -> Option<Duration> {
+        let jitter = js_sys::Math::random();
+        let millis = if now < self.start_ms - EARLY_START_MS || now >= self.end_ms + OVERRUN_MS {
+            return None;
+        } else if now < self.end_ms {
+            self.end_ms - now + jitter * RECONNECT_SPREAD_MS
+        } else {
+            OVERRUN_RETRY_MS * (1.0 + jitter)
+        };
+        Some(Duration::from_secs_f64(millis / 1000.0))
+    }
+}
+
+/// "1 h 05 min", "4 min 30 s": the time left, to the second near the end.
+fn countdown(millis: f64) -> String {
+    let seconds = (millis / 1000.0).ceil().max(0.0) as u64;
+    match seconds {
+        s if s >= 3600 => format!("{} h {:02} min", s / 3600, s % 3600 / 60),
+        s if s >= 60 => format!("{} min {:02} s", s / 60, s % 60),
+        s => format!("{} s", s),
+    }
+}
+
+/// Counts down to a scheduled maintenance window and says when it should
+/// be over. Hidden once it is.
+#[component]
+pub fn MaintenanceBanner(window: ReadSignal<Option<Window>>) -> impl IntoView {
+    let tick = create_trigger();
+    if let Ok(handle) = set_interval_with_handle(move || tick.notify(), COUNTDOWN_TICK) {
+        on_cleanup(move || handle.clear());
+    }
+
+    move || {
+        tick.track();
+        let window = window.get()?;
+        let now = clock::now();
+        if now >= window.end_ms {
+            return None;
+        }
+        let minutes = ((window.end_ms - window.start_ms) / 60_000.0).round();
+        let status = if now < window.start_ms {
+            format!(
+                "Scheduled maintenance starts in {}, at {}, for about {} minutes.",
+                countdown(window.start_ms - now),
+                locale::time(&window.starts_at),
+                minutes
+            )
+        } else {
+            format!(
+                "Maintenance under way, expected to end at {}.",
+                locale::time(&clock::iso(window.end_ms))
+            )
+        };
+        let joins = window.joins_blocked_from.map(|from| {
+            if now >= from {
+                "Joining rooms is paused until it is over.".to_string()
+            } else {
+                format!("Joining rooms pauses in {}.", countdown(from - now))
+            }
+        });
+        Some(view! {
+            <p class="maintenance-banner" role="status">
+                {status}
+                {joins.map(|text| format!(" {}", text))}
+                {window.message.map(|message| view! { <span class="maintenance-message">{message}</span> })}
+            </p>
+        })
+    }
+}
@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+
+use crate::scribble::Scribble;
+use crate::{clock, language, ulid};
+
+/// Delivery state of a message we sent. Received messages are always `Delivered`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageStatus {
+    /// Waiting for the data channel to open
+    #[default]
+    Pending,
+    /// Handed to the data channel, not yet acknowledged by the peer
+    Sent,
+    /// Acknowledged by the peer
+    Delivered,
+    /// The data channel rejected the send
+    Failed,
+}
+
+impl MessageStatus {
+    pub fn label(self) -> &'static str {
+        match self {
+            MessageStatus::Pending => "Sending…",
+            MessageStatus::Sent => "Sent",
+            MessageStatus::Delivered => "Delivered",
+            MessageStatus::Failed => "Failed",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Message {
+    pub id: String,
+    pub content: String,
+    pub sender: String,
+    pub timestamp: String,
+    #[serde(default)]
+    pub scribble: Option<Scribble>,
+    #[serde(default)]
+    pub status: MessageStatus,
+    /// Probable language of a received message, e.g. `de`, detected on
+    /// arrival; `None` for our own and for ones too short to tell
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+}
+
+impl Message {
+    fn new(id: String, sender: &str, content: String, scribble: Option<Scribble>, status: MessageStatus) -> Self {
+        Message {
+            id,
+            content,
+            sender: sender.to_string(),
+            timestamp: clock::now_iso(),
+            scribble,
+            status,
+            lang: None,
+        }
+    }
+
+    /// A message typed by the local user, not yet handed to the data channel.
+    pub fn outgoing(content: String, scribble: Option<Scribble>) -> Self {
+        Message::new(new_message_id(), "me", content, scribble, MessageStatus::Pending)
+    }
+
+    /// `sent_at` is the sender's server-corrected clock; without it the time
+    /// comes from the message's ULID, and failing that it is the time of
+    /// arrival.
+    pub fn received(id: String, content: String, scribble: Option<Scribble>, sent_at: Option<f64>) -> Self {
+        let sent_at = sent_at.or_else(|| ulid::timestamp(&id));
+        let mut message = Message::new(id, "peer", content, scribble, MessageStatus::Delivered);
+        if let Some(sent_at) = sent_at {
+            message.timestamp = clock::iso(sent_at);
+        }
+        message.lang = language::detect(&message.content);
+        message
+    }
+
+    /// Rebuilds an unsent outgoing message from its queued payload, e.g. after
+    /// a reload restored the outbox.
+    pub fn from_queued(payload: &PeerMessage) -> Option<Self> {
+        let (id, content, scribble, sent_at) = match payload {
+            PeerMessage::Text { id, content, sent_at } => (id, content.clone(), None, sent_at),
+            PeerMessage::Scribble { id, scribble, sent_at } => (id, String::new(), Some(scribble.clone()), sent_at),
+            PeerMessage::Ack { .. } | PeerMessage::Ping => return None,
+        };
+        let mut message = Message::new(id.clone(), "me", content, scribble, MessageStatus::Pending);
+        if let Some(sent_at) = sent_at {
+            message.timestamp = clock::iso(*sent_at);
+        }
+        Some(message)
+    }
+
+    /// Local notices (command output, reminders) that are never sent.
+    pub fn system(content: String) -> Self {
+        Message::new(new_message_id(), "system", content, None, MessageStatus::Delivered)
+    }
+
+    /// The data channel payload that carries this message, used for sending
+    /// and for retrying after a failure.
+    pub fn payload(&self) -> PeerMessage {
+        let sent_at = js_sys::Date::new(&self.timestamp.as_str().into()).get_time();
+        let sent_at = (!sent_at.is_nan()).then_some(sent_at);
+        match &self.scribble {
+            Some(scribble) => PeerMessage::Scribble {
+                id: self.id.clone(),
+                scribble: scribble.clone(),
+                sent_at,
+            },
+            None => PeerMessage::Text {
+                id: self.id.clone(),
+                content: self.content.clone(),
+                sent_at,
+            },
+        }
+    }
+}
+
+/// Payloads exchanged with the peer over the data channel, serialized as JSON.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PeerMessage {
+    /// `sent_at` is when it was written, in milliseconds since the epoch by
+    /// the sender's server-corrected clock
+    Text {
+        id: String,
+        content: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sent_at: Option<f64>,
+    },
+    Scribble {
+        id: String,
+        scribble: Scribble,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sent_at: Option<f64>,
+    },
+    /// Confirms receipt of the message with this id
+    Ack { id: String },
+    /// Keepalive sent over an open data channel; never queued or answered
+    Ping,
+}
+
+impl PeerMessage {
+    /// Empty for pings, which have none.
+    pub fn id(&self) -> &str {
+        match self {
+            PeerMessage::Text { id, .. } | PeerMessage::Scribble { id, .. } | PeerMessage::Ack { id } => id,
+            PeerMessage::Ping => "",
+        }
+    }
+}
+
+/// Message IDs are ULIDs, so they sort by when the message was written.
+/// They key the message list, the outbox and acks, and let a message that
+/// arrives twice be recognized.
+pub fn new_message_id() -> String {
+    ulid::new()
+}
@@ -0,0 +1,140 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{self, StorageError};
+use crate::message::{MessageStatus, PeerMessage};
+
+/// Upper bound on the serialized size of everything waiting to be sent.
+pub const MAX_QUEUED_BYTES: usize = 256 * 1024;
+/// Attempts before a message is given up on and marked failed.
+const MAX_ATTEMPTS: u32 = 6;
+const BASE_BACKOFF_MS: f64 = 1_000.0;
+const MAX_BACKOFF_MS: f64 = 60_000.0;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct OutboxEntry {
+    payload: PeerMessage,
+    bytes: usize,
+    attempts: u32,
+    /// `Date.now()` before which the entry is not retried
+    not_before: f64,
+}
+
+/// Outgoing messages waiting for the data channel, persisted per room in
+/// localStorage so they survive a reload. Entries are sent strictly in order:
+/// a failed send holds back everything behind it until its backoff expires.
+#[derive(Clone, Debug, Default)]
+pub struct Outbox {
+    room: String,
+    entries: VecDeque<OutboxEntry>,
+    /// Set while a retry timer is pending so only one is scheduled at a time
+    pub retry_scheduled: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutboxFull;
+
+fn storage_key(room: &str) -> String {
+    format!("outbox:{}", room)
+}
+
+fn now() -> f64 {
+    js_sys::Date::now()
+}
+
+impl Outbox {
+    pub fn load(room: &str) -> Self {
+        let entries = web_sys::window()
+            .and_then(|w| w.local_storage().ok().flatten())
+            .and_then(|s| s.get_item(&storage_key(room)).ok().flatten())
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Outbox {
+            room: room.to_string(),
+            entries,
+            retry_scheduled: false,
+        }
+    }
+
+    fn persist(&self) {
+        let Some(storage) = web_sys::window().and_then(|w| w.local_storage().ok().flatten()) else {
+            return;
+        };
+        if self.entries.is_empty() {
+            let _ = storage.remove_item(&storage_key(&self.room));
+        } else if let Ok(json) = serde_json::to_string(&self.entries) {
+            if let Err(err) = storage.set_item(&storage_key(&self.room), &json) {
+                // Still sent from memory; only a reload would lose it
+                error::record(&StorageError::WriteFailed(format!("{:?}", err)).into());
+            }
+        }
+    }
+
+    pub fn push(&mut self, payload: PeerMessage) -> Result<(), OutboxFull> {
+        let bytes = serde_json::to_string(&payload).map(|s| s.len()).unwrap_or(0);
+        if self.bytes() + bytes > MAX_QUEUED_BYTES {
+            return Err(OutboxFull);
+        }
+        self.entries.push_back(OutboxEntry {
+            payload,
+            bytes,
+            attempts: 0,
+            not_before: 0.0,
+        });
+        self.persist();
+        Ok(())
+    }
+
+    /// Sends queued entries in order with `send`, which says whether the
+    /// transport took the data, and reports the resulting status for each
+    /// message that left the queue.
+    pub fn flush(&mut self, send: impl Fn(&str) -> bool) -> Vec<(String, MessageStatus)> {
+        let mut updates = Vec::new();
+        while let Some(entry) = self.entries.front_mut() {
+            if entry.not_before > now() {
+                break;
+            }
+            let data = serde_json::to_string(&entry.payload).unwrap();
+            if send(&data) {
+                let entry = self.entries.pop_front().unwrap();
+                updates.push((entry.payload.id().to_string(), MessageStatus::Sent));
+                continue;
+            }
+
+            entry.attempts += 1;
+            if entry.attempts >= MAX_ATTEMPTS {
+                let entry = self.entries.pop_front().unwrap();
+                updates.push((entry.payload.id().to_string(), MessageStatus::Failed));
+                continue;
+            }
+            let backoff = (BASE_BACKOFF_MS * 2f64.powi(entry.attempts as i32 - 1)).min(MAX_BACKOFF_MS);
+            entry.not_before = now() + backoff;
+            break;
+        }
+        self.persist();
+        updates
+    }
+
+    /// Milliseconds until the head of the queue may be retried, if it is backing off.
+    pub fn next_retry_in(&self) -> Option<u32> {
+        let wait = self.entries.front()?.not_before - now();
+        (wait > 0.0).then(|| wait.ceil() as u32)
+    }
+
+    pub fn payloads(&self) -> impl Iterator<Item = &PeerMessage> {
+        self.entries.iter().map(|e| &e.payload)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.entries.iter().map(|e| e.bytes).sum()
+    }
+}
@@ -0,0 +1,126 @@
+use leptos::*;
+use leptos_router::use_navigate;
+
+use crate::account_rooms::{self, AccountRooms};
+use crate::petnames;
+
+/// One thing the palette can jump to.
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    label: String,
+    path: String,
+}
+
+/// "Mom (dm-1a2b…)": a room's petname with its real name, so either finds it.
+fn room_label(name: &str, petname: Option<&str>) -> String {
+    match petname {
+        Some(petname) => format!("{} ({})", petname, name),
+        None => name.to_string(),
+    }
+}
+
+/// Favorite and recent rooms, then other rooms the user named, plus the
+/// main pages, narrowed down by `query`. A query that names no listed room
+/// offers to join it.
+fn entries(rooms: &AccountRooms, named: &[(String, String)], query: &str) -> Vec<Entry> {
+    let query = query.trim();
+    let needle = query.to_lowercase();
+    let petname = |room: &str| named.iter().find(|(name, _)| name == room).map(|(_, petname)| petname.as_str());
+    let quick = rooms.quick_access();
+    let others = named.iter().filter(|(name, _)| !quick.iter().any(|(room, _)| room == name)).map(|(name, _)| (name.clone(), false));
+    let mut entries: Vec<Entry> = quick
+        .iter()
+        .cloned()
+        .chain(others)
+        .map(|(name, favorite)| Entry {
+            label: format!("{}{}", if favorite { "★ " } else { "" }, room_label(&name, petname(&name))),
+            path: format!("/chat/{}", name),
+        })
+        .chain([
+            Entry { label: "Rooms".to_string(), path: "/rooms".to_string() },
+            Entry { label: "Settings".to_string(), path: "/settings".to_string() },
+        ])
+        .filter(|entry| entry.label.to_lowercase().contains(&needle))
+        .collect();
+    let mut listed = quick.iter().map(|(name, _)| name).chain(named.iter().flat_map(|(name, petname)| [name, petname]));
+    if !query.is_empty() && !listed.any(|name| name == query) {
+        entries.push(Entry { label: format!("Join {}", query), path: format!("/chat/{}", query) });
+    }
+    entries
+}
+
+/// Ctrl+K (Cmd+K on macOS) opens a quick switcher over the user's rooms,
+/// found by name or by the petname the user gave them.
+/// Enter goes to the first match, Escape closes it.
+#[component]
+pub fn CommandPalette() -> impl IntoView {
+    let navigate = use_navigate();
+    let (open, set_open) = create_signal(false);
+    let (query, set_query) = create_signal("".to_string());
+    let (rooms, set_rooms) = create_signal(AccountRooms::default());
+
+    // Reload on every open so rooms starred on another device show up
+    create_effect(move |_| {
+        if open.get() {
+            set_query.set("".to_string());
+            spawn_local(async move {
+                set_rooms.set(account_rooms::load().await.unwrap_or_default());
+            });
+        }
+    });
+
+    let handle = window_event_listener(ev::keydown, move |ev| {
+        if (ev.ctrl_key() || ev.meta_key()) && ev.key().eq_ignore_ascii_case("k") {
+            ev.prevent_default();
+            set_open.update(|open| *open = !*open);
+        } else if ev.key() == "Escape" {
+            set_open.set(false);
+        }
+    });
+    on_cleanup(move || handle.remove());
+
+    let matches = move || {
+        let named = petnames::rooms();
+        rooms.with(|r| query.with(|q| entries(r, &named, q)))
+    };
+    let go = move |path: String| {
+        set_open.set(false);
+        navigate(&path, Default::default());
+    };
+    let go_first = go.clone();
+
+    view! {
+        <Show when=move || open.get()>
+            <div class="command-palette" on:click=move |_| set_open.set(false)>
+                <form on:click=|ev| ev.stop_propagation() on:submit={
+                    let go = go_first.clone();
+                    move |ev| {
+                        ev.prevent_default();
+                        if let Some(entry) = matches().into_iter().next() {
+                            go(entry.path);
+                        }
+                    }
+                }>
+                    <input
+                        type="text"
+                        placeholder="Go to room…"
+                        autofocus=true
+                        prop:value=query
+                        on:input=move |ev| set_query.set(event_target_value(&ev))
+                    />
+                    <ul>
+                        {
+                            let go = go.clone();
+                            move || matches().into_iter().map(|entry| {
+                                let go = go.clone();
+                                view! {
+                                    <li><button type="button" on:click=move |_| go(entry.path.clone())>{entry.label}</button></li>
+                                }
+                            }).collect_view()
+                        }
+                    </ul>
+                </form>
+            </div>
+        </Show>
+    }
+}
@@ -0,0 +1,105 @@
+use leptos::*;
+use leptos_router::use_navigate;
+use serde::Deserialize;
+
+use crate::api;
+use crate::petnames::{self, PetnameEditor, Target};
+
+/// Shorter searches are refused by the server.
+const MIN_QUERY_LENGTH: usize = 2;
+
+/// A user as `GET /users` lists them.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+struct UserSummary {
+    username: String,
+    display_name: Option<String>,
+}
+
+/// A room as `POST /dm/:username` returns it; only the name is needed.
+#[derive(Deserialize)]
+struct DirectRoom {
+    name: String,
+}
+
+/// Finds other users by username or display name and opens a direct
+/// conversation with one, instead of agreeing on a room name elsewhere.
+#[component]
+pub fn PeopleSearch() -> impl IntoView {
+    let navigate = use_navigate();
+    let (query, set_query) = create_signal(String::new());
+    let (found, set_found) = create_signal::<Vec<UserSummary>>(Vec::new());
+    let (error, set_error) = create_signal::<Option<String>>(None);
+
+    create_effect(move |_| {
+        let query = query.get().trim().to_string();
+        if query.chars().count() < MIN_QUERY_LENGTH {
+            set_found.set(Vec::new());
+            return;
+        }
+        let path = format!("/users?query={}", String::from(js_sys::encode_uri_component(&query)));
+        spawn_local(async move {
+            match api::get(&path).await {
+                Ok(resp) if resp.ok() => set_found.set(resp.json().unwrap_or_default()),
+                Ok(resp) => set_error.set(Some(resp.body)),
+                Err(err) => set_error.set(Some(err.to_string())),
+            }
+        });
+    });
+
+    let message = create_action(move |username: &String| {
+        let path = format!("/dm/{}", String::from(js_sys::encode_uri_component(username)));
+        let navigate = navigate.clone();
+        let petname = untrack(|| petnames::person(username));
+        async move {
+            match api::request::<()>("POST", &path, None).await {
+                Ok(resp) if resp.ok() => {
+                    if let Some(room) = resp.json::<DirectRoom>() {
+                        // The conversation goes by what we call them, unless
+                        // it was named already
+                        if let Some(petname) = petname.filter(|_| untrack(|| petnames::room(&room.name)).is_none()) {
+                            let _ = petnames::set(&Target::Room(room.name.clone()), &petname).await;
+                        }
+                        navigate(&format!("/chat/{}", room.name), Default::default());
+                    }
+                }
+                Ok(resp) => set_error.set(Some(resp.body)),
+                Err(err) => set_error.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    view! {
+        <details class="people">
+            <summary>"Message someone"</summary>
+            <input
+                type="search"
+                placeholder="Search people"
+                prop:value=query
+                on:input=move |ev| {
+                    set_error.set(None);
+                    set_query.set(event_target_value(&ev));
+                }
+            />
+            <ul class="people-list">
+                <For
+                    each=move || found.get()
+                    key=|user| user.username.clone()
+                    children=move |user| {
+                        let username = user.username.clone();
+                        let display_name = user.display_name.clone().unwrap_or_else(|| user.username.clone());
+                        let labelled = user.username.clone();
+                        view! {
+                            <li>
+                                <span class="name">{move || petnames::person_label(Some(&labelled), &display_name)}</span>
+                                <span class="username">{format!("@{}", user.username)}</span>
+                                <button type="button" on:click=move |_| message.dispatch(username.clone())>"Message"</button>
+                                <PetnameEditor target=Target::Person(user.username.clone())/>
+                            </li>
+                        }
+                    }
+                />
+            </ul>
+            {move || error.get().map(|e| view! { <p class="error">{e}</p> })}
+        </details>
+    }
+}
@@ -0,0 +1,172 @@
+use leptos::*;
+use serde_json::Value;
+
+use crate::account_settings;
+
+/// Petnames live in the synced settings as `petname:room:<name>` and
+/// `petname:person:<username>`, sealed like the rest, so neither the server
+/// nor the room or person named ever sees them.
+const ROOM_PREFIX: &str = "petname:room:";
+const PERSON_PREFIX: &str = "petname:person:";
+/// Enough to tell rooms and people apart; synced settings are small.
+const MAX_PETNAME_CHARS: usize = 40;
+
+/// What a petname is for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    Room(String),
+    /// By username; display names change and need not be unique
+    Person(String),
+}
+
+impl Target {
+    fn key(&self) -> String {
+        match self {
+            Target::Room(name) => format!("{}{}", ROOM_PREFIX, name),
+            Target::Person(username) => format!("{}{}", PERSON_PREFIX, username),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Target::Room(name) => format!("room {}", name),
+            Target::Person(username) => format!("@{}", username),
+        }
+    }
+}
+
+/// The user's own name for `target`, tracked.
+pub fn of(target: &Target) -> Option<String> {
+    account_settings::get(&target.key()).and_then(|value| value.as_str().map(str::to_string))
+}
+
+pub fn room(name: &str) -> Option<String> {
+    of(&Target::Room(name.to_string()))
+}
+
+pub fn person(username: &str) -> Option<String> {
+    of(&Target::Person(username.to_string()))
+}
+
+/// How to show a room: its petname if it has one, else its name.
+pub fn room_label(name: &str) -> String {
+    room(name).unwrap_or_else(|| name.to_string())
+}
+
+/// How to show someone: their petname if we know who they are and gave
+/// them one, else the name they chose.
+pub fn person_label(username: Option<&str>, display_name: &str) -> String {
+    username.and_then(person).unwrap_or_else(|| display_name.to_string())
+}
+
+/// Every room with a petname, as (name, petname), tracked.
+pub fn rooms() -> Vec<(String, String)> {
+    all()
+        .into_iter()
+        .filter_map(|(target, petname)| match target {
+            Target::Room(name) => Some((name, petname)),
+            Target::Person(_) => None,
+        })
+        .collect()
+}
+
+/// Every petname, rooms first, tracked.
+fn all() -> Vec<(Target, String)> {
+    named(ROOM_PREFIX, Target::Room).chain(named(PERSON_PREFIX, Target::Person)).collect()
+}
+
+fn named(prefix: &'static str, target: fn(String) -> Target) -> impl Iterator<Item = (Target, String)> {
+    account_settings::keys_with_prefix(prefix).into_iter().filter_map(move |(key, value)| {
+        let petname = value.as_str()?.to_string();
+        Some((target(key.strip_prefix(prefix)?.to_string()), petname))
+    })
+}
+
+/// Names `target` for this user; an empty `petname` removes the name.
+pub async fn set(target: &Target, petname: &str) -> Result<(), String> {
+    let petname = petname.trim();
+    if petname.chars().count() > MAX_PETNAME_CHARS {
+        return Err(format!("Names can be at most {} characters", MAX_PETNAME_CHARS));
+    }
+    let value = if petname.is_empty() { Value::Null } else { Value::from(petname) };
+    account_settings::set(&target.key(), value).await
+}
+
+/// A small form to give `target` a name only this user sees.
+#[component]
+pub fn PetnameEditor(target: Target) -> impl IntoView {
+    let current = {
+        let target = target.clone();
+        create_memo(move |_| of(&target).unwrap_or_default())
+    };
+    let (input, set_input) = create_signal(current.get_untracked());
+    let (notice, set_notice) = create_signal::<Option<String>>(None);
+    // Follows renames made elsewhere
+    create_effect(move |_| set_input.set(current.get()));
+    let describe = target.describe();
+    let save = create_action(move |petname: &String| {
+        let (target, petname) = (target.clone(), petname.clone());
+        async move {
+            set_notice.set(set(&target, &petname).await.err());
+        }
+    });
+
+    view! {
+        <form class="petname" on:submit=move |ev| {
+            ev.prevent_default();
+            save.dispatch(input.get_untracked());
+        }>
+            <input
+                type="text"
+                placeholder="Your name for them"
+                title=format!("Only you see what you call {}", describe)
+                maxlength=MAX_PETNAME_CHARS.to_string()
+                prop:value=input
+                on:input=move |ev| set_input.set(event_target_value(&ev))
+            />
+            <button type="submit">"Save name"</button>
+            {move || notice.get().map(|n| view! { <span class="notice">{n}</span> })}
+        </form>
+    }
+}
+
+/// Settings page section listing the names the user gave rooms and people.
+#[component]
+pub fn PetnameSettings() -> impl IntoView {
+    let (notice, set_notice) = create_signal::<Option<String>>(None);
+    let remove = create_action(move |target: &Target| {
+        let target = target.clone();
+        async move {
+            set_notice.set(set(&target, "").await.err());
+        }
+    });
+
+    view! {
+        <section>
+            <h3>"Your names for rooms and people"</h3>
+            <p class="hint">"Kept with your encrypted settings. The rooms and people you name never see them."</p>
+            {move || {
+                let named = all();
+                if named.is_empty() {
+                    return view! { <p>"Give a room a name from its chat page, or a person from the people search."</p> }.into_view();
+                }
+                view! {
+                    <ul class="petnames">
+                        {named.into_iter().map(|(target, petname)| {
+                            let describe = target.describe();
+                            view! {
+                                <li>
+                                    <strong>{petname}</strong>
+                                    " for "
+                                    {describe}
+                                    <button type="button" on:click=move |_| remove.dispatch(target.clone())>"Remove"</button>
+                                </li>
+                            }
+                        }).collect_view()}
+                    </ul>
+                }.into_view()
+            }}
+            {move || notice.get().map(|n| view! { <p class="notice">{n}</p> })}
+        </section>
+    }
+}
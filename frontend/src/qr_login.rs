@@ -0,0 +1,232 @@
+use leptos::*;
+use leptos_router::*;
+use qrcode::render::svg;
+use qrcode::QrCode;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+use crate::{account_settings, api, display, vault, LoginResponse};
+
+/// How often both devices check on a handoff.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+/// URL fragment carrying the synced-settings key, see [`vault::share`].
+const KEY_FRAGMENT: &str = "#k=";
+
+#[derive(Deserialize)]
+struct Started {
+    code: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+struct Device {
+    ip: Option<String>,
+    user_agent: Option<String>,
+}
+
+/// `GET /auth/qr/<code>`: `waiting`, `claimed` (with the device that
+/// scanned the code), `approved` or `denied`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+struct Status {
+    state: String,
+    device: Option<Device>,
+}
+
+#[derive(Deserialize)]
+struct Claimed {
+    claim: String,
+    username: String,
+}
+
+#[derive(Serialize)]
+struct Confirm {
+    approve: bool,
+}
+
+#[derive(Serialize)]
+struct Redeem<'a> {
+    claim: &'a str,
+}
+
+/// Runs `tick` every [`POLL_INTERVAL`] while the component is mounted.
+fn poll(tick: impl Fn() + 'static) {
+    if let Ok(handle) = set_interval_with_handle(tick, POLL_INTERVAL) {
+        on_cleanup(move || handle.clear());
+    }
+}
+
+fn qr_svg(text: &str) -> String {
+    QrCode::new(text.as_bytes())
+        .map(|qr| qr.render::<svg::Color>().min_dimensions(200, 200).build())
+        .unwrap_or_default()
+}
+
+/// The code in groups of four, for reading it out or typing it.
+fn spaced(code: &str) -> String {
+    code.as_bytes().chunks(4).map(|c| String::from_utf8_lossy(c)).collect::<Vec<_>>().join(" ")
+}
+
+/// Settings page section for signing in on another device without typing
+/// the password: the other device scans the QR code shown here, and this
+/// one confirms. The link also carries the synced-settings key, in the
+/// fragment the server never sees.
+#[component]
+pub fn ShareLogin() -> impl IntoView {
+    let (code, set_code) = create_signal::<Option<String>>(None);
+    let (status, set_status) = create_signal::<Option<Status>>(None);
+    let (notice, set_notice) = create_signal::<Option<String>>(None);
+
+    poll(move || {
+        let Some(current) = code.get_untracked() else { return };
+        spawn_local(async move {
+            match api::get(&format!("/auth/qr/{}", current)).await {
+                Ok(resp) if resp.ok() => set_status.set(resp.json()),
+                Ok(resp) if resp.status == 404 && code.get_untracked().as_ref() == Some(&current) => {
+                    set_code.set(None);
+                    set_notice.set(Some("The code expired. Show a new one to try again.".to_string()));
+                }
+                _ => {}
+            }
+        });
+    });
+
+    let on_start = create_action(move |()| async move {
+        set_notice.set(None);
+        set_status.set(None);
+        match api::post_json("/auth/qr/start", &()).await {
+            Ok(resp) if resp.ok() => set_code.set(resp.json::<Started>().map(|s| s.code)),
+            Ok(resp) => set_notice.set(Some(resp.body)),
+            Err(err) => set_notice.set(Some(err.to_string())),
+        }
+    });
+
+    let on_confirm = create_action(move |approve: &bool| {
+        let approve = *approve;
+        async move {
+            let Some(current) = code.get_untracked() else { return };
+            match api::post_json(&format!("/auth/qr/{}/confirm", current), &Confirm { approve }).await {
+                Ok(resp) if resp.ok() => {
+                    set_code.set(None);
+                    set_status.set(None);
+                    set_notice.set(Some(
+                        if approve { "The other device is signing in." } else { "Sign-in declined." }.to_string(),
+                    ));
+                }
+                Ok(resp) => set_notice.set(Some(resp.body)),
+                Err(err) => set_notice.set(Some(err.to_string())),
+            }
+        }
+    });
+
+    let link = move |code: &str| {
+        let origin = window().location().origin().unwrap_or_default();
+        let key = vault::share().map(|key| format!("{}{}", KEY_FRAGMENT, key)).unwrap_or_default();
+        format!("{}/login/qr/{}{}", origin, code, key)
+    };
+
+    view! {
+        <section class="share-login">
+            <h3>"Sign in on another device"</h3>
+            {move || match (code.get(), status.get()) {
+                (Some(_), Some(Status { state, device: Some(device) })) if state == "claimed" => view! {
+                    <div class="qr-confirm">
+                        <p>{format!(
+                            "Let {} at {} sign in as you?",
+                            device.user_agent.unwrap_or_else(|| "an unknown browser".to_string()),
+                            device.ip.unwrap_or_else(|| "an unknown address".to_string()),
+                        )}</p>
+                        <button type="button" on:click=move |_| on_confirm.dispatch(true)>"Allow"</button>
+                        <button type="button" on:click=move |_| on_confirm.dispatch(false)>"Deny"</button>
+                    </div>
+                }
+                .into_view(),
+                (Some(code), _) => view! {
+                    <div class="qr" inner_html=qr_svg(&link(&code))></div>
+                    <p>"Scan this with the other device's camera, or enter this code on its login page:"</p>
+                    <p class="qr-code">{spaced(&code)}</p>
+                    <p class="hint">"It works once, for two minutes. You confirm here before the other device gets in."</p>
+                }
+                .into_view(),
+                (None, _) => view! {
+                    <button type="button" on:click=move |_| on_start.dispatch(())>"Show a QR code"</button>
+                }
+                .into_view(),
+            }}
+            {move || notice.get().map(|n| view! { <p class="notice">{n}</p> })}
+        </section>
+    }
+}
+
+/// Where the QR code leads on the new device (`/login/qr/<code>`): claims
+/// the code, then waits for the signed-in device to confirm.
+#[component]
+pub fn QrLoginPage() -> impl IntoView {
+    let params = use_params_map();
+    let code = params.with_untracked(|p| p.get("code").map(|c| c.replace(' ', "")).unwrap_or_default());
+    let navigate = use_navigate();
+    let (message, set_message) = create_signal("Checking the code…".to_string());
+    let claim = create_rw_signal(None::<String>);
+    let done = create_rw_signal(false);
+
+    // Taken out of the address bar and history right away
+    let location = window().location();
+    let shared_key = location.hash().ok().and_then(|h| h.strip_prefix(KEY_FRAGMENT).map(str::to_string));
+    if shared_key.is_some() {
+        if let Ok(history) = window().history() {
+            let _ = history.replace_state_with_url(&JsValue::NULL, "", Some(&format!("/login/qr/{}", code)));
+        }
+    }
+
+    let claim_code = code.clone();
+    spawn_local(async move {
+        match api::post_json(&format!("/auth/qr/{}/claim", claim_code), &()).await {
+            Ok(resp) if resp.ok() => {
+                if let Some(Claimed { claim: secret, username }) = resp.json() {
+                    claim.set(Some(secret));
+                    set_message.set(format!("Confirm on the device you are signed in on to continue as {}.", username));
+                }
+            }
+            Ok(resp) => {
+                done.set(true);
+                set_message.set(resp.body);
+            }
+            Err(err) => {
+                done.set(true);
+                set_message.set(err.to_string());
+            }
+        }
+    });
+
+    poll(move || {
+        let Some(secret) = claim.get_untracked().filter(|_| !done.get_untracked()) else { return };
+        let (code, navigate, shared_key) = (code.clone(), navigate.clone(), shared_key.clone());
+        spawn_local(async move {
+            let Ok(resp) = api::post_json(&format!("/auth/qr/{}/redeem", code), &Redeem { claim: &secret }).await else {
+                return;
+            };
+            if resp.status == 202 || done.get_untracked() {
+                return;
+            }
+            done.set(true);
+            match resp.json::<LoginResponse>().filter(|_| resp.ok()) {
+                Some(LoginResponse { token }) => {
+                    api::store_token(&token);
+                    if let Some(key) = shared_key {
+                        vault::accept(&key);
+                    }
+                    display::load_and_apply();
+                    account_settings::load();
+                    navigate("/rooms", Default::default());
+                }
+                None => set_message.set(resp.body),
+            }
+        });
+    });
+
+    view! {
+        <div class="auth-form">
+            <h2>"Log in with a QR code"</h2>
+            <p>{message}</p>
+            <a href="/login">"Log in with your password instead"</a>
+        </div>
+    }
+}
@@ -0,0 +1,95 @@
+use leptos::*;
+use leptos_router::use_params_map;
+use serde::Deserialize;
+
+use crate::insights::{draw_bars, CHART_HEIGHT, CHART_WIDTH};
+use crate::{api, locale};
+
+/// One hour of a room's activity, as `GET /rooms/:name/stats` lists it.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+struct HourStats {
+    start: String,
+    joins: u32,
+    peak_peers: u32,
+    relays: u32,
+    relay_bytes: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+struct RoomStats {
+    peers: usize,
+    hours: Vec<HourStats>,
+}
+
+/// Every sixth hour is labelled, to fit under the bars.
+fn hour_labels(hours: &[HourStats]) -> Vec<String> {
+    hours
+        .iter()
+        .enumerate()
+        .map(|(i, hour)| if i % 6 == 0 { locale::time(&hour.start) } else { String::new() })
+        .collect()
+}
+
+/// The server's view of a room over the last day: joins, the most members
+/// at once and the traffic it relayed. Only the room's owner gets to see it.
+#[component]
+pub fn RoomStatsPage() -> impl IntoView {
+    let params = use_params_map();
+    let room = move || params.with(|p| p.get("name").cloned().unwrap_or_default());
+    let (stats, set_stats) = create_signal::<Option<RoomStats>>(None);
+    let (error, set_error) = create_signal::<Option<String>>(None);
+    let joins_ref = create_node_ref::<html::Canvas>();
+    let peers_ref = create_node_ref::<html::Canvas>();
+    let relays_ref = create_node_ref::<html::Canvas>();
+
+    create_effect(move |_| {
+        let path = format!("/rooms/{}/stats", String::from(js_sys::encode_uri_component(&room())));
+        spawn_local(async move {
+            match api::get(&path).await {
+                Ok(resp) if resp.ok() => set_stats.set(resp.json()),
+                Ok(resp) => set_error.set(Some(resp.body)),
+                Err(err) => set_error.set(Some(err.to_string())),
+            }
+        });
+    });
+
+    create_effect(move |_| {
+        let Some(stats) = stats.get() else { return };
+        let labels = hour_labels(&stats.hours);
+        for (canvas, values) in [
+            (joins_ref.get(), stats.hours.iter().map(|h| h.joins).collect::<Vec<_>>()),
+            (peers_ref.get(), stats.hours.iter().map(|h| h.peak_peers).collect()),
+            (relays_ref.get(), stats.hours.iter().map(|h| h.relays).collect()),
+        ] {
+            if let Some(canvas) = canvas {
+                draw_bars(&canvas, &labels, &values);
+            }
+        }
+    });
+
+    let total = move |field: fn(&HourStats) -> u64| stats.with(|s| s.as_ref().map_or(0, |s| s.hours.iter().map(field).sum()));
+    let peak = move || stats.with(|s| s.as_ref().and_then(|s| s.hours.iter().map(|h| h.peak_peers).max()).unwrap_or(0));
+
+    view! {
+        <div class="room-stats">
+            <h2>{move || format!("{} · last 24 hours", room())}</h2>
+            {move || error.get().map(|e| view! { <p class="error">{e}</p> })}
+            <Show when=move || stats.with(Option::is_some)>
+                <ul class="insights-summary">
+                    <li>"In the room now: " {move || stats.with(|s| s.as_ref().map_or(0, |s| s.peers))}</li>
+                    <li>"Joins: " {move || locale::number(total(|h| h.joins.into()) as f64, 0)}</li>
+                    <li>"Most at once: " {peak}</li>
+                    <li>"Messages relayed: " {move || locale::number(total(|h| h.relays.into()) as f64, 0)}</li>
+                    <li>"Relayed: " {move || format!("{} KB", locale::number(total(|h| h.relay_bytes) as f64 / 1024.0, 1))}</li>
+                </ul>
+            </Show>
+            <h4>"Joins per hour"</h4>
+            <canvas node_ref=joins_ref width=CHART_WIDTH height=CHART_HEIGHT/>
+            <h4>"Most members at once"</h4>
+            <canvas node_ref=peers_ref width=CHART_WIDTH height=CHART_HEIGHT/>
+            <h4>"Signaling messages relayed"</h4>
+            <canvas node_ref=relays_ref width=CHART_WIDTH height=CHART_HEIGHT/>
+            <a href=move || format!("/chat/{}", room())>"Back to the room"</a>
+        </div>
+    }
+}
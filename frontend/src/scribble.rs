@@ -0,0 +1,145 @@
+use leptos::*;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsCast;
+
+/// Side length of the drawing surface; coordinates fit in a `u8`.
+const SIZE: u32 = 256;
+/// Caps the payload at a few KB of JSON regardless of how long the user draws.
+const MAX_POINTS: usize = 1500;
+
+/// A doodle as a list of strokes, each a flat `[x0, y0, x1, y1, ...]` list on
+/// a 256x256 grid. Much smaller than sending the canvas as an image.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scribble {
+    pub strokes: Vec<Vec<u8>>,
+}
+
+impl Scribble {
+    fn point_count(&self) -> usize {
+        self.strokes.iter().map(|s| s.len() / 2).sum()
+    }
+}
+
+fn context(canvas: &HtmlElement<html::Canvas>) -> Option<web_sys::CanvasRenderingContext2d> {
+    canvas.get_context("2d").ok()??.dyn_into().ok()
+}
+
+fn point(ev: &web_sys::PointerEvent) -> (u8, u8) {
+    let clamp = |v: i32| v.clamp(0, SIZE as i32 - 1) as u8;
+    (clamp(ev.offset_x()), clamp(ev.offset_y()))
+}
+
+/// Popover with a small canvas for drawing a quick reply.
+#[component]
+pub fn ScribblePad(
+    #[prop(into)] on_send: Callback<Scribble>,
+    #[prop(into)] on_close: Callback<()>,
+) -> impl IntoView {
+    let canvas_ref = create_node_ref::<html::Canvas>();
+    let (scribble, set_scribble) = create_signal(Scribble::default());
+    let (drawing, set_drawing) = create_signal(false);
+
+    let on_pointer_down = move |ev: web_sys::PointerEvent| {
+        let Some(canvas) = canvas_ref.get() else { return };
+        if scribble.with(Scribble::point_count) >= MAX_POINTS {
+            return;
+        }
+        let _ = canvas.set_pointer_capture(ev.pointer_id());
+        let (x, y) = point(&ev);
+        if let Some(ctx) = context(&canvas) {
+            ctx.set_line_width(3.0);
+            ctx.set_line_cap("round");
+            ctx.set_line_join("round");
+            ctx.begin_path();
+            ctx.move_to(x as f64, y as f64);
+            ctx.line_to(x as f64, y as f64);
+            ctx.stroke();
+        }
+        set_scribble.update(|s| s.strokes.push(vec![x, y]));
+        set_drawing.set(true);
+    };
+
+    let on_pointer_move = move |ev: web_sys::PointerEvent| {
+        if !drawing.get() || scribble.with(Scribble::point_count) >= MAX_POINTS {
+            return;
+        }
+        let (x, y) = point(&ev);
+        if let Some(ctx) = canvas_ref.get().and_then(|c| context(&c)) {
+            ctx.line_to(x as f64, y as f64);
+            ctx.stroke();
+        }
+        set_scribble.update(|s| {
+            if let Some(stroke) = s.strokes.last_mut() {
+                stroke.extend([x, y]);
+            }
+        });
+    };
+
+    let clear = move |_| {
+        if let Some(ctx) = canvas_ref.get().and_then(|c| context(&c)) {
+            ctx.clear_rect(0.0, 0.0, SIZE as f64, SIZE as f64);
+        }
+        set_scribble.set(Scribble::default());
+    };
+
+    let send = move |_| {
+        let drawn = scribble.get();
+        if !drawn.strokes.is_empty() {
+            on_send.call(drawn);
+        }
+    };
+
+    view! {
+        <div class="scribble-pad">
+            <canvas
+                node_ref=canvas_ref
+                width=SIZE
+                height=SIZE
+                on:pointerdown=on_pointer_down
+                on:pointermove=on_pointer_move
+                on:pointerup=move |_| set_drawing.set(false)
+                on:pointercancel=move |_| set_drawing.set(false)
+            />
+            <div class="scribble-actions">
+                <button type="button" on:click=clear>"Clear"</button>
+                <button type="button" on:click=move |_| on_close.call(())>"Cancel"</button>
+                <button type="button" on:click=send>"Send"</button>
+            </div>
+        </div>
+    }
+}
+
+/// Renders a received or sent scribble inline as SVG.
+#[component]
+pub fn ScribbleView(scribble: Scribble) -> impl IntoView {
+    let polylines = scribble
+        .strokes
+        .into_iter()
+        .map(|stroke| {
+            let mut points: Vec<String> = stroke
+                .chunks_exact(2)
+                .map(|p| format!("{},{}", p[0], p[1]))
+                .collect();
+            // A single tap still needs two points to render as a dot
+            if points.len() == 1 {
+                points.push(points[0].clone());
+            }
+            view! {
+                <polyline
+                    points=points.join(" ")
+                    fill="none"
+                    stroke="currentColor"
+                    stroke-width="3"
+                    stroke-linecap="round"
+                    stroke-linejoin="round"
+                />
+            }
+        })
+        .collect_view();
+
+    view! {
+        <svg class="scribble" viewBox=format!("0 0 {} {}", SIZE, SIZE) width="128" height="128">
+            {polylines}
+        </svg>
+    }
+}
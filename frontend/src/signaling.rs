@@ -0,0 +1,403 @@
+use std::cell::Cell;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+use web_sys::WebSocket;
+
+use crate::ice::IceServer;
+
+/// Signaling protocol version we speak, announced in [`SignalingMessage::Hello`].
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Optional protocol features this client understands.
+pub const CAPABILITIES: &[&str] = &["broadcast", "knock", "lobby", "mesh", "presence", "protected_rooms", "resume", "time_sync"];
+/// Binary encodings we speak besides JSON, offered in `Hello`.
+pub const ENCODINGS: &[&str] = &[MSGPACK];
+const MSGPACK: &str = "msgpack";
+/// Close code for a connection whose session was revoked or whose account
+/// was disabled; the close reason says why.
+pub const CLOSE_SIGNED_OUT: u16 = 4001;
+/// Close code the server uses when our protocol version is too old.
+pub const CLOSE_UNSUPPORTED_VERSION: u16 = 4002;
+/// Close code for a connection a moderator kicked or banned from a room;
+/// the close reason says which.
+pub const CLOSE_REMOVED: u16 = 4003;
+/// Close code of a server shutting down, after its `server_shutdown` notice.
+pub const CLOSE_SERVER_RESTART: u16 = 1012;
+
+/// A room member as listed in [`SignalingMessage::Peers`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PeerInfo {
+    pub id: String,
+    pub name: String,
+    /// What petnames are kept by; older servers do not send it
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Their connection dropped; the server holds their place for a while
+    #[serde(default)]
+    pub away: bool,
+}
+
+/// A letter someone left for us while we were away, as
+/// [`SignalingMessage::Mail`] delivers it. The payload was encrypted for us
+/// by its sender; the server cannot read it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Letter {
+    pub id: String,
+    pub from: String,
+    pub payload: String,
+    pub sent_at: String,
+    pub expires_at: String,
+}
+
+/// One of our letters that reached its recipient.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub id: String,
+    pub to: String,
+}
+
+/// Messages exchanged with the signaling server over the WebSocket. Requests
+/// we send are tagged in PascalCase; events the server pushes are lowercase.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum SignalingMessage {
+    /// First message on every connection
+    Hello { version: u32, capabilities: Vec<String>, encodings: Vec<String> },
+    /// Clock probe; `sent` is our local time in milliseconds
+    TimeSync { sent: f64 },
+    /// Password-protected rooms also need the `password` or an `invite`;
+    /// `resume` gets back the place a dropped connection had in the room
+    JoinRoom {
+        room: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        invite: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        resume: Option<String>,
+    },
+    /// Leave the room but keep the connection, e.g. to join another one
+    LeaveRoom { room: String },
+    /// Negotiation with one member: we address it with `to`, the server
+    /// fills in `from` on the way in
+    Offer {
+        room: String,
+        sdp: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to: Option<String>,
+        #[serde(default, skip_serializing)]
+        from: Option<String>,
+    },
+    Answer {
+        room: String,
+        sdp: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to: Option<String>,
+        #[serde(default, skip_serializing)]
+        from: Option<String>,
+    },
+    IceCandidate {
+        room: String,
+        candidate: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to: Option<String>,
+        #[serde(default, skip_serializing)]
+        from: Option<String>,
+    },
+    /// A chat payload sent through the server because the peer connection
+    /// never came up; addressed and stamped like negotiation messages
+    ChatRelay {
+        room: String,
+        payload: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to: Option<String>,
+        #[serde(default, skip_serializing)]
+        from: Option<String>,
+    },
+    AnswerKnock { room: String, id: String, admit: bool },
+    LockRoom { room: String, locked: bool },
+    /// Moderators only: set the room's topic, or clear it with an empty one
+    SetRoomTopic { room: String, topic: String },
+    /// Moderators only: remove a member, who may come back
+    Kick { room: String, peer_id: String },
+    /// Moderators only: keep a user out for `duration` seconds, or for good
+    Ban {
+        room: String,
+        username: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        duration: Option<u64>,
+    },
+    /// Letters from a `mail` event that we took
+    MailAck { ids: Vec<String> },
+
+    /// The server's answer to `Hello`: the version both sides speak and the
+    /// capabilities both support, plus the binary `encoding` both sides
+    /// switch to, if any
+    #[serde(rename = "welcome")]
+    Welcome {
+        version: u32,
+        capabilities: Vec<String>,
+        #[serde(default)]
+        encoding: Option<String>,
+    },
+    /// Answer to `TimeSync`: our `sent` echoed back and the server's clock
+    #[serde(rename = "time")]
+    Time { sent: f64, server: f64 },
+    /// Our `LeaveRoom` went through
+    #[serde(rename = "left")]
+    Left { room: String },
+    /// Everyone in the room, oldest member first, sent whenever someone
+    /// enters or leaves; `you` is our own peer id
+    #[serde(rename = "peers")]
+    Peers {
+        room: String,
+        you: String,
+        peers: Vec<PeerInfo>,
+        /// The room's own STUN/TURN servers, to use instead of ours
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        ice_servers: Vec<IceServer>,
+        /// Broadcast rooms only: the broadcaster's peer id while they are
+        /// in, and how many others watch
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        broadcaster: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        viewers: Option<usize>,
+        /// Messages are kept for email digests, see `POST /rooms/:name/history`
+        #[serde(default)]
+        digests: bool,
+        /// Sent with `JoinRoom` from a new connection to get our place back
+        /// if this one drops
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resume_token: Option<String>,
+    },
+    /// Someone entered the room; sent to everyone already inside
+    #[serde(rename = "peer_joined")]
+    PeerJoined {
+        room: String,
+        id: String,
+        name: String,
+        #[serde(default)]
+        username: Option<String>,
+    },
+    /// Someone left the room or their connection dropped
+    #[serde(rename = "peer_left")]
+    PeerLeft { room: String, id: String, name: String },
+    /// Someone's connection dropped; their place is held for `grace`
+    /// seconds, after which they leave unless they came back
+    #[serde(rename = "peer_away")]
+    PeerAway { room: String, id: String, name: String, grace: u64 },
+    /// Someone whose connection dropped is back with a new peer id
+    #[serde(rename = "peer_resumed")]
+    PeerResumed { room: String, id: String, previous: String, name: String },
+    #[serde(rename = "error")]
+    Error { message: String },
+    /// Our account settings were saved, maybe from another device
+    #[serde(rename = "settings_changed")]
+    SettingsChanged { version: u64, settings: serde_json::Map<String, serde_json::Value> },
+    /// The server is shutting down and will close the connection shortly;
+    /// reconnect after this many seconds
+    #[serde(rename = "server_shutdown")]
+    ServerShutdown { reconnect_after: u64 },
+    /// The room is at its member limit, so we are queued instead
+    #[serde(rename = "room_full")]
+    RoomFull { room: String, limit: usize },
+    /// We knocked and are this far back in the queue
+    #[serde(rename = "waiting")]
+    Waiting { room: String, position: usize },
+    /// The room is protected; `rejected` if the password or invite we sent
+    /// did not work
+    #[serde(rename = "password_required")]
+    PasswordRequired { room: String, rejected: bool },
+    /// A moderator took us out of the room; the connection stays up
+    #[serde(rename = "kicked")]
+    Kicked { room: String },
+    /// We are banned from the room, until the given time if not for good
+    #[serde(rename = "banned")]
+    Banned { room: String, until: Option<String> },
+    #[serde(rename = "admitted")]
+    Admitted { room: String },
+    #[serde(rename = "knock_denied")]
+    KnockDenied { room: String },
+    /// Someone is asking to be let into our room
+    #[serde(rename = "knock")]
+    Knock { room: String, id: String, name: String },
+    /// Someone in the lobby of our room asks us, its owner, to let them in
+    #[serde(rename = "admit_request")]
+    AdmitRequest { room: String, id: String, name: String },
+    /// A knock was answered by the moderator or withdrawn
+    #[serde(rename = "knock_resolved")]
+    KnockResolved { room: String, id: String, admitted: bool },
+    #[serde(rename = "room_locked")]
+    RoomLocked { room: String, locked: bool },
+    /// The topic changed, or the current one for us as we come in; `by` is
+    /// who changed it
+    #[serde(rename = "room_updated")]
+    RoomUpdated {
+        room: String,
+        topic: String,
+        #[serde(default)]
+        by: Option<String>,
+    },
+    /// Who moderates the room; `temporary` while standing in for the owner
+    #[serde(rename = "moderator")]
+    Moderator { room: String, name: String, temporary: bool, you: bool },
+    /// Letters waiting for us, on connect or as they arrive; they come
+    /// again until we send `MailAck`
+    #[serde(rename = "mail")]
+    Mail { letters: Vec<Letter> },
+    /// Letters we left reached their recipients
+    #[serde(rename = "mail_delivered")]
+    MailDelivered { receipts: Vec<Receipt> },
+    /// Downtime is scheduled for `duration` seconds from `starts_at`, on
+    /// connect or when an admin schedules it; joining rooms is refused from
+    /// `joins_blocked_from`, if set
+    #[serde(rename = "maintenance_notice")]
+    MaintenanceNotice {
+        starts_at: String,
+        duration: u64,
+        #[serde(default)]
+        joins_blocked_from: Option<String>,
+        #[serde(default)]
+        message: Option<String>,
+    },
+    /// The scheduled maintenance was called off
+    #[serde(rename = "maintenance_cancelled")]
+    MaintenanceCancelled,
+}
+
+impl SignalingMessage {
+    /// The room a message is about, for those that are about one.
+    pub fn room(&self) -> Option<&str> {
+        match self {
+            SignalingMessage::Hello { .. }
+            | SignalingMessage::TimeSync { .. }
+            | SignalingMessage::Welcome { .. }
+            | SignalingMessage::Time { .. }
+            | SignalingMessage::SettingsChanged { .. }
+            | SignalingMessage::ServerShutdown { .. }
+            | SignalingMessage::MailAck { .. }
+            | SignalingMessage::Mail { .. }
+            | SignalingMessage::MailDelivered { .. }
+            | SignalingMessage::MaintenanceNotice { .. }
+            | SignalingMessage::MaintenanceCancelled
+            | SignalingMessage::Error { .. } => None,
+            SignalingMessage::JoinRoom { room, .. }
+            | SignalingMessage::LeaveRoom { room }
+            | SignalingMessage::Offer { room, .. }
+            | SignalingMessage::Answer { room, .. }
+            | SignalingMessage::IceCandidate { room, .. }
+            | SignalingMessage::ChatRelay { room, .. }
+            | SignalingMessage::AnswerKnock { room, .. }
+            | SignalingMessage::LockRoom { room, .. }
+            | SignalingMessage::SetRoomTopic { room, .. }
+            | SignalingMessage::Kick { room, .. }
+            | SignalingMessage::Ban { room, .. }
+            | SignalingMessage::Left { room }
+            | SignalingMessage::Peers { room, .. }
+            | SignalingMessage::PeerJoined { room, .. }
+            | SignalingMessage::PeerLeft { room, .. }
+            | SignalingMessage::PeerAway { room, .. }
+            | SignalingMessage::PeerResumed { room, .. }
+            | SignalingMessage::RoomFull { room, .. }
+            | SignalingMessage::Waiting { room, .. }
+            | SignalingMessage::PasswordRequired { room, .. }
+            | SignalingMessage::Kicked { room }
+            | SignalingMessage::Banned { room, .. }
+            | SignalingMessage::Admitted { room }
+            | SignalingMessage::KnockDenied { room }
+            | SignalingMessage::Knock { room, .. }
+            | SignalingMessage::AdmitRequest { room, .. }
+            | SignalingMessage::KnockResolved { room, .. }
+            | SignalingMessage::RoomLocked { room, .. }
+            | SignalingMessage::RoomUpdated { room, .. }
+            | SignalingMessage::Moderator { room, .. } => Some(room),
+        }
+    }
+}
+
+thread_local! {
+    /// Whether the current connection negotiated MessagePack
+    static BINARY: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Switches the encoding of what we send; `None` is JSON, which every
+/// connection starts with.
+pub fn use_encoding(encoding: Option<&str>) {
+    BINARY.with(|b| b.set(encoding == Some(MSGPACK)));
+}
+
+/// Sends `msg` in the connection's encoding; `false` if it could not go out.
+pub fn send(ws: &WebSocket, msg: &SignalingMessage) -> bool {
+    if BINARY.with(Cell::get) {
+        rmp_serde::to_vec_named(msg).is_ok_and(|bytes| ws.send_with_u8_array(&bytes).is_ok())
+    } else {
+        serde_json::to_string(msg).is_ok_and(|json| ws.send_with_str(&json).is_ok())
+    }
+}
+
+/// Reads a frame in either encoding: the server may switch to binary as
+/// early as its `welcome`.
+pub fn decode(data: &JsValue) -> Option<SignalingMessage> {
+    match data.as_string() {
+        Some(text) => serde_json::from_str(&text).ok(),
+        None => rmp_serde::from_slice(&js_sys::Uint8Array::new(data).to_vec()).ok(),
+    }
+}
+
+/// Checks our side of the protocol against the golden fixtures the backend
+/// checks too. Run with `wasm-pack test --node`.
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    const REQUESTS: &str = include_str!("../../protocol/fixtures/v1/requests.json");
+    const EVENTS: &str = include_str!("../../protocol/fixtures/v1/events.json");
+    const REQUESTS_MSGPACK: &str = include_str!("../../protocol/fixtures/v1/requests.msgpack.hex");
+    const EVENTS_MSGPACK: &str = include_str!("../../protocol/fixtures/v1/events.msgpack.hex");
+
+    fn frames(json: &str) -> Vec<Value> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn decode_text(frame: &Value) -> SignalingMessage {
+        decode(&JsValue::from_str(&frame.to_string())).unwrap_or_else(|| panic!("{} does not decode", frame))
+    }
+
+    /// What we send must be exactly what the fixtures say.
+    #[wasm_bindgen_test]
+    fn requests_round_trip() {
+        for frame in frames(REQUESTS) {
+            let msg = decode_text(&frame);
+            assert_eq!(serde_json::to_value(&msg).unwrap(), frame);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn every_event_decodes() {
+        for frame in frames(EVENTS) {
+            decode_text(&frame);
+        }
+    }
+
+    /// Binary frames carry the same messages as their JSON twins.
+    #[wasm_bindgen_test]
+    fn binary_frames_decode() {
+        for (json, hex) in [(REQUESTS, REQUESTS_MSGPACK), (EVENTS, EVENTS_MSGPACK)] {
+            let frames = frames(json);
+            let golden: Vec<&str> = hex.lines().collect();
+            assert_eq!(frames.len(), golden.len());
+            for (frame, hex) in frames.iter().zip(golden) {
+                let bytes = js_sys::Uint8Array::from(from_hex(hex).as_slice());
+                let binary = decode(&bytes.buffer().into()).unwrap_or_else(|| panic!("binary {} does not decode", frame));
+                assert_eq!(format!("{:?}", binary), format!("{:?}", decode_text(frame)));
+            }
+        }
+    }
+}
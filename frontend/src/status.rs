@@ -0,0 +1,271 @@
+use leptos::*;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SignalingState {
+    #[default]
+    Connecting,
+    Connected,
+    /// The socket dropped and the page is waiting to rejoin
+    Reconnecting,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PeerState {
+    /// No peer in the room yet
+    #[default]
+    Waiting,
+    Gathering,
+    Connecting,
+    Connected,
+    /// Connected, but only through a TURN relay
+    RelayFallback,
+    /// No peer connection; chat goes through the signaling server
+    ServerRelay,
+    /// The data channel looks open but the peer stopped answering pings,
+    /// e.g. because it lost power or its network
+    Unreachable,
+    Disconnected,
+    Failed,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum E2eState {
+    #[default]
+    Unverified,
+    Verified,
+}
+
+/// Signaling, peer transport and end-to-end verification tracked separately,
+/// since any one of them can be healthy while another is not.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionStatus {
+    pub signaling: SignalingState,
+    pub peer: PeerState,
+    pub e2e: E2eState,
+}
+
+impl SignalingState {
+    fn label(self) -> &'static str {
+        match self {
+            SignalingState::Connecting => "Connecting",
+            SignalingState::Connected => "Connected",
+            SignalingState::Reconnecting => "Reconnecting",
+        }
+    }
+}
+
+impl PeerState {
+    fn label(self) -> &'static str {
+        match self {
+            PeerState::Waiting => "Waiting for peer",
+            PeerState::Gathering => "Gathering candidates",
+            PeerState::Connecting => "Connecting",
+            PeerState::Connected => "Connected (direct)",
+            PeerState::RelayFallback => "Connected (via relay)",
+            PeerState::ServerRelay => "Relayed through the server (not end-to-end encrypted)",
+            PeerState::Unreachable => "Peer unreachable",
+            PeerState::Disconnected => "Disconnected",
+            PeerState::Failed => "Failed",
+        }
+    }
+
+    /// Maps `RTCPeerConnection.connectionState` onto the peer layer.
+    pub fn from_connection_state(state: web_sys::RtcPeerConnectionState) -> Self {
+        use web_sys::RtcPeerConnectionState as S;
+        match state {
+            S::New => PeerState::Waiting,
+            S::Connecting => PeerState::Connecting,
+            S::Connected => PeerState::Connected,
+            S::Disconnected | S::Closed => PeerState::Disconnected,
+            S::Failed => PeerState::Failed,
+            _ => PeerState::Connecting,
+        }
+    }
+}
+
+impl E2eState {
+    fn label(self) -> &'static str {
+        match self {
+            E2eState::Unverified => "Encrypted, not verified",
+            E2eState::Verified => "Verified",
+        }
+    }
+}
+
+impl ConnectionStatus {
+    /// One-word summary and CSS level for the compact indicator; the worst
+    /// layer wins.
+    fn summary(&self) -> (&'static str, &'static str) {
+        match (self.signaling, self.peer) {
+            (_, PeerState::Failed) => ("Failed", "error"),
+            (SignalingState::Reconnecting, _) => ("Reconnecting", "warning"),
+            (_, PeerState::Unreachable) => ("Peer unreachable", "warning"),
+            (_, PeerState::Connected) => ("Connected", "ok"),
+            (_, PeerState::RelayFallback) => ("Relayed", "ok"),
+            (_, PeerState::ServerRelay) => ("Server relay", "warning"),
+            (_, PeerState::Disconnected) => ("Disconnected", "warning"),
+            (SignalingState::Connecting, _) => ("Connecting", "pending"),
+            _ => ("Waiting", "pending"),
+        }
+    }
+}
+
+impl ConnectionStatus {
+    /// Plain-text breakdown of every layer plus the browser, for attaching to
+    /// a feedback report.
+    pub fn diagnostics(&self) -> String {
+        let browser = web_sys::window()
+            .and_then(|w| w.navigator().user_agent().ok())
+            .unwrap_or_default();
+        format!(
+            "signaling: {}\npeer: {}\nend-to-end: {}\nbrowser: {}",
+            self.signaling.label(),
+            self.peer.label(),
+            self.e2e.label(),
+            browser
+        )
+    }
+}
+
+/// One end of the selected ICE candidate pair.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    /// `host`, `srflx`, `prflx` or `relay`
+    pub kind: String,
+    /// Browsers hide host addresses behind `.local` names unless the page
+    /// may use the camera or microphone
+    pub address: Option<String>,
+    /// STUN or TURN server the candidate came from; only known for ours
+    pub url: Option<String>,
+}
+
+/// The path the data channel takes, as the selected candidate pair says.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    pub local: Candidate,
+    pub remote: Candidate,
+}
+
+impl Route {
+    fn path(&self) -> &'static str {
+        match (self.local.kind.as_str(), self.remote.kind.as_str()) {
+            ("relay", _) => "Through your TURN relay",
+            (_, "relay") => "Through your peer's TURN relay",
+            ("host", "host") => "Direct, on your local network",
+            _ => "Direct, over the internet",
+        }
+    }
+
+    /// What each side, and any relay in between, learns about the other.
+    fn privacy(&self) -> String {
+        match (self.local.kind.as_str(), self.remote.kind.as_str()) {
+            ("relay", _) => format!(
+                "Your peer sees the relay's address instead of yours. Whoever runs {} sees both your addresses and \
+                 how much you send, but not what: the data channel is encrypted end to end.",
+                self.local.url.as_deref().unwrap_or("the relay"),
+            ),
+            (_, "relay") => "You see the relay's address instead of your peer's. Whoever runs it sees your address \
+                 and how much you send, but not what: the data channel is encrypted end to end."
+                .to_string(),
+            ("host", "host") => "Your peer can see your address on the local network. Nothing leaves it.".to_string(),
+            _ => "Your peer can see your public IP address, which tells roughly where you are, and you can see \
+                 theirs. Only a TURN relay would hide it."
+                .to_string(),
+        }
+    }
+}
+
+fn address(candidate: &Candidate) -> String {
+    match candidate.address.as_deref() {
+        Some(address) if address.ends_with(".local") => format!("{} (hidden by the browser)", address),
+        Some(address) => address.to_string(),
+        None => "Unknown".to_string(),
+    }
+}
+
+/// Both ends of the currently selected ICE candidate pair, read from
+/// `getStats()`.
+pub async fn selected_route(pc: &web_sys::RtcPeerConnection) -> Option<Route> {
+    let report = JsFuture::from(pc.get_stats()).await.ok()?;
+    let get = |obj: &JsValue, key: &str| js_sys::Reflect::get(obj, &JsValue::from_str(key)).ok();
+    let text = |obj: &JsValue, key: &str| get(obj, key).and_then(|v| v.as_string());
+    let stats: Vec<JsValue> = js_sys::try_iter(&report)
+        .ok()??
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.dyn_into::<js_sys::Array>().ok().map(|pair| pair.get(1)))
+        .collect();
+
+    let pair = stats.iter().find(|s| {
+        text(s, "type").as_deref() == Some("candidate-pair")
+            && (get(s, "selected").and_then(|v| v.as_bool()) == Some(true)
+                || (get(s, "nominated").and_then(|v| v.as_bool()) == Some(true)
+                    && text(s, "state").as_deref() == Some("succeeded")))
+    })?;
+    let candidate = |id_key: &str| {
+        let id = text(pair, id_key)?;
+        let stat = stats.iter().find(|s| text(s, "id").as_deref() == Some(id.as_str()))?;
+        Some(Candidate {
+            kind: text(stat, "candidateType")?,
+            // Older browsers call it `ip`
+            address: text(stat, "address").or_else(|| text(stat, "ip")),
+            url: text(stat, "url"),
+        })
+    };
+    Some(Route { local: candidate("localCandidateId")?, remote: candidate("remoteCandidateId")? })
+}
+
+/// Which way chat travels and what that reveals to whom.
+#[component]
+pub fn RoutePanel(#[prop(into)] route: Signal<Option<Route>>) -> impl IntoView {
+    move || {
+        route.get().map(|route| {
+            view! {
+                <details class="route-panel">
+                    <summary>{route.path()}</summary>
+                    <dl>
+                        <dt>"Your address, as your peer sees it"</dt>
+                        <dd>{address(&route.local)}</dd>
+                        <dt>"Your peer's address"</dt>
+                        <dd>{address(&route.remote)}</dd>
+                        {(route.local.kind == "relay").then(|| view! {
+                            <dt>"TURN relay"</dt>
+                            <dd>{route.local.url.clone().unwrap_or_else(|| "Unknown".to_string())}</dd>
+                        })}
+                    </dl>
+                    <p class="hint">{route.privacy()}</p>
+                </details>
+            }
+        })
+    }
+}
+
+/// Compact status dot with an expandable per-layer breakdown.
+#[component]
+pub fn StatusIndicator(#[prop(into)] status: Signal<ConnectionStatus>) -> impl IntoView {
+    let (expanded, set_expanded) = create_signal(false);
+
+    view! {
+        <div class="status">
+            <button
+                type="button"
+                class=move || format!("status-indicator {}", status.with(|s| s.summary().1))
+                on:click=move |_| set_expanded.update(|open| *open = !*open)
+            >
+                <span class="status-dot"></span>
+                {move || status.with(|s| s.summary().0)}
+            </button>
+            <Show when=move || expanded.get()>
+                <dl class="status-details">
+                    <dt>"Signaling"</dt>
+                    <dd>{move || status.with(|s| s.signaling.label())}</dd>
+                    <dt>"Peer"</dt>
+                    <dd>{move || status.with(|s| s.peer.label())}</dd>
+                    <dt>"End-to-end"</dt>
+                    <dd>{move || status.with(|s| s.e2e.label())}</dd>
+                </dl>
+            </Show>
+        </div>
+    }
+}
@@ -0,0 +1,123 @@
+use leptos::*;
+use serde::{Deserialize, Serialize};
+
+use crate::api;
+
+/// One stop of the tour: the element to spotlight and what to say about it.
+pub struct TourStep {
+    /// CSS selector of the element; steps whose element is not on the page
+    /// are shown in the middle of the screen instead
+    pub target: &'static str,
+    pub title: &'static str,
+    pub body: &'static str,
+}
+
+/// The first-login tour of the lobby, in order.
+pub const STEPS: &[TourStep] = &[
+    TourStep {
+        target: ".create-room",
+        title: "Create a room",
+        body: "Rooms have to be created before anyone can join. You own the rooms you create and moderate them.",
+    },
+    TourStep {
+        target: ".lobby > form",
+        title: "Join by name",
+        body: "Type the name of a room to join it. If it is full or locked you knock and wait to be let in.",
+    },
+    TourStep {
+        target: ".directory-filters",
+        title: "Find rooms",
+        body: "Search the directory by name, description or tag, or narrow it down by category.",
+    },
+    TourStep {
+        target: ".trending",
+        title: "Popular now",
+        body: "The rooms with the most activity in the last hour.",
+    },
+    TourStep {
+        target: "nav a[href='/settings']",
+        title: "Settings",
+        body: "Manage your profile, display settings, signed-in devices and API keys for bots.",
+    },
+    TourStep {
+        target: "",
+        title: "Quick switcher",
+        body: "Press Ctrl+K (Cmd+K on macOS) anywhere to jump to a favorite or recent room.",
+    },
+];
+
+#[derive(Serialize, Deserialize)]
+struct Onboarding {
+    completed: bool,
+}
+
+async fn set_completed(completed: bool) {
+    let _ = api::put_json("/account/onboarding", &Onboarding { completed }).await;
+}
+
+/// Shows the tour again on the next visit to the lobby, on any device.
+pub fn restart() {
+    spawn_local(set_completed(false));
+}
+
+/// Where the spotlight goes for `step`, as an inline style, if its element
+/// is on the page.
+fn spotlight_style(step: &TourStep) -> Option<String> {
+    if step.target.is_empty() {
+        return None;
+    }
+    let element = document().query_selector(step.target).ok().flatten()?;
+    let rect = element.get_bounding_client_rect();
+    Some(format!(
+        "top: {}px; left: {}px; width: {}px; height: {}px;",
+        rect.top() - 4.0,
+        rect.left() - 4.0,
+        rect.width() + 8.0,
+        rect.height() + 8.0
+    ))
+}
+
+/// Spotlight overlay walking a new user through [`STEPS`]. It only starts
+/// if the server says the user has not finished or skipped it yet.
+#[component]
+pub fn OnboardingTour() -> impl IntoView {
+    let (step, set_step) = create_signal::<Option<usize>>(None);
+    spawn_local(async move {
+        let pending = match api::get("/account/onboarding").await {
+            Ok(resp) => resp.json::<Onboarding>().is_some_and(|o| !o.completed),
+            Err(_) => false,
+        };
+        if pending {
+            set_step.set(Some(0));
+        }
+    });
+
+    let finish = move || {
+        set_step.set(None);
+        spawn_local(set_completed(true));
+    };
+    let next = move |_| match step.get_untracked() {
+        Some(i) if i + 1 < STEPS.len() => set_step.set(Some(i + 1)),
+        _ => finish(),
+    };
+
+    view! {
+        {move || step.get().map(|i| {
+            let current = &STEPS[i];
+            let spotlight = spotlight_style(current);
+            let last = i + 1 == STEPS.len();
+            view! {
+                <div class="tour-overlay" class:centered=spotlight.is_none()>
+                    {spotlight.map(|style| view! { <div class="tour-spotlight" style=style></div> })}
+                    <div class="tour-popover" role="dialog" aria-label=current.title>
+                        <h3>{current.title}</h3>
+                        <p>{current.body}</p>
+                        <span class="tour-progress">{format!("{} of {}", i + 1, STEPS.len())}</span>
+                        <button type="button" on:click=move |_| finish()>"Skip"</button>
+                        <button type="button" on:click=next>{if last { "Done" } else { "Next" }}</button>
+                    </div>
+                </div>
+            }
+        })}
+    }
+}
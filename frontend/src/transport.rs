@@ -0,0 +1,252 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{console, WebSocket};
+
+use crate::config;
+use crate::error::{self, SignalingError};
+use crate::signaling::{self, SignalingMessage};
+
+/// Close code for a connection that ended without the server closing it.
+pub const CLOSE_ABNORMAL: u16 = 1006;
+
+/// How we reach the signaling server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    WebSocket,
+    /// Server-sent events down, posts up: slower, but gets through proxies
+    /// that break WebSockets
+    EventStream,
+}
+
+/// What a transport reports to the chat page.
+pub struct Handlers {
+    pub on_open: Box<dyn FnMut(&Transport)>,
+    pub on_message: Box<dyn FnMut(SignalingMessage)>,
+    /// Close code and reason, as a WebSocket reports them
+    pub on_close: Box<dyn FnMut(u16, String)>,
+    pub on_error: Box<dyn FnMut()>,
+}
+
+/// An open, or opening, connection to the signaling server.
+#[derive(Clone)]
+pub enum Transport {
+    WebSocket(WebSocket),
+    EventStream(Rc<EventStream>),
+}
+
+impl Transport {
+    /// Connects with `jwt`; `None` if the browser refused to even try.
+    pub fn connect(kind: Kind, jwt: &str, handlers: Handlers) -> Option<Transport> {
+        match kind {
+            Kind::WebSocket => connect_websocket(jwt, handlers),
+            Kind::EventStream => Some(Transport::EventStream(EventStream::connect(jwt, handlers))),
+        }
+    }
+
+    pub fn kind(&self) -> Kind {
+        match self {
+            Transport::WebSocket(_) => Kind::WebSocket,
+            Transport::EventStream(_) => Kind::EventStream,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        match self {
+            Transport::WebSocket(ws) => ws.ready_state() == WebSocket::OPEN,
+            Transport::EventStream(stream) => stream.is_open(),
+        }
+    }
+
+    /// Sends `msg`; `false` if it could not go out.
+    pub fn send(&self, msg: &SignalingMessage) -> bool {
+        match self {
+            Transport::WebSocket(ws) => signaling::send(ws, msg),
+            Transport::EventStream(stream) => stream.send(msg),
+        }
+    }
+}
+
+fn connect_websocket(jwt: &str, handlers: Handlers) -> Option<Transport> {
+    let Handlers { mut on_open, mut on_message, mut on_close, mut on_error } = handlers;
+    // The token travels as a subprotocol entry rather than in the URL,
+    // which would leak it into server and proxy logs
+    let protocols = js_sys::Array::of2(&JsValue::from_str("p2p-chat"), &JsValue::from_str(&format!("bearer.{}", jwt)));
+    let ws = WebSocket::new_with_str_sequence(&config::ws_url(), &protocols).ok()?;
+    ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
+    let transport = Transport::WebSocket(ws.clone());
+    let onopen = Closure::wrap(Box::new(move |_ev| on_open(&transport)) as Box<dyn FnMut(web_sys::Event)>);
+    ws.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+    onopen.forget();
+    let onmessage = Closure::wrap(Box::new(move |ev: web_sys::MessageEvent| {
+        if let Some(msg) = signaling::decode(&ev.data()) {
+            on_message(msg);
+        }
+    }) as Box<dyn FnMut(web_sys::MessageEvent)>);
+    ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+    onmessage.forget();
+    let onclose = Closure::wrap(
+        Box::new(move |ev: web_sys::CloseEvent| on_close(ev.code(), ev.reason())) as Box<dyn FnMut(web_sys::CloseEvent)>
+    );
+    ws.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+    onclose.forget();
+    let onerror = Closure::wrap(Box::new(move |_ev| on_error()) as Box<dyn FnMut(web_sys::Event)>);
+    ws.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+    onerror.forget();
+    Some(Transport::WebSocket(ws))
+}
+
+/// The `close` event that ends a stream the server closed.
+#[derive(Default, Deserialize)]
+struct Close {
+    code: u16,
+    reason: String,
+}
+
+/// Signaling over `GET /events`, read with fetch since `EventSource`
+/// cannot send our token, with what we send posted to `/signal` one
+/// message at a time so it arrives in order.
+pub struct EventStream {
+    jwt: String,
+    /// Client id from the stream's first event, which posts name
+    session: RefCell<Option<String>>,
+    closed: Cell<bool>,
+    queue: RefCell<VecDeque<String>>,
+    posting: Cell<bool>,
+}
+
+impl EventStream {
+    fn connect(jwt: &str, mut handlers: Handlers) -> Rc<EventStream> {
+        let stream = Rc::new(EventStream {
+            jwt: jwt.to_string(),
+            session: RefCell::new(None),
+            closed: Cell::new(false),
+            queue: RefCell::new(VecDeque::new()),
+            posting: Cell::new(false),
+        });
+        let reading = stream.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let close = match reading.read(&mut handlers).await {
+                Ok(close) => close,
+                Err(err) => {
+                    console::log_1(&format!("Event stream failed: {:?}", err).into());
+                    (handlers.on_error)();
+                    None
+                }
+            };
+            reading.closed.set(true);
+            let Close { code, reason } = close.unwrap_or(Close { code: CLOSE_ABNORMAL, reason: String::new() });
+            (handlers.on_close)(code, reason);
+        });
+        stream
+    }
+
+    fn is_open(&self) -> bool {
+        !self.closed.get() && self.session.borrow().is_some()
+    }
+
+    /// Reads events until the stream ends: with the server's close, if it
+    /// sent one.
+    async fn read(self: &Rc<Self>, handlers: &mut Handlers) -> Result<Option<Close>, JsValue> {
+        let opts = web_sys::RequestInit::new();
+        opts.set_method("GET");
+        let request = web_sys::Request::new_with_str_and_init(&format!("{}/events", config::api_base()), &opts)?;
+        request.headers().set("Accept", "text/event-stream")?;
+        request.headers().set("Authorization", &format!("Bearer {}", self.jwt))?;
+        let response: web_sys::Response = JsFuture::from(window().fetch_with_request(&request)).await?.dyn_into()?;
+        if !response.ok() {
+            return Err(JsValue::from_str(&format!("GET /events: {}", response.status())));
+        }
+        let body = response.body().ok_or_else(|| JsValue::from_str("GET /events: no body"))?;
+        let reader: web_sys::ReadableStreamDefaultReader = body.get_reader().dyn_into()?;
+
+        let mut buffer = Vec::new();
+        loop {
+            let chunk = JsFuture::from(reader.read()).await?;
+            if js_sys::Reflect::get(&chunk, &"done".into())?.is_truthy() {
+                return Ok(None);
+            }
+            buffer.extend(js_sys::Uint8Array::new(&js_sys::Reflect::get(&chunk, &"value".into())?).to_vec());
+            // Events end with a blank line
+            while let Some(end) = buffer.windows(2).position(|w| w == b"\n\n") {
+                let event: Vec<u8> = buffer.drain(..end + 2).collect();
+                let event = String::from_utf8_lossy(&event);
+                let (name, data) = parse_event(&event);
+                match name {
+                    "session" => {
+                        *self.session.borrow_mut() = Some(data);
+                        (handlers.on_open)(&Transport::EventStream(self.clone()));
+                    }
+                    "close" => return Ok(Some(serde_json::from_str(&data).unwrap_or_default())),
+                    _ => {
+                        if let Ok(msg) = serde_json::from_str(&data) {
+                            (handlers.on_message)(msg);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn send(self: &Rc<Self>, msg: &SignalingMessage) -> bool {
+        if !self.is_open() {
+            return false;
+        }
+        let Ok(json) = serde_json::to_string(msg) else { return false };
+        self.queue.borrow_mut().push_back(json);
+        if !self.posting.replace(true) {
+            let posting = self.clone();
+            wasm_bindgen_futures::spawn_local(async move { posting.post_queued().await });
+        }
+        true
+    }
+
+    async fn post_queued(&self) {
+        loop {
+            let Some(json) = self.queue.borrow_mut().pop_front() else { break };
+            if let Err(err) = self.post(&json).await {
+                error::record(&SignalingError::Unreachable(format!("POST /signal: {:?}", err)).into());
+            }
+        }
+        self.posting.set(false);
+    }
+
+    async fn post(&self, json: &str) -> Result<(), JsValue> {
+        let session = self.session.borrow().clone().unwrap_or_default();
+        let opts = web_sys::RequestInit::new();
+        opts.set_method("POST");
+        opts.set_body(&JsValue::from_str(json));
+        let request =
+            web_sys::Request::new_with_str_and_init(&format!("{}/signal?session={}", config::api_base(), session), &opts)?;
+        request.headers().set("Content-Type", "application/json")?;
+        request.headers().set("Authorization", &format!("Bearer {}", self.jwt))?;
+        let response: web_sys::Response = JsFuture::from(window().fetch_with_request(&request)).await?.dyn_into()?;
+        if !response.ok() {
+            return Err(JsValue::from_str(&response.status().to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn window() -> web_sys::Window {
+    web_sys::window().unwrap()
+}
+
+/// Name and data of one server-sent event; unnamed events are `message`,
+/// and keep-alive comments come out with no data.
+fn parse_event(event: &str) -> (&str, String) {
+    let mut name = "message";
+    let mut data = Vec::new();
+    for line in event.lines() {
+        if let Some(value) = line.strip_prefix("event:") {
+            name = value.trim();
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data.push(value.strip_prefix(' ').unwrap_or(value));
+        }
+    }
+    (name, data.join("\n"))
+}
@@ -0,0 +1,59 @@
+use std::cell::Cell;
+
+use crate::clock;
+use crate::error::{self, CryptoError};
+
+/// Crockford's base32, which leaves out I, L, O and U.
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const RANDOM_BITS: u32 = 80;
+
+thread_local! {
+    /// Last ID handed out, so IDs from the same millisecond still sort in
+    /// the order they were made
+    static LAST: Cell<u128> = const { Cell::new(0) };
+}
+
+fn random_bits() -> u128 {
+    let mut bytes = [0u8; 10];
+    let filled = web_sys::window()
+        .and_then(|w| w.crypto().ok())
+        .is_some_and(|crypto| crypto.get_random_values_with_u8_array(&mut bytes).is_ok());
+    if !filled {
+        error::record(&CryptoError::NoRandomSource.into());
+    }
+    bytes.iter().fold(0u128, |acc, b| (acc << 8) | *b as u128)
+}
+
+fn encode(value: u128) -> String {
+    (0..26).rev().map(|i| ALPHABET[((value >> (i * 5)) & 0x1f) as usize] as char).collect()
+}
+
+/// A new ULID: 48 bits of server-corrected milliseconds followed by 80
+/// random bits, as 26 characters. IDs sort by creation time as strings,
+/// also across peers, and within one millisecond by creation order.
+pub fn new() -> String {
+    let millis = clock::now().max(0.0) as u128 & ((1 << 48) - 1);
+    let mut value = (millis << RANDOM_BITS) | random_bits();
+    LAST.with(|last| {
+        if value >> RANDOM_BITS <= last.get() >> RANDOM_BITS {
+            // Same millisecond, or the clock was corrected backwards
+            value = last.get() + 1;
+        }
+        last.set(value);
+    });
+    encode(value)
+}
+
+/// When a ULID was made, in milliseconds since the epoch. `None` for other
+/// IDs, such as the UUIDs older clients send.
+pub fn timestamp(id: &str) -> Option<f64> {
+    if id.len() != 26 {
+        return None;
+    }
+    let mut value = 0u128;
+    for c in id.bytes() {
+        let digit = ALPHABET.iter().position(|a| *a == c.to_ascii_uppercase())?;
+        value = (value << 5) | digit as u128;
+    }
+    Some((value >> RANDOM_BITS) as f64)
+}
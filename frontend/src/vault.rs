@@ -0,0 +1,176 @@
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use js_sys::{Array, Uint8Array};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{AesGcmParams, CryptoKey, Pbkdf2Params, SubtleCrypto};
+
+use crate::error::CryptoError;
+
+/// The derived key is kept next to the session token, and removed with it.
+const KEY_STORAGE_KEY: &str = "vault_key";
+/// OWASP's recommendation for PBKDF2-HMAC-SHA256.
+const PBKDF2_ITERATIONS: u32 = 600_000;
+const SALT_LEN: usize = 16;
+const IV_LEN: usize = 12;
+const KEY_BITS: u32 = 256;
+const SEALED_VERSION: u32 = 1;
+
+/// An AES-256-GCM key derived from the account password, and the salt it
+/// was derived with. Sealed data names its salt, so a key made from another
+/// password is told apart without trying it.
+#[derive(Clone)]
+pub struct Key {
+    salt: Vec<u8>,
+    bytes: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredKey {
+    salt: String,
+    key: String,
+}
+
+/// Ciphertext as the server stores it; all fields but the version are base64.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sealed {
+    v: u32,
+    salt: String,
+    iv: String,
+    data: String,
+}
+
+fn subtle() -> Result<SubtleCrypto, CryptoError> {
+    Ok(web_sys::window().and_then(|w| w.crypto().ok()).ok_or(CryptoError::NoRandomSource)?.subtle())
+}
+
+async fn resolve(promise: Result<js_sys::Promise, JsValue>) -> Result<JsValue, CryptoError> {
+    let failed = |err: JsValue| CryptoError::Failed(format!("{:?}", err));
+    JsFuture::from(promise.map_err(failed)?).await.map_err(failed)
+}
+
+fn random(len: usize) -> Result<Vec<u8>, CryptoError> {
+    let mut bytes = vec![0u8; len];
+    web_sys::window()
+        .and_then(|w| w.crypto().ok())
+        .filter(|crypto| crypto.get_random_values_with_u8_array(&mut bytes).is_ok())
+        .ok_or(CryptoError::NoRandomSource)?;
+    Ok(bytes)
+}
+
+pub fn new_salt() -> Result<Vec<u8>, CryptoError> {
+    random(SALT_LEN)
+}
+
+/// Stretches the password into a key with PBKDF2-SHA256. Takes a moment
+/// on purpose.
+pub async fn derive(password: &str, salt: Vec<u8>) -> Result<Key, CryptoError> {
+    let subtle = subtle()?;
+    let password_key: CryptoKey = resolve(subtle.import_key_with_str(
+        "raw",
+        &Uint8Array::from(password.as_bytes()),
+        "PBKDF2",
+        false,
+        &Array::of1(&"deriveBits".into()),
+    ))
+    .await?
+    .unchecked_into();
+    let params = Pbkdf2Params::new("PBKDF2", &"SHA-256".into(), PBKDF2_ITERATIONS, &Uint8Array::from(&salt[..]));
+    let bits = resolve(subtle.derive_bits_with_object(&params, &password_key, KEY_BITS)).await?;
+    Ok(Key { salt, bytes: Uint8Array::new(&bits).to_vec() })
+}
+
+/// The key stored on this device, if the user signed in here since it was
+/// last rotated.
+pub fn current() -> Option<Key> {
+    let stored = web_sys::window()?.local_storage().ok()??.get_item(KEY_STORAGE_KEY).ok()??;
+    let stored: StoredKey = serde_json::from_str(&stored).ok()?;
+    Some(Key { salt: STANDARD.decode(stored.salt).ok()?, bytes: STANDARD.decode(stored.key).ok()? })
+}
+
+pub fn keep(key: &Key) {
+    let stored = StoredKey { salt: STANDARD.encode(&key.salt), key: STANDARD.encode(&key.bytes) };
+    if let Some(storage) = web_sys::window().and_then(|w| w.local_storage().ok().flatten()) {
+        let _ = storage.set_item(KEY_STORAGE_KEY, &serde_json::to_string(&stored).unwrap_or_default());
+    }
+}
+
+/// The stored key as text for a URL fragment, which browsers never send to
+/// a server, so a QR login can bring it to the new device.
+pub fn share() -> Option<String> {
+    let key = current()?;
+    Some(format!("{}.{}", URL_SAFE_NO_PAD.encode(&key.salt), URL_SAFE_NO_PAD.encode(&key.bytes)))
+}
+
+/// Keeps a key from [`share`]; false if `shared` is not one.
+pub fn accept(shared: &str) -> bool {
+    let Some((salt, bytes)) = shared.split_once('.') else { return false };
+    match (URL_SAFE_NO_PAD.decode(salt), URL_SAFE_NO_PAD.decode(bytes)) {
+        (Ok(salt), Ok(bytes)) => {
+            keep(&Key { salt, bytes });
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Drops the key from this device, e.g. on signing out.
+pub fn forget() {
+    if let Some(storage) = web_sys::window().and_then(|w| w.local_storage().ok().flatten()) {
+        let _ = storage.remove_item(KEY_STORAGE_KEY);
+    }
+}
+
+async fn aes_key(key: &Key) -> Result<CryptoKey, CryptoError> {
+    Ok(resolve(subtle()?.import_key_with_str(
+        "raw",
+        &Uint8Array::from(&key.bytes[..]),
+        "AES-GCM",
+        false,
+        &Array::of2(&"encrypt".into(), &"decrypt".into()),
+    ))
+    .await?
+    .unchecked_into())
+}
+
+impl Sealed {
+    /// The salt of the key this was sealed with.
+    pub fn salt(&self) -> Option<Vec<u8>> {
+        STANDARD.decode(&self.salt).ok()
+    }
+
+    pub async fn seal(key: &Key, plaintext: &[u8]) -> Result<Sealed, CryptoError> {
+        let iv = random(IV_LEN)?;
+        let params = AesGcmParams::new("AES-GCM", &Uint8Array::from(&iv[..]));
+        let data = resolve(subtle()?.encrypt_with_object_and_buffer_source(
+            &params,
+            &aes_key(key).await?,
+            &Uint8Array::from(plaintext),
+        ))
+        .await?;
+        Ok(Sealed {
+            v: SEALED_VERSION,
+            salt: STANDARD.encode(&key.salt),
+            iv: STANDARD.encode(&iv),
+            data: STANDARD.encode(Uint8Array::new(&data).to_vec()),
+        })
+    }
+
+    /// Decrypts with `key`; [`CryptoError::Locked`] if the data was sealed
+    /// with a key from another password.
+    pub async fn open(&self, key: &Key) -> Result<Vec<u8>, CryptoError> {
+        if self.v != SEALED_VERSION || self.salt().as_deref() != Some(&key.salt[..]) {
+            return Err(CryptoError::Locked);
+        }
+        let decode = |field: &str| STANDARD.decode(field).map_err(|err| CryptoError::Failed(err.to_string()));
+        let params = AesGcmParams::new("AES-GCM", &Uint8Array::from(&decode(&self.iv)?[..]));
+        let plaintext = resolve(subtle()?.decrypt_with_object_and_buffer_source(
+            &params,
+            &aes_key(key).await?,
+            &Uint8Array::from(&decode(&self.data)?[..]),
+        ))
+        .await?;
+        Ok(Uint8Array::new(&plaintext).to_vec())
+    }
+}